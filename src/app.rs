@@ -1,23 +1,32 @@
 use std::io::{self, Write};
+use std::time::{Duration, Instant};
 
 use termion::event::Key;
 
-use crate::cursor::Cursor;
-use crate::editor::Editor;
-use crate::handler::{self, HandlerResult};
+use crate::command_line::CommandLine;
+use crate::config::{EditorConfig, LineNumberMode};
+use crate::cursor::{Cursor, Position};
+use crate::editor::{Editor, word_at};
+use crate::handler::{self, HandlerResult, normal::NormalState};
 use crate::mode::{Mode, ModeManager};
-use crate::screen::Screen;
+use crate::range::LineRange;
+use crate::screen::{Screen, ViewState};
+use crate::search::Highlight;
 
 pub struct App {
     pub editor: Editor,
-    pub cursor: Cursor,
     pub mode_manager: ModeManager,
-    pub command_buffer: String,
-    pub pending_key: Option<char>,
+    pub command_line: CommandLine,
+    pub normal_state: NormalState,
     pub status_message: String,
     pub terminal_size: (u16, u16),
     pub editor_rows: u16,
+    screen: Screen,
     prev_mode: Mode,
+    /// 続くキーを待ち始めた時刻 (`timeoutlen` の経過で取り消す)
+    pending_since: Option<Instant>,
+    /// 直前のキーで変わったのがカーソル行だけか (`refresh_line` で描画できる)
+    line_only: bool,
 }
 
 impl App {
@@ -25,49 +34,156 @@ impl App {
         let editor_rows = Screen::editor_rows(terminal_size.1);
         Self {
             editor,
-            cursor: Cursor::new(),
             mode_manager: ModeManager::new(),
-            command_buffer: String::new(),
-            pending_key: None,
+            command_line: CommandLine::new(),
+            normal_state: NormalState::default(),
             status_message: String::new(),
             terminal_size,
             editor_rows,
+            screen: Screen::new(),
             prev_mode: Mode::Normal,
+            pending_since: None,
+            line_only: false,
         }
     }
 
+    /// カーソル位置 (行, 列)。どちらも 0 から数えた文字単位
+    pub fn cursor_position(&self) -> (usize, usize) {
+        let pos = self.editor.cursor.position();
+        (pos.row, pos.col)
+    }
+
+    /// カーソルを (行, 列) に移動する (`:normal` やテストなどから使う)
+    ///
+    /// バッファの範囲外の場合は最終行・行末に丸め、画面をスクロールする。
+    /// 行末の扱いはキーで移動した場合と同じく、Insert mode では行末の次、
+    /// それ以外では最後の文字 (`virtualedit` の範囲内) まで。
+    pub fn set_cursor_position(&mut self, row: usize, col: usize) {
+        let buffer_len = self.editor.buffer().len();
+        let row = row.min(buffer_len.saturating_sub(1));
+        let line_len = self.editor.current_line_len(row);
+        let limit = if self.mode_manager.is_insert() {
+            line_len + 1
+        } else {
+            self.editor.config.virtualedit.line_limit(line_len)
+        };
+        let col = col.min(limit.saturating_sub(1));
+        self.editor
+            .cursor
+            .move_to_position(Position::new(row, col), buffer_len, self.editor_rows);
+        self.editor.cursor.scroll(self.editor_rows, buffer_len);
+        self.scroll_wrapped();
+    }
+
+    /// ファイルが外部で変更されていれば、`autoread` に従って読み込み直すか警告する
+    ///
+    /// 入力の途中で内容が変わらないよう、Normal mode でキーを待っている間だけ確認する。
+    ///
+    /// # Returns
+    ///
+    /// 画面を更新する必要がある場合は true
+    pub fn check_file_changed(&mut self) -> bool {
+        if !self.mode_manager.is_normal()
+            || self.normal_state.is_pending()
+            || self.editor.prompt.is_some()
+            || self.editor.confirm.is_some()
+        {
+            return false;
+        }
+        let Some(msg) = self.editor.check_file_changed() else {
+            return false;
+        };
+        self.status_message = msg;
+        true
+    }
+
+    /// 入力途中のキーを取り消すまでの残り時間 (待っていない場合は None)
+    pub fn pending_timeout(&self) -> Option<Duration> {
+        let timeoutlen = self.editor.config.timeoutlen;
+        if timeoutlen == 0 {
+            return None;
+        }
+        let since = self.pending_since?;
+        Some(Duration::from_millis(timeoutlen as u64).saturating_sub(since.elapsed()))
+    }
+
+    /// `now` の時点で `timeoutlen` を過ぎていれば、入力途中のキーを取り消す
+    ///
+    /// # Returns
+    ///
+    /// 取り消した場合は true
+    pub fn expire_pending(&mut self, now: Instant) -> bool {
+        let timeoutlen = self.editor.config.timeoutlen;
+        let Some(since) = self.pending_since else {
+            return false;
+        };
+        if timeoutlen == 0 || now.duration_since(since) < Duration::from_millis(timeoutlen as u64) {
+            return false;
+        }
+        self.normal_state.reset();
+        self.pending_since = None;
+        true
+    }
+
     pub fn handle_key(&mut self, key: Key) -> HandlerResult {
         let prev_mode = self.mode_manager.current();
+        // カーソル行以外の表示に影響する状態
+        let layout = (
+            self.editor.buffer().len(),
+            self.editor.cursor.file_row(),
+            self.editor.cursor.row_offset(),
+            self.status_message.clone(),
+        );
+        // Visual mode を抜けたときに `< / `> マークに記録する選択範囲
+        let selection = self
+            .mode_manager
+            .visual_start()
+            .filter(|_| self.mode_manager.is_visual() || self.mode_manager.is_visual_line())
+            .map(|start| {
+                (
+                    start,
+                    self.editor.cursor.position(),
+                    self.mode_manager.is_visual_line(),
+                )
+            });
 
-        let result = if self.mode_manager.is_normal() {
-            let r = handler::normal::handle(
+        let result = if self.editor.prompt.is_some() {
+            // `:confirm q` などの確認中はすべてのキーを確認の応答として扱う
+            handler::prompt::handle(key, &mut self.editor)
+        } else if self.editor.confirm.is_some() {
+            // `:s///c` の確認中はすべてのキーを確認の応答として扱う
+            handler::confirm::handle(key, &mut self.editor, self.editor_rows)
+        } else if self.mode_manager.is_normal() {
+            let r = handler::normal::dispatch(
                 key,
                 &mut self.editor,
-                &mut self.cursor,
                 &mut self.mode_manager,
-                &mut self.pending_key,
+                &mut self.normal_state,
                 self.terminal_size,
                 self.editor_rows,
             );
-            // ':' でコマンドモードに入った場合、command_buffer をクリアする
+            // ':' / '/' / '?' でコマンドモードに入った場合、コマンドラインをクリアする
             if self.mode_manager.is_command() {
-                self.command_buffer.clear();
+                let prompt = match key {
+                    Key::Char(c @ ('/' | '?')) => c,
+                    _ => ':',
+                };
+                self.command_line.start(prompt);
             }
             r
         } else if self.mode_manager.is_command() {
             handler::command::handle(
                 key,
                 &mut self.editor,
-                &mut self.cursor,
                 &mut self.mode_manager,
-                &mut self.command_buffer,
+                &mut self.command_line,
+                self.terminal_size,
                 self.editor_rows,
             )
         } else if self.mode_manager.is_insert() {
             handler::insert::handle(
                 key,
                 &mut self.editor,
-                &mut self.cursor,
                 &mut self.mode_manager,
                 self.terminal_size,
                 self.editor_rows,
@@ -76,8 +192,8 @@ impl App {
             handler::visual::handle(
                 key,
                 &mut self.editor,
-                &mut self.cursor,
                 &mut self.mode_manager,
+                &mut self.normal_state.pending_key,
                 self.terminal_size,
                 self.editor_rows,
             )
@@ -85,17 +201,25 @@ impl App {
             handler::visual_line::handle(
                 key,
                 &mut self.editor,
-                &mut self.cursor,
                 &mut self.mode_manager,
+                &mut self.normal_state.pending_key,
                 self.editor_rows,
             )
         } else {
             HandlerResult::Continue
         };
+        if let Some((start, end, linewise)) = selection
+            && !self.mode_manager.is_visual()
+            && !self.mode_manager.is_visual_line()
+        {
+            self.editor.set_visual_marks(start, end, linewise);
+        }
 
         // HandlerResult に基づいてステータスメッセージを更新
         match &result {
-            HandlerResult::StatusMessage(msg) => self.status_message = msg.clone(),
+            HandlerResult::StatusMessage(msg) | HandlerResult::Error(msg) => {
+                self.status_message = msg.clone()
+            }
             HandlerResult::ClearStatus => self.status_message.clear(),
             _ => {}
         }
@@ -103,28 +227,410 @@ impl App {
         // モードが変わった場合はステータスメッセージをクリア
         if self.mode_manager.current() != prev_mode {
             // ただし、ハンドラが明示的にメッセージを設定した場合は維持する
-            if !matches!(&result, HandlerResult::StatusMessage(_)) {
+            if !matches!(
+                &result,
+                HandlerResult::StatusMessage(_) | HandlerResult::Error(_)
+            ) {
                 self.status_message.clear();
             }
         }
         self.prev_mode = self.mode_manager.current();
+        // キーを入力するたびに待ち時間を数え直す
+        self.pending_since = self.normal_state.is_pending().then(Instant::now);
 
-        self.cursor
+        self.editor
+            .cursor
             .scroll(self.editor_rows, self.editor.buffer().len());
+        self.scroll_wrapped();
+
+        // Insert mode で行数・カーソル行・スクロール位置・メッセージが変わらない文字の入力と削除
+        // (折り返した行の数が変わりうる `:set wrap` の場合を除く)
+        self.line_only = prev_mode == Mode::Insert
+            && !self.editor.config.wrap
+            && self.mode_manager.is_insert()
+            && matches!(key, Key::Char(_) | Key::Backspace)
+            && layout
+                == (
+                    self.editor.buffer().len(),
+                    self.editor.cursor.file_row(),
+                    self.editor.cursor.row_offset(),
+                    self.status_message.clone(),
+                );
 
         result
     }
 
-    pub fn refresh(&self, stdout: &mut impl Write) -> io::Result<()> {
-        Screen::refresh(
+    /// 直前のキーで変わったのがカーソル行だけか
+    pub fn line_only_changed(&self) -> bool {
+        self.line_only
+    }
+
+    /// カーソル行だけを描画し直す (`line_only_changed` の場合に使う)
+    pub fn refresh_line(&self, stdout: &mut impl Write) -> io::Result<()> {
+        let mode = self.mode_manager.current();
+        let view = ViewState {
+            mode,
+            command_prompt: self.command_line.prompt,
+            command_buffer: &self.command_line.buffer,
+            status_message: &self.status_message,
+            visual_start: None,
+            number_mode: effective_number_mode(&self.editor.config, mode),
+            highlight: None,
+            pending_keys: "",
+        };
+        self.screen.refresh_line(
             stdout,
-            &self.cursor,
-            self.mode_manager.current(),
-            &self.command_buffer,
-            self.editor.buffer(),
-            self.editor.filename(),
-            &self.status_message,
-            self.mode_manager.visual_start(),
+            self.terminal_size,
+            &self.editor.cursor,
+            &self.editor,
+            &view,
         )
     }
+
+    pub fn refresh(&self, stdout: &mut impl Write) -> io::Result<()> {
+        self.draw(stdout, &self.editor.cursor)
+    }
+
+    /// カーソルを対応する括弧の位置に移動したように描画する (`:set showmatch`)
+    ///
+    /// 実際のカーソル位置は変更しない。
+    pub fn refresh_showing_match(&self, stdout: &mut impl Write, pos: Position) -> io::Result<()> {
+        let mut cursor = self.editor.cursor.clone();
+        cursor.move_to_position(pos, self.editor.buffer().len(), self.editor_rows);
+        self.draw(stdout, &cursor)
+    }
+
+    /// `showmatch` で対応する括弧を表示しておく時間
+    pub fn match_time(&self) -> Duration {
+        Duration::from_millis(self.editor.config.matchtime as u64 * 100)
+    }
+
+    fn draw(&self, stdout: &mut impl Write, cursor: &Cursor) -> io::Result<()> {
+        let mode = self.mode_manager.current();
+        let word_highlight = self.word_highlight();
+        // ほかにメッセージがない場合は単語の一致数を表示する
+        let word_status = match &word_highlight {
+            Some(highlight) if self.status_message.is_empty() => {
+                let (current, total) =
+                    highlight.count_matches(self.editor.buffer(), self.editor.cursor.position());
+                format!("match {} of {}", current, total)
+            }
+            _ => String::new(),
+        };
+        let view = ViewState {
+            mode,
+            command_prompt: self.command_line.prompt,
+            command_buffer: &self.command_line.buffer,
+            status_message: if word_status.is_empty() {
+                &self.status_message
+            } else {
+                &word_status
+            },
+            visual_start: self.mode_manager.visual_start(),
+            number_mode: effective_number_mode(&self.editor.config, mode),
+            highlight: self
+                .command_line
+                .preview
+                .as_ref()
+                .or(word_highlight.as_ref()),
+            pending_keys: &self.normal_state.pending_keys(),
+        };
+        self.screen
+            .refresh(stdout, self.terminal_size, cursor, &self.editor, &view)
+    }
+
+    /// `:set wrap` で折り返した行の分だけスクロールする
+    fn scroll_wrapped(&mut self) {
+        let number_mode = effective_number_mode(&self.editor.config, self.mode_manager.current());
+        let cols = Screen::text_cols(self.terminal_size.0, number_mode, &self.editor);
+        Screen::scroll_wrapped(&mut self.editor, cols, self.editor_rows);
+    }
+
+    /// `:set hlword` で強調表示する、カーソル位置の単語の一致 (表示中の行のみ)
+    fn word_highlight(&self) -> Option<Highlight> {
+        if !self.editor.config.hlword || !self.mode_manager.is_normal() {
+            return None;
+        }
+        let pos = self.editor.cursor.position();
+        let line = self.editor.buffer().row(pos.row)?;
+        let word = word_at(line.chars(), pos.col)?;
+        let first_row = self.editor.cursor.row_offset() as usize;
+        let last_row = first_row + (self.editor_rows as usize).saturating_sub(1);
+        Some(Highlight::word(
+            &word,
+            Some(LineRange::new(first_row, last_row)),
+        ))
+    }
+}
+
+/// 設定と現在のモードから実際に使用する行番号の表示方法を決める
+///
+/// `number` と `relativenumber` が両方有効な場合、Insert モードでは絶対行番号、
+/// それ以外のモードではカーソル行のみ絶対行番号の相対行番号で表示する。
+pub fn effective_number_mode(config: &EditorConfig, mode: Mode) -> LineNumberMode {
+    match (config.number, config.relativenumber) {
+        (false, false) => LineNumberMode::Off,
+        (true, false) => LineNumberMode::Absolute,
+        (false, true) => LineNumberMode::Relative,
+        (true, true) if mode == Mode::Insert => LineNumberMode::Absolute,
+        (true, true) => LineNumberMode::Hybrid,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(number: bool, relativenumber: bool) -> EditorConfig {
+        EditorConfig {
+            number,
+            relativenumber,
+            ..EditorConfig::default()
+        }
+    }
+
+    fn type_keys(app: &mut App, keys: &str) -> HandlerResult {
+        let mut result = HandlerResult::Continue;
+        for c in keys.chars() {
+            result = app.handle_key(Key::Char(c));
+        }
+        result
+    }
+
+    #[test]
+    fn test_nomodifiable_rejects_changes() {
+        use crate::buffer::{FileEncoding, FileFormat};
+
+        let buffer = crate::buffer::Buffer::from_lines(["hello"]);
+        let mut app = App::new(Editor::from_buffer(buffer, None), (80, 24));
+        type_keys(&mut app, ":set noma\n");
+        assert!(!app.editor.is_modifiable());
+
+        // x や dd は何もせずにメッセージを表示する
+        let message = "Cannot make changes, 'modifiable' is off";
+        assert!(
+            matches!(type_keys(&mut app, "x"), HandlerResult::StatusMessage(msg) if msg == message)
+        );
+        type_keys(&mut app, "dd");
+        assert_eq!(app.editor.buffer().row(0).unwrap().chars(), "hello");
+        assert!(!app.editor.is_dirty());
+        assert_eq!(app.status_message, message);
+
+        // Insert mode には入らない
+        type_keys(&mut app, "o");
+        assert!(app.mode_manager.is_normal());
+        assert_eq!(app.editor.buffer().len(), 1);
+
+        // Ex コマンドでの変更も受け付けない
+        type_keys(&mut app, ":s/hello/bye/\n");
+        assert_eq!(app.editor.buffer().row(0).unwrap().chars(), "hello");
+
+        // 保存内容が変わるバッファごとの設定も変更できない
+        for option in ["bomb", "fileformat=dos", "fileencoding=latin1"] {
+            type_keys(&mut app, &format!(":set {}\n", option));
+            assert_eq!(app.status_message, message);
+        }
+        assert!(!app.editor.buffer().bom());
+        assert_eq!(app.editor.buffer().file_format(), FileFormat::Unix);
+        assert_eq!(app.editor.buffer().encoding(), FileEncoding::Utf8);
+        assert!(!app.editor.is_dirty());
+
+        // :set modifiable で再び編集できる
+        type_keys(&mut app, ":set ma\n");
+        type_keys(&mut app, "x");
+        assert_eq!(app.editor.buffer().row(0).unwrap().chars(), "ello");
+        assert!(app.editor.is_dirty());
+    }
+
+    #[test]
+    fn test_nomodifiable_keeps_registers_and_undo() {
+        let buffer = crate::buffer::Buffer::from_lines(["abc", "def", "ghi"]);
+        let mut app = App::new(Editor::from_buffer(buffer, None), (80, 24));
+        // x で "- に、dd で無名レジスタと "1 に入れる
+        type_keys(&mut app, "xdd");
+        type_keys(&mut app, ":set noma\n");
+
+        // dd / x / u はレジスタもアンドゥの履歴も変えない
+        type_keys(&mut app, "jddxu");
+        let rows: Vec<&str> = app.editor.buffer().iter().map(|r| r.chars()).collect();
+        assert_eq!(rows, vec!["def", "ghi"]);
+        assert_eq!(app.editor.yank.content(), ["bc"]);
+        assert_eq!(app.editor.registers.get('1').unwrap().content, ["bc"]);
+        assert_eq!(app.editor.registers.get('-').unwrap().content, ["a"]);
+
+        // 元に戻すと、変更できなかった dd ではなくその前の dd が取り消される
+        type_keys(&mut app, ":set ma\nu");
+        let rows: Vec<&str> = app.editor.buffer().iter().map(|r| r.chars()).collect();
+        assert_eq!(rows, vec!["bc", "def", "ghi"]);
+    }
+
+    #[test]
+    fn test_help_buffer_is_not_modifiable() {
+        let mut app = App::new(Editor::new(), (80, 24));
+        type_keys(&mut app, ":help\n");
+        assert!(!app.editor.is_modifiable());
+        let first = app.editor.buffer().row(0).unwrap().chars().to_string();
+        type_keys(&mut app, "x");
+        assert_eq!(app.editor.buffer().row(0).unwrap().chars(), first);
+
+        // 元のバッファに戻ると編集できる
+        type_keys(&mut app, ":q\n");
+        assert!(app.editor.is_modifiable());
+    }
+
+    #[test]
+    fn test_marks_select_last_change() {
+        let buffer = crate::buffer::Buffer::from_lines(["one", "two", "three"]);
+        let mut app = App::new(Editor::from_buffer(buffer, None), (80, 24));
+
+        // yy で 1 行ヤンクして 2 行目の下に貼り付け、v`] で貼り付けた行を選択する
+        type_keys(&mut app, "yyjp`[v`]d");
+        let rows: Vec<&str> = app.editor.buffer().iter().map(|r| r.chars()).collect();
+        assert_eq!(rows, vec!["one", "two", "", "three"]);
+
+        // Visual mode を抜けると `< / `> に選択範囲を記録する
+        type_keys(&mut app, "ggVj");
+        app.handle_key(Key::Esc);
+        assert_eq!(app.editor.mark('<'), Ok(crate::cursor::Position::new(0, 0)));
+        assert_eq!(app.editor.mark('>'), Ok(crate::cursor::Position::new(1, 2)));
+        type_keys(&mut app, "G'<");
+        assert_eq!(app.editor.cursor.file_row(), 0);
+        assert!(matches!(type_keys(&mut app, "`x"), HandlerResult::Error(_)));
+    }
+
+    #[test]
+    fn test_set_cursor_position_clamps() {
+        let buffer = crate::buffer::Buffer::from_lines(["short", "", "日本語"]);
+        let mut app = App::new(Editor::from_buffer(buffer, None), (80, 24));
+
+        app.set_cursor_position(0, 3);
+        assert_eq!(app.cursor_position(), (0, 3));
+        // 列は最後の文字、行は最終行に丸める
+        app.set_cursor_position(0, 100);
+        assert_eq!(app.cursor_position(), (0, 4));
+        app.set_cursor_position(1, 5);
+        assert_eq!(app.cursor_position(), (1, 0));
+        app.set_cursor_position(99, 99);
+        assert_eq!(app.cursor_position(), (2, 2));
+
+        // Insert mode では行末の次まで移動できる
+        app.mode_manager.enter_insert();
+        app.set_cursor_position(0, 100);
+        assert_eq!(app.cursor_position(), (0, 5));
+    }
+
+    #[test]
+    fn test_set_cursor_position_scrolls() {
+        let buffer = crate::buffer::Buffer::from_lines((1..=100).map(|i| format!("line {}", i)));
+        let mut app = App::new(Editor::from_buffer(buffer, None), (80, 24));
+
+        app.set_cursor_position(80, 0);
+        assert_eq!(app.cursor_position(), (80, 0));
+        let offset = app.editor.cursor.row_offset() as usize;
+        assert!(offset <= 80 && 80 < offset + app.editor_rows as usize);
+    }
+
+    #[test]
+    fn test_scroll_wrapped_lines() {
+        let buffer = crate::buffer::Buffer::from_lines(vec!["x".repeat(25); 3]);
+        let mut app = App::new(Editor::from_buffer(buffer, None), (10, 6));
+        app.editor.config.wrap = true;
+
+        // 1 行が 3 行に折り返されるので、3 行目を表示するには 1 行目を画面の外に出す
+        app.set_cursor_position(2, 0);
+        assert_eq!(app.editor.cursor.row_offset(), 1);
+        app.set_cursor_position(2, 24);
+        assert_eq!(app.editor.cursor.row_offset(), 2);
+        assert_eq!(app.cursor_position(), (2, 24));
+    }
+
+    #[test]
+    fn test_refresh_uses_terminal_size() {
+        let buffer = crate::buffer::Buffer::from_lines(["hello"]);
+        let app = App::new(Editor::from_buffer(buffer, None), (20, 4));
+        // 端末がなくても、App が持つ大きさで描画できる
+        let mut out = Vec::new();
+        app.refresh(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("hello"));
+        // コマンドラインは最終行 (4 行目) に描画する
+        assert!(out.contains(&termion::cursor::Goto(1, 4).to_string()));
+    }
+
+    #[test]
+    fn test_line_only_changed() {
+        let buffer = crate::buffer::Buffer::from_lines(["hello"]);
+        let mut app = App::new(Editor::from_buffer(buffer, None), (80, 24));
+
+        // Insert mode に入るキーはモードの表示が変わる
+        type_keys(&mut app, "i");
+        assert!(!app.line_only_changed());
+        type_keys(&mut app, "ab");
+        assert!(app.line_only_changed());
+        app.handle_key(Key::Backspace);
+        assert!(app.line_only_changed());
+
+        // 改行で行が増えた場合と、Insert mode を抜けた場合はすべて描画し直す
+        type_keys(&mut app, "\n");
+        assert!(!app.line_only_changed());
+        type_keys(&mut app, "x");
+        assert!(app.line_only_changed());
+        app.handle_key(Key::Esc);
+        assert!(!app.line_only_changed());
+    }
+
+    #[test]
+    fn test_pending_keys_timeout() {
+        let mut app = App::new(Editor::new(), (80, 24));
+        assert_eq!(app.pending_timeout(), None);
+
+        app.handle_key(Key::Char('2'));
+        app.handle_key(Key::Char('d'));
+        assert_eq!(app.normal_state.pending_keys(), "2d");
+        assert!(app.pending_timeout().is_some());
+
+        // timeoutlen を過ぎるまでは取り消さない
+        let now = Instant::now();
+        assert!(!app.expire_pending(now));
+        assert!(app.expire_pending(now + Duration::from_millis(1000)));
+        assert_eq!(app.normal_state, NormalState::default());
+        assert_eq!(app.pending_timeout(), None);
+
+        // timeoutlen=0 の場合は待ち続ける
+        app.editor.config.timeoutlen = 0;
+        app.handle_key(Key::Char('g'));
+        assert_eq!(app.pending_timeout(), None);
+        assert!(!app.expire_pending(now + Duration::from_secs(60)));
+        assert_eq!(app.normal_state.pending_keys(), "g");
+
+        // 続くキーを入力すると待ち状態は終わる
+        app.editor.config.timeoutlen = 1000;
+        app.handle_key(Key::Char('g'));
+        assert_eq!(app.pending_timeout(), None);
+    }
+
+    #[test]
+    fn test_effective_number_mode() {
+        let cases = [
+            (false, false, Mode::Normal, LineNumberMode::Off),
+            (false, false, Mode::Insert, LineNumberMode::Off),
+            (true, false, Mode::Normal, LineNumberMode::Absolute),
+            (true, false, Mode::Insert, LineNumberMode::Absolute),
+            (false, true, Mode::Normal, LineNumberMode::Relative),
+            (false, true, Mode::Insert, LineNumberMode::Relative),
+            (true, true, Mode::Normal, LineNumberMode::Hybrid),
+            (true, true, Mode::Visual, LineNumberMode::Hybrid),
+            (true, true, Mode::Insert, LineNumberMode::Absolute),
+        ];
+        for (number, relativenumber, mode, expected) in cases {
+            assert_eq!(
+                effective_number_mode(&config(number, relativenumber), mode),
+                expected,
+                "number={} relativenumber={} mode={:?}",
+                number,
+                relativenumber,
+                mode
+            );
+        }
+    }
 }
@@ -1,20 +1,43 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use unicode_width::UnicodeWidthChar;
+
 use crate::cursor::Position;
+use crate::range::LineRange;
 
 #[derive(Clone)]
 pub struct Row {
     chars: String,
     render: String,
+    /// 行の内容を変更するたびに新しい値になる番号
+    version: u64,
 }
 
+/// 行の番号の払い出し元 (すべての行で共有し、同じ番号を 2 度使わない)
+static NEXT_ROW_VERSION: AtomicU64 = AtomicU64::new(0);
+
 impl Row {
     pub fn new(text: String) -> Self {
         let render = text.clone();
         Self {
             chars: text,
             render,
+            version: NEXT_ROW_VERSION.fetch_add(1, Ordering::Relaxed),
         }
     }
 
+    /// 行の内容の番号
+    ///
+    /// 行を変更するたびに、どの行でも使っていない値になる。番号が同じ行 (複製を含む) は
+    /// 同じ内容を持つため、行ごとの表示用の計算結果のキャッシュのキーに使える。
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// 行の内容が変わったことを記録する
+    fn touch(&mut self) {
+        self.version = NEXT_ROW_VERSION.fetch_add(1, Ordering::Relaxed);
+    }
+
     pub fn chars(&self) -> &str {
         &self.chars
     }
@@ -36,6 +59,12 @@ impl Row {
         self.chars.is_empty()
     }
 
+    /// 最初の非空白文字の列 (空白だけの行では最後の文字の列)
+    pub fn first_non_blank(&self) -> usize {
+        let indent = self.chars.chars().take_while(|c| c.is_whitespace()).count();
+        indent.min(self.char_count().saturating_sub(1))
+    }
+
     /// 指定位置に文字を挿入
     pub fn insert_char(&mut self, at: usize, ch: char) {
         let byte_pos = self
@@ -47,6 +76,7 @@ impl Row {
         self.chars.insert(byte_pos, ch);
         // TODO: タブ展開は後で実装
         self.render = self.chars.clone();
+        self.touch();
     }
 
     /// 指定位置に文字列を挿入
@@ -59,6 +89,7 @@ impl Row {
             .unwrap_or(self.chars.len());
         self.chars.insert_str(byte_pos, s);
         self.render = self.chars.clone();
+        self.touch();
     }
 
     /// 指定位置の文字を削除し、削除した文字を返す
@@ -66,6 +97,7 @@ impl Row {
         if let Some((byte_pos, ch)) = self.chars.char_indices().nth(at) {
             self.chars.remove(byte_pos);
             self.render = self.chars.clone();
+            self.touch();
             Some(ch)
         } else {
             None
@@ -82,6 +114,7 @@ impl Row {
             .unwrap_or(self.chars.len());
         let tail = self.chars.split_off(byte_pos);
         self.render = self.chars.clone();
+        self.touch();
         tail
     }
 
@@ -89,6 +122,89 @@ impl Row {
     pub fn append(&mut self, s: &str) {
         self.chars.push_str(s);
         self.render = self.chars.clone();
+        self.touch();
+    }
+}
+
+/// 保存時の改行コード (`:set fileformat`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FileFormat {
+    /// `\n`
+    #[default]
+    Unix,
+    /// `\r\n`
+    Dos,
+    /// `\r`
+    Mac,
+}
+
+impl FileFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "unix" => Some(Self::Unix),
+            "dos" => Some(Self::Dos),
+            "mac" => Some(Self::Mac),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Unix => "unix",
+            Self::Dos => "dos",
+            Self::Mac => "mac",
+        }
+    }
+
+    pub fn line_ending(self) -> &'static str {
+        match self {
+            Self::Unix => "\n",
+            Self::Dos => "\r\n",
+            Self::Mac => "\r",
+        }
+    }
+
+    /// ファイルの内容から改行コードを判定する
+    ///
+    /// Vim の `fileformats` と同様に、すべての `\n` の前に `\r` があれば dos、
+    /// `\n` がなく `\r` だけがあれば mac とする。改行コードが混ざっている場合は unix とし、
+    /// 行末の `\r` は行の内容として残す。
+    pub fn detect(content: &str) -> Self {
+        let newlines = content.matches('\n').count();
+        if newlines > 0 && content.matches("\r\n").count() == newlines {
+            Self::Dos
+        } else if !content.contains('\n') && content.contains('\r') {
+            Self::Mac
+        } else {
+            Self::Unix
+        }
+    }
+}
+
+/// ファイルの文字コード (`:set fileencoding`)
+///
+/// バッファの内容は常に UTF-8 で保持し、読み込み・保存時に変換する。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FileEncoding {
+    #[default]
+    Utf8,
+    Latin1,
+}
+
+impl FileEncoding {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "utf-8" | "utf8" => Some(Self::Utf8),
+            "latin1" | "iso-8859-1" => Some(Self::Latin1),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Utf8 => "utf-8",
+            Self::Latin1 => "latin1",
+        }
     }
 }
 
@@ -96,8 +212,17 @@ impl Row {
 pub struct Buffer {
     rows: Vec<Row>,
     trailing_newline: bool,
+    file_format: FileFormat,
+    encoding: FileEncoding,
+    /// ファイルの先頭に BOM を付けるか (`:set bomb`)
+    bom: bool,
+    /// 行の内容を変更するたびに新しい値になる番号
+    version: u64,
 }
 
+/// バッファの番号の払い出し元 (すべてのバッファで共有し、同じ番号を 2 度使わない)
+static NEXT_VERSION: AtomicU64 = AtomicU64::new(0);
+
 impl Default for Buffer {
     fn default() -> Self {
         Self::new()
@@ -105,13 +230,46 @@ impl Default for Buffer {
 }
 
 impl Buffer {
+    /// 空の 1 行だけを持つバッファを作る
+    ///
+    /// Vim と同様に、バッファは常に 1 行以上を持つ。
     pub fn new() -> Self {
         Self {
-            rows: Vec::new(),
+            rows: vec![Row::new(String::new())],
             trailing_newline: false,
+            file_format: FileFormat::Unix,
+            encoding: FileEncoding::Utf8,
+            bom: false,
+            version: NEXT_VERSION.fetch_add(1, Ordering::Relaxed),
         }
     }
 
+    /// 各行の内容からバッファを作る (行がない場合は空の 1 行を持つ)
+    pub fn from_lines<S: AsRef<str>>(lines: impl IntoIterator<Item = S>) -> Self {
+        let mut buffer = Self {
+            rows: lines
+                .into_iter()
+                .map(|line| Row::new(line.as_ref().to_string()))
+                .collect(),
+            ..Self::new()
+        };
+        buffer.ensure_row();
+        buffer
+    }
+
+    /// 行の内容の番号
+    ///
+    /// 行を変更するたびに、どのバッファでも使っていない値になる。番号が同じバッファ
+    /// (複製を含む) は同じ内容を持つため、表示用の計算結果のキャッシュのキーに使える。
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// 行の内容が変わったことを記録する
+    fn touch(&mut self) {
+        self.version = NEXT_VERSION.fetch_add(1, Ordering::Relaxed);
+    }
+
     pub fn trailing_newline(&self) -> bool {
         self.trailing_newline
     }
@@ -120,7 +278,32 @@ impl Buffer {
         self.trailing_newline = value;
     }
 
+    pub fn file_format(&self) -> FileFormat {
+        self.file_format
+    }
+
+    pub fn set_file_format(&mut self, format: FileFormat) {
+        self.file_format = format;
+    }
+
+    pub fn encoding(&self) -> FileEncoding {
+        self.encoding
+    }
+
+    pub fn set_encoding(&mut self, encoding: FileEncoding) {
+        self.encoding = encoding;
+    }
+
+    pub fn bom(&self) -> bool {
+        self.bom
+    }
+
+    pub fn set_bom(&mut self, bom: bool) {
+        self.bom = bom;
+    }
+
     pub fn insert_row(&mut self, at: usize, text: String) {
+        self.touch();
         if at <= self.rows.len() {
             self.rows.insert(at, Row::new(text));
         }
@@ -134,6 +317,14 @@ impl Buffer {
         self.rows.is_empty()
     }
 
+    /// ステータスバーに表示する行数
+    ///
+    /// Vim と同様に、行がないバッファも空の 1 行として数える。
+    /// 末尾の改行の有無は行数に影響しない。
+    pub fn line_count(&self) -> usize {
+        self.rows.len().max(1)
+    }
+
     pub fn row(&self, index: usize) -> Option<&Row> {
         self.rows.get(index)
     }
@@ -142,15 +333,47 @@ impl Buffer {
         &self.rows
     }
 
+    /// すべての行を先頭から順に返す
+    pub fn iter(&self) -> std::slice::Iter<'_, Row> {
+        self.rows.iter()
+    }
+
+    /// `start` 行目から `end` 行目まで (両端を含む) の行を返す
+    ///
+    /// 範囲がバッファを超える場合は、バッファ内の行だけを返す。
+    pub fn iter_range(&self, start: usize, end: usize) -> std::slice::Iter<'_, Row> {
+        let end = end.saturating_add(1).min(self.rows.len());
+        self.rows[start.min(end)..end].iter()
+    }
+
+    /// `start` 行目から `end` 行目まで (両端を含む) の内容
+    pub fn lines_in_range(&self, start: usize, end: usize) -> Vec<String> {
+        self.iter_range(start, end)
+            .map(|row| row.chars().to_string())
+            .collect()
+    }
+
+    /// 行がない場合に空の 1 行を追加する
+    fn ensure_row(&mut self) {
+        if self.rows.is_empty() {
+            self.rows.push(Row::new(String::new()));
+        }
+    }
+
     /// 指定行を削除
+    ///
+    /// 最後の 1 行を削除した場合は空の 1 行が残る。
     pub fn delete_row(&mut self, at: usize) {
+        self.touch();
         if at < self.rows.len() {
             self.rows.remove(at);
+            self.ensure_row();
         }
     }
 
     /// 指定位置に文字を挿入
     pub fn insert_char(&mut self, pos: Position, ch: char) {
+        self.touch();
         // 行が存在しない場合は空行を追加
         if pos.row >= self.rows.len() {
             self.insert_row(self.rows.len(), String::new());
@@ -163,6 +386,7 @@ impl Buffer {
 
     /// 指定位置の文字を削除する
     pub fn delete_char(&mut self, pos: Position) -> Option<char> {
+        self.touch();
         if let Some(r) = self.rows.get_mut(pos.row) {
             r.delete_char(pos.col)
         } else {
@@ -172,6 +396,7 @@ impl Buffer {
 
     /// 改行を挿入（現在行を分割）
     pub fn insert_newline(&mut self, pos: Position) {
+        self.touch();
         if pos.row >= self.rows.len() {
             // 最後の行より後ろの場合は空行を追加
             self.insert_row(self.rows.len(), String::new());
@@ -185,6 +410,7 @@ impl Buffer {
 
     /// 前の行と結合
     pub fn join_rows(&mut self, row: usize) {
+        self.touch();
         if row > 0 && row < self.rows.len() {
             let current_line = self.rows.remove(row);
             if let Some(prev_row) = self.rows.get_mut(row - 1) {
@@ -194,23 +420,361 @@ impl Buffer {
     }
 
     pub fn row_mut(&mut self, index: usize) -> Option<&mut Row> {
+        self.touch();
         self.rows.get_mut(index)
     }
 
     /// 指定行を削除して、その行の内容を返す
+    ///
+    /// 最後の 1 行を削除した場合は空の 1 行が残る。
     pub fn delete_row_with_content(&mut self, at: usize) -> Option<String> {
+        self.touch();
         if at < self.rows.iter().len() {
             let row = self.rows.remove(at);
+            self.ensure_row();
             Some(row.chars().to_string())
         } else {
             None
         }
     }
 
+    /// 指定行の内容を置き換える
+    pub fn set_row(&mut self, at: usize, text: String) {
+        self.touch();
+        if let Some(row) = self.rows.get_mut(at) {
+            *row = Row::new(text);
+        }
+    }
+
     /// 指定行の内容を取得
     pub fn get_row_content(&self, at: usize) -> Option<String> {
         self.rows.get(at).map(|r| r.chars().to_string())
     }
+
+    /// 行範囲の順番を逆にする (`:reverse` 用)
+    ///
+    /// # Returns
+    ///
+    /// 内容が変わった場合は true
+    pub fn reverse_rows(&mut self, range: LineRange) -> bool {
+        self.touch();
+        let end = range.end.saturating_add(1).min(self.rows.len());
+        let rows = &mut self.rows[range.start.min(end)..end];
+        let changed = rows
+            .iter()
+            .zip(rows.iter().rev())
+            .any(|(a, b)| a.chars() != b.chars());
+        rows.reverse();
+        changed
+    }
+
+    /// 行範囲で同じ内容が続く行を 1 行にまとめる (`:uniq` 用)
+    ///
+    /// `inverse` (`:uniq!`) の場合は、同じ内容が続く行だけを 1 行ずつ残し、ほかの行を削除する。
+    ///
+    /// # Returns
+    ///
+    /// 削除した行数
+    pub fn uniq_rows(&mut self, range: LineRange, inverse: bool) -> usize {
+        self.touch();
+        let end = range.end.saturating_add(1).min(self.rows.len());
+        let start = range.start.min(end);
+        let mut kept: Vec<Row> = Vec::new();
+        let mut rows = self.rows[start..end].iter().peekable();
+        while let Some(row) = rows.next() {
+            let mut duplicated = false;
+            while rows.next_if(|next| next.chars() == row.chars()).is_some() {
+                duplicated = true;
+            }
+            if !inverse || duplicated {
+                kept.push(row.clone());
+            }
+        }
+        let removed = end - start - kept.len();
+        self.rows.splice(start..end, kept);
+        self.ensure_row();
+        removed
+    }
+
+    /// `width` 文字を超える行を、`width` 文字に収まる最後の空白で改行する (`maxlinelength` 用)
+    ///
+    /// 続きの行には元の行と同じインデントを付ける。収まる位置に空白がない場合 (長い単語など) は
+    /// その行をそれ以上折り返さない。空白は改行に置き換えるだけで、ほかの内容は変えない。
+    ///
+    /// # Returns
+    ///
+    /// 増えた行数
+    pub fn hard_wrap(&mut self, width: usize) -> usize {
+        self.touch();
+        let is_blank = |c: char| c == ' ' || c == '\t';
+        let mut wrapped: Vec<Row> = Vec::with_capacity(self.rows.len());
+        for row in &self.rows {
+            if row.chars().chars().count() <= width {
+                wrapped.push(row.clone());
+                continue;
+            }
+            let indent: String = row.chars().chars().take_while(|c| is_blank(*c)).collect();
+            let indent_len = indent.chars().count();
+            let mut chars: Vec<char> = row.chars().chars().collect();
+            while chars.len() > width {
+                // width 文字に収まる最後の空白の並びの先頭
+                let Some(blank_start) = (indent_len + 1..=width.min(chars.len() - 1))
+                    .rev()
+                    .find(|&i| is_blank(chars[i]) && !is_blank(chars[i - 1]))
+                else {
+                    break;
+                };
+                let rest = (blank_start..chars.len())
+                    .find(|&i| !is_blank(chars[i]))
+                    .unwrap_or(chars.len());
+                if rest == chars.len() {
+                    break;
+                }
+                wrapped.push(Row::new(chars[..blank_start].iter().collect()));
+                chars = indent
+                    .chars()
+                    .chain(chars[rest..].iter().copied())
+                    .collect();
+            }
+            wrapped.push(Row::new(chars.into_iter().collect()));
+        }
+        let added = wrapped.len() - self.rows.len();
+        self.rows = wrapped;
+        added
+    }
+
+    /// ファイル先頭からのバイト位置 (0 始まり) に対応する位置を求める (`--goto-byte` 用)
+    ///
+    /// 改行コード・文字コード・BOM は保存時と同じように数える。
+    /// マルチバイト文字の途中を指す場合はその文字の位置、改行コードを指す場合は行末
+    /// (行の文字数) を返す。ファイルの末尾を超える場合は最終行の行末に丸める。
+    pub fn byte_to_position(&self, offset: usize) -> Position {
+        let bom_len = match self.encoding {
+            FileEncoding::Utf8 if self.bom => '\u{feff}'.len_utf8(),
+            _ => 0,
+        };
+        let newline_len = self.file_format.line_ending().len();
+        let mut remaining = offset.saturating_sub(bom_len);
+        for (row, line) in self.rows.iter().enumerate() {
+            let mut col = 0;
+            for c in line.chars().chars() {
+                let len = match self.encoding {
+                    FileEncoding::Utf8 => c.len_utf8(),
+                    FileEncoding::Latin1 => 1,
+                };
+                if remaining < len {
+                    return Position::new(row, col);
+                }
+                remaining -= len;
+                col += 1;
+            }
+            if remaining < newline_len {
+                return Position::new(row, col);
+            }
+            remaining -= newline_len;
+        }
+        let last = self.rows.len().saturating_sub(1);
+        let col = self.row(last).map_or(0, |r| r.chars().chars().count());
+        Position::new(last, col)
+    }
+
+    /// 行範囲を並べ替える (`:sort` 用)
+    ///
+    /// `ignore_case` の場合は大文字と小文字を区別せずに比べる。
+    /// 順序が同じ行は元の順番を保つ。
+    ///
+    /// # Returns
+    ///
+    /// 内容が変わった場合は true
+    pub fn sort_lines(&mut self, range: LineRange, reverse: bool, ignore_case: bool) -> bool {
+        let mut lines = self.lines_in_range(range.start, range.end);
+        if lines.len() < 2 {
+            return false;
+        }
+        let key = |line: &String| {
+            if ignore_case {
+                line.to_lowercase()
+            } else {
+                line.clone()
+            }
+        };
+        lines.sort_by_cached_key(key);
+        if reverse {
+            lines.reverse();
+        }
+
+        let mut changed = false;
+        for (row, line) in (range.start..).zip(lines) {
+            if self.row(row).is_some_and(|r| r.chars() != line) {
+                self.set_row(row, line);
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// 行範囲の共通のインデントを取り除き、最もインデントの浅い行を `target_col` 列から始める
+    ///
+    /// インデントの幅はタブを `tabstop` に合わせて展開して比べる。
+    /// 空白だけの行は比較に含めず、空行にする。
+    ///
+    /// # Returns
+    ///
+    /// 内容が変わった場合は true
+    pub fn reduce_common_indent(
+        &mut self,
+        range: LineRange,
+        target_col: usize,
+        tabstop: usize,
+        expandtab: bool,
+    ) -> bool {
+        let last = range.end.min(self.len().saturating_sub(1));
+        let Some(common) = self
+            .iter_range(range.start, last)
+            .filter(|line| !line.chars().trim().is_empty())
+            .map(|line| indent_width(leading_whitespace(line.chars()), tabstop))
+            .min()
+        else {
+            return false;
+        };
+
+        let mut changed = false;
+        for row in range.start..=last {
+            let Some(line) = self.get_row_content(row) else {
+                continue;
+            };
+            let new_line = if line.trim().is_empty() {
+                String::new()
+            } else {
+                let indent = leading_whitespace(&line);
+                let width = indent_width(indent, tabstop) - common + target_col;
+                let body = &line[indent.len()..];
+                format!("{}{}", indent_string(width, tabstop, expandtab), body)
+            };
+            if new_line != line {
+                self.set_row(row, new_line);
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// 行範囲の空白をタブまたは空白に変換する (`:retab` 用)
+    ///
+    /// 行頭のインデントを `expandtab` に合わせて作り直す。`all` の場合は行の途中の
+    /// タブを含む空白も変換する。表示上の幅は `tabstop` で数え、変換後は `new_tabstop` で
+    /// 同じ位置に表示されるようにする。
+    ///
+    /// # Returns
+    ///
+    /// 内容が変わった場合は true
+    pub fn retab(
+        &mut self,
+        range: LineRange,
+        tabstop: usize,
+        new_tabstop: usize,
+        expandtab: bool,
+        all: bool,
+    ) -> bool {
+        let last = range.end.min(self.len().saturating_sub(1));
+        let mut changed = false;
+        for row in range.start..=last {
+            let Some(line) = self.get_row_content(row) else {
+                continue;
+            };
+            let new_line = retab_line(&line, tabstop, new_tabstop, expandtab, all);
+            if new_line != line {
+                self.set_row(row, new_line);
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+/// 行頭の空白 (インデント) 部分
+pub fn leading_whitespace(line: &str) -> &str {
+    let body = line.trim_start_matches([' ', '\t']);
+    &line[..line.len() - body.len()]
+}
+
+/// 行頭の空白 (インデント) の表示幅
+pub fn indent_width(indent: &str, tabstop: usize) -> usize {
+    indent.chars().fold(0, |width, c| match c {
+        '\t' => (width / tabstop + 1) * tabstop,
+        _ => width + 1,
+    })
+}
+
+/// 指定した幅のインデント文字列 (expandtab でなければタブを使う)
+pub fn indent_string(width: usize, tabstop: usize, expandtab: bool) -> String {
+    if expandtab {
+        " ".repeat(width)
+    } else {
+        format!(
+            "{}{}",
+            "\t".repeat(width / tabstop),
+            " ".repeat(width % tabstop)
+        )
+    }
+}
+
+/// 1 行分の `:retab` の変換
+fn retab_line(
+    line: &str,
+    tabstop: usize,
+    new_tabstop: usize,
+    expandtab: bool,
+    all: bool,
+) -> String {
+    let mut result = String::with_capacity(line.len());
+    // 変換前と変換後の表示上の列
+    let (mut old_col, mut new_col) = (0, 0);
+    let mut rest = line;
+    while let Some(c) = rest.chars().next() {
+        if c != ' ' && c != '\t' {
+            let width = c.width().unwrap_or(0);
+            old_col += width;
+            new_col += width;
+            result.push(c);
+            rest = &rest[c.len_utf8()..];
+            continue;
+        }
+
+        let run = leading_whitespace(rest);
+        rest = &rest[run.len()..];
+        let width = advance_col(old_col, run, tabstop) - old_col;
+        old_col += width;
+        let is_indent = result.is_empty();
+        let text = if is_indent || (all && run.contains('\t')) {
+            fill_whitespace(new_col, new_col + width, new_tabstop, expandtab)
+        } else {
+            run.to_string()
+        };
+        new_col = advance_col(new_col, &text, new_tabstop);
+        result.push_str(&text);
+    }
+    result
+}
+
+/// 表示上の `col` 列から空白 `text` を表示した後の列
+fn advance_col(col: usize, text: &str, tabstop: usize) -> usize {
+    text.chars().fold(col, |col, c| match c {
+        '\t' => (col / tabstop + 1) * tabstop,
+        _ => col + 1,
+    })
+}
+
+/// 表示上の `from` 列から `to` 列までを埋める空白 (expandtab でなければタブを使う)
+fn fill_whitespace(from: usize, to: usize, tabstop: usize, expandtab: bool) -> String {
+    let mut text = String::new();
+    let mut col = from;
+    while !expandtab && (col / tabstop + 1) * tabstop <= to {
+        text.push('\t');
+        col = (col / tabstop + 1) * tabstop;
+    }
+    text.push_str(&" ".repeat(to - col));
+    text
 }
 
 #[cfg(test)]
@@ -306,8 +870,68 @@ mod tests {
     #[test]
     fn test_buffer_new() {
         let buffer = Buffer::new();
-        assert_eq!(buffer.len(), 0);
-        assert!(buffer.is_empty());
+        assert_eq!(buffer.len(), 1);
+        assert_eq!(buffer.row(0).unwrap().chars(), "");
+        assert!(!buffer.is_empty());
+    }
+
+    #[test]
+    fn test_buffer_from_lines() {
+        let buffer = Buffer::from_lines(["a", "b"]);
+        assert_eq!(buffer.lines_in_range(0, usize::MAX), vec!["a", "b"]);
+
+        // 行がない場合も空の 1 行を持つ
+        let buffer = Buffer::from_lines(Vec::<String>::new());
+        assert_eq!(buffer.len(), 1);
+        assert_eq!(buffer.row(0).unwrap().chars(), "");
+    }
+
+    #[test]
+    fn test_buffer_version() {
+        let mut buffer = Buffer::from_lines(["a", "b"]);
+        let version = buffer.version();
+        assert_ne!(Buffer::from_lines(["a", "b"]).version(), version);
+
+        // 複製は同じ番号を持ち、変更すると別の番号になる
+        let copy = buffer.clone();
+        assert_eq!(copy.version(), version);
+        buffer.set_row(0, "x".to_string());
+        assert_ne!(buffer.version(), version);
+
+        // 内容を読む場合や、行以外の設定を変える場合は変わらない
+        let version = buffer.version();
+        buffer.lines_in_range(0, 1);
+        buffer.set_trailing_newline(true);
+        assert_eq!(buffer.version(), version);
+    }
+
+    #[test]
+    fn test_row_version() {
+        let mut buffer = Buffer::from_lines(["a", "b"]);
+        let first = buffer.row(0).unwrap().version();
+        let second = buffer.row(1).unwrap().version();
+        assert_ne!(first, second);
+
+        // 変更した行だけが別の番号になる
+        buffer.insert_char(Position::new(1, 1), 'c');
+        assert_eq!(buffer.row(0).unwrap().version(), first);
+        assert_ne!(buffer.row(1).unwrap().version(), second);
+
+        // 複製は同じ番号を持ち、置き換えた行は別の番号になる
+        let copy = buffer.clone();
+        assert_eq!(copy.row(0).unwrap().version(), first);
+        buffer.set_row(0, "a".to_string());
+        assert_ne!(buffer.row(0).unwrap().version(), first);
+    }
+
+    #[test]
+    fn test_buffer_line_count() {
+        let mut buffer = Buffer::new();
+        assert_eq!(buffer.line_count(), 1);
+        buffer.set_trailing_newline(true);
+        assert_eq!(buffer.line_count(), 1);
+        buffer.insert_row(1, "two".to_string());
+        assert_eq!(buffer.line_count(), 2);
     }
 
     #[test]
@@ -315,16 +939,17 @@ mod tests {
         let mut buffer = Buffer::new();
         buffer.insert_row(0, "first".to_string());
         buffer.insert_row(1, "second".to_string());
-        assert_eq!(buffer.len(), 2);
+
+        // 新しいバッファの空行は挿入した行の後ろに残る
+        assert_eq!(buffer.len(), 3);
         assert_eq!(buffer.row(0).unwrap().chars(), "first");
         assert_eq!(buffer.row(1).unwrap().chars(), "second");
+        assert_eq!(buffer.row(2).unwrap().chars(), "");
     }
 
     #[test]
     fn test_buffer_delete_row_with_content() {
-        let mut buffer = Buffer::new();
-        buffer.insert_row(0, "line1".to_string());
-        buffer.insert_row(1, "line2".to_string());
+        let mut buffer = Buffer::from_lines(["line1", "line2"]);
 
         let content = buffer.delete_row_with_content(0);
         assert_eq!(content, Some("line1".to_string()));
@@ -345,8 +970,7 @@ mod tests {
 
     #[test]
     fn test_buffer_insert_newline() {
-        let mut buffer = Buffer::new();
-        buffer.insert_row(0, "hello".to_string());
+        let mut buffer = Buffer::from_lines(["hello"]);
         buffer.insert_newline(Position::new(0, 2));
 
         assert_eq!(buffer.len(), 2);
@@ -356,13 +980,211 @@ mod tests {
 
     #[test]
     fn test_buffer_join_rows() {
-        let mut buffer = Buffer::new();
-        buffer.insert_row(0, "hello".to_string());
-        buffer.insert_row(1, " world".to_string());
+        let mut buffer = Buffer::from_lines(["hello", " world"]);
 
         buffer.join_rows(1);
 
         assert_eq!(buffer.len(), 1);
         assert_eq!(buffer.row(0).unwrap().chars(), "hello world");
     }
+
+    #[test]
+    fn test_buffer_iter_range() {
+        let buffer = Buffer::from_lines(["a", "b", "c"]);
+        let range = |start, end| -> Vec<&str> {
+            buffer.iter_range(start, end).map(|r| r.chars()).collect()
+        };
+
+        assert_eq!(range(0, 2), vec!["a", "b", "c"]);
+        assert_eq!(range(1, 1), vec!["b"]);
+        // バッファを超える範囲はバッファ内の行だけ
+        assert_eq!(range(1, 10), vec!["b", "c"]);
+        assert_eq!(range(0, usize::MAX), vec!["a", "b", "c"]);
+        assert!(range(3, 5).is_empty());
+        assert!(range(2, 1).is_empty());
+        assert_eq!(buffer.lines_in_range(2, 9), vec!["c".to_string()]);
+        assert_eq!(buffer.iter().count(), 3);
+    }
+
+    #[test]
+    fn test_byte_to_position() {
+        let mut buffer = Buffer::from_lines(["ab", "日本", "", "x"]);
+
+        assert_eq!(buffer.byte_to_position(0), Position::new(0, 0));
+        assert_eq!(buffer.byte_to_position(1), Position::new(0, 1));
+        // 改行
+        assert_eq!(buffer.byte_to_position(2), Position::new(0, 2));
+        assert_eq!(buffer.byte_to_position(3), Position::new(1, 0));
+        // マルチバイト文字の途中はその文字の位置
+        assert_eq!(buffer.byte_to_position(5), Position::new(1, 0));
+        assert_eq!(buffer.byte_to_position(6), Position::new(1, 1));
+        assert_eq!(buffer.byte_to_position(8), Position::new(1, 1));
+        assert_eq!(buffer.byte_to_position(10), Position::new(2, 0));
+        assert_eq!(buffer.byte_to_position(11), Position::new(3, 0));
+        // 末尾を超える場合は最終行の行末
+        assert_eq!(buffer.byte_to_position(100), Position::new(3, 1));
+
+        // 改行コードと BOM も数える
+        buffer.set_file_format(FileFormat::Dos);
+        buffer.set_bom(true);
+        assert_eq!(buffer.byte_to_position(2), Position::new(0, 0));
+        assert_eq!(buffer.byte_to_position(6), Position::new(0, 2));
+        assert_eq!(buffer.byte_to_position(7), Position::new(1, 0));
+        buffer.set_encoding(FileEncoding::Latin1);
+        assert_eq!(buffer.byte_to_position(4), Position::new(1, 0));
+        assert_eq!(buffer.byte_to_position(5), Position::new(1, 1));
+    }
+
+    #[test]
+    fn test_buffer_reverse_rows() {
+        let mut buffer = Buffer::from_lines(["a", "b", "c", "d"]);
+        let rows = |buffer: &Buffer| -> Vec<String> { buffer.lines_in_range(0, usize::MAX) };
+
+        assert!(buffer.reverse_rows(LineRange::new(0, 3)));
+        assert_eq!(rows(&buffer), vec!["d", "c", "b", "a"]);
+        assert!(buffer.reverse_rows(LineRange::new(1, 2)));
+        assert_eq!(rows(&buffer), vec!["d", "b", "c", "a"]);
+        // バッファを超える範囲はバッファ内だけ
+        assert!(buffer.reverse_rows(LineRange::new(2, 10)));
+        assert_eq!(rows(&buffer), vec!["d", "b", "a", "c"]);
+        assert!(!buffer.reverse_rows(LineRange::single(1)));
+    }
+
+    #[test]
+    fn test_buffer_hard_wrap() {
+        let lines = [
+            "Lorem ipsum dolor sit amet, consectetur adipiscing elit",
+            "short line",
+            "    indented text that goes past the limit",
+            "a_single_word_that_is_much_longer_than_thirty",
+            "exactly thirty characters long",
+        ];
+        let mut buffer = Buffer::from_lines(lines);
+
+        assert_eq!(buffer.hard_wrap(30), 2);
+        assert_eq!(
+            buffer.lines_in_range(0, usize::MAX),
+            vec![
+                "Lorem ipsum dolor sit amet,",
+                "consectetur adipiscing elit",
+                "short line",
+                // 続きの行には同じインデントを付ける
+                "    indented text that goes",
+                "    past the limit",
+                // 空白がない行は折り返さない
+                "a_single_word_that_is_much_longer_than_thirty",
+                "exactly thirty characters long",
+            ]
+        );
+        assert_eq!(buffer.hard_wrap(30), 0);
+
+        // 何度でも折り返す
+        let mut buffer = Buffer::from_lines(["aaa bbb ccc ddd"]);
+        assert_eq!(buffer.hard_wrap(7), 1);
+        assert_eq!(buffer.lines_in_range(0, 1), vec!["aaa bbb", "ccc ddd"]);
+        assert_eq!(buffer.hard_wrap(3), 2);
+        assert_eq!(
+            buffer.lines_in_range(0, 3),
+            vec!["aaa", "bbb", "ccc", "ddd"]
+        );
+    }
+
+    #[test]
+    fn test_buffer_uniq_rows() {
+        let lines = ["a", "a", "b", "c", "c", "c", "a"];
+        let make = || Buffer::from_lines(lines);
+        let rows = |buffer: &Buffer| -> Vec<String> { buffer.lines_in_range(0, usize::MAX) };
+
+        let mut buffer = make();
+        assert_eq!(buffer.uniq_rows(LineRange::new(0, 6), false), 3);
+        // 続いていない同じ行はまとめない
+        assert_eq!(rows(&buffer), vec!["a", "b", "c", "a"]);
+        assert_eq!(buffer.uniq_rows(LineRange::new(0, 3), false), 0);
+
+        let mut buffer = make();
+        assert_eq!(buffer.uniq_rows(LineRange::new(0, 6), true), 5);
+        assert_eq!(rows(&buffer), vec!["a", "c"]);
+
+        // 範囲外の行は変えない
+        let mut buffer = make();
+        assert_eq!(buffer.uniq_rows(LineRange::new(1, 4), false), 1);
+        assert_eq!(rows(&buffer), vec!["a", "a", "b", "c", "c", "a"]);
+
+        // すべての行を削除した場合は空の 1 行が残る
+        let mut buffer = make();
+        buffer.uniq_rows(LineRange::new(2, 2), true);
+        buffer.uniq_rows(LineRange::new(0, 5), false);
+        assert_eq!(buffer.uniq_rows(LineRange::new(0, 5), true), 3);
+        assert_eq!(rows(&buffer), vec![""]);
+    }
+
+    #[test]
+    fn test_buffer_sort_lines() {
+        let mut buffer = Buffer::from_lines(["b", "C", "a", "c"]);
+        let rows = |buffer: &Buffer| -> Vec<String> {
+            buffer.iter().map(|r| r.chars().to_string()).collect()
+        };
+
+        assert!(buffer.sort_lines(LineRange::new(0, 3), false, false));
+        assert_eq!(rows(&buffer), vec!["C", "a", "b", "c"]);
+        assert!(buffer.sort_lines(LineRange::new(0, 3), false, true));
+        assert_eq!(rows(&buffer), vec!["a", "b", "C", "c"]);
+        assert!(!buffer.sort_lines(LineRange::new(0, 3), false, true));
+        assert!(buffer.sort_lines(LineRange::new(1, 3), true, false));
+        assert_eq!(rows(&buffer), vec!["a", "c", "b", "C"]);
+    }
+
+    #[test]
+    fn test_buffer_reduce_common_indent() {
+        let lines = ["      if x {", "          y();", "", "      }", "end"];
+        let make = || Buffer::from_lines(lines);
+        let rows = |buffer: &Buffer| -> Vec<String> {
+            buffer.iter().map(|r| r.chars().to_string()).collect()
+        };
+
+        // 最小のインデント (6 桁) を取り除いて 0 列に揃える
+        let mut buffer = make();
+        assert!(buffer.reduce_common_indent(LineRange::new(0, 3), 0, 8, true));
+        assert_eq!(rows(&buffer), vec!["if x {", "    y();", "", "}", "end"]);
+
+        // 2 列に揃える
+        let mut buffer = make();
+        buffer.reduce_common_indent(LineRange::new(0, 3), 2, 8, true);
+        assert_eq!(
+            rows(&buffer),
+            vec!["  if x {", "      y();", "", "  }", "end"]
+        );
+
+        // タブは tabstop に合わせて展開して比べる
+        let mut buffer = Buffer::from_lines(["\t  a", "\t\tb"]);
+        buffer.reduce_common_indent(LineRange::new(0, 1), 0, 4, false);
+        assert_eq!(rows(&buffer)[..2], ["a", "  b"]);
+
+        // すでに揃っている場合は変更しない
+        assert!(!buffer.reduce_common_indent(LineRange::new(0, 1), 0, 4, false));
+    }
+
+    #[test]
+    fn test_buffer_retab() {
+        let mut buffer = Buffer::from_lines(["\tkeep", "\t  a\tb", "        c  d", "\tkeep"]);
+        let rows = |buffer: &Buffer| -> Vec<String> {
+            buffer.iter().map(|r| r.chars().to_string()).collect()
+        };
+
+        // 範囲内の行頭のインデントだけを空白にする
+        assert!(buffer.retab(LineRange::new(1, 2), 8, 8, true, false));
+        assert_eq!(
+            rows(&buffer),
+            vec!["\tkeep", "          a\tb", "        c  d", "\tkeep"]
+        );
+
+        // all の場合は行の途中のタブも変換する (空白だけの並びはそのまま)
+        assert!(buffer.retab(LineRange::new(1, 2), 8, 8, true, true));
+        assert_eq!(rows(&buffer)[1], "          a     b");
+        assert!(!buffer.retab(LineRange::new(1, 2), 8, 8, true, true));
+
+        // タブに戻す。新しい tabstop でも同じ位置に表示されるようにする
+        assert!(buffer.retab(LineRange::new(1, 2), 8, 4, false, false));
+        assert_eq!(rows(&buffer)[1..3], ["\t\t  a     b", "\t\tc  d"]);
+    }
 }
@@ -0,0 +1,316 @@
+use std::fs;
+
+use unicode_width::UnicodeWidthChar;
+
+use crate::ex_command;
+use crate::search::Highlight;
+
+/// コマンドラインモードの状態
+pub struct CommandLine {
+    /// `:` (Ex コマンド) / `/` `?` (検索)
+    pub prompt: char,
+    /// 入力中のコマンド (先頭の `:` は含まない)
+    pub buffer: String,
+    pub completion: Completion,
+    /// 入力中の `:s` のパターンの一致 (プレビュー表示用)
+    pub preview: Option<Highlight>,
+}
+
+impl Default for CommandLine {
+    fn default() -> Self {
+        Self {
+            prompt: ':',
+            buffer: String::new(),
+            completion: Completion::default(),
+            preview: None,
+        }
+    }
+}
+
+impl CommandLine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+        self.completion.reset();
+        self.preview = None;
+    }
+
+    /// 指定したプロンプトで入力を始める
+    pub fn start(&mut self, prompt: char) {
+        self.clear();
+        self.prompt = prompt;
+    }
+
+    /// 検索パターンの入力中か
+    pub fn is_search(&self) -> bool {
+        matches!(self.prompt, '/' | '?')
+    }
+}
+
+/// Tab 補完の状態
+#[derive(Default)]
+pub struct Completion {
+    candidates: Vec<String>,
+    /// 補完対象の引数より前の部分 (例: `e `)
+    head: String,
+    /// 同じ候補に対して Tab が押された回数
+    tab_count: usize,
+}
+
+impl Completion {
+    pub fn reset(&mut self) {
+        self.candidates.clear();
+        self.head.clear();
+        self.tab_count = 0;
+    }
+
+    /// Tab / Ctrl-D が押されたときの補完処理
+    ///
+    /// 1 回目の Tab は候補の共通部分まで補完し、2 回目の Tab で候補一覧を表示する。
+    /// 3 回目以降の Tab は候補を順番に切り替える。`list` が true (Ctrl-D) の場合は
+    /// 常に候補一覧を表示する。
+    ///
+    /// # Returns
+    ///
+    /// 候補一覧を表示する場合は `width` に収まるよう整形した文字列
+    pub fn complete(
+        &mut self,
+        command_buffer: &mut String,
+        list: bool,
+        width: usize,
+    ) -> Option<String> {
+        if self.tab_count == 0 {
+            let (head, partial) = split_file_argument(command_buffer)?;
+            self.head = head.to_string();
+            self.candidates = path_candidates(partial);
+            if self.candidates.is_empty() {
+                return None;
+            }
+            if self.candidates.len() == 1 {
+                *command_buffer = format!("{}{}", self.head, self.candidates[0]);
+                self.reset();
+                return None;
+            }
+            *command_buffer = format!("{}{}", self.head, longest_common_prefix(&self.candidates));
+            self.tab_count = 1;
+            return list.then(|| format_candidates(&self.candidates, width));
+        }
+
+        if list || self.tab_count == 1 {
+            self.tab_count = self.tab_count.max(2);
+            return Some(format_candidates(&self.candidates, width));
+        }
+
+        let index = (self.tab_count - 2) % self.candidates.len();
+        *command_buffer = format!("{}{}", self.head, self.candidates[index]);
+        self.tab_count += 1;
+        None
+    }
+}
+
+/// ファイル名を引数に取るコマンド (`:edit`) であれば、(引数より前, 引数) に分割する
+fn split_file_argument(command_buffer: &str) -> Option<(&str, &str)> {
+    let cmd = command_buffer.split_whitespace().next()?;
+    let (command, _) = ex_command::resolve(cmd)?;
+    if command.name != "edit" {
+        return None;
+    }
+    let index = command_buffer.rfind(' ')?;
+    Some(command_buffer.split_at(index + 1))
+}
+
+/// 部分的なパスに前方一致するファイル・ディレクトリの一覧を返す
+///
+/// ディレクトリには末尾に `/` を付ける。隠しファイルは `.` から入力した場合のみ対象にする。
+pub fn path_candidates(partial: &str) -> Vec<String> {
+    let (dir, prefix) = match partial.rfind('/') {
+        Some(index) => partial.split_at(index + 1),
+        None => ("", partial),
+    };
+    let Ok(entries) = fs::read_dir(if dir.is_empty() { "." } else { dir }) else {
+        return Vec::new();
+    };
+
+    let mut candidates: Vec<String> = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            let suffix = if entry.path().is_dir() { "/" } else { "" };
+            Some(format!("{}{}{}", dir, name, suffix))
+        })
+        .collect();
+    candidates.sort();
+    candidates
+}
+
+/// 候補の共通の先頭部分を返す
+pub fn longest_common_prefix(candidates: &[String]) -> String {
+    let Some(first) = candidates.first() else {
+        return String::new();
+    };
+    let mut prefix_len = first.len();
+    for candidate in &candidates[1..] {
+        prefix_len = first
+            .char_indices()
+            .zip(candidate.chars())
+            .take_while(|((_, a), b)| a == b)
+            .map(|((i, a), _)| i + a.len_utf8())
+            .last()
+            .unwrap_or(0)
+            .min(prefix_len);
+    }
+    first[..prefix_len].to_string()
+}
+
+/// 候補一覧を 1 行に整形する
+///
+/// ディレクトリ部分は省略してファイル名のみを 2 つの空白区切りで並べ、
+/// `width` を超える場合は末尾を `...` にして切り詰める。
+pub fn format_candidates(candidates: &[String], width: usize) -> String {
+    let names: Vec<&str> = candidates
+        .iter()
+        .map(|candidate| {
+            let trimmed = candidate.trim_end_matches('/');
+            let start = trimmed.rfind('/').map(|i| i + 1).unwrap_or(0);
+            &candidate[start..]
+        })
+        .collect();
+    let line = names.join("  ");
+
+    let line_width: usize = line.chars().map(|c| c.width().unwrap_or(0)).sum();
+    if line_width <= width {
+        return line;
+    }
+
+    let mut result = String::new();
+    let mut used = 0;
+    let limit = width.saturating_sub(3);
+    for ch in line.chars() {
+        let w = ch.width().unwrap_or(0);
+        if used + w > limit {
+            break;
+        }
+        used += w;
+        result.push(ch);
+    }
+    result.push_str(&"..."[..width.min(3)]);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_dir(files: &[&str]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for file in files {
+            fs::write(dir.path().join(file), "").unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn test_path_candidates() {
+        let dir = make_dir(&["main.rs", "mode.rs", "mod.rs", "app.rs", ".hidden"]);
+        fs::create_dir(dir.path().join("mods")).unwrap();
+        let base = format!("{}/", dir.path().display());
+
+        assert_eq!(
+            path_candidates(&format!("{}mo", base)),
+            vec![
+                format!("{}mod.rs", base),
+                format!("{}mode.rs", base),
+                format!("{}mods/", base),
+            ]
+        );
+        // 隠しファイルは . から入力した場合のみ
+        assert_eq!(path_candidates(&base).len(), 5);
+        assert_eq!(
+            path_candidates(&format!("{}.", base)),
+            vec![format!("{}.hidden", base)]
+        );
+    }
+
+    #[test]
+    fn test_format_candidates_for_three_matches() {
+        let dir = make_dir(&["test_a.txt", "test_b.txt", "test_c.txt", "other.txt"]);
+        let partial = format!("{}/test_", dir.path().display());
+
+        let candidates = path_candidates(&partial);
+        assert_eq!(
+            format_candidates(&candidates, 80),
+            "test_a.txt  test_b.txt  test_c.txt"
+        );
+    }
+
+    #[test]
+    fn test_format_candidates_truncated() {
+        let candidates = vec![
+            "src/alpha.rs".to_string(),
+            "src/beta.rs".to_string(),
+            "src/gamma/".to_string(),
+        ];
+        assert_eq!(
+            format_candidates(&candidates, 80),
+            "alpha.rs  beta.rs  gamma/"
+        );
+        assert_eq!(format_candidates(&candidates, 12), "alpha.rs ...");
+    }
+
+    #[test]
+    fn test_longest_common_prefix() {
+        let candidates = vec!["src/mode.rs".to_string(), "src/mod.rs".to_string()];
+        assert_eq!(longest_common_prefix(&candidates), "src/mod");
+        assert_eq!(longest_common_prefix(&[]), "");
+    }
+
+    #[test]
+    fn test_complete_tab_sequence() {
+        let dir = make_dir(&["test_a.txt", "test_b.txt", "test_c.txt"]);
+        let base = dir.path().display().to_string();
+        let mut completion = Completion::default();
+        let mut command_buffer = format!("e {}/te", base);
+
+        // 1 回目: 共通部分まで補完
+        assert_eq!(completion.complete(&mut command_buffer, false, 80), None);
+        assert_eq!(command_buffer, format!("e {}/test_", base));
+
+        // 2 回目: 候補一覧を表示
+        assert_eq!(
+            completion.complete(&mut command_buffer, false, 80),
+            Some("test_a.txt  test_b.txt  test_c.txt".to_string())
+        );
+
+        // 3 回目以降: 候補を順に切り替え
+        completion.complete(&mut command_buffer, false, 80);
+        assert_eq!(command_buffer, format!("e {}/test_a.txt", base));
+        completion.complete(&mut command_buffer, false, 80);
+        assert_eq!(command_buffer, format!("e {}/test_b.txt", base));
+    }
+
+    #[test]
+    fn test_complete_ctrl_d_lists_immediately() {
+        let dir = make_dir(&["test_a.txt", "test_b.txt"]);
+        let mut completion = Completion::default();
+        let mut command_buffer = format!("e {}/", dir.path().display());
+
+        assert_eq!(
+            completion.complete(&mut command_buffer, true, 80),
+            Some("test_a.txt  test_b.txt".to_string())
+        );
+    }
+
+    #[test]
+    fn test_complete_ignores_other_commands() {
+        let mut completion = Completion::default();
+        let mut command_buffer = "w fo".to_string();
+        assert_eq!(completion.complete(&mut command_buffer, false, 80), None);
+        assert_eq!(command_buffer, "w fo");
+    }
+}
@@ -0,0 +1,797 @@
+use std::collections::BTreeMap;
+
+use crate::filetype;
+
+/// `:set list` 時に使用する表示用の文字 (Vim の listchars)
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListChars {
+    /// 行末に表示する文字
+    pub eol: Option<char>,
+    /// タブの先頭に表示する文字と、残りを埋める文字
+    pub tab: Option<(char, char)>,
+    /// 行末の空白に表示する文字
+    pub trail: Option<char>,
+}
+
+impl Default for ListChars {
+    fn default() -> Self {
+        Self {
+            eol: Some('$'),
+            tab: Some(('▸', ' ')),
+            trail: Some('·'),
+        }
+    }
+}
+
+impl ListChars {
+    /// `eol:$,tab:▸ ,trail:·` 形式の文字列をパースする
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let mut list_chars = Self {
+            eol: None,
+            tab: None,
+            trail: None,
+        };
+        for item in value.split(',').filter(|s| !s.is_empty()) {
+            let (name, chars) = item
+                .split_once(':')
+                .ok_or_else(|| format!("Invalid argument: listchars={}", value))?;
+            let chars: Vec<char> = chars.chars().collect();
+            match (name, chars.as_slice()) {
+                ("eol", [c]) => list_chars.eol = Some(*c),
+                ("trail", [c]) => list_chars.trail = Some(*c),
+                ("tab", [c]) => list_chars.tab = Some((*c, ' ')),
+                ("tab", [c, fill]) => list_chars.tab = Some((*c, *fill)),
+                _ => return Err(format!("Invalid argument: listchars={}", value)),
+            }
+        }
+        Ok(list_chars)
+    }
+
+    /// `:set listchars?` 用に文字列化する
+    pub fn to_option_string(&self) -> String {
+        let mut items = Vec::new();
+        if let Some(c) = self.eol {
+            items.push(format!("eol:{}", c));
+        }
+        if let Some((c, fill)) = self.tab {
+            items.push(format!("tab:{}{}", c, fill));
+        }
+        if let Some(c) = self.trail {
+            items.push(format!("trail:{}", c));
+        }
+        items.join(",")
+    }
+}
+
+/// 行番号の表示方法
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineNumberMode {
+    /// 表示しない
+    Off,
+    /// 絶対行番号
+    Absolute,
+    /// カーソル行からの相対行番号
+    Relative,
+    /// カーソル行のみ絶対行番号、それ以外は相対行番号
+    Hybrid,
+}
+
+/// 行末より後ろへのカーソル移動 (`:set virtualedit`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VirtualEdit {
+    /// 行の最後の文字まで
+    #[default]
+    None,
+    /// 行の最後の文字の 1 つ後ろまで
+    OneMore,
+    /// 文字のない位置にも自由に移動できる
+    All,
+}
+
+impl VirtualEdit {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "" | "none" => Some(Self::None),
+            "onemore" => Some(Self::OneMore),
+            "all" => Some(Self::All),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::None => "",
+            Self::OneMore => "onemore",
+            Self::All => "all",
+        }
+    }
+
+    /// Normal mode でカーソルを移動できる範囲として扱う行の長さ
+    ///
+    /// `Cursor::move_right` / `Cursor::adjust_cursor_x` に行の長さの代わりに渡す。
+    pub fn line_limit(self, line_len: usize) -> usize {
+        match self {
+            Self::None => line_len,
+            Self::OneMore => line_len + 1,
+            Self::All => u16::MAX as usize,
+        }
+    }
+}
+
+/// 行番号の左に表示するサイン列 (`:set signcolumn`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SignColumn {
+    /// 表示しない
+    #[default]
+    No,
+    /// 常に 1 桁分を確保する
+    Yes,
+}
+
+impl SignColumn {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "no" => Some(Self::No),
+            "yes" => Some(Self::Yes),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::No => "no",
+            Self::Yes => "yes",
+        }
+    }
+
+    /// サイン列の幅
+    pub fn width(self) -> usize {
+        match self {
+            Self::No => 0,
+            Self::Yes => 1,
+        }
+    }
+}
+
+/// ステータスバーに表示するファイル名の形式 (`:set statusfilename`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FileNameStyle {
+    /// カレントディレクトリからの相対パス (カレントディレクトリの外にある場合はそのまま)
+    #[default]
+    Relative,
+    /// 絶対パス
+    Full,
+    /// ファイル名のみ
+    Tail,
+}
+
+impl FileNameStyle {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "relative" => Some(Self::Relative),
+            "full" => Some(Self::Full),
+            "tail" => Some(Self::Tail),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Relative => "relative",
+            Self::Full => "full",
+            Self::Tail => "tail",
+        }
+    }
+}
+
+/// `:mkzimrc` で書き出すオプションの一覧 (書き出す順)
+const OPTION_NAMES: &[&str] = &[
+    "number",
+    "relativenumber",
+    "numberwidth",
+    "signcolumn",
+    "list",
+    "listchars",
+    "wrap",
+    "tabstop",
+    "shiftwidth",
+    "expandtab",
+    "autopairs",
+    "textwidth",
+    "maxlinelength",
+    "wrapscan",
+    "verymagic",
+    "wholeword",
+    "preservecase",
+    "subkeepcursor",
+    "yanktoeol",
+    "hlword",
+    "autocomment",
+    "spell",
+    "dictionary",
+    "spellfile",
+    "virtualedit",
+    "writebackup",
+    "confirm",
+    "timeoutlen",
+    "showmatch",
+    "matchtime",
+    "smarthome",
+    "errorbells",
+    "visualbell",
+    "autoread",
+    "statusfilename",
+];
+
+/// `dictionary` の既定値
+const DEFAULT_DICTIONARY: &str = "/usr/share/dict/words";
+
+/// `:set` で変更できるエディタの設定
+#[derive(Debug, Clone, PartialEq)]
+pub struct EditorConfig {
+    /// タブ・行末・行末の空白を可視化する
+    pub list: bool,
+    pub listchars: ListChars,
+    /// 画面の幅より長い行を折り返して表示する
+    pub wrap: bool,
+    /// タブ文字の表示幅
+    pub tabstop: usize,
+    /// `>` / `<` で増減するインデントの幅
+    pub shiftwidth: usize,
+    /// インデントにタブではなく空白を使う
+    pub expandtab: bool,
+    /// Insert mode で括弧・引用符の閉じ文字を自動で挿入する
+    pub autopairs: bool,
+    /// Insert mode で行がこの幅を超えたら自動で折り返す (0 の場合は折り返さない)
+    pub textwidth: usize,
+    /// 保存時にこの文字数を超える行を空白で改行する (0 の場合は改行しない)
+    pub maxlinelength: usize,
+    /// 検索時にファイルの端で折り返す
+    pub wrapscan: bool,
+    /// 検索パターンを very magic 形式 (`\v`) で解釈する
+    pub verymagic: bool,
+    /// 検索パターンを単語全体にだけ一致させる (パターンを `\<` と `\>` で囲んだ場合と同じ)
+    pub wholeword: bool,
+    /// 行番号を表示する
+    pub number: bool,
+    /// 相対行番号を表示する
+    pub relativenumber: bool,
+    /// `:s` で一致したテキストの大文字小文字に合わせて置換する
+    pub preservecase: bool,
+    /// `:s` の後もカーソルを置換前と同じ文字の上に残す
+    /// (オフの場合は最後に置換した行の最初の非空白文字に移動する)
+    pub subkeepcursor: bool,
+    /// `Y` を `yy` ではなく `y$` (行末までヤンク) として扱う
+    pub yanktoeol: bool,
+    /// カーソル位置の単語と同じ単語を表示中の行で強調表示する
+    pub hlword: bool,
+    /// Insert mode でコメント行から改行したときにコメント記号を継続する
+    pub autocomment: bool,
+    /// 辞書にない単語に下線を引く
+    pub spell: bool,
+    /// `:set spell` で使う単語の一覧のファイル (1 行に 1 単語)
+    pub dictionary: String,
+    /// `zg` で単語を追加する個人辞書のファイル (空の場合は `~/.zim_spell.add`)
+    pub spellfile: String,
+    /// 行末より後ろにカーソルを移動できるか
+    pub virtualedit: VirtualEdit,
+    /// 一時ファイルに書き込んでから置き換えて保存する (シンボリックリンクはリンク先を置き換える)
+    pub writebackup: bool,
+    /// 未保存の変更がある場合に、`:q` をエラーにせず保存するか確認する
+    pub confirm: bool,
+    /// `g` / `d` などの続くキーを待つ時間 (ミリ秒、0 の場合は待ち続ける)
+    pub timeoutlen: usize,
+    /// Insert mode で閉じ括弧を入力したときに、対応する開き括弧に一瞬カーソルを移動する
+    pub showmatch: bool,
+    /// `showmatch` でカーソルを移動しておく時間 (0.1 秒単位)
+    pub matchtime: usize,
+    /// `0` / Home で最初の非空白文字に移動し、すでにそこにいる場合は行頭に移動する
+    pub smarthome: bool,
+    /// 無効な操作 (Esc / Ctrl-C での取り消しや見つからない検索など) でベルを鳴らす
+    pub errorbells: bool,
+    /// ベルの代わりに画面を一瞬反転する
+    pub visualbell: bool,
+    /// 未保存の変更がなければ、外部で変更されたファイルを自動で読み込み直す
+    pub autoread: bool,
+    /// ステータスバーに表示するファイル名の形式
+    pub statusfilename: FileNameStyle,
+    /// 行番号欄の最小の幅 (区切りの空白を含む)
+    pub numberwidth: usize,
+    /// 行番号の左にサイン列を表示するか
+    pub signcolumn: SignColumn,
+    /// ファイルタイプごとに、ファイルを開いたときに適用する `:set` の引数
+    /// (`:autocmd FileType {filetype} set {args}` で変更する)
+    pub filetypes: BTreeMap<String, Vec<String>>,
+}
+
+/// ファイルタイプごとの設定で変更した 1 つのオプション
+///
+/// [`EditorConfig::restore_overrides`] で元に戻すために使う。
+#[derive(Debug, Clone, PartialEq)]
+pub struct OptionOverride {
+    name: String,
+    /// 適用前の値 (`name=value` 形式)
+    before: String,
+    /// 適用後の値 (`name=value` 形式)
+    after: String,
+}
+
+impl Default for EditorConfig {
+    fn default() -> Self {
+        Self {
+            list: false,
+            listchars: ListChars::default(),
+            wrap: false,
+            tabstop: 8,
+            shiftwidth: 8,
+            expandtab: false,
+            autopairs: false,
+            textwidth: 0,
+            maxlinelength: 0,
+            wrapscan: true,
+            verymagic: false,
+            subkeepcursor: false,
+            wholeword: false,
+            number: false,
+            relativenumber: false,
+            preservecase: false,
+            yanktoeol: false,
+            hlword: false,
+            autocomment: false,
+            spell: false,
+            dictionary: DEFAULT_DICTIONARY.to_string(),
+            spellfile: String::new(),
+            virtualedit: VirtualEdit::None,
+            writebackup: true,
+            confirm: false,
+            timeoutlen: 1000,
+            showmatch: false,
+            matchtime: 5,
+            smarthome: false,
+            errorbells: true,
+            visualbell: false,
+            autoread: false,
+            statusfilename: FileNameStyle::Relative,
+            numberwidth: 4,
+            signcolumn: SignColumn::No,
+            filetypes: filetype::default_options(),
+        }
+    }
+}
+
+impl EditorConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `:set` の引数を 1 つ適用する
+    ///
+    /// `name` / `noname` / `invname` / `name!` / `name=value` / `name?` に対応する。
+    ///
+    /// # Returns
+    ///
+    /// `name?` の場合のみ表示用のメッセージを返す
+    pub fn apply(&mut self, arg: &str) -> Result<Option<String>, String> {
+        if let Some(name) = arg.strip_suffix('?') {
+            return self.query(name).map(Some);
+        }
+
+        if let Some((name, value)) = arg.split_once('=') {
+            match name {
+                "listchars" | "lcs" => self.listchars = ListChars::parse(value)?,
+                "tabstop" | "ts" => self.tabstop = parse_number(name, value)?.max(1),
+                "shiftwidth" | "sw" => self.shiftwidth = parse_number(name, value)?,
+                "textwidth" | "tw" => self.textwidth = parse_number(name, value)?,
+                "maxlinelength" | "mll" => self.maxlinelength = parse_number(name, value)?,
+                "timeoutlen" | "tm" => self.timeoutlen = parse_number(name, value)?,
+                "matchtime" | "mat" => self.matchtime = parse_number(name, value)?,
+                // Vim と同じく 1 から 20 の範囲に収める
+                "numberwidth" | "nuw" => self.numberwidth = parse_number(name, value)?.clamp(1, 20),
+                "signcolumn" | "scl" => {
+                    self.signcolumn = SignColumn::from_name(value)
+                        .ok_or_else(|| format!("Invalid argument: {}={}", name, value))?
+                }
+                "dictionary" | "dict" => self.dictionary = value.to_string(),
+                "spellfile" | "spf" => self.spellfile = value.to_string(),
+                "virtualedit" | "ve" => {
+                    self.virtualedit = VirtualEdit::from_name(value)
+                        .ok_or_else(|| format!("Invalid argument: {}={}", name, value))?
+                }
+                "statusfilename" | "sfn" => {
+                    self.statusfilename = FileNameStyle::from_name(value)
+                        .ok_or_else(|| format!("Invalid argument: {}={}", name, value))?
+                }
+                _ => return Err(format!("Unknown option: {}", name)),
+            }
+            return Ok(None);
+        }
+
+        let (name, value) = if let Some(name) = arg.strip_suffix('!') {
+            (name, None)
+        } else if let Some(name) = arg.strip_prefix("inv") {
+            (name, None)
+        } else if let Some(name) = arg.strip_prefix("no") {
+            (name, Some(false))
+        } else {
+            (arg, Some(true))
+        };
+
+        let Some(flag) = self.flag_mut(name) else {
+            // 値を持つオプションは名前だけ指定された場合に現在値を表示する
+            return match self.query(name) {
+                Ok(value) => Ok(Some(value)),
+                Err(_) => Err(format!("Unknown option: {}", arg)),
+            };
+        };
+        // value が None の場合はトグル
+        *flag = value.unwrap_or(!*flag);
+        Ok(None)
+    }
+
+    /// `:set` の引数をまとめて適用する (ファイルタイプごとの設定用)
+    ///
+    /// # Returns
+    ///
+    /// 変更したオプションと、その前後の値の一覧
+    pub fn apply_overrides(&mut self, args: &[String]) -> Result<Vec<OptionOverride>, String> {
+        let mut overrides = Vec::new();
+        for arg in args {
+            let name = arg.split_once('=').map_or(arg.as_str(), |(name, _)| name);
+            let name = match self.query(name) {
+                Ok(_) => name,
+                Err(e) => name
+                    .strip_prefix("no")
+                    .filter(|name| self.query(name).is_ok())
+                    .ok_or(e)?,
+            };
+            let before = self.query(name)?;
+            self.apply(arg)?;
+            overrides.push(OptionOverride {
+                name: name.to_string(),
+                before,
+                after: self.query(name)?,
+            });
+        }
+        Ok(overrides)
+    }
+
+    /// [`apply_overrides`](Self::apply_overrides) で変更したオプションを元の値に戻す
+    ///
+    /// その後 `:set` で変更されたオプションは、変更後の値のまま残す。
+    pub fn restore_overrides(&mut self, overrides: Vec<OptionOverride>) {
+        for option in overrides.into_iter().rev() {
+            if self
+                .query(&option.name)
+                .is_ok_and(|value| value == option.after)
+            {
+                // 適用前の値なので失敗しない
+                let _ = self.apply(&option.before);
+            }
+        }
+    }
+
+    /// ファイルタイプを開いたときに適用する `:set` の引数
+    pub fn filetype_options(&self, filetype: &str) -> &[String] {
+        self.filetypes.get(filetype).map_or(&[], Vec::as_slice)
+    }
+
+    /// ファイルタイプを開いたときに適用する `:set` の引数を置き換える
+    ///
+    /// 適用できない引数を含む場合は置き換えずにエラーを返す。
+    pub fn set_filetype_options(
+        &mut self,
+        filetype: &str,
+        args: Vec<String>,
+    ) -> Result<(), String> {
+        Self::default().apply_overrides(&args)?;
+        self.filetypes.insert(filetype.to_string(), args);
+        Ok(())
+    }
+
+    /// 既定値から変更されている設定を `set` / `autocmd` コマンドの行として返す (`:mkzimrc` 用)
+    ///
+    /// 値に含まれる空白は `\ ` にエスケープする。
+    pub fn to_set_commands(&self) -> Vec<String> {
+        let default = Self::default();
+        let options = OPTION_NAMES.iter().filter_map(|name| {
+            let value = self.query(name).ok()?;
+            (default.query(name).ok()? != value)
+                .then(|| format!("set {}", value.replace(' ', "\\ ")))
+        });
+        let filetypes = self
+            .filetypes
+            .iter()
+            .filter(|(filetype, args)| default.filetypes.get(*filetype) != Some(*args))
+            .map(|(filetype, args)| {
+                let mut line = format!("autocmd FileType {} set", filetype);
+                for arg in args {
+                    line.push(' ');
+                    line.push_str(&arg.replace(' ', "\\ "));
+                }
+                line
+            });
+        options.chain(filetypes).collect()
+    }
+
+    /// オプションの値 (`:echo &tabstop` 用)
+    ///
+    /// 真偽値のオプションは Vim と同じく `1` / `0` を返す。
+    pub fn value(&self, name: &str) -> Result<String, String> {
+        let option = self.query(name)?;
+        // 真偽値のオプションは `name` / `noname` の形式
+        let value = match option.split_once('=') {
+            Some((_, value)) => value,
+            None if option.starts_with("no") => "0",
+            None => "1",
+        };
+        Ok(value.to_string())
+    }
+
+    /// 真偽値オプションへの参照を返す
+    fn flag_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "list" => Some(&mut self.list),
+            "wrap" => Some(&mut self.wrap),
+            "number" | "nu" => Some(&mut self.number),
+            "relativenumber" | "rnu" => Some(&mut self.relativenumber),
+            "preservecase" | "pc" => Some(&mut self.preservecase),
+            "subkeepcursor" | "skc" => Some(&mut self.subkeepcursor),
+            "expandtab" | "et" => Some(&mut self.expandtab),
+            "autopairs" | "ap" => Some(&mut self.autopairs),
+            "wrapscan" | "ws" => Some(&mut self.wrapscan),
+            "verymagic" | "vm" => Some(&mut self.verymagic),
+            "wholeword" | "ww" => Some(&mut self.wholeword),
+            "yanktoeol" | "ye" => Some(&mut self.yanktoeol),
+            "hlword" | "hlw" => Some(&mut self.hlword),
+            "autocomment" | "acm" => Some(&mut self.autocomment),
+            "spell" => Some(&mut self.spell),
+            "writebackup" | "wb" => Some(&mut self.writebackup),
+            "confirm" | "cf" => Some(&mut self.confirm),
+            "showmatch" | "sm" => Some(&mut self.showmatch),
+            "smarthome" | "smh" => Some(&mut self.smarthome),
+            "errorbells" | "eb" => Some(&mut self.errorbells),
+            "visualbell" | "vb" => Some(&mut self.visualbell),
+            "autoread" | "ar" => Some(&mut self.autoread),
+            _ => None,
+        }
+    }
+
+    /// 現在の設定値を `name=value` 形式で返す
+    fn query(&self, name: &str) -> Result<String, String> {
+        match name {
+            "list" => Ok(bool_option("list", self.list)),
+            "wrap" => Ok(bool_option("wrap", self.wrap)),
+            "number" | "nu" => Ok(bool_option("number", self.number)),
+            "relativenumber" | "rnu" => Ok(bool_option("relativenumber", self.relativenumber)),
+            "preservecase" | "pc" => Ok(bool_option("preservecase", self.preservecase)),
+            "subkeepcursor" | "skc" => Ok(bool_option("subkeepcursor", self.subkeepcursor)),
+            "listchars" | "lcs" => Ok(format!("listchars={}", self.listchars.to_option_string())),
+            "tabstop" | "ts" => Ok(format!("tabstop={}", self.tabstop)),
+            "shiftwidth" | "sw" => Ok(format!("shiftwidth={}", self.shiftwidth)),
+            "textwidth" | "tw" => Ok(format!("textwidth={}", self.textwidth)),
+            "maxlinelength" | "mll" => Ok(format!("maxlinelength={}", self.maxlinelength)),
+            "timeoutlen" | "tm" => Ok(format!("timeoutlen={}", self.timeoutlen)),
+            "showmatch" | "sm" => Ok(bool_option("showmatch", self.showmatch)),
+            "smarthome" | "smh" => Ok(bool_option("smarthome", self.smarthome)),
+            "errorbells" | "eb" => Ok(bool_option("errorbells", self.errorbells)),
+            "visualbell" | "vb" => Ok(bool_option("visualbell", self.visualbell)),
+            "autoread" | "ar" => Ok(bool_option("autoread", self.autoread)),
+            "matchtime" | "mat" => Ok(format!("matchtime={}", self.matchtime)),
+            "numberwidth" | "nuw" => Ok(format!("numberwidth={}", self.numberwidth)),
+            "signcolumn" | "scl" => Ok(format!("signcolumn={}", self.signcolumn.name())),
+            "expandtab" | "et" => Ok(bool_option("expandtab", self.expandtab)),
+            "autopairs" | "ap" => Ok(bool_option("autopairs", self.autopairs)),
+            "wrapscan" | "ws" => Ok(bool_option("wrapscan", self.wrapscan)),
+            "verymagic" | "vm" => Ok(bool_option("verymagic", self.verymagic)),
+            "wholeword" | "ww" => Ok(bool_option("wholeword", self.wholeword)),
+            "yanktoeol" | "ye" => Ok(bool_option("yanktoeol", self.yanktoeol)),
+            "hlword" | "hlw" => Ok(bool_option("hlword", self.hlword)),
+            "autocomment" | "acm" => Ok(bool_option("autocomment", self.autocomment)),
+            "spell" => Ok(bool_option("spell", self.spell)),
+            "dictionary" | "dict" => Ok(format!("dictionary={}", self.dictionary)),
+            "spellfile" | "spf" => Ok(format!("spellfile={}", self.spellfile)),
+            "virtualedit" | "ve" => Ok(format!("virtualedit={}", self.virtualedit.name())),
+            "statusfilename" | "sfn" => {
+                Ok(format!("statusfilename={}", self.statusfilename.name()))
+            }
+            "writebackup" | "wb" => Ok(bool_option("writebackup", self.writebackup)),
+            "confirm" | "cf" => Ok(bool_option("confirm", self.confirm)),
+            _ => Err(format!("Unknown option: {}", name)),
+        }
+    }
+}
+
+fn bool_option(name: &str, value: bool) -> String {
+    if value {
+        name.to_string()
+    } else {
+        format!("no{}", name)
+    }
+}
+
+fn parse_number(name: &str, value: &str) -> Result<usize, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Number required after =: {}={}", name, value))
+}
+
+/// `:set` の引数を空白で分割する
+///
+/// `\ ` でエスケープされた空白は値の一部として扱う (例: `listchars=tab:>\ `)
+pub fn split_set_args(args: &str) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut current = String::new();
+    let mut chars = args.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some(next) = chars.next() {
+                    current.push(next);
+                }
+            }
+            c if c.is_whitespace() => {
+                if !current.is_empty() {
+                    result.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        result.push(current);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_gutter_options() {
+        let mut config = EditorConfig::new();
+        config.apply("nuw=0").unwrap();
+        assert_eq!(config.numberwidth, 1);
+        config.apply("numberwidth=99").unwrap();
+        assert_eq!(config.numberwidth, 20);
+        config.apply("scl=yes").unwrap();
+        assert_eq!(config.signcolumn, SignColumn::Yes);
+        assert_eq!(config.query("signcolumn"), Ok("signcolumn=yes".to_string()));
+        assert!(config.apply("signcolumn=auto").is_err());
+    }
+
+    #[test]
+    fn test_set_statusfilename() {
+        let mut config = EditorConfig::new();
+        assert_eq!(config.statusfilename, FileNameStyle::Relative);
+        config.apply("sfn=tail").unwrap();
+        assert_eq!(config.statusfilename, FileNameStyle::Tail);
+        assert_eq!(
+            config.query("statusfilename"),
+            Ok("statusfilename=tail".to_string())
+        );
+        assert!(config.apply("statusfilename=short").is_err());
+        assert_eq!(config.to_set_commands(), vec!["set statusfilename=tail"]);
+    }
+
+    #[test]
+    fn test_set_list_toggle() {
+        let mut config = EditorConfig::new();
+        assert!(!config.list);
+
+        config.apply("list").unwrap();
+        assert!(config.list);
+
+        config.apply("nolist").unwrap();
+        assert!(!config.list);
+
+        config.apply("list!").unwrap();
+        assert!(config.list);
+
+        config.apply("invlist").unwrap();
+        assert!(!config.list);
+    }
+
+    #[test]
+    fn test_set_listchars() {
+        let mut config = EditorConfig::new();
+        config.apply("listchars=eol:¬,tab:>-").unwrap();
+
+        assert_eq!(config.listchars.eol, Some('¬'));
+        assert_eq!(config.listchars.tab, Some(('>', '-')));
+        assert_eq!(config.listchars.trail, None);
+        assert_eq!(
+            config.apply("listchars?").unwrap(),
+            Some("listchars=eol:¬,tab:>-".to_string())
+        );
+    }
+
+    #[test]
+    fn test_set_number_abbreviations() {
+        let mut config = EditorConfig::new();
+        config.apply("nu").unwrap();
+        config.apply("rnu").unwrap();
+        assert!(config.number);
+        assert!(config.relativenumber);
+
+        config.apply("nornu").unwrap();
+        assert!(!config.relativenumber);
+        assert_eq!(
+            config.apply("relativenumber?").unwrap(),
+            Some("norelativenumber".to_string())
+        );
+    }
+
+    #[test]
+    fn test_set_invalid() {
+        let mut config = EditorConfig::new();
+        assert!(config.apply("foo").is_err());
+        assert!(config.apply("listchars=eol:ab").is_err());
+        assert!(config.apply("tabstop=x").is_err());
+    }
+
+    #[test]
+    fn test_to_set_commands() {
+        let mut config = EditorConfig::new();
+        assert!(config.to_set_commands().is_empty());
+
+        config.apply("number").unwrap();
+        config.apply("tabstop=2").unwrap();
+        assert_eq!(
+            config.to_set_commands(),
+            vec!["set number", "set tabstop=2"]
+        );
+
+        // 書き出した行を読み込むと同じ設定になる
+        config.apply("nowrapscan").unwrap();
+        config.apply("listchars=tab:> ,eol:$").unwrap();
+        let mut restored = EditorConfig::new();
+        for line in config.to_set_commands() {
+            for arg in split_set_args(line.strip_prefix("set ").unwrap()) {
+                restored.apply(&arg).unwrap();
+            }
+        }
+        assert_eq!(restored, config);
+    }
+
+    #[test]
+    fn test_restore_overrides() {
+        let mut config = EditorConfig::new();
+        let args = ["et".to_string(), "ts=4".to_string(), "sw=4".to_string()];
+        let overrides = config.apply_overrides(&args).unwrap();
+        assert!(config.expandtab);
+        assert_eq!((config.tabstop, config.shiftwidth), (4, 4));
+
+        // 上書きした後に変更したオプションは戻さない
+        config.apply("ts=2").unwrap();
+        config.restore_overrides(overrides);
+        assert!(!config.expandtab);
+        assert_eq!((config.tabstop, config.shiftwidth), (2, 8));
+
+        assert!(config.apply_overrides(&["foo".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_to_set_commands_filetypes() {
+        let mut config = EditorConfig::new();
+        config
+            .set_filetype_options("rust", vec!["ts=2".to_string(), "lcs=tab:> ".to_string()])
+            .unwrap();
+        config.set_filetype_options("markdown", Vec::new()).unwrap();
+        assert_eq!(
+            config.to_set_commands(),
+            vec![
+                "autocmd FileType markdown set",
+                r"autocmd FileType rust set ts=2 lcs=tab:>\ ",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_split_set_args_escaped_space() {
+        assert_eq!(
+            split_set_args(r"list listchars=tab:>\ ,eol:$"),
+            vec!["list".to_string(), "listchars=tab:> ,eol:$".to_string()]
+        );
+    }
+}
@@ -1,3 +1,5 @@
+use crate::buffer::Row;
+
 /// ファイル内の位置を表す構造体 (0-indexed)
 ///
 /// バッファ操作は常に 0-indexed で行われるため、
@@ -23,6 +25,7 @@ impl Position {
     }
 }
 
+#[derive(Clone)]
 pub struct Cursor {
     x: u16,
     y: u16,
@@ -57,6 +60,12 @@ impl Cursor {
     pub fn row_offset(&self) -> u16 {
         self.row_offset
     }
+
+    /// 指定した行が画面に表示されているか
+    pub fn is_row_visible(&self, row: usize, editor_rows: u16) -> bool {
+        let first = self.row_offset as usize;
+        row >= first && row < first + editor_rows as usize
+    }
     pub fn col_offset(&self) -> u16 {
         self.col_offset
     }
@@ -123,6 +132,11 @@ impl Cursor {
         self.x = 1;
     }
 
+    /// 行の最初の非空白文字に移動する (`^`)
+    pub fn move_to_first_non_blank(&mut self, row: &Row) {
+        self.x = row.first_non_blank() as u16 + 1;
+    }
+
     pub fn move_to_line_end(&mut self, line_len: u16) {
         if line_len == 0 {
             self.x = 1;
@@ -131,6 +145,22 @@ impl Cursor {
         }
     }
 
+    /// 同じ行の指定した列に移動する
+    ///
+    /// 列が行の範囲内にあるかは呼び出し側で確認する。
+    pub fn move_to_col(&mut self, col: usize) {
+        self.x = col as u16 + 1;
+    }
+
+    /// 行の最後の文字の 1 つ後ろに移動する (`A` で Insert mode に入るとき用)
+    ///
+    /// # Arguments
+    ///
+    /// - `line_len`: 行の長さ (char 単位)
+    pub fn move_past_line_end(&mut self, line_len: usize) {
+        self.x = line_len as u16 + 1;
+    }
+
     /// ファイル先頭に移動
     pub fn move_to_top(&mut self) {
         self.y = 1;
@@ -153,11 +183,47 @@ impl Cursor {
             self.row_offset = 0;
         } else {
             // スクロールが必要な場合
-            self.row_offset = last_line.saturating_sub(editor_rows - 1);
+            self.row_offset = last_line.saturating_sub(editor_rows.saturating_sub(1));
             self.y = last_line - self.row_offset + 1;
         }
     }
 
+    /// 指定した行に移動する
+    ///
+    /// 行が画面外にある場合は、行が画面内に入るようにスクロールする。
+    ///
+    /// # Arguments
+    ///
+    /// - `row`: 移動先の行 (0-indexed)
+    /// - `buffer_len`: バッファの行数
+    /// - `editor_rows`: エディタ領域の行数
+    pub fn move_to_row(&mut self, row: usize, buffer_len: usize, editor_rows: u16) {
+        let row = row.min(buffer_len.saturating_sub(1)) as u16;
+        if row < self.row_offset || row >= self.row_offset + editor_rows {
+            self.row_offset = row.saturating_sub(editor_rows / 2);
+        }
+        self.y = row - self.row_offset + 1;
+    }
+
+    /// 指定した位置に移動する
+    ///
+    /// 列が行の範囲内にあるかは呼び出し側で確認する。
+    pub fn move_to_position(&mut self, pos: Position, buffer_len: usize, editor_rows: u16) {
+        self.move_to_row(pos.row, buffer_len, editor_rows);
+        self.x = pos.col as u16 + 1;
+    }
+
+    /// 指定した位置に移動する (スクロールはしない)
+    ///
+    /// 行が画面の下にはみ出した場合は、描画の前に [`scroll`](Self::scroll) で画面内に収める。
+    /// 画面の上にある場合は、その行が画面の一番上になるようにする。
+    pub fn set_position(&mut self, pos: Position) {
+        let row = pos.row as u16;
+        self.row_offset = self.row_offset.min(row);
+        self.y = row - self.row_offset + 1;
+        self.x = pos.col as u16 + 1;
+    }
+
     pub fn adjust_cursor_x(&mut self, line_len: usize) {
         if line_len == 0 {
             self.x = 1;
@@ -216,7 +282,7 @@ impl Cursor {
                 self.y = last_row + 1;
                 self.row_offset = 0;
             } else {
-                self.row_offset = last_row.saturating_sub(editor_rows - 1);
+                self.row_offset = last_row.saturating_sub(editor_rows.saturating_sub(1));
                 self.y = last_row - self.row_offset + 1;
             }
             return;
@@ -229,13 +295,22 @@ impl Cursor {
 
         // 画面下端より下にカーソルがある場合
         if file_row >= self.row_offset + editor_rows {
-            self.row_offset = file_row.saturating_sub(editor_rows - 1);
+            self.row_offset = file_row.saturating_sub(editor_rows.saturating_sub(1));
         }
 
         // カーソルの y 座標を画面内の位置に調整
         self.y = file_row - self.row_offset + 1;
     }
 
+    /// ファイル内の行を変えずに、画面の一番上に表示する行を変える
+    ///
+    /// `row_offset` はカーソルのある行以下であること。
+    pub fn set_row_offset(&mut self, row_offset: u16) {
+        let file_row = self.row_offset + self.y - 1;
+        self.row_offset = row_offset;
+        self.y = file_row - row_offset + 1;
+    }
+
     /// カーソル位置をスナップショットから復元する
     pub fn restore(&mut self, x: u16, y: u16, row_offset: u16) {
         self.x = x;
@@ -272,20 +347,6 @@ impl Cursor {
     pub fn position(&self) -> Position {
         Position::new(self.file_row(), self.col_index())
     }
-
-    /// カーソルの端末上の表示カラム番号を返す（1-indexed）
-    ///
-    /// 全角文字は2カラム占有するため、端末の Goto にはこのメソッドの値を使う。
-    pub fn screen_col(&self, line: &str) -> u16 {
-        use unicode_width::UnicodeWidthChar;
-        let col_idx = self.col_index(); // 0-indexed char position
-        let width: usize = line
-            .chars()
-            .take(col_idx)
-            .map(|c| c.width().unwrap_or(1))
-            .sum();
-        (width as u16) + 1
-    }
 }
 
 #[cfg(test)]
@@ -402,6 +463,19 @@ mod tests {
         assert_eq!(cursor.row_offset(), 0);
     }
 
+    #[test]
+    fn test_cursor_set_row_offset() {
+        let mut cursor = Cursor::new();
+        cursor.move_to_row(10, 20, 24);
+        assert_eq!(cursor.y(), 11);
+
+        // 行を変えずに画面の一番上の行だけを変える
+        cursor.set_row_offset(4);
+        assert_eq!(cursor.row_offset(), 4);
+        assert_eq!(cursor.y(), 7);
+        assert_eq!(cursor.file_row(), 10);
+    }
+
     #[test]
     fn test_cursor_move_to_bottom_large_file() {
         let mut cursor = Cursor::new();
@@ -556,6 +630,45 @@ mod tests {
         assert_eq!(cursor.row_offset(), 5);
     }
 
+    #[test]
+    fn test_cursor_set_position() {
+        let mut cursor = Cursor::new();
+        cursor.move_to_row(30, 100, 24);
+        let offset = cursor.row_offset();
+
+        // 画面の下の行ではスクロールせず、scroll で画面内に収める
+        cursor.set_position(Position::new(60, 3));
+        assert_eq!(cursor.position(), Position::new(60, 3));
+        assert_eq!(cursor.row_offset(), offset);
+        cursor.scroll(24, 100);
+        assert_eq!(cursor.position(), Position::new(60, 3));
+        assert_eq!(cursor.row_offset(), 37);
+
+        // 画面の上の行は画面の一番上にする
+        cursor.set_position(Position::new(10, 0));
+        assert_eq!(cursor.position(), Position::new(10, 0));
+        assert_eq!(cursor.row_offset(), 10);
+    }
+
+    #[test]
+    fn test_cursor_move_to_row() {
+        let mut cursor = Cursor::new();
+
+        // 画面内の行
+        cursor.move_to_row(5, 100, 24);
+        assert_eq!(cursor.file_row(), 5);
+        assert_eq!(cursor.row_offset(), 0);
+
+        // 画面外の行は中央付近に表示する
+        cursor.move_to_row(50, 100, 24);
+        assert_eq!(cursor.file_row(), 50);
+        assert_eq!(cursor.row_offset(), 38);
+
+        // バッファの範囲外は最終行
+        cursor.move_to_row(200, 100, 24);
+        assert_eq!(cursor.file_row(), 99);
+    }
+
     #[test]
     fn test_position_new() {
         let pos = Position::new(5, 10);
@@ -605,4 +718,17 @@ mod tests {
         let pos = cursor.position();
         assert_eq!(pos, Position::new(1, 1));
     }
+
+    #[test]
+    fn test_zero_editor_rows() {
+        // テキストを表示する行がない小さな端末でもパニックしない
+        let mut cursor = Cursor::new();
+        cursor.move_down(0, 10);
+        cursor.scroll(0, 10);
+        cursor.move_to_bottom(10, 0);
+        assert_eq!(cursor.file_row(), 9);
+        cursor.move_to_row(3, 10, 0);
+        cursor.scroll(0, 10);
+        assert_eq!(cursor.file_row(), 3);
+    }
 }
@@ -0,0 +1,128 @@
+use std::ops::Range;
+
+use similar::{Algorithm, DiffOp, capture_diff_slices};
+
+/// 保存済みのファイルと比べた行の変更の種類 (`:diff` でサイン列に表示する)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffSign {
+    /// 追加された行
+    Added,
+    /// 変更された行
+    Changed,
+    /// この行の前で行が削除された
+    Removed,
+}
+
+impl DiffSign {
+    pub fn symbol(self) -> char {
+        match self {
+            Self::Added => '+',
+            Self::Changed => '~',
+            Self::Removed => '-',
+        }
+    }
+}
+
+/// `old` から `new` への変更を、`new` の各行のサインとして求める
+///
+/// 置き換えられた行は `~`、置き換えより多い分の行は `+` にする。
+/// 削除された行は削除された位置の次の行 (ファイルの末尾の場合は最後の行) に `-` を付ける。
+pub fn line_signs(old: &[&str], new: &[&str]) -> Vec<Option<DiffSign>> {
+    let mut signs = vec![None; new.len()];
+    for op in capture_diff_slices(Algorithm::Myers, old, new) {
+        match op {
+            DiffOp::Equal { .. } => {}
+            DiffOp::Insert {
+                new_index, new_len, ..
+            } => signs[new_index..new_index + new_len].fill(Some(DiffSign::Added)),
+            DiffOp::Replace {
+                old_len,
+                new_index,
+                new_len,
+                ..
+            } => {
+                let changed = new_len.min(old_len);
+                signs[new_index..new_index + changed].fill(Some(DiffSign::Changed));
+                signs[new_index + changed..new_index + new_len].fill(Some(DiffSign::Added));
+            }
+            DiffOp::Delete { new_index, .. } => {
+                let row = new_index.min(new.len().saturating_sub(1));
+                if let Some(sign @ None) = signs.get_mut(row) {
+                    *sign = Some(DiffSign::Removed);
+                }
+            }
+        }
+    }
+    signs
+}
+
+/// `old` から `new` への変更の塊を、`new` の行の範囲として求める (`]c` / `[c` 用)
+///
+/// 削除された行の塊は、サインと同じく削除された位置の次の行 (末尾の場合は最後の行) とする。
+pub fn hunks(old: &[&str], new: &[&str]) -> Vec<Range<usize>> {
+    let mut hunks: Vec<Range<usize>> = Vec::new();
+    for op in capture_diff_slices(Algorithm::Myers, old, new) {
+        let range = match op {
+            DiffOp::Equal { .. } => continue,
+            DiffOp::Insert {
+                new_index, new_len, ..
+            }
+            | DiffOp::Replace {
+                new_index, new_len, ..
+            } => new_index..new_index + new_len,
+            DiffOp::Delete { new_index, .. } => {
+                let row = new_index.min(new.len().saturating_sub(1));
+                row..row + 1
+            }
+        };
+        match hunks.last_mut() {
+            Some(last) if last.end >= range.start => last.end = last.end.max(range.end),
+            _ => hunks.push(range),
+        }
+    }
+    hunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symbols(old: &[&str], new: &[&str]) -> String {
+        line_signs(old, new)
+            .iter()
+            .map(|sign| sign.map_or(' ', DiffSign::symbol))
+            .collect()
+    }
+
+    #[test]
+    fn test_line_signs() {
+        assert_eq!(symbols(&["a", "b", "c"], &["a", "b", "c"]), "   ");
+        assert_eq!(symbols(&["a", "c"], &["a", "b", "c"]), " + ");
+        assert_eq!(symbols(&["a", "b", "c"], &["a", "x", "c"]), " ~ ");
+        // 削除は次の行に付ける
+        assert_eq!(symbols(&["a", "b", "c"], &["a", "c"]), " -");
+        // 末尾の行の削除は最後の行に付ける
+        assert_eq!(symbols(&["a", "b", "c"], &["a", "b"]), " -");
+        // 置き換えより多い行は追加
+        assert_eq!(symbols(&["a", "b", "c"], &["a", "x", "y", "c"]), " ~+ ");
+        assert_eq!(symbols(&[], &["a", "b"]), "++");
+        assert_eq!(symbols(&["a", "b"], &[]), "");
+    }
+
+    #[test]
+    fn test_hunks() {
+        assert!(hunks(&["a", "b", "c"], &["a", "b", "c"]).is_empty());
+        assert_eq!(
+            hunks(
+                &["a", "b", "c", "d", "e", "f"],
+                &["a", "x", "y", "c", "e", "f", "g"]
+            ),
+            vec![1..3, 4..5, 6..7]
+        );
+        // 隣り合う変更は 1 つの塊にする
+        assert_eq!(hunks(&["a", "b", "c"], &["x", "y", "c"]), vec![0..2]);
+        // 末尾の行の削除は最後の行
+        assert_eq!(hunks(&["a", "b", "c"], &["a", "b"]), vec![1..2]);
+        assert_eq!(hunks(&[], &["a"]), vec![0..1]);
+    }
+}
@@ -1,12 +1,32 @@
 use crate::{
-    buffer::Buffer,
+    buffer::{Buffer, FileEncoding, FileFormat, indent_string, indent_width, leading_whitespace},
+    config::{EditorConfig, OptionOverride, SignColumn},
     cursor::{Cursor, Position},
+    diff::{self, DiffSign},
     file_io::FileIO,
+    filetype,
     history::{Snapshot, UndoHistory},
-    yank::YankManager,
+    literal::LiteralInput,
+    motion::{self, Motion, Operator},
+    pattern,
+    prompt::Prompt,
+    range::LineRange,
+    register::{BLACK_HOLE, RegisterFile},
+    registry::Registry,
+    search::{self, SearchDirection, SearchResult},
+    spell::{self, Dictionary},
+    substitute::{ConfirmSubstitute, Substitute, SubstituteResult},
+    yank::{YankManager, YankType},
 };
+use regex::Regex;
+use std::cell::RefCell;
 use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use unicode_width::UnicodeWidthChar;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PasteDirection {
     // `p`
     Below,
@@ -17,11 +37,95 @@ pub enum PasteDirection {
 pub enum PasteResult {
     Empty,
     // カーソルのある行に挿入
-    InLine,
+    InLine(PasteSpan),
     // 上の行に挿入
-    Above,
+    Above(PasteSpan),
     // 下の行に挿入
-    Below,
+    Below(PasteSpan),
+}
+
+/// 貼り付けたテキストの範囲 (`end` は最後に挿入した文字の位置)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PasteSpan {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl PasteResult {
+    /// `gp` / `gP` でカーソルを置く、貼り付けたテキストの直後の位置
+    ///
+    /// 行単位の場合は次の行の先頭 (バッファの末尾を超える場合は None)。
+    pub fn position_after(&self, buffer_len: usize) -> Option<Position> {
+        match self {
+            Self::Empty => None,
+            Self::InLine(span) => Some(Position::new(span.end.row, span.end.col + 1)),
+            Self::Above(span) | Self::Below(span) => {
+                let row = span.end.row + 1;
+                (row < buffer_len).then(|| Position::new(row, 0))
+            }
+        }
+    }
+}
+
+/// `writebackup` に従ってバッファをファイルに書き込む
+fn write_buffer(filename: Option<&str>, buffer: &Buffer, config: &EditorConfig) -> io::Result<()> {
+    let Some(filename) = filename else {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "No filename specified",
+        ));
+    };
+    if config.writebackup {
+        FileIO::save_atomic(filename, buffer)
+    } else {
+        FileIO::save(filename, buffer)
+    }
+}
+
+/// 一時的に別のバッファを開いている間、元のバッファの状態を保持する
+struct SavedBuffer {
+    buffer: Buffer,
+    filename: Option<String>,
+    dirty: bool,
+    readonly: bool,
+    modifiable: bool,
+    history: UndoHistory,
+    cursor: Cursor,
+    mtime: Option<SystemTime>,
+}
+
+/// ディスク上のファイルが読み込んだ後に変更された場合の対応 (`:set autoread`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileChangeAction {
+    /// 未保存の変更がないので読み込み直す
+    Reload,
+    /// 読み込み直さずに警告を表示する
+    Warn,
+}
+
+impl FileChangeAction {
+    /// ファイルが変更されたか (`changed`)、未保存の変更があるか (`dirty`) から対応を決める
+    ///
+    /// 未保存の変更がある場合は `autoread` でも読み込み直さない。
+    pub fn decide(changed: bool, dirty: bool, autoread: bool) -> Option<Self> {
+        if !changed {
+            None
+        } else if autoread && !dirty {
+            Some(Self::Reload)
+        } else {
+            Some(Self::Warn)
+        }
+    }
+}
+
+/// バッファの各行の内容
+fn buffer_lines(buffer: &Buffer) -> Vec<String> {
+    buffer.iter().map(|r| r.chars().to_string()).collect()
+}
+
+/// ファイルの更新日時 (ファイルがない場合は None)
+fn modified_time(filename: Option<&str>) -> Option<SystemTime> {
+    std::fs::metadata(filename?).and_then(|m| m.modified()).ok()
 }
 
 pub struct Editor {
@@ -29,8 +133,59 @@ pub struct Editor {
     filename: Option<String>,
     /// 未保存の変更があるか
     dirty: bool,
+    /// 読み込み専用か (`!` なしでの保存を禁止する)
+    readonly: bool,
+    /// バッファを変更できるか (`:set nomodifiable` で編集を禁止する)
+    ///
+    /// false の場合、バッファを変更するメソッドは何もせずに戻る。
+    modifiable: bool,
+    /// スクラッチバッファ (`:help` など) の表示名。通常のバッファでは None
+    scratch: Option<String>,
+    /// スクラッチバッファを開く前のバッファ
+    previous: Option<SavedBuffer>,
+    /// バッファ上のカーソル位置と画面のスクロール位置
+    pub cursor: Cursor,
     pub yank: YankManager,
+    /// 名前付きレジスタ
+    pub registers: RegisterFile,
     pub history: UndoHistory,
+    pub config: EditorConfig,
+    /// 直前に実行した Ex コマンド (`@:` / `:<Up>` 用)
+    pub last_command: Option<String>,
+    /// 確認付き置換 (`:s///c`) の途中状態。確認中でなければ None
+    pub confirm: Option<ConfirmSubstitute>,
+    /// 1 キーで応答する確認 (`:confirm q` など)。確認中でなければ None
+    pub prompt: Option<Prompt>,
+    /// 直前の `:s` の内容 (`&` / `g&` 用)
+    pub last_substitute: Option<Substitute>,
+    /// 直前の検索パターンと方向 (`n` / `N` 用)
+    pub last_search: Option<(String, SearchDirection)>,
+    /// 現在の Insert mode で入力したテキスト (終了時に `".` に保存する)
+    pub inserted_text: String,
+    /// Insert mode の `Ctrl-V` に続く入力の途中状態。入力中でなければ None
+    pub literal_input: Option<LiteralInput>,
+    /// 起動時に追加した Ex コマンドと Normal mode のキー
+    pub registry: Registry,
+    /// ファイル名から判定したファイルタイプ
+    filetype: Option<&'static str>,
+    /// ファイルタイプごとの設定で変更したオプション (次のファイルを開くときに元に戻す)
+    filetype_overrides: Vec<OptionOverride>,
+    /// `:set spell` で読み込んだ辞書。一度も有効にしていなければ None
+    spell_dictionary: Option<Dictionary>,
+    /// 直前に編集していたファイルと、そのときのカーソル位置 (`Ctrl-^` 用)
+    alternate: Option<(String, Position)>,
+    /// 直前に変更・ヤンクしたテキストの先頭と末尾 (`` `[ `` / `` `] `` マーク)
+    last_change: Option<(Position, Position)>,
+    /// 直前の Visual mode の選択範囲の先頭と末尾 (`` `< `` / `` `> `` マーク)
+    last_visual: Option<(Position, Position)>,
+    /// 読み込み・保存したときのファイルの更新日時 (外部での変更の検出用)
+    mtime: Option<SystemTime>,
+    /// 読み込んだとき、または最後に保存したときの内容 (変更された行のサインの比較元)
+    baseline: Vec<String>,
+    /// `:diff` で変更された行のサインを表示しているか
+    diff: bool,
+    /// 最後に求めたサインと、そのときのバッファの番号 (描画のたびに差分を取り直さないため)
+    signs: RefCell<Option<(u64, Vec<Option<DiffSign>>)>>,
 }
 
 impl Default for Editor {
@@ -45,19 +200,73 @@ impl Editor {
             buffer: Buffer::new(),
             filename: None,
             dirty: false,
+            readonly: false,
+            modifiable: true,
+            scratch: None,
+            previous: None,
+            cursor: Cursor::new(),
             yank: YankManager::new(),
+            registers: RegisterFile::new(),
             history: UndoHistory::new(1000),
+            config: EditorConfig::new(),
+            last_command: None,
+            confirm: None,
+            prompt: None,
+            last_substitute: None,
+            last_change: None,
+            last_visual: None,
+            mtime: None,
+            baseline: Vec::new(),
+            diff: false,
+            signs: RefCell::new(None),
+            last_search: None,
+            inserted_text: String::new(),
+            literal_input: None,
+            registry: Registry::new(),
+            filetype: None,
+            filetype_overrides: Vec::new(),
+            spell_dictionary: None,
+            alternate: None,
         }
     }
 
     pub fn from_buffer(buffer: Buffer, filename: Option<String>) -> Self {
-        Self {
+        let mut editor = Self {
             buffer,
             filename,
             dirty: false,
+            readonly: false,
+            modifiable: true,
+            scratch: None,
+            previous: None,
+            cursor: Cursor::new(),
             yank: YankManager::new(),
+            registers: RegisterFile::new(),
             history: UndoHistory::new(1000),
-        }
+            config: EditorConfig::new(),
+            last_command: None,
+            confirm: None,
+            prompt: None,
+            last_substitute: None,
+            last_change: None,
+            last_visual: None,
+            mtime: None,
+            baseline: Vec::new(),
+            diff: false,
+            signs: RefCell::new(None),
+            last_search: None,
+            inserted_text: String::new(),
+            literal_input: None,
+            registry: Registry::new(),
+            filetype: None,
+            filetype_overrides: Vec::new(),
+            spell_dictionary: None,
+            alternate: None,
+        };
+        editor.mtime = modified_time(editor.filename.as_deref());
+        editor.set_baseline(buffer_lines(&editor.buffer));
+        editor.apply_filetype();
+        editor
     }
 
     pub fn open_file(&mut self, filename: String) -> io::Result<()> {
@@ -66,40 +275,304 @@ impl Editor {
         self.buffer = buffer;
         self.filename = Some(filename);
         self.dirty = false;
+        self.readonly = false;
+        self.modifiable = true;
         self.history = UndoHistory::new(1000);
+        self.last_change = None;
+        self.last_visual = None;
+        self.mtime = modified_time(self.filename.as_deref());
+        self.set_baseline(buffer_lines(&self.buffer));
         // yank の状態は継続して良いため、YankManager は意図的に更新していない
+        self.apply_filetype();
         Ok(())
     }
 
+    pub fn filetype(&self) -> Option<&str> {
+        self.filetype
+    }
+
+    /// ファイル名からファイルタイプを判定し、対応する設定を適用する
+    ///
+    /// 前のファイルのファイルタイプで上書きした設定は、先に元の値に戻す。
+    pub fn apply_filetype(&mut self) {
+        self.restore_filetype();
+        self.filetype = self.filename.as_deref().and_then(filetype::detect);
+        if let Some(filetype) = self.filetype {
+            let args = self.config.filetype_options(filetype).to_vec();
+            // 登録時に確認しているので失敗しない
+            self.filetype_overrides = self.config.apply_overrides(&args).unwrap_or_default();
+        }
+    }
+
+    /// ファイルタイプごとの設定で上書きしたオプションを元の値に戻す
+    ///
+    /// 上書きした後に `:set` で変更されたオプションはそのままにする。
+    pub fn restore_filetype(&mut self) {
+        let overrides = std::mem::take(&mut self.filetype_overrides);
+        self.config.restore_overrides(overrides);
+    }
+
+    /// ファイルを読み込み直す (`autoread` 用)。取り消し履歴は消す
     pub fn reload(&mut self) -> io::Result<()> {
+        self.read_from_disk()?;
+        self.history = UndoHistory::new(1000);
+        Ok(())
+    }
+
+    /// ファイルを読み込み直す (`:e!` 用)
+    ///
+    /// 読み込み直す前のバッファを取り消し履歴に残すので、誤って読み込み直しても
+    /// `u` で未保存の変更を戻せる。
+    pub fn reload_keeping_undo(&mut self) -> io::Result<()> {
+        let snapshot = self.snapshot();
+        self.read_from_disk()?;
+        if self.changed_since(&snapshot) {
+            self.history.commit(snapshot);
+        }
+        Ok(())
+    }
+
+    fn read_from_disk(&mut self) -> io::Result<()> {
         if let Some(filename) = &self.filename {
             let buffer = FileIO::open(filename)?;
             // Editor のプロパティを更新する
             self.buffer = buffer;
             self.dirty = false;
-            self.history = UndoHistory::new(1000);
+            self.mtime = modified_time(Some(filename));
+            self.set_baseline(buffer_lines(&self.buffer));
+            self.clamp_cursor();
             Ok(())
         } else {
             Err(io::Error::new(io::ErrorKind::NotFound, "No file name"))
         }
     }
 
+    /// 読み込んだ後にディスク上のファイルが変更されていれば、`autoread` に従って対応する
+    ///
+    /// 未保存の変更がなく `autoread` が有効なら読み込み直し、それ以外は警告する。
+    /// 同じ変更について繰り返し警告しないよう、確認した更新日時を記録する。
+    ///
+    /// # Returns
+    ///
+    /// 表示するメッセージ。ファイルが変更されていなければ None
+    pub fn check_file_changed(&mut self) -> Option<String> {
+        let current = modified_time(self.filename.as_deref())?;
+        let changed = self.mtime.is_some_and(|mtime| mtime != current);
+        let action = FileChangeAction::decide(changed, self.dirty, self.config.autoread)?;
+        self.mtime = Some(current);
+        let name = self.filename.clone().unwrap_or_default();
+        match action {
+            FileChangeAction::Reload => Some(match self.reload() {
+                Ok(()) => format!("\"{}\" file changed, reloaded", name),
+                Err(e) => format!("Error: {}", e),
+            }),
+            FileChangeAction::Warn => {
+                // サインはディスク上の新しい内容と比べる
+                let buffer = self.filename.as_deref().and_then(|f| FileIO::open(f).ok());
+                if let Some(buffer) = buffer {
+                    self.set_baseline(buffer_lines(&buffer));
+                }
+                Some(format!(
+                    "Warning: File \"{}\" has changed since editing started",
+                    name
+                ))
+            }
+        }
+    }
+
+    /// 保存済みの内容と比べて変更された行のサインの表示を始める (`:diff`)
+    pub fn diff_start(&mut self) -> Result<(), String> {
+        if self.filename.is_none() || self.is_scratch() {
+            return Err("No file name".to_string());
+        }
+        self.diff = true;
+        Ok(())
+    }
+
+    /// 変更された行のサインの表示をやめる (`:diffoff`)
+    pub fn diff_off(&mut self) {
+        self.diff = false;
+    }
+
+    /// 保存済みの内容と比べた各行のサイン
+    ///
+    /// `:diff` を使っているか `signcolumn` が有効な場合だけ求め、それ以外は None を返す。
+    pub fn diff_signs(&self) -> Option<Vec<Option<DiffSign>>> {
+        if self.signcolumn() == SignColumn::No || self.is_scratch() {
+            return None;
+        }
+        let version = self.buffer.version();
+        if let Some((cached, signs)) = self.signs.borrow().as_ref()
+            && *cached == version
+        {
+            return Some(signs.clone());
+        }
+        let (old, new) = self.diff_lines();
+        let signs = diff::line_signs(&old, &new);
+        *self.signs.borrow_mut() = Some((version, signs.clone()));
+        Some(signs)
+    }
+
+    /// サインの比較元を置き換える
+    fn set_baseline(&mut self, lines: Vec<String>) {
+        self.baseline = lines;
+        self.signs.replace(None);
+    }
+
+    /// `row` より後 (`Forward`) / 前 (`Backward`) にある、変更された行の塊の先頭 (`]c` / `[c` 用)
+    pub fn find_hunk(&self, row: usize, direction: SearchDirection) -> Option<usize> {
+        let (old, new) = self.diff_lines();
+        let mut starts = diff::hunks(&old, &new).into_iter().map(|hunk| hunk.start);
+        match direction {
+            SearchDirection::Forward => starts.find(|&start| start > row),
+            SearchDirection::Backward => starts.rev().find(|&start| start < row),
+        }
+    }
+
+    /// サインの比較元と現在の内容
+    fn diff_lines(&self) -> (Vec<&str>, Vec<&str>) {
+        let old = self.baseline.iter().map(String::as_str).collect();
+        let new = self.buffer.iter().map(|r| r.chars()).collect();
+        (old, new)
+    }
+
+    /// 実際に表示するサイン列 (差分を表示している間は常に表示する)
+    pub fn signcolumn(&self) -> SignColumn {
+        if self.diff && !self.is_scratch() {
+            SignColumn::Yes
+        } else {
+            self.config.signcolumn
+        }
+    }
+
+    /// ファイルと関連付かない読み込み専用のバッファを開く (`:help` 用)
+    ///
+    /// 元のバッファとカーソル位置は close_scratch で復元できるよう退避する。
+    pub fn open_scratch(&mut self, name: &str, buffer: Buffer) {
+        if self.scratch.is_none() {
+            self.previous = Some(SavedBuffer {
+                buffer: std::mem::take(&mut self.buffer),
+                filename: self.filename.take(),
+                dirty: self.dirty,
+                readonly: self.readonly,
+                modifiable: self.modifiable,
+                history: std::mem::replace(&mut self.history, UndoHistory::new(1000)),
+                cursor: std::mem::take(&mut self.cursor),
+                mtime: self.mtime.take(),
+            });
+        }
+        self.buffer = buffer;
+        self.dirty = false;
+        self.readonly = true;
+        self.modifiable = false;
+        self.scratch = Some(name.to_string());
+        self.history = UndoHistory::new(1000);
+        self.cursor = Cursor::new();
+    }
+
+    /// スクラッチバッファを閉じて元のバッファに戻る
+    ///
+    /// # Returns
+    ///
+    /// スクラッチバッファを開いていなかった場合は false
+    pub fn close_scratch(&mut self) -> bool {
+        let Some(saved) = self.previous.take() else {
+            return false;
+        };
+        self.buffer = saved.buffer;
+        self.filename = saved.filename;
+        self.dirty = saved.dirty;
+        self.readonly = saved.readonly;
+        self.modifiable = saved.modifiable;
+        self.history = saved.history;
+        self.mtime = saved.mtime;
+        self.scratch = None;
+        self.cursor = saved.cursor;
+        true
+    }
+
+    pub fn is_scratch(&self) -> bool {
+        self.scratch.is_some()
+    }
+
+    /// ステータスバーに表示するバッファ名
+    pub fn display_name(&self) -> Option<&str> {
+        self.filename.as_deref().or(self.scratch.as_deref())
+    }
+
+    /// 特殊マーク (`[` / `]` / `<` / `>`) の位置
+    ///
+    /// 変更やヤンクの後に行が減っている場合は、バッファ内に収まる位置に丸める。
+    ///
+    /// # Errors
+    ///
+    /// マークが設定されていない場合と、対応していないマーク名の場合
+    pub fn mark(&self, name: char) -> Result<Position, String> {
+        let (marks, first) = match name {
+            '[' | ']' => (self.last_change, name == '['),
+            '<' | '>' => (self.last_visual, name == '<'),
+            _ => return Err(format!("Unknown mark: {}", name)),
+        };
+        let (start, end) = marks.ok_or_else(|| "Mark not set".to_string())?;
+        let pos = if first { start } else { end };
+        let row = pos.row.min(self.buffer.len().saturating_sub(1));
+        let col = pos.col.min(self.current_line_len(row).saturating_sub(1));
+        Ok(Position::new(row, col))
+    }
+
+    /// 直前に変更・ヤンクしたテキストの範囲を記録する (`` `[ `` / `` `] ``)
+    fn set_change_marks(&mut self, start: Position, end: Position) {
+        self.last_change = Some((start, end));
+    }
+
+    /// Visual mode の選択範囲を記録する (`` `< `` / `` `> ``)
+    ///
+    /// 行単位の選択は先頭の行の行頭から末尾の行の行末までとする。
+    pub fn set_visual_marks(&mut self, start: Position, end: Position, linewise: bool) {
+        let (start, end) = Self::normalize_range(start, end);
+        self.last_visual = Some(if linewise {
+            let last_col = self.current_line_len(end.row).saturating_sub(1);
+            (
+                Position::new(start.row, 0),
+                Position::new(end.row, last_col),
+            )
+        } else {
+            (start, end)
+        });
+    }
+
     /// 現在のバッファ状態とカーソル位置をスナップショットとして作成する
-    pub fn snapshot(&self, cursor: &Cursor) -> Snapshot {
+    pub fn snapshot(&self) -> Snapshot {
         Snapshot {
             buffer: self.buffer.clone(),
-            cursor_x: cursor.x(),
-            cursor_y: cursor.y(),
-            cursor_row_offset: cursor.row_offset(),
+            cursor_x: self.cursor.x(),
+            cursor_y: self.cursor.y(),
+            cursor_row_offset: self.cursor.row_offset(),
             was_dirty: self.dirty,
         }
     }
 
     /// スナップショットをエディタとカーソルに復元する
-    pub fn restore_snapshot(&mut self, snapshot: Snapshot, cursor: &mut Cursor) {
+    pub fn restore_snapshot(&mut self, snapshot: Snapshot) {
         self.buffer = snapshot.buffer;
         self.dirty = snapshot.was_dirty;
-        cursor.restore(snapshot.cursor_x, snapshot.cursor_y, snapshot.cursor_row_offset);
+        self.cursor.restore(
+            snapshot.cursor_x,
+            snapshot.cursor_y,
+            snapshot.cursor_row_offset,
+        );
+        self.clamp_cursor();
+    }
+
+    /// `snapshot` を取得してからバッファの内容が変わったか
+    pub fn changed_since(&self, snapshot: &Snapshot) -> bool {
+        let before = snapshot.buffer.rows();
+        let after = self.buffer.rows();
+        before.len() != after.len()
+            || before
+                .iter()
+                .zip(after)
+                .any(|(a, b)| a.chars() != b.chars())
     }
 
     pub fn buffer(&self) -> &Buffer {
@@ -110,6 +583,31 @@ impl Editor {
         &mut self.buffer
     }
 
+    /// カーソルをバッファの範囲内に戻す
+    ///
+    /// 再読み込みや取り消しで行が減り、カーソルが範囲外になった後に呼び出す。
+    pub fn clamp_cursor(&mut self) {
+        self.place_cursor(self.cursor.position());
+    }
+
+    /// 編集後のカーソルを `pos` に置く
+    ///
+    /// 行はバッファの範囲内に、列は行の最後の文字までに収める。
+    /// 画面のスクロールは、キーを処理した後の `Cursor::scroll` に任せる。
+    fn place_cursor(&mut self, pos: Position) {
+        let row = pos.row.min(self.buffer.len().saturating_sub(1));
+        let col = pos.col.min(self.current_line_len(row).saturating_sub(1));
+        self.cursor.set_position(Position::new(row, col));
+    }
+
+    /// カーソルを `pos` に移動し、列を移動先の行の最後の文字までに収める
+    pub fn move_cursor(&mut self, pos: Position, editor_rows: u16) {
+        self.cursor
+            .move_to_position(pos, self.buffer.len(), editor_rows);
+        let line_len = self.current_line_len(self.cursor.file_row());
+        self.cursor.adjust_cursor_x(line_len);
+    }
+
     /// バッファの長さと指定行の長さを取得
     ///
     /// カーソル位置調整時に頻繁に使用される
@@ -139,185 +637,1393 @@ impl Editor {
         self.filename.as_deref()
     }
 
-    pub fn is_dirty(&self) -> bool {
-        self.dirty
+    /// 直前に編集していたファイル (alternate file) とカーソル位置
+    pub fn alternate(&self) -> Option<&(String, Position)> {
+        self.alternate.as_ref()
     }
 
-    /// 文字を挿入
-    pub fn insert_char(&mut self, pos: Position, ch: char) {
-        self.buffer.insert_char(pos, ch);
-        self.dirty = true;
+    pub fn set_alternate(&mut self, filename: String, pos: Position) {
+        self.alternate = Some((filename, pos));
     }
 
-    /// 文字を削除
-    pub fn delete_char(&mut self, pos: Position) {
-        self.buffer.delete_char(pos);
-        self.dirty = true;
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
     }
 
-    /// 改行を挿入
-    pub fn insert_newline(&mut self, pos: Position) {
-        self.buffer.insert_newline(pos);
-        self.dirty = true;
+    pub fn is_readonly(&self) -> bool {
+        self.readonly
     }
 
-    /// 前の行と結合
-    pub fn join_rows(&mut self, row: usize) {
-        self.buffer.join_rows(row);
-        self.dirty = true;
+    pub fn set_readonly(&mut self, value: bool) {
+        self.readonly = value;
     }
 
-    /// ファイルに保存
-    pub fn save(&mut self) -> io::Result<()> {
-        if let Some(filename) = &self.filename {
-            FileIO::save(filename, &self.buffer)?;
-            self.dirty = false;
+    pub fn is_modifiable(&self) -> bool {
+        self.modifiable
+    }
+
+    /// 変更できないバッファ (`:set nomodifiable`) の場合はエラーメッセージを返す
+    pub fn check_modifiable(&self) -> Result<(), String> {
+        if self.modifiable {
             Ok(())
         } else {
-            Err(io::Error::new(
-                io::ErrorKind::NotFound,
-                "No filename specified",
-            ))
+            Err("Cannot make changes, 'modifiable' is off".to_string())
         }
     }
 
-    /// カーソル位置の文字を削除する
-    pub fn delete_char_at_cursor(&mut self, pos: Position) -> bool {
-        if let Some(line) = self.buffer.row(pos.row)
-            && pos.col < line.char_count()
-        {
-            // 削除文字列を取得できた場合は yank_buffer に入れる
-            if let Some(ch) = self.buffer.delete_char(pos) {
-                self.yank.yank_inline(ch.to_string());
-                self.yank.sync_to_clipboard();
+    /// `:set` の引数を 1 つ適用する
+    ///
+    /// `fileformat` / `fileencoding` / `bomb` / `modifiable` はバッファごとの設定なので Editor で扱い、
+    /// それ以外は EditorConfig に渡す。
+    /// 改行コードや文字コードを変更すると次の保存内容が変わるため、未保存の変更ありにする。
+    /// 変更できないバッファ (`nomodifiable`) ではこれらを変更できない。
+    pub fn set_option(&mut self, arg: &str) -> Result<Option<String>, String> {
+        let bom = match arg {
+            "bomb?" => {
+                let name = if self.buffer.bom() { "bomb" } else { "nobomb" };
+                return Ok(Some(name.to_string()));
             }
-            self.dirty = true;
-            return true;
+            "bomb" => Some(true),
+            "nobomb" => Some(false),
+            "invbomb" | "bomb!" => Some(!self.buffer.bom()),
+            _ => None,
+        };
+        let modifiable = match arg {
+            "modifiable?" | "ma?" => {
+                let name = if self.modifiable {
+                    "modifiable"
+                } else {
+                    "nomodifiable"
+                };
+                return Ok(Some(name.to_string()));
+            }
+            "modifiable" | "ma" => Some(true),
+            "nomodifiable" | "noma" => Some(false),
+            "invmodifiable" | "invma" | "modifiable!" | "ma!" => Some(!self.modifiable),
+            _ => None,
+        };
+        if let Some(modifiable) = modifiable {
+            self.modifiable = modifiable;
+            return Ok(None);
+        }
+        if let Some(bom) = bom {
+            self.check_modifiable()?;
+            if bom != self.buffer.bom() {
+                self.buffer.set_bom(bom);
+                self.dirty = true;
+            }
+            return Ok(None);
         }
-        false
-    }
 
-    /// 指定行を削除してヤンクバッファに保存 (dd 用
-    pub fn delete_line(&mut self, row: usize) -> bool {
-        if let Some(content) = self.buffer.delete_row_with_content(row) {
-            self.yank.yank_line(content);
-            self.yank.sync_to_clipboard();
-            self.dirty = true;
-            true
-        } else {
-            false
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (arg.trim_end_matches('?'), None),
+        };
+        let invalid = || format!("Invalid argument: {}", arg);
+        match (name, value) {
+            ("fileformat" | "ff", None) => Ok(Some(format!(
+                "fileformat={}",
+                self.buffer.file_format().name()
+            ))),
+            ("fileformat" | "ff", Some(value)) => {
+                self.check_modifiable()?;
+                let format = FileFormat::from_name(value).ok_or_else(invalid)?;
+                if format != self.buffer.file_format() {
+                    self.buffer.set_file_format(format);
+                    self.dirty = true;
+                }
+                Ok(None)
+            }
+            ("fileencoding" | "fenc", None) => Ok(Some(format!(
+                "fileencoding={}",
+                self.buffer.encoding().name()
+            ))),
+            ("fileencoding" | "fenc", Some(value)) => {
+                self.check_modifiable()?;
+                let encoding = FileEncoding::from_name(value).ok_or_else(invalid)?;
+                if encoding != self.buffer.encoding() {
+                    self.buffer.set_encoding(encoding);
+                    self.dirty = true;
+                }
+                Ok(None)
+            }
+            _ => {
+                let message = self.config.apply(arg)?;
+                // 辞書のファイルが変わった場合は読み込み直す
+                let reload = self.spell_dictionary.is_none()
+                    || matches!(name, "dictionary" | "dict" | "spellfile" | "spf");
+                if self.config.spell
+                    && reload
+                    && let Err(e) = self.load_spell_dictionary()
+                {
+                    self.config.spell = false;
+                    return Err(e);
+                }
+                Ok(message)
+            }
         }
     }
 
-    /// ヤンクバッファにコピーする (yy 用
-    pub fn yank_line(&mut self, row: usize) -> bool {
-        if let Some(content) = self.buffer.get_row_content(row) {
-            self.yank.yank_line(content);
-            self.yank.sync_to_clipboard();
-            true
-        } else {
-            false
+    /// `dictionary` と個人辞書 (`spellfile`) から単語を読み込む
+    ///
+    /// 個人辞書はまだ作られていないことがあるので、存在しなくてもエラーにしない。
+    fn load_spell_dictionary(&mut self) -> Result<(), String> {
+        let mut dictionary = Dictionary::new();
+        let path = Path::new(&self.config.dictionary);
+        dictionary
+            .load_file(path)
+            .map_err(|e| format!("Can't read dictionary \"{}\": {}", path.display(), e))?;
+        if let Some(path) = spell::personal_file(&self.config.spellfile) {
+            match dictionary.load_file(&path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                    return Err(format!(
+                        "Can't read spellfile \"{}\": {}",
+                        path.display(),
+                        e
+                    ));
+                }
+                _ => {}
+            }
         }
+        self.spell_dictionary = Some(dictionary);
+        Ok(())
     }
 
-    /// 複数行ヤンク (VisualLine mode 用)
-    pub fn yank_lines_range(&mut self, start_row: usize, end_row: usize) -> bool {
-        let min_row = start_row.min(end_row);
-        let max_row = start_row.max(end_row);
-        let lines: Vec<String> = (min_row..=max_row)
-            .filter_map(|r| self.buffer.get_row_content(r))
-            .collect();
-        if lines.is_empty() {
-            return false;
+    /// `:set spell` が有効な場合の辞書
+    pub fn spell_dictionary(&self) -> Option<&Dictionary> {
+        self.spell_dictionary.as_ref().filter(|_| self.config.spell)
+    }
+
+    /// 単語を正しい単語として個人辞書に追加する (`zg`)
+    ///
+    /// # Returns
+    ///
+    /// 表示用のメッセージ
+    pub fn spell_good(&mut self, word: &str) -> Result<String, String> {
+        let path = spell::personal_file(&self.config.spellfile)
+            .ok_or_else(|| "spellfile is not set".to_string())?;
+        spell::append_word(&path, word)
+            .map_err(|e| format!("Can't write \"{}\": {}", path.display(), e))?;
+        if let Some(dictionary) = &mut self.spell_dictionary {
+            dictionary.add(word);
         }
-        self.yank.yank_lines(lines);
-        self.yank.sync_to_clipboard();
-        true
+        Ok(format!("Word '{}' added to {}", word, path.display()))
     }
 
-    /// 複数行削除してヤンク (VisualLine mode 用)
-    pub fn delete_lines_range(&mut self, start_row: usize, end_row: usize) -> bool {
-        let min_row = start_row.min(end_row);
-        let max_row = start_row.max(end_row);
-        if !self.yank_lines_range(min_row, max_row) {
-            return false;
+    /// 単語を辞書と個人辞書から取り除き、誤った単語として扱う (`zw`)
+    pub fn spell_wrong(&mut self, word: &str) -> Result<String, String> {
+        let path = spell::personal_file(&self.config.spellfile)
+            .ok_or_else(|| "spellfile is not set".to_string())?;
+        spell::remove_word(&path, word)
+            .map_err(|e| format!("Can't write \"{}\": {}", path.display(), e))?;
+        if let Some(dictionary) = &mut self.spell_dictionary {
+            dictionary.remove(word);
         }
-        let count = max_row - min_row + 1;
-        for _ in 0..count {
-            self.buffer.delete_row(min_row);
+        Ok(format!("Word '{}' removed from {}", word, path.display()))
+    }
+
+    /// 行末より後ろの位置に入力するときに、その位置まで行を空白で埋める
+    fn pad_to_col(&mut self, pos: Position) {
+        if let Some(line) = self.buffer.row_mut(pos.row) {
+            let len = line.char_count();
+            if pos.col > len {
+                line.append(&" ".repeat(pos.col - len));
+            }
         }
+    }
+
+    /// 行の最初の空白以外の文字の列 (`dd` / `>>` / `:d` の後のカーソル位置)
+    ///
+    /// 空白だけの行では最後の文字の列を返す。
+    pub fn first_non_blank(&self, row: usize) -> usize {
+        motion::first_non_blank(&self.buffer, row)
+    }
+
+    /// 未保存の変更ありにする (バッファを直接変更した場合に使う)
+    pub fn mark_dirty(&mut self) {
         self.dirty = true;
-        true
     }
 
-    /// 範囲ヤンク(Visual mode 用)
-    pub fn yank_range(&mut self, start: Position, end: Position) -> bool {
-        let yank_lines = self.extract_range_text(start, end);
+    /// 文字を挿入
+    ///
+    /// 行末より後ろの位置 (`virtualedit`) の場合は、その位置まで空白で埋めてから挿入する。
+    pub fn insert_char(&mut self, pos: Position, ch: char) {
+        if !self.modifiable {
+            return;
+        }
+        self.pad_to_col(pos);
+        self.buffer.insert_char(pos, ch);
+        self.dirty = true;
+    }
 
-        if yank_lines.is_empty() {
-            return false;
+    /// 文字を削除
+    pub fn delete_char(&mut self, pos: Position) {
+        if !self.modifiable {
+            return;
         }
+        self.buffer.delete_char(pos);
+        self.dirty = true;
+    }
 
-        if yank_lines.len() == 1 {
-            // 単一行の場合は inline
-            self.yank.yank_inline(yank_lines[0].clone());
-        } else {
-            for line in yank_lines {
-                self.yank.yank_line(line);
-            }
+    /// 改行を挿入
+    pub fn insert_newline(&mut self, pos: Position) {
+        if !self.modifiable {
+            return;
         }
+        self.buffer.insert_newline(pos);
+        self.dirty = true;
+    }
 
-        self.yank.sync_to_clipboard();
-        true
+    /// Insert mode の Enter で改行する
+    ///
+    /// `autocomment` が有効でコメント行の場合は、新しい行にもインデントとコメント記号を入れる。
+    /// 継続したコメントに何も入力せずに Enter を押した場合は、改行せずにコメント記号を削除する。
+    /// カーソルは改行後の入力位置に移動する。
+    ///
+    /// # Returns
+    ///
+    /// 改行後のカーソル位置
+    pub fn insert_newline_with_comment(&mut self, pos: Position) -> Position {
+        let new_pos = self.newline_with_comment(pos);
+        self.cursor.set_position(new_pos);
+        new_pos
     }
 
-    /// 範囲削除(Visual mode 用)
-    pub fn delete_range(&mut self, start: Position, end: Position) -> bool {
-        if !self.yank_range(start, end) {
-            return false;
+    /// [`insert_newline_with_comment`](Self::insert_newline_with_comment) の本体
+    fn newline_with_comment(&mut self, pos: Position) -> Position {
+        if !self.modifiable {
+            return pos;
         }
+        let line = self.buffer.get_row_content(pos.row).unwrap_or_default();
+        let leader = self
+            .filetype
+            .filter(|_| self.config.autocomment)
+            .and_then(|filetype| filetype::comment_leader(filetype, &line));
+        let indent = leading_whitespace(&line).to_string();
+        let Some(leader) = leader.filter(|leader| {
+            // カーソルがコメント記号より前にある場合は継続しない
+            pos.col >= indent.chars().count() + leader.chars().count()
+        }) else {
+            self.insert_newline(pos);
+            return Position::new(pos.row + 1, 0);
+        };
 
-        let (norm_start, norm_end) = Self::normalize_range(start, end);
-
-        if norm_start.row == norm_end.row {
-            if let Some(row) = self.buffer.row_mut(norm_start.row) {
-                for _ in norm_start.col..=norm_end.col {
-                    // 削除されると次の削除対象文字がその index になるため
-                    // norm_start.col 固定で良い
-                    row.delete_char(norm_start.col);
+        let body = line[indent.len() + leader.len()..].trim();
+        if body.is_empty() && pos.col >= line.chars().count() {
+            self.buffer.set_row(pos.row, indent.clone());
+            self.dirty = true;
+            return Position::new(pos.row, indent.chars().count());
+        }
+
+        self.insert_newline(pos);
+        let prefix = format!("{}{} ", indent, leader);
+        if let Some(row) = self.buffer.row_mut(pos.row + 1) {
+            row.insert_str(0, &prefix);
+        }
+        Position::new(pos.row + 1, prefix.chars().count())
+    }
+
+    /// 前の行と結合
+    ///
+    /// カーソルは結合した位置 (Insert mode で続けて入力する位置) に移動する。
+    pub fn join_rows(&mut self, row: usize) {
+        if !self.modifiable || row == 0 {
+            return;
+        }
+        let col = self.current_line_len(row - 1);
+        self.buffer.join_rows(row);
+        self.dirty = true;
+        self.cursor.set_position(Position::new(row - 1, col));
+    }
+
+    /// テキストを挿入する (改行を含む場合は行を分割する)
+    ///
+    /// カーソルは挿入したテキストの直後 (Insert mode で続けて入力する位置) に移動する。
+    ///
+    /// # Returns
+    ///
+    /// 挿入したテキストの直後の位置
+    pub fn insert_text(&mut self, pos: Position, text: &str) -> Position {
+        let end = self.insert_str_at(pos.row, pos.col, text);
+        self.cursor.set_position(end);
+        end
+    }
+
+    /// `row` 行の `col` 文字目に文字列を挿入する (char 単位)
+    ///
+    /// `\n` の位置で行を分割し、`row` がバッファの行数と同じ場合は末尾に行を追加する。
+    /// `col` が行末より後ろの場合は、その位置まで空白で埋める。
+    /// 元に戻せるようにするには、呼び出す前に `history.commit` する。
+    ///
+    /// # Returns
+    ///
+    /// 挿入した文字列の直後の位置
+    pub fn insert_str_at(&mut self, row: usize, col: usize, text: &str) -> Position {
+        if !self.modifiable {
+            return Position::new(row, col);
+        }
+        let row = row.min(self.buffer.len());
+        if row == self.buffer.len() {
+            self.buffer.insert_row(row, String::new());
+        }
+        let mut lines = text.split('\n');
+        let first = lines.next().unwrap_or_default();
+        self.pad_to_col(Position::new(row, col));
+        let Some(line) = self.buffer.row_mut(row) else {
+            return Position::new(row, col);
+        };
+        let tail = line.split_off(col);
+        line.append(first);
+
+        let mut end = Position::new(row, col + first.chars().count());
+        for text in lines {
+            end = Position::new(end.row + 1, text.chars().count());
+            self.buffer.insert_row(end.row, text.to_string());
+        }
+        if let Some(line) = self.buffer.row_mut(end.row) {
+            line.append(&tail);
+        }
+        self.dirty = true;
+        end
+    }
+
+    /// Insert mode の終了時に、入力したテキストを `".` に保存する
+    pub fn finish_insert(&mut self) {
+        let text = std::mem::take(&mut self.inserted_text);
+        self.registers.set_last_insert(&text);
+    }
+
+    /// ファイルに保存
+    ///
+    /// `maxlinelength` が設定されている場合は、長い行を改行してから保存する
+    /// (変更できないバッファでは改行せずにそのまま保存する)。
+    pub fn save(&mut self) -> io::Result<()> {
+        if self.config.maxlinelength > 0 && self.modifiable {
+            self.buffer.hard_wrap(self.config.maxlinelength);
+            self.clamp_cursor();
+        }
+        write_buffer(self.filename.as_deref(), &self.buffer, &self.config)?;
+        self.dirty = false;
+        self.mtime = modified_time(self.filename.as_deref());
+        self.set_baseline(buffer_lines(&self.buffer));
+        Ok(())
+    }
+
+    /// 現在のファイル以外のファイルに書き込む (`:w {file}`)
+    ///
+    /// Vim と同じく、バッファに名前がない場合は書き込んだファイルをバッファの名前にする。
+    /// それ以外の場合は、バッファの名前と未保存の変更があるかどうかは変わらない。
+    pub fn write_to(&mut self, filename: &str) -> io::Result<()> {
+        if self.filename.is_some() {
+            return write_buffer(Some(filename), &self.buffer, &self.config);
+        }
+        self.filename = Some(filename.to_string());
+        self.apply_filetype();
+        self.save()
+    }
+
+    /// `filename` が現在のファイルを指しているか (`./file` などの別の書き方も同じファイルとみなす)
+    pub fn is_current_file(&self, filename: &str) -> bool {
+        let Some(current) = self.filename.as_deref() else {
+            return false;
+        };
+        match (
+            std::fs::canonicalize(current),
+            std::fs::canonicalize(filename),
+        ) {
+            (Ok(current), Ok(target)) => current == target,
+            _ => Path::new(current) == Path::new(filename),
+        }
+    }
+
+    /// 未保存の変更があるバッファの名前 (`:help` を開いている間の元のバッファを含む)
+    pub fn dirty_buffers(&self) -> Vec<String> {
+        let current = (self.dirty && !self.is_scratch()).then_some(&self.filename);
+        let previous = self
+            .previous
+            .as_ref()
+            .filter(|saved| saved.dirty)
+            .map(|saved| &saved.filename);
+        current
+            .into_iter()
+            .chain(previous)
+            .map(|filename| filename.as_deref().unwrap_or("[No Name]").to_string())
+            .collect()
+    }
+
+    /// 未保存の変更があるバッファをすべて保存する (`:wqa` 用)
+    ///
+    /// `force` が false の場合、読み込み専用のバッファは保存しない。
+    ///
+    /// # Returns
+    ///
+    /// 保存できなかったバッファごとのエラーメッセージ
+    pub fn save_all(&mut self, force: bool) -> Vec<String> {
+        let mut errors = Vec::new();
+        let mut targets = Vec::new();
+        if self.dirty && !self.is_scratch() {
+            targets.push((
+                &self.filename,
+                &mut self.buffer,
+                &mut self.dirty,
+                &mut self.mtime,
+                self.readonly,
+                self.modifiable,
+            ));
+        }
+        if let Some(saved) = self.previous.as_mut().filter(|saved| saved.dirty) {
+            targets.push((
+                &saved.filename,
+                &mut saved.buffer,
+                &mut saved.dirty,
+                &mut saved.mtime,
+                saved.readonly,
+                saved.modifiable,
+            ));
+        }
+        // スクラッチバッファを開いていなければ現在のバッファ、開いていれば元のバッファ
+        // のどちらか一方だけが対象になる
+        let mut saved = false;
+        for (filename, buffer, dirty, mtime, readonly, modifiable) in targets {
+            let name = filename.as_deref().unwrap_or("[No Name]");
+            if readonly && !force {
+                errors.push(format!("\"{}\": 'readonly' option is set", name));
+                continue;
+            }
+            // 変更できないバッファは `save` と同様に改行しない
+            if self.config.maxlinelength > 0 && modifiable {
+                buffer.hard_wrap(self.config.maxlinelength);
+            }
+            match write_buffer(filename.as_deref(), buffer, &self.config) {
+                Ok(()) => {
+                    *dirty = false;
+                    *mtime = modified_time(filename.as_deref());
+                    saved = true;
                 }
-                self.dirty = true;
-                return true;
+                Err(e) => errors.push(format!("\"{}\": {}", name, e)),
             }
+        }
+        if saved {
+            let buffer = self
+                .previous
+                .as_ref()
+                .map_or(&self.buffer, |saved| &saved.buffer);
+            self.set_baseline(buffer_lines(buffer));
+        }
+        errors
+    }
+
+    /// カーソル位置の文字を削除する
+    pub fn delete_char_at_cursor(&mut self, pos: Position) -> bool {
+        self.delete_chars(pos, 1, false) > 0
+    }
+
+    /// `pos` から最大 `count` 文字を削除する (`x` / `X` 用)
+    ///
+    /// `before` が true の場合 (`X`) は `pos` より前の文字を削除する。
+    /// 行をまたいでは削除しない。削除したテキストはまとめて無名レジスタと
+    /// `"-` に保存する (`dd` と違い `"1`〜`"9` には入れない)。
+    ///
+    /// # Returns
+    ///
+    /// 削除した文字数
+    pub fn delete_chars(&mut self, pos: Position, count: usize, before: bool) -> usize {
+        if !self.modifiable {
+            return 0;
+        }
+        let line_len = self.current_line_len(pos.row);
+        let (start, end) = if before {
+            (
+                pos.col.min(line_len).saturating_sub(count),
+                pos.col.min(line_len),
+            )
         } else {
-            if let Some(first_row) = self.buffer.row_mut(norm_start.row) {
-                let chars: Vec<char> = first_row.chars().chars().collect();
-                let remaining: String = chars.iter().take(norm_start.col).collect();
-                // 入れ替え
-                *first_row = crate::buffer::Row::new(remaining);
-            }
+            (pos.col, pos.col.saturating_add(count).min(line_len))
+        };
+        if start >= end {
+            return 0;
+        }
+        self.delete_selection(
+            Position::new(pos.row, start),
+            Position::new(pos.row, end - 1),
+        );
+        end - start
+    }
 
-            let tail = if let Some(last_row) = self.buffer.row(norm_end.row) {
-                let chars: Vec<char> = last_row.chars().chars().collect();
-                chars.iter().skip(norm_end.col + 1).collect()
+    /// 指定行を削除してヤンクバッファに保存 (dd 用
+    ///
+    /// カーソルは削除した位置の行の最初の非空白文字に移動する。
+    pub fn delete_line(&mut self, row: usize) -> bool {
+        if !self.modifiable {
+            return false;
+        }
+        if let Some(content) = self.buffer.delete_row_with_content(row) {
+            self.store_delete(vec![content], YankType::NewLine);
+            self.dirty = true;
+            self.place_cursor_on_first_non_blank(row);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// ヤンクバッファにコピーする (yy 用
+    pub fn yank_line(&mut self, row: usize) -> bool {
+        if let Some(content) = self.buffer.get_row_content(row) {
+            let last_col = content.chars().count().saturating_sub(1);
+            self.set_change_marks(Position::new(row, 0), Position::new(row, last_col));
+            self.store_yank(vec![content], YankType::NewLine);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// 複数行ヤンク (VisualLine mode 用)
+    pub fn yank_lines_range(&mut self, start_row: usize, end_row: usize) -> bool {
+        let lines = self.lines_in_range(start_row, end_row);
+        if lines.is_empty() {
+            return false;
+        }
+        let first = start_row.min(end_row);
+        let last_col = lines
+            .last()
+            .map_or(0, |line| line.chars().count().saturating_sub(1));
+        let last = Position::new(first + lines.len() - 1, last_col);
+        self.set_change_marks(Position::new(first, 0), last);
+        self.store_yank(lines, YankType::NewLine);
+        true
+    }
+
+    /// 複数行削除してヤンク (VisualLine mode 用)
+    ///
+    /// カーソルは削除した位置の行 (最後の行を削除した場合は新しい最後の行) の
+    /// 最初の非空白文字に移動する。
+    pub fn delete_lines_range(&mut self, start_row: usize, end_row: usize) -> bool {
+        if !self.modifiable {
+            return false;
+        }
+        let min_row = start_row.min(end_row);
+        let max_row = start_row.max(end_row);
+        let lines = self.lines_in_range(min_row, max_row);
+        if lines.is_empty() {
+            return false;
+        }
+        self.store_delete(lines, YankType::NewLine);
+        let count = max_row - min_row + 1;
+        for _ in 0..count {
+            self.buffer.delete_row(min_row);
+        }
+        self.dirty = true;
+        // 削除した場合は `[ / `] とも削除した位置になる
+        let pos = Position::new(min_row, 0);
+        self.set_change_marks(pos, pos);
+        self.place_cursor_on_first_non_blank(min_row);
+        true
+    }
+
+    /// カーソルを `row` 行 (バッファの範囲内に収める) の最初の非空白文字に移動する
+    fn place_cursor_on_first_non_blank(&mut self, row: usize) {
+        let row = row.min(self.buffer.len().saturating_sub(1));
+        self.place_cursor(Position::new(row, self.first_non_blank(row)));
+    }
+
+    /// カーソル位置から行末までの範囲 (Y / D / C 用)
+    fn line_end_span(&self, pos: Position) -> Option<(Position, Position)> {
+        let len = self.current_line_len(pos.row);
+        if pos.col >= len {
+            return None;
+        }
+        Some((pos, Position::new(pos.row, len - 1)))
+    }
+
+    /// カーソル位置から行末までヤンクする (y$ 用)
+    pub fn yank_to_line_end(&mut self, pos: Position) -> bool {
+        match self.line_end_span(pos) {
+            Some((start, end)) => self.yank_range(start, end),
+            None => false,
+        }
+    }
+
+    /// カーソル位置から行末まで削除する (D 用)
+    ///
+    /// カーソルは削除した後の行の最後の文字に移動する。
+    pub fn delete_to_line_end(&mut self, pos: Position) -> bool {
+        match self.line_end_span(pos) {
+            Some((start, end)) => self.delete_selection(start, end),
+            None => false,
+        }
+    }
+
+    /// カーソル位置から行末まで削除する (C 用)
+    ///
+    /// カーソルは Insert mode で続けて入力する位置 (削除した位置) に移動する。
+    pub fn change_to_line_end(&mut self, pos: Position) -> bool {
+        if !self.delete_to_line_end(pos) {
+            return false;
+        }
+        self.cursor.set_position(pos);
+        true
+    }
+
+    /// 範囲ヤンク(Visual mode 用)
+    pub fn yank_range(&mut self, start: Position, end: Position) -> bool {
+        self.yank_span(start, end, None)
+    }
+
+    /// 範囲をヤンクする
+    ///
+    /// `yank_type` が None の場合は、Visual mode と同じく複数行なら行単位にする。
+    fn yank_span(&mut self, start: Position, end: Position, yank_type: Option<YankType>) -> bool {
+        let Some((yank_lines, yank_type)) = self.range_text(start, end, yank_type) else {
+            return false;
+        };
+        let (first, last) = Self::normalize_range(start, end);
+        self.set_change_marks(first, last);
+        self.store_yank(yank_lines, yank_type);
+        true
+    }
+
+    /// 範囲削除(Visual mode 用)
+    ///
+    /// `end` の位置の文字も削除し、削除したテキストはレジスタに保存する。
+    /// カーソルは範囲の先頭 (行末を超える場合は行の最後の文字) に移動する。
+    pub fn delete_selection(&mut self, start: Position, end: Position) -> bool {
+        self.delete_span(start, end, None)
+    }
+
+    /// 範囲を削除してレジスタに保存する
+    ///
+    /// `yank_type` が None の場合は、Visual mode と同じく複数行なら行単位にする。
+    fn delete_span(&mut self, start: Position, end: Position, yank_type: Option<YankType>) -> bool {
+        if !self.modifiable {
+            return false;
+        }
+        let Some((yank_lines, yank_type)) = self.range_text(start, end, yank_type) else {
+            return false;
+        };
+        self.store_delete(yank_lines, yank_type);
+
+        let (start, end) = Self::normalize_range(start, end);
+        self.delete_range(start, Position::new(end.row, end.col + 1));
+        self.set_change_marks(start, start);
+        self.place_cursor(start);
+        true
+    }
+
+    /// オペレータをモーションの範囲に適用する (`dw` / `c$` / `yG` など)
+    ///
+    /// `j` / `G` などの行単位のモーションは行全体を対象にし、
+    /// それ以外は移動先の文字を含むか (inclusive / exclusive) に従って範囲を決める。
+    /// カーソルは範囲の先頭 (`dd` などの行単位の削除では最初の非空白文字) に移動する。
+    ///
+    /// # Returns
+    ///
+    /// モーションで移動できない場合は false
+    pub fn apply_operator(
+        &mut self,
+        op: Operator,
+        from: Position,
+        motion: Motion,
+        count: Option<usize>,
+    ) -> bool {
+        let Some(pos) = self.run_operator(op, from, motion, count) else {
+            return false;
+        };
+        self.place_operator_cursor(op, pos);
+        true
+    }
+
+    /// オペレータを `from` の行から `to_row` までの行全体に適用する (`dd` / `cc` / `yj` など)
+    pub fn apply_operator_to_lines(&mut self, op: Operator, from: Position, to_row: usize) {
+        let pos = self.run_operator_on_lines(op, from, to_row);
+        self.place_operator_cursor(op, pos);
+    }
+
+    /// オペレータを適用した後のカーソルを `pos` に置く
+    ///
+    /// `c` の後は Insert mode で入力するので、行の最後の文字の後ろにも置ける。
+    fn place_operator_cursor(&mut self, op: Operator, pos: Position) {
+        if op == Operator::Change {
+            self.cursor.set_position(pos);
+        } else {
+            self.place_cursor(pos);
+        }
+    }
+
+    /// [`apply_operator`](Self::apply_operator) の本体
+    ///
+    /// # Returns
+    ///
+    /// 適用後のカーソル位置。モーションで移動できない場合は None
+    fn run_operator(
+        &mut self,
+        op: Operator,
+        from: Position,
+        motion: Motion,
+        count: Option<usize>,
+    ) -> Option<Position> {
+        if op != Operator::Yank && !self.modifiable {
+            return Some(from);
+        }
+        // cw はカーソル位置の単語の後ろの空白を含めない (ce と同じ)
+        let change_word = op == Operator::Change
+            && motion == Motion::WordForward
+            && self
+                .buffer
+                .row(from.row)
+                .and_then(|line| line.chars().chars().nth(from.col))
+                .is_some_and(|c| !c.is_whitespace());
+        let (target, inclusive) = if change_word {
+            let count = count.unwrap_or(1).max(1);
+            (motion::current_word_end(&self.buffer, from, count), true)
+        } else {
+            (
+                motion.target(&self.buffer, from, count)?,
+                motion.is_inclusive(),
+            )
+        };
+
+        if motion.is_linewise() || op.is_linewise() {
+            return Some(self.run_operator_on_lines(op, from, target.row));
+        }
+
+        let (start, mut end) = Self::normalize_range(from, target);
+        if !inclusive {
+            // dw で行をまたいだ場合は、次の行ではなく前の行の行末までを対象にする
+            if motion == Motion::WordForward && end.row > start.row {
+                end = Position::new(end.row - 1, self.current_line_len(end.row - 1));
+            }
+            // 移動先の文字は含めない
+            if end.col > 0 {
+                end.col -= 1;
+            } else if end.row > start.row {
+                end = Position::new(end.row - 1, self.current_line_len(end.row - 1));
             } else {
-                String::new()
+                return Some(from);
+            }
+        }
+        if start > end || (start.row == end.row && start.col >= self.current_line_len(start.row)) {
+            return Some(from);
+        }
+
+        // 行をまたいでも文字単位のモーションは文字単位でレジスタに入れる (`d%` / `y}` など)
+        match op {
+            Operator::Yank => {
+                self.yank_span(start, end, Some(YankType::InLine));
+            }
+            Operator::Delete | Operator::Change => {
+                self.delete_span(start, end, Some(YankType::InLine));
+            }
+            Operator::ShiftRight | Operator::ShiftLeft => {
+                self.shift_lines(start.row, end.row, op == Operator::ShiftRight);
+            }
+        }
+        Some(start)
+    }
+
+    /// [`apply_operator_to_lines`](Self::apply_operator_to_lines) の本体
+    ///
+    /// # Returns
+    ///
+    /// 適用後のカーソル位置
+    fn run_operator_on_lines(&mut self, op: Operator, from: Position, to_row: usize) -> Position {
+        if op != Operator::Yank && !self.modifiable {
+            return from;
+        }
+        let start_row = from.row.min(to_row);
+        let end_row = from.row.max(to_row);
+        match op {
+            Operator::Yank => {
+                self.yank_lines_range(start_row, end_row);
+                Position::new(start_row, from.col)
+            }
+            Operator::Delete => {
+                self.delete_lines_range(start_row, end_row);
+                let row = start_row.min(self.buffer.len().saturating_sub(1));
+                Position::new(row, self.first_non_blank(row))
+            }
+            Operator::Change => {
+                // 行を削除して、空の 1 行を残す
+                let lines = self.lines_in_range(start_row, end_row);
+                if !lines.is_empty() {
+                    self.store_delete(lines, YankType::NewLine);
+                    for _ in start_row..end_row {
+                        self.buffer.delete_row(start_row + 1);
+                    }
+                    self.buffer.set_row(start_row, String::new());
+                    self.dirty = true;
+                }
+                Position::new(start_row, 0)
+            }
+            Operator::ShiftRight | Operator::ShiftLeft => {
+                self.shift_lines(start_row, end_row, op == Operator::ShiftRight);
+                Position::new(start_row, self.first_non_blank(start_row))
+            }
+        }
+    }
+
+    /// `start` から `end` の直前までのテキストを削除する (char 単位)
+    ///
+    /// 複数行にまたがる場合は開始行の前半と終了行の後半を結合する。
+    /// レジスタには保存しないので、必要な場合は呼び出し側で保存する。
+    /// 元に戻せるようにするには、呼び出す前に `history.commit` する。
+    ///
+    /// # Returns
+    ///
+    /// 削除したテキスト (行の区切りは `\n`)
+    pub fn delete_range(&mut self, start: Position, end: Position) -> String {
+        if !self.modifiable {
+            return String::new();
+        }
+        let (start, end) = Self::normalize_range(start, end);
+        let last_row = self.buffer.len().saturating_sub(1);
+        if start.row > last_row {
+            return String::new();
+        }
+        let end = if end.row > last_row {
+            Position::new(last_row, usize::MAX)
+        } else {
+            end
+        };
+        let start_col = start.col.min(self.current_line_len(start.row));
+        let end_col = end.col.min(self.current_line_len(end.row));
+
+        // 終了行の後半を残して、開始行より後ろの行を取り除く
+        let tail = self
+            .buffer
+            .row_mut(end.row)
+            .map(|line| line.split_off(end_col))
+            .unwrap_or_default();
+        let removed: Vec<String> = (start.row + 1..=end.row)
+            .filter_map(|_| self.buffer.delete_row_with_content(start.row + 1))
+            .collect();
+        let Some(line) = self.buffer.row_mut(start.row) else {
+            return String::new();
+        };
+        let mut deleted = line.split_off(start_col);
+        line.append(&tail);
+        for text in removed {
+            deleted.push('\n');
+            deleted.push_str(&text);
+        }
+        if !deleted.is_empty() {
+            self.dirty = true;
+        }
+        deleted
+    }
+
+    /// 行範囲のインデントを shiftwidth 分増やす / 減らす (`>` / `<` 用)
+    ///
+    /// 空行は `>` でインデントしない。カーソルは最初の行の最初の非空白文字に移動する。
+    pub fn shift_lines(&mut self, start_row: usize, end_row: usize, right: bool) {
+        if !self.modifiable {
+            return;
+        }
+        for row in start_row.min(end_row)..=start_row.max(end_row) {
+            if right && self.current_line_len(row) == 0 {
+                continue;
+            }
+            self.shift_row(row, right, false);
+        }
+        self.dirty = true;
+        self.place_cursor_on_first_non_blank(start_row.min(end_row));
+    }
+
+    /// 1 行のインデントを shiftwidth 分増やす / 減らす (Insert mode の `Ctrl-T` / `Ctrl-D` 用)
+    ///
+    /// `>` と異なり空行もインデントし、インデントの幅は shiftwidth の倍数に揃える。
+    /// カーソルは変更前と同じ文字に留まる。
+    ///
+    /// # Returns
+    ///
+    /// 変更前と同じ文字を指すように調整した `col`
+    pub fn shift_line(&mut self, row: usize, col: usize, right: bool) -> usize {
+        if !self.modifiable {
+            return col;
+        }
+        let Some(old) = self.shift_row(row, right, true) else {
+            return col;
+        };
+        self.dirty = true;
+        let new = self
+            .buffer
+            .row(row)
+            .map_or(0, |line| leading_whitespace(line.chars()).chars().count());
+        // インデントの途中にいた場合は新しいインデントの範囲に収める
+        let col = if col >= old {
+            col + new - old
+        } else {
+            col.min(new)
+        };
+        self.cursor.set_position(Position::new(row, col));
+        col
+    }
+
+    /// 行のインデントを変更して、変更前のインデントの文字数を返す
+    ///
+    /// `round` が true の場合は変更後の幅を shiftwidth の倍数に揃える。
+    fn shift_row(&mut self, row: usize, right: bool, round: bool) -> Option<usize> {
+        let line = self.buffer.get_row_content(row)?;
+        let indent = leading_whitespace(&line);
+        let body = &line[indent.len()..];
+        let width = indent_width(indent, self.config.tabstop);
+        let shiftwidth = self.config.shiftwidth;
+        let width = match (right, round) {
+            (true, false) => width + shiftwidth,
+            (false, false) => width.saturating_sub(shiftwidth),
+            (true, true) => (width / shiftwidth.max(1) + 1) * shiftwidth,
+            (false, true) => (width.saturating_sub(1) / shiftwidth.max(1)) * shiftwidth,
+        };
+        let old = indent.chars().count();
+        let indent = self.indent_string(width);
+        self.buffer.set_row(row, format!("{}{}", indent, body));
+        Some(old)
+    }
+
+    /// 指定した幅のインデント文字列を作る (expandtab でなければタブを使う)
+    fn indent_string(&self, width: usize) -> String {
+        indent_string(width, self.config.tabstop, self.config.expandtab)
+    }
+
+    /// 行範囲の共通のインデントを取り除き、`target_col` 列に揃える (`:left` / Visual mode の `g<` 用)
+    ///
+    /// カーソルは範囲の最初の行の最初の非空白文字に移動する。
+    pub fn reduce_common_indent(&mut self, range: LineRange, target_col: usize) -> bool {
+        if !self.modifiable {
+            return false;
+        }
+        let (tabstop, expandtab) = (self.config.tabstop, self.config.expandtab);
+        let changed = self
+            .buffer
+            .reduce_common_indent(range, target_col, tabstop, expandtab);
+        if changed {
+            self.dirty = true;
+        }
+        self.place_cursor_on_first_non_blank(range.start);
+        changed
+    }
+
+    /// 行範囲を並べ替える (`:sort` 用)
+    pub fn sort_lines(&mut self, range: LineRange, reverse: bool, ignore_case: bool) -> bool {
+        if !self.modifiable {
+            return false;
+        }
+        let changed = self.buffer.sort_lines(range, reverse, ignore_case);
+        if changed {
+            self.dirty = true;
+        }
+        changed
+    }
+
+    /// 行範囲の順番を逆にする (`:reverse` 用)
+    pub fn reverse_lines(&mut self, range: LineRange) -> bool {
+        if !self.modifiable {
+            return false;
+        }
+        let changed = self.buffer.reverse_rows(range);
+        if changed {
+            self.dirty = true;
+        }
+        changed
+    }
+
+    /// 行範囲の空白を `expandtab` に合わせてタブまたは空白に変換する (`:retab` 用)
+    ///
+    /// 変換後は `tabstop` を `new_tabstop` に変える。
+    pub fn retab(&mut self, range: LineRange, new_tabstop: usize, all: bool) -> bool {
+        if !self.modifiable {
+            return false;
+        }
+        let (tabstop, expandtab) = (self.config.tabstop, self.config.expandtab);
+        let changed = self
+            .buffer
+            .retab(range, tabstop, new_tabstop, expandtab, all);
+        self.config.tabstop = new_tabstop;
+        if changed {
+            self.dirty = true;
+        }
+        changed
+    }
+
+    /// 行範囲で同じ内容が続く行を 1 行にまとめる (`:uniq` 用)
+    ///
+    /// # Returns
+    ///
+    /// 削除した行数
+    pub fn uniq_lines(&mut self, range: LineRange, inverse: bool) -> usize {
+        if !self.modifiable {
+            return 0;
+        }
+        let removed = self.buffer.uniq_rows(range, inverse);
+        if removed > 0 {
+            self.dirty = true;
+        }
+        removed
+    }
+
+    /// 範囲内の各文字を変換する (Visual mode の `u` / `U` / `~` / `r` 用)
+    ///
+    /// `end` の列は行末を超えていてもよく、その場合は行末までを対象にする。
+    /// カーソルは範囲の先頭に移動する。
+    pub fn map_range_chars(&mut self, start: Position, end: Position, f: impl Fn(char) -> char) {
+        if !self.modifiable {
+            return;
+        }
+        let (start, end) = Self::normalize_range(start, end);
+        for row in start.row..=end.row {
+            let Some(line) = self.buffer.get_row_content(row) else {
+                continue;
+            };
+            let first = if row == start.row { start.col } else { 0 };
+            let last = if row == end.row { end.col } else { usize::MAX };
+            let mapped: String = line
+                .chars()
+                .enumerate()
+                .map(|(col, c)| {
+                    if (first..=last).contains(&col) {
+                        f(c)
+                    } else {
+                        c
+                    }
+                })
+                .collect();
+            self.buffer.set_row(row, mapped);
+        }
+        self.dirty = true;
+        self.place_cursor(start);
+    }
+
+    /// textwidth を超えた行を折り返す (Insert mode の自動折り返し)
+    ///
+    /// `col` の直前に文字を入力したあと、行の表示幅が textwidth を超えていれば、
+    /// textwidth に収まる最後の空白で改行し、続きを同じインデントの新しい行に移す。
+    /// 折り返した場合、カーソルは `col` の文字の移動先に移動する。
+    ///
+    /// # Returns
+    ///
+    /// 折り返した場合は `col` に対応する移動後のカーソル位置
+    pub fn auto_wrap(&mut self, row: usize, col: usize) -> Option<Position> {
+        let pos = self.wrap_line(row, col)?;
+        self.cursor.set_position(pos);
+        Some(pos)
+    }
+
+    /// [`auto_wrap`](Self::auto_wrap) の本体
+    fn wrap_line(&mut self, row: usize, col: usize) -> Option<Position> {
+        if !self.modifiable {
+            return None;
+        }
+        let textwidth = self.config.textwidth;
+        let tabstop = self.config.tabstop;
+        let line = self.buffer.row(row)?.chars();
+        if textwidth == 0 || display_width(line, tabstop) <= textwidth {
+            return None;
+        }
+
+        let chars: Vec<char> = line.chars().collect();
+        let indent = leading_whitespace(line).to_string();
+        let indent_len = indent.chars().count();
+        let is_blank = |c: char| c == ' ' || c == '\t';
+
+        // textwidth に収まる最後の空白。なければ最初の空白で折り返す
+        let mut break_at = None;
+        let mut vcol = 0;
+        for (i, c) in chars.iter().enumerate() {
+            if i >= indent_len && is_blank(*c) && (i == 0 || !is_blank(chars[i - 1])) {
+                if vcol <= textwidth || break_at.is_none() {
+                    break_at = Some(i);
+                }
+                if vcol > textwidth {
+                    break;
+                }
+            }
+            vcol += match c {
+                '\t' => tabstop - vcol % tabstop,
+                c => c.width().unwrap_or(0),
             };
+        }
+        let blank_start = break_at?;
+        let blank_end = (blank_start..chars.len())
+            .find(|i| !is_blank(chars[*i]))
+            .unwrap_or(chars.len());
+        if blank_end == chars.len() {
+            return None;
+        }
+
+        let head: String = chars[..blank_start].iter().collect();
+        let tail: String = chars[blank_end..].iter().collect();
+        self.buffer.set_row(row, head);
+        self.buffer
+            .insert_row(row + 1, format!("{}{}", indent, tail));
+        self.dirty = true;
+
+        Some(if col >= blank_end {
+            Position::new(row + 1, indent_len + col - blank_end)
+        } else {
+            Position::new(row, col.min(blank_start))
+        })
+    }
+
+    /// `row` 行の下に空行を追加し、カーソルを追加した行に移動する (`o` 用)
+    ///
+    /// 取り消せるように、呼び出し側で変更前にスナップショットを記録する。
+    pub fn open_line_below(&mut self, row: usize) {
+        if !self.modifiable {
+            return;
+        }
+        self.buffer.insert_row(row + 1, String::new());
+        self.dirty = true;
+        self.cursor.set_position(Position::new(row + 1, 0));
+    }
+
+    /// `row` 行の上に空行を追加し、カーソルを追加した行に移動する (`O` 用)
+    pub fn open_line_above(&mut self, row: usize) {
+        if !self.modifiable {
+            return;
+        }
+        self.buffer.insert_row(row, String::new());
+        self.dirty = true;
+        self.cursor.set_position(Position::new(row, 0));
+    }
 
-            // 中間行と最後の行を削除
-            for _ in norm_start.row + 1..=norm_end.row {
-                self.buffer.delete_row(norm_start.row + 1);
+    /// 行範囲を 1 行に結合する (`J` 用)
+    ///
+    /// 結合する行の先頭の空白は取り除き、間に空白を 1 つ入れる。
+    /// 範囲が 1 行の場合は次の行と結合する。カーソルは最後に結合した位置に移動する。
+    pub fn join_lines(&mut self, start_row: usize, end_row: usize) -> bool {
+        if !self.modifiable {
+            return false;
+        }
+        let first = start_row.min(end_row);
+        let last = start_row.max(end_row).max(first + 1);
+        if last >= self.buffer.len() {
+            return false;
+        }
+        let mut joined = self.buffer.get_row_content(first).unwrap_or_default();
+        let mut col = 0;
+        for _ in first..last {
+            let Some(next) = self.buffer.delete_row_with_content(first + 1) else {
+                break;
+            };
+            col = joined.chars().count();
+            let next = next.trim_start();
+            if !joined.is_empty() && !next.is_empty() && !joined.ends_with([' ', '\t']) {
+                joined.push(' ');
             }
+            joined.push_str(next);
+        }
+        self.buffer.set_row(first, joined);
+        self.dirty = true;
+        self.place_cursor(Position::new(first, col));
+        true
+    }
+
+    /// `row` 行に次の行をそのまま連結する (`gJ` 用)
+    ///
+    /// `J` と異なり、間に空白を入れず、次の行の先頭の空白も取り除かない。
+    /// カーソルは結合した位置に移動する。
+    pub fn join_with_next_raw(&mut self, row: usize) -> bool {
+        if !self.modifiable {
+            return false;
+        }
+        if row + 1 >= self.buffer.len() {
+            return false;
+        }
+        let col = self.current_line_len(row);
+        self.join_rows(row + 1);
+        self.place_cursor(Position::new(row, col));
+        true
+    }
+
+    /// 指定した行範囲の内容を取得する
+    fn lines_in_range(&self, start_row: usize, end_row: usize) -> Vec<String> {
+        self.buffer
+            .lines_in_range(start_row.min(end_row), start_row.max(end_row))
+    }
+
+    /// 範囲のテキストとヤンクの種類を取得する
+    ///
+    /// `yank_type` が None の場合は Visual mode の選択範囲として、単一行なら inline にする。
+    fn range_text(
+        &self,
+        start: Position,
+        end: Position,
+        yank_type: Option<YankType>,
+    ) -> Option<(Vec<String>, YankType)> {
+        let yank_lines = self.extract_range_text(start, end);
+        if yank_lines.is_empty() {
+            return None;
+        }
+
+        // 単一行の場合は inline
+        let yank_type = yank_type.unwrap_or(if yank_lines.len() == 1 {
+            YankType::InLine
+        } else {
+            YankType::NewLine
+        });
+        Some((yank_lines, yank_type))
+    }
+
+    /// ヤンクしたテキストをレジスタに保存する
+    fn store_yank(&mut self, content: Vec<String>, yank_type: YankType) {
+        self.store_register(content, yank_type, false);
+    }
+
+    /// 削除したテキストをレジスタに保存する
+    ///
+    /// レジスタの指定がない場合は削除履歴 (`"1`〜`"9` / `"-`) にも保存する。
+    fn store_delete(&mut self, content: Vec<String>, yank_type: YankType) {
+        self.store_register(content, yank_type, true);
+    }
+
+    /// テキストを無名レジスタと、選択中のレジスタに保存する
+    ///
+    /// ブラックホールレジスタ (`"_`) が選択されている場合はどこにも保存しない。
+    fn store_register(&mut self, content: Vec<String>, yank_type: YankType, deleted: bool) {
+        if let Some(name) = self.registers.take_selected() {
+            if name == BLACK_HOLE {
+                return;
+            }
+            self.registers.set(name, content, yank_type);
+            // 無名レジスタには追加後のレジスタの内容を入れる
+            if let Some(register) = self.registers.get(name) {
+                self.yank
+                    .set(register.content.clone(), register.yank_type.clone());
+            }
+        } else {
+            if deleted {
+                self.registers
+                    .push_delete(content.clone(), yank_type.clone());
+            }
+            self.yank.set(content, yank_type);
+        }
+        self.yank.sync_to_clipboard();
+    }
+
+    /// 行範囲に `:s` の置換を適用する
+    ///
+    /// `cursor` の行を置換した場合は、置換前と同じ文字を指すように `cursor` の列を更新する。
+    ///
+    /// # Returns
+    ///
+    /// 置換結果。一致する箇所がなかった場合は None
+    pub fn substitute(
+        &mut self,
+        range: LineRange,
+        sub: &Substitute,
+        cursor: &mut Position,
+    ) -> Result<Option<SubstituteResult>, String> {
+        self.check_modifiable()?;
+        let keep_case = self.config.preservecase;
+        let regex = sub.regex(keep_case, self.config.verymagic)?;
+
+        let mut result: Option<SubstituteResult> = None;
+        for row in range.start..=range.end.min(self.buffer.len().saturating_sub(1)) {
+            let Some(line) = self.buffer.row(row) else {
+                continue;
+            };
+            if let Some((replaced, count)) = sub.apply_line(&regex, line.chars(), keep_case) {
+                if row == cursor.row {
+                    cursor.col = sub.map_col(&regex, line.chars(), cursor.col, keep_case);
+                }
+                self.buffer.set_row(row, replaced);
+                let total = result.get_or_insert(SubstituteResult {
+                    count: 0,
+                    lines: 0,
+                    last_row: row,
+                });
+                total.count += count;
+                total.lines += 1;
+                total.last_row = row;
+            }
+        }
+
+        if result.is_some() {
+            self.dirty = true;
+        }
+        Ok(result)
+    }
+
+    /// `/` / `?` の検索パターンをオプション (`verymagic` / `wholeword`) に従ってコンパイルする
+    pub fn search_regex(&self, pattern: &str) -> Result<Regex, String> {
+        if self.config.wholeword {
+            pattern::compile_whole_word(pattern, self.config.verymagic)
+        } else {
+            pattern::compile(pattern, false, self.config.verymagic)
+        }
+    }
+
+    /// 直前の検索パターンの次の一致の範囲を返す (`gn` 用)
+    ///
+    /// `from` が一致の中にある場合はその一致を返す。範囲の終端は一致の最後の文字を指す。
+    ///
+    /// # Returns
+    ///
+    /// 一致の開始位置と終了位置。一致がない場合は None
+    pub fn next_match_span(&self, from: Position) -> Result<Option<(Position, Position)>, String> {
+        let Some((pattern, _)) = &self.last_search else {
+            return Err("No previous regular expression".to_string());
+        };
+        let regex = self.search_regex(pattern)?;
+
+        // 一致の開始位置 (char 単位) と終端 (char 単位、含まない) の組
+        let spans = |row: usize| -> Vec<(usize, usize)> {
+            let line = self.buffer.row(row).map(|r| r.chars()).unwrap_or("");
+            regex
+                .find_iter(line)
+                .map(|m| {
+                    let start = line[..m.start()].chars().count();
+                    (start, start + m.as_str().chars().count())
+                })
+                .collect()
+        };
+        let to_span = |row: usize, (start, end): (usize, usize)| {
+            (
+                Position::new(row, start),
+                Position::new(row, end.saturating_sub(1).max(start)),
+            )
+        };
 
-            // tail を最初の行に結合して文字詰め
-            if let Some(first_row) = self.buffer.row_mut(norm_start.row) {
-                first_row.append(&tail);
-            }
+        if let Some(span) = spans(from.row)
+            .into_iter()
+            .find(|(start, end)| *start <= from.col && from.col < *end)
+        {
+            return Ok(Some(to_span(from.row, span)));
+        }
+        let pos = match search::find(
+            &self.buffer,
+            &regex,
+            from,
+            SearchDirection::Forward,
+            self.config.wrapscan,
+        ) {
+            SearchResult::Found(pos) | SearchResult::Wrapped(pos) => pos,
+            SearchResult::NotFound | SearchResult::HitBoundary => return Ok(None),
+        };
+        Ok(spans(pos.row)
+            .into_iter()
+            .find(|(start, _)| *start == pos.col)
+            .map(|span| to_span(pos.row, span)))
+    }
 
-            self.dirty = true;
-            return true;
+    /// カーソル位置にあるファイルパスを、現在のファイルのディレクトリ基準で解決して返す (gf 用)
+    pub fn path_under_cursor(&self, pos: Position) -> Option<PathBuf> {
+        let line = self.buffer.row(pos.row)?;
+        let token = path_token_at(line.chars(), pos.col)?;
+        let path = Path::new(&token);
+        if path.is_absolute() {
+            return Some(path.to_path_buf());
         }
-        false
+        let base_dir = self
+            .filename
+            .as_deref()
+            .and_then(|f| Path::new(f).parent())
+            .filter(|dir| !dir.as_os_str().is_empty());
+        Some(match base_dir {
+            Some(dir) => dir.join(path),
+            None => path.to_path_buf(),
+        })
     }
 
     pub fn normalize_range(start: Position, end: Position) -> (Position, Position) {
@@ -346,7 +2052,7 @@ impl Editor {
         } else {
             // 複数行にまたがる選択
             for row_idx in norm_start.row..=norm_end.row {
-                if let Some(row) = self.buffer().row(norm_start.row) {
+                if let Some(row) = self.buffer().row(row_idx) {
                     let chars: Vec<char> = row.chars().chars().collect();
                     let text: String = if row_idx == norm_start.row {
                         // 最初の行: start.col から行末まで
@@ -365,38 +2071,241 @@ impl Editor {
         result
     }
 
-    pub fn paste(&mut self, pos: Position, direction: PasteDirection) -> PasteResult {
-        if self.yank.is_empty() {
+    /// レジスタの内容を `count` 回貼り付ける (`p` / `3p` など)
+    ///
+    /// 行単位の内容は続けて `count` 回分の行を、行内の内容は `count` 回繰り返した文字列を挿入する。
+    /// カーソルの移動先は [`place_cursor_after_paste`](Self::place_cursor_after_paste) を参照。
+    pub fn paste(&mut self, pos: Position, direction: PasteDirection, count: usize) -> PasteResult {
+        if !self.modifiable {
             return PasteResult::Empty;
         }
+        let Some((content, is_newline_yank)) = self.paste_source() else {
+            return PasteResult::Empty;
+        };
+        let count = count.max(1);
+        let content = if is_newline_yank {
+            content
+                .iter()
+                .cycle()
+                .take(content.len() * count)
+                .cloned()
+                .collect()
+        } else {
+            vec![content.join("\n").repeat(count)]
+        };
+        let result = self.insert_content(pos, direction, &content, is_newline_yank);
+        self.place_cursor_after_paste(&result);
+        result
+    }
 
-        if self.yank.is_newline_yank() {
-            match direction {
-                PasteDirection::Below => {
-                    for (i, line) in self.yank.content().iter().enumerate() {
-                        self.buffer.insert_row(pos.row + i + 1, line.clone());
-                    }
-                    self.dirty = true;
-                    PasteResult::Below
-                }
-                PasteDirection::Above => {
-                    for (i, line) in self.yank.content().iter().enumerate() {
-                        self.buffer.insert_row(pos.row + i, line.clone());
-                    }
-                    self.dirty = true;
-                    PasteResult::Above
+    /// レジスタの内容を `count` 回貼り付け、カーソルを貼り付けたテキストの直後に置く (`gp` / `gP` 用)
+    pub fn paste_and_move_past(
+        &mut self,
+        pos: Position,
+        direction: PasteDirection,
+        count: usize,
+    ) -> PasteResult {
+        let result = self.paste(pos, direction, count);
+        match result.position_after(self.buffer.len()) {
+            Some(pos) => {
+                // 行内の貼り付けでは行末を超えない (virtualedit の範囲内は除く)
+                let line_len = self.current_line_len(pos.row);
+                let limit = self.config.virtualedit.line_limit(line_len);
+                let col = pos.col.min(limit.saturating_sub(1));
+                self.cursor.set_position(Position::new(pos.row, col));
+            }
+            // 行単位でバッファの末尾に貼り付けた場合は最後の行の先頭
+            None if !matches!(result, PasteResult::Empty) => {
+                let last = Position::new(self.buffer.len() - 1, 0);
+                self.cursor.set_position(last);
+            }
+            None => {}
+        }
+        result
+    }
+
+    /// 貼り付けた範囲に合わせてカーソルを移動する (`p` / `P` / `]p` / `[p`)
+    ///
+    /// 行単位の場合は最初に貼り付けた行の最初の非空白文字、行内の場合は最後に貼り付けた文字に置く。
+    /// 行をまたぐ文字単位の内容の場合は、貼り付けた最初の文字に置く。
+    fn place_cursor_after_paste(&mut self, result: &PasteResult) {
+        let pos = match result {
+            PasteResult::Empty => return,
+            PasteResult::InLine(span) if span.start.row != span.end.row => span.start,
+            PasteResult::InLine(span) => span.end,
+            PasteResult::Above(span) | PasteResult::Below(span) => {
+                Position::new(span.start.row, self.first_non_blank(span.start.row))
+            }
+        };
+        self.place_cursor(pos);
+    }
+
+    /// レジスタの内容を行単位で貼り付ける (`:put` 用)
+    ///
+    /// 行内のヤンクも新しい行として挿入する。カーソルは貼り付けた最後の行の先頭に移動する。
+    ///
+    /// # Returns
+    ///
+    /// 挿入した行数。レジスタが空の場合は None
+    pub fn put_lines(&mut self, row: usize, direction: PasteDirection) -> Option<usize> {
+        if !self.modifiable {
+            return None;
+        }
+        let (content, _) = self.paste_source()?;
+        let result = self.insert_content(Position::new(row, 0), direction, &content, true);
+        if let PasteResult::Above(span) | PasteResult::Below(span) = result {
+            self.place_cursor(Position::new(span.end.row, 0));
+        }
+        Some(content.len())
+    }
+
+    /// 現在行のインデントに合わせて貼り付ける (`]p` / `[p` 用)
+    ///
+    /// 行単位の内容は共通のインデントを取り除いてから、現在行のインデントに揃える。
+    /// 行内の内容は通常の貼り付けと同じ。カーソルは `p` と同じ位置に移動する。
+    pub fn paste_adjusted(&mut self, pos: Position, direction: PasteDirection) -> PasteResult {
+        if !self.modifiable {
+            return PasteResult::Empty;
+        }
+        let Some((content, is_newline_yank)) = self.paste_source() else {
+            return PasteResult::Empty;
+        };
+        if !is_newline_yank {
+            let result = self.insert_content(pos, direction, &content, false);
+            self.place_cursor_after_paste(&result);
+            return result;
+        }
+
+        let tabstop = self.config.tabstop;
+        let current = self.buffer.get_row_content(pos.row).unwrap_or_default();
+        let target = indent_width(leading_whitespace(&current), tabstop);
+        let common = content
+            .iter()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| indent_width(leading_whitespace(line), tabstop))
+            .min()
+            .unwrap_or(0);
+        let adjusted: Vec<String> = content
+            .iter()
+            .map(|line| {
+                if line.trim().is_empty() {
+                    return String::new();
                 }
+                let indent = leading_whitespace(line);
+                let width = indent_width(indent, tabstop) - common + target;
+                format!("{}{}", self.indent_string(width), &line[indent.len()..])
+            })
+            .collect();
+        let result = self.insert_content(pos, direction, &adjusted, true);
+        self.place_cursor_after_paste(&result);
+        result
+    }
+
+    /// 選択範囲をレジスタの内容で置き換える (Visual mode の `p` 用)
+    ///
+    /// 置き換えられたテキストは無名レジスタに入るため、続けて `p` すると入れ替えられる。
+    /// 行内の選択を行単位の内容で置き換える場合は、選択範囲の位置で行を分割して間に行を挿入する。
+    /// カーソルは選択範囲の先頭 (行単位の選択では先頭の行の行頭) に移動する。
+    ///
+    /// # Returns
+    ///
+    /// 置き換えた場合は true。レジスタが空の場合は何もせずに false
+    pub fn replace_selection(&mut self, start: Position, end: Position, linewise: bool) -> bool {
+        if !self.modifiable {
+            return false;
+        }
+        // 選択範囲の削除で無名レジスタが上書きされる前に取得する
+        let Some((content, is_newline_yank)) = self.paste_source() else {
+            return false;
+        };
+        let (start, end) = Self::normalize_range(start, end);
+
+        if linewise {
+            // すべての行を削除すると空の行が 1 行残るので、挿入した後に取り除く
+            let whole_buffer = start.row == 0 && end.row + 1 >= self.buffer.len();
+            if !self.delete_lines_range(start.row, end.row) {
+                return false;
+            }
+            // 行単位の選択は行として挿入する
+            let pos = Position::new(start.row, 0);
+            self.insert_content(pos, PasteDirection::Above, &content, true);
+            if whole_buffer {
+                self.buffer.delete_row(content.len());
+            }
+            self.place_cursor(pos);
+        } else {
+            if !self.delete_selection(start, end) {
+                return false;
+            }
+            if is_newline_yank {
+                // 選択範囲の前後で行を分割し、その間に挿入する
+                self.buffer.insert_newline(start);
+                self.insert_content(start, PasteDirection::Below, &content, true);
+            } else {
+                self.insert_content(start, PasteDirection::Above, &content, false);
+            }
+            self.place_cursor(start);
+        }
+        true
+    }
+
+    /// 貼り付ける内容と、行単位かどうかを取得する
+    ///
+    /// `"x` でレジスタが選択されていればその内容、なければ無名レジスタの内容
+    fn paste_source(&mut self) -> Option<(Vec<String>, bool)> {
+        let (content, is_newline_yank) = match self.registers.take_selected() {
+            Some(name) => {
+                let register = self.registers.get(name)?;
+                (register.content.clone(), register.is_newline_yank())
+            }
+            None => (self.yank.content().to_vec(), self.yank.is_newline_yank()),
+        };
+        (!content.is_empty()).then_some((content, is_newline_yank))
+    }
+
+    /// 貼り付ける内容をバッファに挿入する
+    fn insert_content(
+        &mut self,
+        pos: Position,
+        direction: PasteDirection,
+        content: &[String],
+        is_newline_yank: bool,
+    ) -> PasteResult {
+        if is_newline_yank {
+            let first = match direction {
+                PasteDirection::Below => pos.row + 1,
+                PasteDirection::Above => pos.row,
+            };
+            for (i, line) in content.iter().enumerate() {
+                self.buffer.insert_row(first + i, line.clone());
+            }
+            self.dirty = true;
+            let last = first + content.len().saturating_sub(1);
+            let last_len = content.last().map_or(0, |line| line.chars().count());
+            let span = PasteSpan {
+                start: Position::new(first, 0),
+                end: Position::new(last, last_len.saturating_sub(1)),
+            };
+            self.set_change_marks(span.start, span.end);
+            match direction {
+                PasteDirection::Below => PasteResult::Below(span),
+                PasteDirection::Above => PasteResult::Above(span),
             }
         } else {
             let col = match direction {
                 PasteDirection::Below => pos.col + 1,
                 PasteDirection::Above => pos.col,
             };
-            if let Some(r) = self.buffer.row_mut(pos.row) {
+            if let Some(r) = self.buffer.row(pos.row) {
                 let safe_col = col.min(r.char_count());
-                r.insert_str(safe_col, &self.yank.content()[0]);
-                self.dirty = true;
-                PasteResult::InLine
+                // 複数行の内容は行を分割して挿入する
+                let end = self.insert_str_at(pos.row, safe_col, &content.join("\n"));
+                let span = PasteSpan {
+                    start: Position::new(pos.row, safe_col),
+                    end: Position::new(end.row, end.col.saturating_sub(1)),
+                };
+                self.set_change_marks(span.start, span.end);
+                PasteResult::InLine(span)
             } else {
                 PasteResult::Empty
             }
@@ -404,6 +2313,77 @@ impl Editor {
     }
 }
 
+/// 行の表示幅 (タブは tabstop に合わせて展開する)
+fn display_width(line: &str, tabstop: usize) -> usize {
+    line.chars().fold(0, |width, c| match c {
+        '\t' => (width / tabstop + 1) * tabstop,
+        c => width + c.width().unwrap_or(0),
+    })
+}
+
+/// 単語として扱う文字か
+fn is_word_char(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_'
+}
+
+/// 指定列にある単語を取り出す (カーソルが単語の上にない場合は None)
+pub fn word_at(line: &str, col: usize) -> Option<String> {
+    let range = word_range_at(line, col)?;
+    Some(line.chars().skip(range.start).take(range.len()).collect())
+}
+
+/// 指定列にある単語の範囲 (char 単位) を返す (カーソルが単語の上にない場合は None)
+pub fn word_range_at(line: &str, col: usize) -> Option<Range<usize>> {
+    let chars: Vec<char> = line.chars().collect();
+    if !chars.get(col).is_some_and(|c| is_word_char(*c)) {
+        return None;
+    }
+    let start = (0..col)
+        .rev()
+        .take_while(|&i| is_word_char(chars[i]))
+        .last()
+        .unwrap_or(col);
+    let end = (col..chars.len())
+        .take_while(|&i| is_word_char(chars[i]))
+        .last()
+        .unwrap_or(col);
+    Some(start..end + 1)
+}
+
+/// ファイルパスとして扱う文字か
+fn is_path_char(ch: char) -> bool {
+    ch.is_alphanumeric() || matches!(ch, '/' | '.' | '-' | '_' | '~' | '+')
+}
+
+/// 指定列にあるファイルパスらしき文字列を取り出す
+///
+/// カーソルがパスの上にない場合は Vim の gf と同様に、
+/// 同じ行でカーソルより後ろにある最初のパスを対象にする。
+pub fn path_token_at(line: &str, col: usize) -> Option<String> {
+    let chars: Vec<char> = line.chars().collect();
+    let start_search = col.min(chars.len());
+    let anchor = (start_search..chars.len()).find(|&i| is_path_char(chars[i]))?;
+
+    let start = (0..anchor)
+        .rev()
+        .take_while(|&i| is_path_char(chars[i]))
+        .last()
+        .unwrap_or(anchor);
+    let end = (anchor..chars.len())
+        .take_while(|&i| is_path_char(chars[i]))
+        .last()
+        .unwrap_or(anchor);
+
+    // 文末のピリオドなどはパスに含めない
+    let token: String = chars[start..=end].iter().collect();
+    let token = token.trim_end_matches('.');
+    if token.is_empty() {
+        None
+    } else {
+        Some(token.to_string())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -411,7 +2391,9 @@ mod tests {
     #[test]
     fn test_editor_new() {
         let editor = Editor::new();
-        assert!(editor.buffer().is_empty());
+        // 空のバッファも空の 1 行を持つ
+        assert_eq!(editor.buffer().len(), 1);
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "");
         assert!(!editor.is_dirty());
         assert_eq!(editor.filename(), None);
     }
@@ -429,7 +2411,7 @@ mod tests {
     #[test]
     fn test_editor_delete_line() {
         let mut editor = Editor::new();
-        editor.buffer_mut().insert_row(0, "line1".to_string());
+        editor.buffer_mut().set_row(0, "line1".to_string());
         editor.buffer_mut().insert_row(1, "line2".to_string());
 
         let success = editor.delete_line(0);
@@ -442,10 +2424,22 @@ mod tests {
         assert_eq!(editor.yank.content(), &["line1"]);
     }
 
+    #[test]
+    fn test_delete_only_line_keeps_empty_row() {
+        let mut editor = Editor::new();
+        editor.buffer_mut().set_row(0, "only".to_string());
+
+        assert!(editor.delete_line(0));
+        assert_eq!(editor.buffer().len(), 1);
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "");
+        assert!(editor.delete_lines_range(0, 0));
+        assert_eq!(editor.buffer().len(), 1);
+    }
+
     #[test]
     fn test_editor_yank_line() {
         let mut editor = Editor::new();
-        editor.buffer_mut().insert_row(0, "content".to_string());
+        editor.buffer_mut().set_row(0, "content".to_string());
 
         let success = editor.yank_line(0);
 
@@ -459,7 +2453,7 @@ mod tests {
     #[test]
     fn test_editor_delete_char_at_cursor() {
         let mut editor = Editor::new();
-        editor.buffer_mut().insert_row(0, "hello".to_string());
+        editor.buffer_mut().set_row(0, "hello".to_string());
 
         let success = editor.delete_char_at_cursor(Position::new(0, 0));
 
@@ -473,12 +2467,14 @@ mod tests {
     #[test]
     fn test_editor_paste_newline_below() {
         let mut editor = Editor::new();
-        editor.buffer_mut().insert_row(0, "line1".to_string());
+        editor.buffer_mut().set_row(0, "line1".to_string());
         editor.yank.yank_line("yanked".to_string());
 
-        let result = editor.paste(Position::new(0, 0), PasteDirection::Below);
+        let result = editor.paste(Position::new(0, 0), PasteDirection::Below, 1);
 
-        assert!(matches!(result, PasteResult::Below));
+        assert!(matches!(result, PasteResult::Below(_)));
+        // 行単位の gp は貼り付けた行の次の行 (ここでは存在しない)
+        assert_eq!(result.position_after(editor.buffer().len()), None);
         assert_eq!(editor.buffer().len(), 2);
         assert_eq!(editor.buffer().row(0).unwrap().chars(), "line1");
         assert_eq!(editor.buffer().row(1).unwrap().chars(), "yanked");
@@ -487,12 +2483,12 @@ mod tests {
     #[test]
     fn test_editor_paste_newline_above() {
         let mut editor = Editor::new();
-        editor.buffer_mut().insert_row(0, "line1".to_string());
+        editor.buffer_mut().set_row(0, "line1".to_string());
         editor.yank.yank_line("yanked".to_string());
 
-        let result = editor.paste(Position::new(0, 0), PasteDirection::Above);
+        let result = editor.paste(Position::new(0, 0), PasteDirection::Above, 1);
 
-        assert!(matches!(result, PasteResult::Above));
+        assert!(matches!(result, PasteResult::Above(_)));
         assert_eq!(editor.buffer().len(), 2);
         assert_eq!(editor.buffer().row(0).unwrap().chars(), "yanked");
         assert_eq!(editor.buffer().row(1).unwrap().chars(), "line1");
@@ -501,40 +2497,416 @@ mod tests {
     #[test]
     fn test_editor_paste_inline_below() {
         let mut editor = Editor::new();
-        editor.buffer_mut().insert_row(0, "helo".to_string());
+        editor.buffer_mut().set_row(0, "helo".to_string());
         editor.yank.yank_inline("l".to_string());
 
         // col=2 (e の後ろ) で Below なので col+1=3 に挿入
-        let result = editor.paste(Position::new(0, 2), PasteDirection::Below);
+        let result = editor.paste(Position::new(0, 2), PasteDirection::Below, 1);
 
-        assert!(matches!(result, PasteResult::InLine));
+        assert!(matches!(
+            result,
+            PasteResult::InLine(span)
+                if span.start == Position::new(0, 3) && span.end == Position::new(0, 3)
+        ));
         assert_eq!(editor.buffer().row(0).unwrap().chars(), "hello");
     }
 
     #[test]
     fn test_editor_paste_inline_above() {
         let mut editor = Editor::new();
-        editor.buffer_mut().insert_row(0, "helo".to_string());
+        editor.buffer_mut().set_row(0, "helo".to_string());
         editor.yank.yank_inline("l".to_string());
 
         // col=3 (o の位置) で Above なので col=3 に挿入
-        let result = editor.paste(Position::new(0, 3), PasteDirection::Above);
+        let result = editor.paste(Position::new(0, 3), PasteDirection::Above, 1);
 
-        assert!(matches!(result, PasteResult::InLine));
+        assert!(matches!(result, PasteResult::InLine(_)));
         assert_eq!(editor.buffer().row(0).unwrap().chars(), "hello");
     }
 
+    #[test]
+    fn test_editor_paste_span() {
+        let mut editor = Editor::new();
+        editor.buffer_mut().set_row(0, "line1".to_string());
+        editor.buffer_mut().insert_row(1, "line2".to_string());
+        editor
+            .yank
+            .yank_lines(vec!["  one".to_string(), "two".to_string()]);
+
+        // 行単位: 挿入した最初の行から最後の行まで
+        let result = editor.paste(Position::new(0, 3), PasteDirection::Below, 1);
+        let PasteResult::Below(span) = result else {
+            panic!("expected a line-wise paste");
+        };
+        assert_eq!(span.start, Position::new(1, 0));
+        assert_eq!(span.end, Position::new(2, 2));
+
+        // 行内: 挿入した最初の文字から最後の文字まで
+        editor.yank.yank_inline("xyz".to_string());
+        let result = editor.paste(Position::new(0, 1), PasteDirection::Above, 1);
+        let PasteResult::InLine(span) = result else {
+            panic!("expected an inline paste");
+        };
+        assert_eq!(span.start, Position::new(0, 1));
+        assert_eq!(span.end, Position::new(0, 3));
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "lxyzine1");
+    }
+
+    #[test]
+    fn test_cursor_follows_edits() {
+        let buffer = Buffer::from_lines(["first line", "two", "third line"]);
+        let mut editor = Editor::from_buffer(buffer, None);
+
+        // 移動先の行が短い場合は最後の文字に収める
+        editor.move_cursor(Position::new(2, 8), 22);
+        assert_eq!(editor.cursor.position(), Position::new(2, 8));
+        editor.move_cursor(Position::new(1, 8), 22);
+        assert_eq!(editor.cursor.position(), Position::new(1, 2));
+
+        // 最終行を削除した後は新しい最終行の最初の非空白文字に戻す
+        editor.move_cursor(Position::new(2, 8), 22);
+        editor.delete_lines_range(1, 2);
+        assert_eq!(editor.cursor.position(), Position::new(0, 0));
+
+        // 行単位の貼り付けは貼り付けた最初の行に移動する
+        editor.paste(Position::new(0, 0), PasteDirection::Below, 1);
+        assert_eq!(editor.cursor.position(), Position::new(1, 0));
+        editor.move_cursor(Position::new(99, 99), 22);
+        assert_eq!(editor.cursor.position(), Position::new(2, 9));
+        editor.delete_lines_range(0, 2);
+        assert_eq!(editor.buffer().len(), 1);
+        assert_eq!(editor.cursor.position(), Position::new(0, 0));
+    }
+
+    #[test]
+    fn test_open_line_moves_cursor() {
+        let buffer = Buffer::from_lines(["    first", "second"]);
+        let mut editor = Editor::from_buffer(buffer, None);
+
+        editor.open_line_below(0);
+        assert_eq!(editor.buffer().len(), 3);
+        assert_eq!(editor.buffer().row(1).unwrap().chars(), "");
+        assert_eq!(editor.cursor.position(), Position::new(1, 0));
+
+        editor.open_line_above(0);
+        assert_eq!(editor.buffer().len(), 4);
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "");
+        assert_eq!(editor.buffer().row(1).unwrap().chars(), "    first");
+        assert_eq!(editor.cursor.position(), Position::new(0, 0));
+    }
+
+    #[test]
+    fn test_paste_moves_cursor() {
+        let buffer = Buffer::from_lines(["abc", "def"]);
+        let mut editor = Editor::from_buffer(buffer, None);
+
+        // 行内: 貼り付けた最後の文字
+        editor.yank.yank_inline("xy".to_string());
+        editor.paste(Position::new(0, 0), PasteDirection::Below, 2);
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "axyxybc");
+        assert_eq!(editor.cursor.position(), Position::new(0, 4));
+
+        // gp: 貼り付けたテキストの直後
+        editor.paste_and_move_past(Position::new(1, 0), PasteDirection::Below, 1);
+        assert_eq!(editor.buffer().row(1).unwrap().chars(), "dxyef");
+        assert_eq!(editor.cursor.position(), Position::new(1, 3));
+
+        // 行単位の gP: 貼り付けた行の次の行
+        editor.yank.yank_line("new".to_string());
+        editor.paste_and_move_past(Position::new(0, 0), PasteDirection::Above, 1);
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "new");
+        assert_eq!(editor.cursor.position(), Position::new(1, 0));
+    }
+
+    #[test]
+    fn test_operator_moves_cursor() {
+        let buffer = Buffer::from_lines(["one two three", "  four", "five"]);
+        let mut editor = Editor::from_buffer(buffer, None);
+
+        // dw: 削除した範囲の先頭
+        editor.move_cursor(Position::new(0, 4), 22);
+        assert!(editor.apply_operator(
+            Operator::Delete,
+            Position::new(0, 4),
+            Motion::WordForward,
+            None
+        ));
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "one three");
+        assert_eq!(editor.cursor.position(), Position::new(0, 4));
+
+        // d$: 行末を超えないよう最後の文字に収める
+        assert!(editor.apply_operator(
+            Operator::Delete,
+            Position::new(0, 4),
+            Motion::LineEnd,
+            None
+        ));
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "one ");
+        assert_eq!(editor.cursor.position(), Position::new(0, 3));
+
+        // dd: 次の行の最初の非空白文字
+        editor.apply_operator_to_lines(Operator::Delete, Position::new(0, 3), 0);
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "  four");
+        assert_eq!(editor.cursor.position(), Position::new(0, 2));
+
+        // c$: Insert mode で入力する位置 (行末の次)
+        assert!(editor.apply_operator(
+            Operator::Change,
+            Position::new(1, 2),
+            Motion::LineEnd,
+            None
+        ));
+        assert_eq!(editor.buffer().row(1).unwrap().chars(), "fi");
+        assert_eq!(editor.cursor.position(), Position::new(1, 2));
+    }
+
+    #[test]
+    fn test_delete_and_join_move_cursor() {
+        let buffer = Buffer::from_lines(["abcdef", "  ghi", "jkl"]);
+        let mut editor = Editor::from_buffer(buffer, None);
+
+        // x を行末で繰り返すと最後の文字に収める
+        editor.delete_chars(Position::new(0, 4), 5, false);
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "abcd");
+        assert_eq!(editor.cursor.position(), Position::new(0, 3));
+
+        // C: 削除した位置 (Insert mode)
+        assert!(editor.change_to_line_end(Position::new(0, 2)));
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "ab");
+        assert_eq!(editor.cursor.position(), Position::new(0, 2));
+
+        // J: 最後に結合した位置
+        assert!(editor.join_lines(0, 2));
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "ab ghi jkl");
+        assert_eq!(editor.cursor.position(), Position::new(0, 6));
+
+        // Insert mode の行頭の Backspace: 前の行の末尾 (行末の次)
+        editor.buffer_mut().insert_row(1, "mn".to_string());
+        editor.join_rows(1);
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "ab ghi jklmn");
+        assert_eq!(editor.cursor.position(), Position::new(0, 10));
+    }
+
+    #[test]
+    fn test_indent_and_case_edits_move_cursor() {
+        let buffer = Buffer::from_lines(["abc def", "  ghi", "jkl"]);
+        let mut editor = Editor::from_buffer(buffer, None);
+        editor.config.shiftwidth = 2;
+        editor.config.expandtab = true;
+
+        // > : 最初の行の最初の非空白文字
+        editor.move_cursor(Position::new(0, 5), 22);
+        editor.shift_lines(1, 0, true);
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "  abc def");
+        assert_eq!(editor.cursor.position(), Position::new(0, 2));
+
+        // :left / g< : 範囲の最初の行の最初の非空白文字
+        editor.move_cursor(Position::new(2, 2), 22);
+        assert!(editor.reduce_common_indent(LineRange::new(0, 1), 1));
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), " abc def");
+        assert_eq!(editor.buffer().row(1).unwrap().chars(), "   ghi");
+        assert_eq!(editor.cursor.position(), Position::new(0, 1));
+
+        // Ctrl-T: 同じ文字に留まる
+        assert_eq!(editor.shift_line(2, 1, true), 3);
+        assert_eq!(editor.buffer().row(2).unwrap().chars(), "  jkl");
+        assert_eq!(editor.cursor.position(), Position::new(2, 3));
+
+        // U / ~ / r: 範囲の先頭
+        editor.map_range_chars(Position::new(1, 5), Position::new(1, 3), |c| {
+            c.to_ascii_uppercase()
+        });
+        assert_eq!(editor.buffer().row(1).unwrap().chars(), "   GHI");
+        assert_eq!(editor.cursor.position(), Position::new(1, 3));
+    }
+
+    #[test]
+    fn test_change_marks_after_paste() {
+        let mut editor = Editor::new();
+        editor.buffer_mut().set_row(0, "line1".to_string());
+        editor.buffer_mut().insert_row(1, "line2".to_string());
+        assert_eq!(editor.mark('['), Err("Mark not set".to_string()));
+
+        // 行単位: `[ は最初の行の行頭、`] は最後の行の最後の文字
+        editor
+            .yank
+            .yank_lines(vec!["  one".to_string(), "two".to_string()]);
+        editor.paste(Position::new(0, 3), PasteDirection::Below, 1);
+        assert_eq!(editor.mark('['), Ok(Position::new(1, 0)));
+        assert_eq!(editor.mark(']'), Ok(Position::new(2, 2)));
+
+        // 行内: 挿入した最初の文字から最後の文字まで
+        editor.yank.yank_inline("xyz".to_string());
+        editor.paste(Position::new(3, 1), PasteDirection::Below, 2);
+        assert_eq!(editor.buffer().row(3).unwrap().chars(), "lixyzxyzne2");
+        assert_eq!(editor.mark('['), Ok(Position::new(3, 2)));
+        assert_eq!(editor.mark(']'), Ok(Position::new(3, 7)));
+
+        // ヤンクでも更新し、削除後は削除した位置になる
+        editor.yank_range(Position::new(3, 4), Position::new(0, 1));
+        assert_eq!(editor.mark('['), Ok(Position::new(0, 1)));
+        assert_eq!(editor.mark(']'), Ok(Position::new(3, 4)));
+        editor.delete_lines_range(1, 2);
+        assert_eq!(editor.mark('['), Ok(Position::new(1, 0)));
+        assert_eq!(editor.mark(']'), Ok(Position::new(1, 0)));
+        assert!(editor.mark('a').is_err());
+    }
+
     #[test]
     fn test_editor_paste_empty() {
         let mut editor = Editor::new();
-        editor.buffer_mut().insert_row(0, "line".to_string());
+        editor.buffer_mut().set_row(0, "line".to_string());
 
-        let result = editor.paste(Position::new(0, 0), PasteDirection::Below);
+        let result = editor.paste(Position::new(0, 0), PasteDirection::Below, 1);
 
         assert!(matches!(result, PasteResult::Empty));
         assert_eq!(editor.buffer().len(), 1); // 変更なし
     }
 
+    #[test]
+    fn test_editor_paste_adjusted_to_deeper_indent() {
+        let buffer = Buffer::from_lines(["fn main() {", "    if x {", "    }", "}"]);
+        let mut editor = Editor::from_buffer(buffer, None);
+        editor.config.expandtab = true;
+        editor
+            .yank
+            .yank_lines(vec!["foo();".to_string(), "    bar();".to_string()]);
+
+        // "    if x {" の下に、現在行と同じインデントで貼り付ける
+        let result = editor.paste_adjusted(Position::new(1, 0), PasteDirection::Below);
+        assert!(matches!(result, PasteResult::Below(_)));
+        let rows: Vec<&str> = editor.buffer.iter().map(|r| r.chars()).collect();
+        assert_eq!(
+            rows,
+            vec![
+                "fn main() {",
+                "    if x {",
+                "    foo();",
+                "        bar();",
+                "    }",
+                "}"
+            ]
+        );
+
+        // [p は上に貼り付ける
+        editor.paste_adjusted(Position::new(0, 0), PasteDirection::Above);
+        assert_eq!(editor.buffer.row(0).unwrap().chars(), "foo();");
+        assert_eq!(editor.buffer.row(1).unwrap().chars(), "    bar();");
+    }
+
+    #[test]
+    fn test_replace_line_selection_with_line_register() {
+        let buffer = Buffer::from_lines(["a", "b", "c", "d"]);
+        let mut editor = Editor::from_buffer(buffer, None);
+        editor
+            .yank
+            .yank_lines(vec!["x".to_string(), "y".to_string()]);
+
+        // "b" と "c" を "x" "y" で置き換える
+        assert!(editor.replace_selection(Position::new(2, 0), Position::new(1, 0), true));
+        let rows: Vec<&str> = editor.buffer.iter().map(|r| r.chars()).collect();
+        assert_eq!(rows, vec!["a", "x", "y", "d"]);
+
+        // 置き換えられた内容は無名レジスタに入る
+        assert!(editor.yank.is_newline_yank());
+        assert_eq!(editor.yank.content(), &["b", "c"]);
+
+        // 最終行の置き換え
+        assert!(editor.replace_selection(Position::new(3, 0), Position::new(3, 0), true));
+        let rows: Vec<&str> = editor.buffer.iter().map(|r| r.chars()).collect();
+        assert_eq!(rows, vec!["a", "x", "y", "b", "c"]);
+        assert_eq!(editor.yank.content(), &["d"]);
+    }
+
+    #[test]
+    fn test_replace_whole_buffer_with_line_register() {
+        let buffer = Buffer::from_lines(["a", "b"]);
+        let mut editor = Editor::from_buffer(buffer, None);
+        editor.yank.yank_lines(vec!["x".to_string()]);
+
+        // すべての行を置き換えても空の行は残らない
+        assert!(editor.replace_selection(Position::new(1, 0), Position::new(0, 0), true));
+        let rows: Vec<&str> = editor.buffer.iter().map(|r| r.chars()).collect();
+        assert_eq!(rows, vec!["x"]);
+        assert_eq!(editor.yank.content(), &["a", "b"]);
+    }
+
+    #[test]
+    fn test_replace_inline_selection_with_line_register() {
+        let mut editor = Editor::new();
+        editor.buffer.set_row(0, "abcde".to_string());
+        editor
+            .yank
+            .yank_lines(vec!["x".to_string(), "y".to_string()]);
+
+        // 選択範囲の前後で行を分割して、間に行を挿入する
+        assert!(editor.replace_selection(Position::new(0, 2), Position::new(0, 2), false));
+        let rows: Vec<&str> = editor.buffer.iter().map(|r| r.chars()).collect();
+        assert_eq!(rows, vec!["ab", "x", "y", "de"]);
+        assert_eq!(editor.yank.content(), &["c"]);
+    }
+
+    #[test]
+    fn test_replace_selection_with_empty_register() {
+        let mut editor = Editor::new();
+        editor.buffer.set_row(0, "hello".to_string());
+
+        assert!(!editor.replace_selection(Position::new(0, 0), Position::new(0, 1), false));
+        assert_eq!(editor.buffer.row(0).unwrap().chars(), "hello");
+        assert!(!editor.is_dirty());
+    }
+
+    #[test]
+    fn test_replace_inline_selection() {
+        let mut editor = Editor::new();
+        editor.buffer.set_row(0, "hello world".to_string());
+        editor.yank.yank_inline("there".to_string());
+
+        assert!(editor.replace_selection(Position::new(0, 6), Position::new(0, 10), false));
+        assert_eq!(editor.buffer.row(0).unwrap().chars(), "hello there");
+        assert_eq!(editor.yank.content(), &["world"]);
+    }
+
+    #[test]
+    fn test_insert_str_at_with_newlines() {
+        let mut editor = Editor::new();
+        editor.buffer.set_row(0, "hello world".to_string());
+
+        let end = editor.insert_str_at(0, 6, "big\nnew\nbright ");
+        let rows: Vec<&str> = editor.buffer.iter().map(|r| r.chars()).collect();
+        assert_eq!(rows, vec!["hello big", "new", "bright world"]);
+        assert_eq!(end, Position::new(2, 7));
+        assert!(editor.is_dirty());
+
+        // 最終行の次の行を指定した場合は行を追加する
+        let end = editor.insert_str_at(3, 0, "あい");
+        assert_eq!(editor.buffer.row(3).unwrap().chars(), "あい");
+        assert_eq!(end, Position::new(3, 2));
+    }
+
+    #[test]
+    fn test_delete_range_multiple_rows() {
+        let mut editor = Editor::new();
+        for (i, line) in ["one", "two", "three", "four"].iter().enumerate() {
+            editor.buffer.insert_row(i, line.to_string());
+        }
+        editor.buffer.delete_row(4);
+        editor.history.commit(editor.snapshot());
+
+        let deleted = editor.delete_range(Position::new(2, 2), Position::new(0, 1));
+        assert_eq!(deleted, "ne\ntwo\nth");
+        let rows: Vec<&str> = editor.buffer.iter().map(|r| r.chars()).collect();
+        assert_eq!(rows, vec!["oree", "four"]);
+        // レジスタには保存しない
+        assert!(editor.yank.content().is_empty());
+
+        // undo で元に戻せる
+        let prev = editor.history.undo(editor.snapshot()).unwrap();
+        editor.restore_snapshot(prev);
+        assert_eq!(editor.buffer.len(), 4);
+        assert_eq!(editor.buffer.row(0).unwrap().chars(), "one");
+    }
+
     #[test]
     fn test_normalize_range() {
         let start = Position::new(1, 5);
@@ -549,4 +2921,367 @@ mod tests {
         assert_eq!(norm_start, start);
         assert_eq!(norm_end, end);
     }
+
+    #[test]
+    fn test_open_and_close_scratch() {
+        let buffer = Buffer::from_lines(["original"]);
+        let mut editor = Editor::from_buffer(buffer, Some("file.txt".to_string()));
+        editor.insert_char(Position::new(0, 0), 'x');
+        editor.cursor.move_right(80, 9);
+
+        let scratch = Buffer::from_lines(["help"]);
+        editor.open_scratch("[Help]", scratch);
+
+        assert!(editor.is_scratch());
+        assert!(editor.is_readonly());
+        assert!(!editor.is_dirty());
+        assert_eq!(editor.display_name(), Some("[Help]"));
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "help");
+        assert_eq!(editor.cursor.x(), 1);
+
+        assert!(editor.close_scratch());
+        assert!(!editor.is_scratch());
+        assert!(editor.is_dirty());
+        assert_eq!(editor.display_name(), Some("file.txt"));
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "xoriginal");
+        assert_eq!(editor.cursor.x(), 2);
+
+        // 2 回目は何もしない
+        assert!(!editor.close_scratch());
+    }
+
+    #[test]
+    fn test_insert_newline_continues_comment() {
+        let buffer = Buffer::from_lines(["    // first"]);
+        let mut editor = Editor::from_buffer(buffer, Some("main.rs".to_string()));
+        editor.config.autocomment = true;
+
+        let pos = editor.insert_newline_with_comment(Position::new(0, 12));
+        assert_eq!(pos, Position::new(1, 7));
+        assert_eq!(editor.buffer().row(1).unwrap().chars(), "    // ");
+
+        // 何も入力せずに Enter を押すとコメント記号を削除する
+        let pos = editor.insert_newline_with_comment(pos);
+        assert_eq!(pos, Position::new(1, 4));
+        assert_eq!(editor.buffer().len(), 2);
+        assert_eq!(editor.buffer().row(1).unwrap().chars(), "    ");
+
+        // 無効な場合は通常の改行
+        editor.config.autocomment = false;
+        let pos = editor.insert_newline_with_comment(Position::new(0, 12));
+        assert_eq!(pos, Position::new(1, 0));
+        assert_eq!(editor.buffer().row(1).unwrap().chars(), "");
+    }
+
+    #[test]
+    fn test_word_at() {
+        assert_eq!(word_at("let foo_bar = 1;", 5).as_deref(), Some("foo_bar"));
+        assert_eq!(word_at("let foo_bar = 1;", 4).as_deref(), Some("foo_bar"));
+        assert_eq!(word_at("let foo_bar = 1;", 0).as_deref(), Some("let"));
+        assert_eq!(word_at("let foo_bar = 1;", 3), None);
+        assert_eq!(word_at("", 0), None);
+    }
+
+    #[test]
+    fn test_path_token_at_cursor_positions() {
+        let line = "see src/editor.rs for details.";
+        // パスの先頭・途中・末尾
+        assert_eq!(path_token_at(line, 4), Some("src/editor.rs".to_string()));
+        assert_eq!(path_token_at(line, 8), Some("src/editor.rs".to_string()));
+        assert_eq!(path_token_at(line, 16), Some("src/editor.rs".to_string()));
+        // 空白の上にいる場合は後ろにある最初のトークン
+        assert_eq!(path_token_at(line, 3), Some("src/editor.rs".to_string()));
+        // 文末のピリオドは含めない
+        assert_eq!(path_token_at(line, 25), Some("details".to_string()));
+    }
+
+    #[test]
+    fn test_path_token_at_symbols() {
+        let line = "include(\"../lib/my-file_v2.txt\")";
+        assert_eq!(
+            path_token_at(line, 12),
+            Some("../lib/my-file_v2.txt".to_string())
+        );
+        // 後ろにパスがない場合は None
+        assert_eq!(path_token_at("foo   ", 4), None);
+        assert_eq!(path_token_at("", 0), None);
+    }
+
+    #[test]
+    fn test_path_under_cursor_relative_to_file() {
+        let buffer = Buffer::from_lines(["other.txt"]);
+        let editor = Editor::from_buffer(buffer, Some("docs/readme.md".to_string()));
+
+        assert_eq!(
+            editor.path_under_cursor(Position::new(0, 0)),
+            Some(PathBuf::from("docs/other.txt"))
+        );
+
+        let buffer = Buffer::from_lines(["/etc/hosts"]);
+        let editor = Editor::from_buffer(buffer, Some("readme.md".to_string()));
+        assert_eq!(
+            editor.path_under_cursor(Position::new(0, 3)),
+            Some(PathBuf::from("/etc/hosts"))
+        );
+    }
+
+    #[test]
+    fn test_file_change_action() {
+        use FileChangeAction::{Reload, Warn};
+        // (changed, dirty, autoread)
+        assert_eq!(FileChangeAction::decide(false, false, true), None);
+        assert_eq!(FileChangeAction::decide(false, true, false), None);
+        assert_eq!(FileChangeAction::decide(true, false, true), Some(Reload));
+        assert_eq!(FileChangeAction::decide(true, true, true), Some(Warn));
+        assert_eq!(FileChangeAction::decide(true, false, false), Some(Warn));
+        assert_eq!(FileChangeAction::decide(true, true, false), Some(Warn));
+    }
+
+    #[test]
+    fn test_check_file_changed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("watched.txt");
+        std::fs::write(&path, "old\n").unwrap();
+        let mut editor = Editor::new();
+        editor
+            .open_file(path.to_string_lossy().into_owned())
+            .unwrap();
+        editor.config.autoread = true;
+        assert_eq!(editor.check_file_changed(), None);
+
+        // 更新日時を変えて外部での変更にする
+        let touch = |content: &str, secs: u64| {
+            std::fs::write(&path, content).unwrap();
+            let time = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(secs);
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(time)
+                .unwrap();
+        };
+        touch("new\n", 1_000_000);
+        let msg = editor.check_file_changed().unwrap();
+        assert!(msg.ends_with("file changed, reloaded"));
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "new");
+        // 同じ変更では繰り返し読み込まない
+        assert_eq!(editor.check_file_changed(), None);
+
+        // 未保存の変更がある場合は警告だけ
+        editor.buffer_mut().set_row(0, "mine".to_string());
+        editor.mark_dirty();
+        touch("theirs\n", 2_000_000);
+        let msg = editor.check_file_changed().unwrap();
+        assert!(msg.starts_with("Warning: File"));
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "mine");
+        assert_eq!(editor.check_file_changed(), None);
+
+        // 自分で保存した場合は変更として扱わない
+        editor.save().unwrap();
+        assert_eq!(editor.check_file_changed(), None);
+    }
+
+    #[test]
+    fn test_set_fileformat_dos_saves_crlf() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("unix.txt");
+        std::fs::write(&path, "one\ntwo\n").unwrap();
+
+        let mut editor = Editor::new();
+        editor
+            .open_file(path.to_string_lossy().into_owned())
+            .unwrap();
+        assert_eq!(
+            editor.set_option("ff?"),
+            Ok(Some("fileformat=unix".to_string()))
+        );
+
+        editor.set_option("fileformat=dos").unwrap();
+        assert!(editor.is_dirty());
+        editor.save().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"one\r\ntwo\r\n");
+
+        editor.set_option("ff=mac").unwrap();
+        editor.save().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"one\rtwo\r");
+
+        assert!(editor.set_option("ff=amiga").is_err());
+    }
+
+    #[test]
+    fn test_set_bomb_saves_bom() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bom.txt");
+        std::fs::write(&path, b"\xef\xbb\xbfone\n").unwrap();
+
+        let mut editor = Editor::new();
+        editor
+            .open_file(path.to_string_lossy().into_owned())
+            .unwrap();
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "one");
+        assert_eq!(editor.set_option("bomb?"), Ok(Some("bomb".to_string())));
+
+        // BOM はそのまま保存される
+        editor.save().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"\xef\xbb\xbfone\n");
+
+        editor.set_option("nobomb").unwrap();
+        assert!(editor.is_dirty());
+        editor.save().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"one\n");
+
+        editor.set_option("bomb!").unwrap();
+        assert_eq!(editor.set_option("bomb?"), Ok(Some("bomb".to_string())));
+    }
+
+    #[test]
+    fn test_set_fileencoding_latin1_saves_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("utf8.txt");
+        std::fs::write(&path, "caf\u{e9}\n").unwrap();
+
+        let mut editor = Editor::new();
+        editor
+            .open_file(path.to_string_lossy().into_owned())
+            .unwrap();
+        assert_eq!(
+            editor.set_option("fenc?"),
+            Ok(Some("fileencoding=utf-8".to_string()))
+        );
+
+        editor.set_option("fileencoding=latin1").unwrap();
+        assert!(editor.is_dirty());
+        editor.save().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"caf\xe9\n");
+
+        assert!(editor.set_option("fenc=ebcdic").is_err());
+    }
+
+    #[test]
+    fn test_auto_wrap() {
+        let mut editor = Editor::new();
+        editor.config.textwidth = 20;
+        editor
+            .buffer_mut()
+            .set_row(0, "  the quick brown fox".to_string());
+
+        // 21 桁目に入力した直後: 最後の空白で折り返してインデントを合わせる
+        let pos = editor.auto_wrap(0, 21);
+        assert_eq!(pos, Some(Position::new(1, 5)));
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "  the quick brown");
+        assert_eq!(editor.buffer().row(1).unwrap().chars(), "  fox");
+        assert!(editor.is_dirty());
+
+        // 収まっている場合や textwidth=0 では折り返さない
+        assert_eq!(editor.auto_wrap(1, 5), None);
+        editor.config.textwidth = 0;
+        editor.buffer_mut().insert_row(2, "a".repeat(30) + " b");
+        assert_eq!(editor.auto_wrap(2, 32), None);
+    }
+
+    #[test]
+    fn test_next_match_span() {
+        let mut editor = Editor::new();
+        editor.buffer_mut().set_row(0, "foo bar foo".to_string());
+        editor.buffer_mut().insert_row(1, "x foo".to_string());
+        assert!(editor.next_match_span(Position::new(0, 0)).is_err());
+
+        editor.last_search = Some(("foo".to_string(), SearchDirection::Forward));
+        // カーソルが一致の中にある場合はその一致
+        assert_eq!(
+            editor.next_match_span(Position::new(0, 1)),
+            Ok(Some((Position::new(0, 0), Position::new(0, 2))))
+        );
+        assert_eq!(
+            editor.next_match_span(Position::new(0, 3)),
+            Ok(Some((Position::new(0, 8), Position::new(0, 10))))
+        );
+        assert_eq!(
+            editor.next_match_span(Position::new(1, 0)),
+            Ok(Some((Position::new(1, 2), Position::new(1, 4))))
+        );
+
+        editor.last_search = Some(("qux".to_string(), SearchDirection::Forward));
+        assert_eq!(editor.next_match_span(Position::new(0, 0)), Ok(None));
+    }
+
+    #[test]
+    fn test_filetype_overrides() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, "").unwrap();
+            path.to_string_lossy().into_owned()
+        };
+
+        let mut editor = Editor::from_buffer(Buffer::new(), Some(path("main.rs")));
+        assert_eq!(editor.filetype(), Some("rust"));
+        assert!(editor.config.expandtab);
+        assert_eq!(editor.config.tabstop, 4);
+
+        editor.open_file(path("Makefile")).unwrap();
+        assert_eq!(editor.filetype(), Some("make"));
+        assert!(!editor.config.expandtab);
+        assert_eq!(editor.config.tabstop, 8);
+
+        editor.open_file(path("README.md")).unwrap();
+        assert_eq!(editor.filetype(), Some("markdown"));
+        assert!(editor.config.wrap);
+
+        // 判定できないファイルでは基本の設定に戻る
+        editor.open_file(path("notes.txt")).unwrap();
+        assert_eq!(editor.filetype(), None);
+        assert_eq!(editor.config, EditorConfig::new());
+    }
+
+    #[test]
+    fn test_filetype_overrides_keep_user_options() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, "").unwrap();
+            path.to_string_lossy().into_owned()
+        };
+
+        let mut editor = Editor::from_buffer(Buffer::new(), Some(path("main.rs")));
+        editor.set_option("tabstop=2").unwrap();
+        editor.set_option("number").unwrap();
+
+        // :set で変更したオプションは次のファイルを開いても残る
+        editor.open_file(path("notes.txt")).unwrap();
+        assert_eq!(editor.config.tabstop, 2);
+        assert!(editor.config.number);
+        // 変更していないオプションは元に戻る
+        assert!(!editor.config.expandtab);
+        assert_eq!(editor.config.shiftwidth, 8);
+
+        editor.open_file(path("main.rs")).unwrap();
+        assert_eq!(editor.config.tabstop, 4);
+        editor.open_file(path("Makefile")).unwrap();
+        assert_eq!(editor.config.tabstop, 8);
+    }
+
+    #[test]
+    fn test_filetype_options_from_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("main.rs");
+        std::fs::write(&path, "").unwrap();
+
+        let mut editor = Editor::new();
+        editor
+            .config
+            .set_filetype_options("rust", vec!["tabstop=2".to_string()])
+            .unwrap();
+        assert!(
+            editor
+                .config
+                .set_filetype_options("rust", vec!["foo".to_string()])
+                .is_err()
+        );
+        editor
+            .open_file(path.to_string_lossy().into_owned())
+            .unwrap();
+        assert_eq!(editor.config.tabstop, 2);
+        assert!(!editor.config.expandtab);
+    }
 }
@@ -0,0 +1,1495 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::config::split_set_args;
+use crate::cursor::{Cursor, Position};
+use crate::editor::{Editor, PasteDirection};
+use crate::expr;
+use crate::handler::{self, HandlerResult, confirm};
+use crate::help::help_buffer;
+use crate::prompt::Prompt;
+use crate::range::LineRange;
+use crate::substitute::{ConfirmSubstitute, Substitute, SubstituteFlags};
+
+/// Ex コマンドの実行時に渡す情報
+pub struct ExContext<'a> {
+    pub editor: &'a mut Editor,
+    pub editor_rows: u16,
+    /// 指定された範囲 (`:1,3d` など)
+    pub range: Option<LineRange>,
+    /// `!` 付きで実行されたか
+    pub bang: bool,
+    /// コマンド名より後ろの引数
+    pub args: &'a str,
+    /// `:confirm` 付きで実行されたか
+    pub confirm: bool,
+}
+
+impl ExContext<'_> {
+    /// 範囲の指定がない場合はカーソル行を対象にする
+    fn range_or_current_line(&self) -> LineRange {
+        self.range
+            .unwrap_or(LineRange::single(self.editor.cursor.file_row()))
+    }
+}
+
+/// Ex コマンドの定義
+#[derive(Clone)]
+pub struct ExCommand {
+    pub name: &'static str,
+    /// 省略できる最短の形 (Vim の `:w[rite]` 表記に対応)
+    pub abbrev: &'static str,
+    /// 範囲の指定を受け付けるか
+    pub range: bool,
+    /// `!` を受け付けるか
+    pub bang: bool,
+    pub run: fn(&mut ExContext) -> HandlerResult,
+}
+
+const fn command(
+    name: &'static str,
+    abbrev: &'static str,
+    range: bool,
+    bang: bool,
+    run: fn(&mut ExContext) -> HandlerResult,
+) -> ExCommand {
+    ExCommand {
+        name,
+        abbrev,
+        range,
+        bang,
+        run,
+    }
+}
+
+/// 実装済みの Ex コマンドの一覧
+pub const COMMANDS: &[ExCommand] = &[
+    command("write", "w", false, true, write),
+    command("wq", "wq", false, true, write_quit),
+    command("quit", "q", false, true, quit),
+    command("qall", "qa", false, true, quit_all),
+    command("wqall", "wqa", false, true, write_quit_all),
+    command("edit", "e", false, true, edit),
+    command("help", "h", false, false, help),
+    command("set", "se", false, false, set),
+    command("autocmd", "au", false, false, autocmd),
+    command("source", "so", false, false, source),
+    command("substitute", "s", true, false, substitute),
+    command("delete", "d", true, false, delete),
+    command("yank", "y", true, false, yank),
+    command("put", "pu", true, true, put),
+    command("redraw", "redr", false, true, redraw),
+    command("sleep", "sl", false, false, sleep),
+    command("mkzimrc", "mkz", false, true, mkzimrc),
+    command("confirm", "conf", false, false, confirm_command),
+    command("echo", "ec", false, false, echo),
+    command("left", "le", true, false, left),
+    command("sort", "sor", true, true, sort),
+    command("reverse", "rev", true, false, reverse),
+    command("uniq", "uni", true, true, uniq),
+    command("retab", "ret", true, true, retab),
+    command("diff", "dif", false, false, diff),
+    command("diffoff", "diffo", false, false, diff_off),
+];
+
+/// `:mkzimrc` でファイル名を省略したときに書き出すファイル
+const DEFAULT_ZIMRC: &str = ".zimrc";
+
+/// 起動時に読み込む設定ファイル (ホームディレクトリからの相対パス)
+const USER_ZIMRC: &str = ".zimrc";
+
+/// 起動時に読み込む設定ファイルのパス (`~/.zimrc`)
+pub fn user_zimrc() -> Option<PathBuf> {
+    let home = std::env::var_os("HOME")?;
+    Some(Path::new(&home).join(USER_ZIMRC))
+}
+
+/// 設定ファイルの各行を Ex コマンドとして実行する (`:source` と起動時の読み込み用)
+///
+/// 空行と `"` で始まるコメント行は読み飛ばす。エラーがあっても残りの行は実行し、
+/// 最初のエラーを返す。読み込み後に現在のファイルのファイルタイプの設定を適用し直す。
+pub fn source_file(editor: &mut Editor, path: &Path, editor_rows: u16) -> Result<(), String> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Can't open file {}: {}", path.display(), e))?;
+    // ファイルタイプの設定を外した状態で読み込み、基本の設定として扱う
+    editor.restore_filetype();
+    let mut error = None;
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('"') {
+            continue;
+        }
+        // :set などのエラーは StatusMessage で返るため、表示するメッセージはエラーとして扱う
+        if let HandlerResult::StatusMessage(msg) | HandlerResult::Error(msg) =
+            handler::command::execute(line, editor, editor_rows)
+            && !msg.is_empty()
+            && error.is_none()
+        {
+            error = Some(format!(
+                "Error in {} line {}: {}",
+                path.display(),
+                i + 1,
+                msg
+            ));
+        }
+    }
+    editor.apply_filetype();
+    error.map_or(Ok(()), Err)
+}
+
+/// 入力されたコマンド名からコマンドを探す
+///
+/// 省略形以上の長さで正式な名前に前方一致する場合のみ一致とする (`wr` は `write`)。
+///
+/// # Returns
+///
+/// コマンドと、末尾に `!` が付いていたか
+pub fn resolve(input: &str) -> Option<(&'static ExCommand, bool)> {
+    let (name, bang) = match input.strip_suffix('!') {
+        Some(name) => (name, true),
+        None => (input, false),
+    };
+    let mut matches = COMMANDS
+        .iter()
+        .filter(|c| name.starts_with(c.abbrev) && c.name.starts_with(name));
+    match (matches.next(), matches.next()) {
+        (Some(command), None) => Some((command, bang)),
+        _ => None,
+    }
+}
+
+/// 入力されたコマンド名に最も近いコマンド名を返す (打ち間違いの候補表示用)
+///
+/// 編集距離が 2 以下のものだけを候補とする。
+pub fn suggest(input: &str) -> Option<&'static str> {
+    let name = input.trim_end_matches('!');
+    COMMANDS
+        .iter()
+        .map(|c| (edit_distance(name, c.name), c.name))
+        .filter(|(distance, _)| *distance <= 2)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, name)| name)
+}
+
+/// 2 つの文字列のレーベンシュタイン距離
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            current.push((prev[j] + cost).min(prev[j + 1] + 1).min(current[j] + 1));
+        }
+        prev = current;
+    }
+    prev[b.len()]
+}
+
+/// `:q`
+fn quit(ctx: &mut ExContext) -> HandlerResult {
+    // スクラッチバッファ (:help) を閉じて元のバッファに戻る
+    if ctx.editor.close_scratch() {
+        return HandlerResult::ClearStatus;
+    }
+    // 未保存の変更がある場合は警告 (`:confirm` / `confirm` オプションの場合は保存するか確認する)
+    if !ctx.bang && ctx.editor.is_dirty() {
+        if ctx.confirm || ctx.editor.config.confirm {
+            return handler::prompt::start(Prompt::SaveChanges, ctx.editor);
+        }
+        return HandlerResult::StatusMessage(
+            "No write since last change (add ! to override)".to_string(),
+        );
+    }
+    HandlerResult::Quit
+}
+
+/// `:confirm {cmd}`
+fn confirm_command(ctx: &mut ExContext) -> HandlerResult {
+    if ctx.args.is_empty() {
+        return HandlerResult::StatusMessage("Argument required".to_string());
+    }
+    handler::command::execute_with_confirm(ctx.args, ctx.editor, ctx.editor_rows, true)
+}
+
+/// `:qa`
+fn quit_all(ctx: &mut ExContext) -> HandlerResult {
+    if !ctx.bang
+        && let Some(name) = ctx.editor.dirty_buffers().first()
+    {
+        return HandlerResult::StatusMessage(format!(
+            "No write since last change for buffer \"{}\" (add ! to override)",
+            name
+        ));
+    }
+    HandlerResult::Quit
+}
+
+/// `:wqa`
+fn write_quit_all(ctx: &mut ExContext) -> HandlerResult {
+    let errors = ctx.editor.save_all(ctx.bang);
+    if errors.is_empty() {
+        HandlerResult::Quit
+    } else {
+        HandlerResult::StatusMessage(format!("Error: {}", errors.join(", ")))
+    }
+}
+
+/// `:w[!] [file]`
+///
+/// ファイル名を指定した場合はそのファイルに書き込む。
+/// 現在のファイル以外の既存のファイルは、`!` を付けなければ上書きしない。
+/// `readonly` のバッファでも、`!` を付けるか別のファイルを指定すれば書き込める。
+/// 保存時に `maxlinelength` で行を改行した場合は、その変更を取り消せるようにする。
+fn write(ctx: &mut ExContext) -> HandlerResult {
+    let msg = match write_target(ctx) {
+        Ok(name) => {
+            let editor = &ctx.editor;
+            let bytes = editor
+                .buffer()
+                .iter()
+                .map(|r| r.chars().len())
+                .sum::<usize>();
+            format!("\"{}\" {}L {}B written", name, editor.buffer().len(), bytes)
+        }
+        Err(msg) => msg,
+    };
+    HandlerResult::StatusMessage(msg)
+}
+
+/// `:wq[!] [file]`
+///
+/// `:w` と同じ規則で書き込み、書き込めた場合だけ終了する。
+fn write_quit(ctx: &mut ExContext) -> HandlerResult {
+    match write_target(ctx) {
+        Ok(_) => HandlerResult::Quit,
+        Err(msg) => HandlerResult::StatusMessage(msg),
+    }
+}
+
+/// `:w` / `:wq` の引数のファイル (省略時は現在のファイル) に書き込む
+///
+/// # Returns
+///
+/// 書き込んだファイル名。書き込めなかった場合は表示するメッセージ
+fn write_target(ctx: &mut ExContext) -> Result<String, String> {
+    let editor = &mut *ctx.editor;
+    let target = ctx.args.trim();
+    let own_file = target.is_empty() || editor.is_current_file(target);
+    if own_file && !ctx.bang && editor.is_readonly() {
+        return Err("'readonly' option is set (add ! to override)".to_string());
+    }
+    let result = if own_file {
+        let snapshot = editor.snapshot();
+        let len = editor.buffer().len();
+        let result = editor.save();
+        if editor.buffer().len() != len {
+            editor.history.commit(snapshot);
+        }
+        result.map(|()| editor.filename().unwrap_or("[No Name]").to_string())
+    } else if !ctx.bang && Path::new(target).exists() {
+        return Err("File exists (add ! to override)".to_string());
+    } else {
+        editor.write_to(target).map(|()| target.to_string())
+    };
+    result.map_err(|e| format!("Error: {}", e))
+}
+
+/// `:e [file]`
+fn edit(ctx: &mut ExContext) -> HandlerResult {
+    let ExContext {
+        editor,
+        editor_rows,
+        bang: force,
+        args,
+        ..
+    } = ctx;
+    let msg = if args.trim() == "#" {
+        edit_alternate(editor, *editor_rows, *force)
+    } else if let Some(filename) = args.split_whitespace().next() {
+        edit_file(editor, filename, *force)
+    } else {
+        // ファイル名なしのパターン
+        if !*force && editor.is_dirty() {
+            "No write since last change (add ! to override)".to_string()
+        } else {
+            match editor.reload_keeping_undo() {
+                // このときはカーソル位置をリセットしない(いきなり位置が変わるとびっくりするため
+                // (ファイルが短くなった場合は読み込み直すときにバッファ内に収める)
+                Ok(_) => format!("\"{}\" reloaded", editor.filename().unwrap_or("[No Name]")),
+                Err(e) => format!("Error: {}", e),
+            }
+        }
+    };
+    HandlerResult::StatusMessage(msg)
+}
+
+/// `:help`
+fn help(ctx: &mut ExContext) -> HandlerResult {
+    ctx.editor.open_scratch("[Help]", help_buffer());
+    HandlerResult::ClearStatus
+}
+
+/// `:set`
+fn set(ctx: &mut ExContext) -> HandlerResult {
+    let mut msg = String::new();
+    for arg in split_set_args(ctx.args) {
+        match ctx.editor.set_option(&arg) {
+            Ok(Some(value)) => msg = value,
+            Ok(None) => {}
+            Err(e) => {
+                msg = e;
+                break;
+            }
+        }
+    }
+    HandlerResult::StatusMessage(msg)
+}
+
+/// `:autocmd FileType {filetype} set {args}`
+///
+/// ファイルタイプのファイルを開いたときに適用する設定を置き換える。
+/// `{filetype}` は `,` 区切りで複数指定できる。
+fn autocmd(ctx: &mut ExContext) -> HandlerResult {
+    let mut parts = ctx.args.trim().splitn(3, char::is_whitespace);
+    let (event, filetypes) = match (parts.next(), parts.next()) {
+        (Some(event), Some(filetypes)) => (event, filetypes),
+        _ => return HandlerResult::StatusMessage("Argument required".to_string()),
+    };
+    if !event.eq_ignore_ascii_case("FileType") {
+        return HandlerResult::StatusMessage(format!("No such event: {}", event));
+    }
+    let command = parts.next().unwrap_or("").trim_start();
+    let (name, args) = command.split_once(' ').unwrap_or((command, ""));
+    if !matches!(name, "se" | "set" | "setl" | "setlocal") {
+        return HandlerResult::StatusMessage(format!("Not supported: {}", command));
+    }
+    let args = split_set_args(args);
+    for filetype in filetypes.split(',') {
+        if let Err(e) = ctx
+            .editor
+            .config
+            .set_filetype_options(filetype, args.clone())
+        {
+            return HandlerResult::StatusMessage(e);
+        }
+    }
+    HandlerResult::ClearStatus
+}
+
+/// `:source {file}`
+fn source(ctx: &mut ExContext) -> HandlerResult {
+    let path = ctx.args.trim();
+    if path.is_empty() {
+        return HandlerResult::StatusMessage("Argument required".to_string());
+    }
+    match source_file(ctx.editor, Path::new(path), ctx.editor_rows) {
+        Ok(()) => HandlerResult::ClearStatus,
+        Err(e) => HandlerResult::StatusMessage(e),
+    }
+}
+
+/// `:echo {expr}`
+fn echo(ctx: &mut ExContext) -> HandlerResult {
+    match expr::evaluate(ctx.args, ctx.editor) {
+        Ok(value) => HandlerResult::StatusMessage(value),
+        Err(e) => HandlerResult::StatusMessage(e),
+    }
+}
+
+/// `:redraw`
+fn redraw(_ctx: &mut ExContext) -> HandlerResult {
+    HandlerResult::Redraw
+}
+
+/// `:diff`
+///
+/// 保存済みのファイルと比べて、変更された行のサインをサイン列に表示する。
+fn diff(ctx: &mut ExContext) -> HandlerResult {
+    match ctx.editor.diff_start() {
+        Ok(()) => HandlerResult::ClearStatus,
+        Err(e) => HandlerResult::StatusMessage(e),
+    }
+}
+
+/// `:diffoff`
+fn diff_off(ctx: &mut ExContext) -> HandlerResult {
+    ctx.editor.diff_off();
+    HandlerResult::ClearStatus
+}
+
+/// `:sleep [N][m]`
+fn sleep(ctx: &mut ExContext) -> HandlerResult {
+    match parse_duration(ctx.args) {
+        Ok(duration) => HandlerResult::Sleep(duration),
+        Err(e) => HandlerResult::StatusMessage(e),
+    }
+}
+
+/// `:sleep` の引数を解釈する (`2` は 2 秒、`500m` は 500 ミリ秒、省略時は 1 秒)
+fn parse_duration(args: &str) -> Result<Duration, String> {
+    let args = args.trim();
+    if args.is_empty() {
+        return Ok(Duration::from_secs(1));
+    }
+    let invalid = || format!("Invalid argument: {}", args);
+    match args.strip_suffix('m') {
+        Some(millis) => millis
+            .parse()
+            .map(Duration::from_millis)
+            .map_err(|_| invalid()),
+        None => args.parse().map(Duration::from_secs).map_err(|_| invalid()),
+    }
+}
+
+/// `:mkzimrc [file]`
+///
+/// 既定値から変更した設定を `set` コマンドとして書き出す。
+/// 既存のファイルは `!` を付けた場合のみ上書きする。
+fn mkzimrc(ctx: &mut ExContext) -> HandlerResult {
+    let path = match ctx.args.trim() {
+        "" => DEFAULT_ZIMRC,
+        path => path,
+    };
+    if !ctx.bang && Path::new(path).exists() {
+        return HandlerResult::StatusMessage(format!("\"{}\" exists (add ! to override)", path));
+    }
+    let mut content = String::new();
+    for line in ctx.editor.config.to_set_commands() {
+        content.push_str(&line);
+        content.push('\n');
+    }
+    match fs::write(path, content) {
+        Ok(_) => HandlerResult::StatusMessage(format!("\"{}\" written", path)),
+        Err(e) => HandlerResult::StatusMessage(format!("Error: {}", e)),
+    }
+}
+
+/// `:s`
+fn substitute(ctx: &mut ExContext) -> HandlerResult {
+    if let Err(msg) = ctx.editor.check_modifiable() {
+        return HandlerResult::StatusMessage(msg);
+    }
+    let range = ctx.range_or_current_line();
+    let ExContext {
+        editor,
+        editor_rows,
+        args,
+        ..
+    } = ctx;
+    let sub = match Substitute::parse(args) {
+        Ok(sub) => sub,
+        Err(e) => return HandlerResult::StatusMessage(e),
+    };
+    editor.last_substitute = Some(sub.clone());
+
+    if sub.flags.confirm {
+        let pattern = sub.pattern.clone();
+        return match ConfirmSubstitute::new(
+            sub,
+            range,
+            editor.config.preservecase,
+            editor.config.verymagic,
+        ) {
+            Ok(state) => confirm::start(state, &pattern, editor, *editor_rows),
+            Err(e) => HandlerResult::StatusMessage(e),
+        };
+    }
+
+    apply_substitute(editor, *editor_rows, range, &sub)
+}
+
+/// 直前の `:s` を繰り返す (`&` / `g&`)
+///
+/// `&` は現在行にフラグなしで、`g&` はファイル全体に同じフラグで置換する。
+/// ただし `g&` でも確認 (`c`) はしない。
+pub fn repeat_substitute(editor: &mut Editor, editor_rows: u16, whole_file: bool) -> HandlerResult {
+    if let Err(msg) = editor.check_modifiable() {
+        return HandlerResult::StatusMessage(msg);
+    }
+    let Some(mut sub) = editor.last_substitute.clone() else {
+        return HandlerResult::StatusMessage(
+            "No previous substitute regular expression".to_string(),
+        );
+    };
+    let range = if whole_file {
+        sub.flags.confirm = false;
+        LineRange::new(0, editor.buffer().len().saturating_sub(1))
+    } else {
+        sub.flags = SubstituteFlags::default();
+        LineRange::new(editor.cursor.file_row(), editor.cursor.file_row())
+    };
+    apply_substitute(editor, editor_rows, range, &sub)
+}
+
+/// 確認なしで置換して、カーソルを移動する
+///
+/// `subkeepcursor` がオンの場合は置換前と同じ文字の上に残す。その文字が置換された場合は
+/// 置換後の文字列の先頭、行が短くなった場合は行末の文字に移動する。
+/// オフの場合は Vim と同じく最後に置換した行の最初の非空白文字に移動する。
+fn apply_substitute(
+    editor: &mut Editor,
+    editor_rows: u16,
+    range: LineRange,
+    sub: &Substitute,
+) -> HandlerResult {
+    let snapshot = editor.snapshot();
+    let mut pos = editor.cursor.position();
+    match editor.substitute(range, sub, &mut pos) {
+        Ok(Some(result)) => {
+            editor.history.commit(snapshot);
+            if !editor.config.subkeepcursor {
+                pos = Position::new(result.last_row, editor.first_non_blank(result.last_row));
+            }
+            editor.move_cursor(pos, editor_rows);
+            if result.lines > 1 {
+                HandlerResult::StatusMessage(format!(
+                    "{} substitutions on {} lines",
+                    result.count, result.lines
+                ))
+            } else {
+                HandlerResult::ClearStatus
+            }
+        }
+        Ok(None) => HandlerResult::Error(format!("Pattern not found: {}", sub.pattern)),
+        Err(e) => HandlerResult::StatusMessage(e),
+    }
+}
+
+/// `:left [col]`
+///
+/// 範囲の共通のインデントを取り除き、最もインデントの浅い行を `col` 列 (既定は 0) に揃える。
+fn left(ctx: &mut ExContext) -> HandlerResult {
+    if let Err(msg) = ctx.editor.check_modifiable() {
+        return HandlerResult::StatusMessage(msg);
+    }
+    let range = ctx.range_or_current_line();
+    let col = match ctx.args.trim() {
+        "" => 0,
+        arg => match arg.parse() {
+            Ok(col) => col,
+            Err(_) => return HandlerResult::StatusMessage(format!("Invalid argument: {}", arg)),
+        },
+    };
+    let editor = &mut *ctx.editor;
+    let snapshot = editor.snapshot();
+    if editor.reduce_common_indent(range, col) {
+        editor.history.commit(snapshot);
+    }
+    HandlerResult::ClearStatus
+}
+
+/// `:sort[!] [i]`
+///
+/// 範囲の指定がない場合はファイル全体を並べ替える。`!` で逆順、`i` で大文字と小文字を区別しない。
+fn sort(ctx: &mut ExContext) -> HandlerResult {
+    if let Err(msg) = ctx.editor.check_modifiable() {
+        return HandlerResult::StatusMessage(msg);
+    }
+    let ignore_case = match ctx.args.trim() {
+        "" => false,
+        "i" => true,
+        arg => return HandlerResult::StatusMessage(format!("Invalid argument: {}", arg)),
+    };
+    let last = ctx.editor.buffer().len().saturating_sub(1);
+    let range = ctx.range.unwrap_or(LineRange::new(0, last));
+    let reverse = ctx.bang;
+    with_saved_cursor(ctx, |ctx| {
+        let editor = &mut *ctx.editor;
+        let snapshot = editor.snapshot();
+        if editor.sort_lines(range, reverse, ignore_case) {
+            editor.history.commit(snapshot);
+        }
+    });
+    HandlerResult::ClearStatus
+}
+
+/// `:reverse`
+///
+/// 範囲の指定がない場合はファイル全体の行の順番を逆にする。
+fn reverse(ctx: &mut ExContext) -> HandlerResult {
+    if let Err(msg) = ctx.editor.check_modifiable() {
+        return HandlerResult::StatusMessage(msg);
+    }
+    let last = ctx.editor.buffer().len().saturating_sub(1);
+    let range = ctx.range.unwrap_or(LineRange::new(0, last));
+    with_saved_cursor(ctx, |ctx| {
+        let editor = &mut *ctx.editor;
+        let snapshot = editor.snapshot();
+        if editor.reverse_lines(range) {
+            editor.history.commit(snapshot);
+        }
+    });
+    HandlerResult::ClearStatus
+}
+
+/// `:uniq[!]`
+///
+/// 範囲の指定がない場合はファイル全体を対象にする。`!` の場合は同じ内容が続く行だけを残す。
+fn uniq(ctx: &mut ExContext) -> HandlerResult {
+    if let Err(msg) = ctx.editor.check_modifiable() {
+        return HandlerResult::StatusMessage(msg);
+    }
+    let last = ctx.editor.buffer().len().saturating_sub(1);
+    let range = ctx.range.unwrap_or(LineRange::new(0, last));
+    let inverse = ctx.bang;
+    let removed = with_saved_cursor(ctx, |ctx| {
+        let editor = &mut *ctx.editor;
+        let snapshot = editor.snapshot();
+        let removed = editor.uniq_lines(range, inverse);
+        if removed > 0 {
+            editor.history.commit(snapshot);
+        }
+        removed
+    });
+    match removed {
+        1 => HandlerResult::StatusMessage("1 line removed".to_string()),
+        n => HandlerResult::StatusMessage(format!("{} lines removed", n)),
+    }
+}
+
+/// `:retab[!] [tabstop]`
+///
+/// 範囲の指定がない場合はファイル全体を対象にし、行頭のインデントを `expandtab` に合わせて
+/// タブまたは空白に変換する。`!` の場合は行の途中のタブも変換する。
+/// `tabstop` を指定した場合は、表示が変わらないように変換してから新しい値を設定する。
+fn retab(ctx: &mut ExContext) -> HandlerResult {
+    if let Err(msg) = ctx.editor.check_modifiable() {
+        return HandlerResult::StatusMessage(msg);
+    }
+    let new_tabstop = match ctx.args.trim() {
+        "" => ctx.editor.config.tabstop,
+        arg => match arg.parse() {
+            Ok(tabstop) if tabstop > 0 => tabstop,
+            _ => return HandlerResult::StatusMessage(format!("Invalid argument: {}", arg)),
+        },
+    };
+    let last = ctx.editor.buffer().len().saturating_sub(1);
+    let range = ctx.range.unwrap_or(LineRange::new(0, last));
+    let all = ctx.bang;
+    with_saved_cursor(ctx, |ctx| {
+        let editor = &mut *ctx.editor;
+        let snapshot = editor.snapshot();
+        if editor.retab(range, new_tabstop, all) {
+            editor.history.commit(snapshot);
+        }
+    });
+    HandlerResult::ClearStatus
+}
+
+/// 行を書き換えるコマンドを実行し、カーソルを実行前の位置に戻す
+///
+/// 実行後に行が減っている場合や行が短くなっている場合は、カーソルをバッファ内に収める。
+fn with_saved_cursor<T>(ctx: &mut ExContext, f: impl FnOnce(&mut ExContext) -> T) -> T {
+    let pos = ctx.editor.cursor.position();
+    let result = f(ctx);
+    ctx.editor.move_cursor(pos, ctx.editor_rows);
+    result
+}
+
+/// `:d [x]`
+fn delete(ctx: &mut ExContext) -> HandlerResult {
+    if let Err(msg) = ctx.editor.check_modifiable() {
+        return HandlerResult::StatusMessage(msg);
+    }
+    let range = ctx.range_or_current_line();
+    if let Err(e) = select_register(ctx.args, ctx.editor) {
+        return HandlerResult::StatusMessage(e);
+    }
+    let editor = &mut *ctx.editor;
+    editor.history.commit(editor.snapshot());
+    editor.delete_lines_range(range.start, range.end);
+    line_count_message(range.end - range.start + 1, "fewer lines")
+}
+
+/// `:y [x]`
+fn yank(ctx: &mut ExContext) -> HandlerResult {
+    let range = ctx.range_or_current_line();
+    if let Err(e) = select_register(ctx.args, ctx.editor) {
+        return HandlerResult::StatusMessage(e);
+    }
+    ctx.editor.yank_lines_range(range.start, range.end);
+    line_count_message(range.end - range.start + 1, "lines yanked")
+}
+
+/// `:put [x]` (`:put!` は指定行の上に貼り付ける)
+fn put(ctx: &mut ExContext) -> HandlerResult {
+    if let Err(msg) = ctx.editor.check_modifiable() {
+        return HandlerResult::StatusMessage(msg);
+    }
+    let row = ctx
+        .range
+        .map_or(ctx.editor.cursor.file_row(), |range| range.end);
+    let direction = if ctx.bang {
+        PasteDirection::Above
+    } else {
+        PasteDirection::Below
+    };
+    if let Err(e) = select_register(ctx.args, ctx.editor) {
+        return HandlerResult::StatusMessage(e);
+    }
+    let editor = &mut *ctx.editor;
+    let snapshot = editor.snapshot();
+    let Some(count) = editor.put_lines(row, direction) else {
+        return HandlerResult::StatusMessage("Nothing in register".to_string());
+    };
+    editor.history.commit(snapshot);
+    line_count_message(count, "more lines")
+}
+
+/// `:d` / `:y` / `:put` の引数で指定されたレジスタを選択する
+fn select_register(args: &str, editor: &mut Editor) -> Result<(), String> {
+    let mut chars = args.chars();
+    match (chars.next(), chars.next()) {
+        (None, _) => Ok(()),
+        (Some(name), None) if editor.registers.select(name) => Ok(()),
+        _ => Err(format!("Trailing characters: {}", args)),
+    }
+}
+
+/// 複数行を変更したときのメッセージ (Vim と同様に 3 行以上の場合のみ表示する)
+pub fn line_count_message(count: usize, what: &str) -> HandlerResult {
+    if count > 2 {
+        HandlerResult::StatusMessage(format!("{} {}", count, what))
+    } else {
+        HandlerResult::ClearStatus
+    }
+}
+
+/// 指定したファイルを開く (`:e {file}` / `gf` 用)
+///
+/// 未保存の変更がある場合は `force` が true のときのみ開く。
+///
+/// # Returns
+///
+/// ステータスバーに表示するメッセージ
+pub fn edit_file(editor: &mut Editor, filename: &str, force: bool) -> String {
+    // :help から開いた場合は元のバッファに戻ってから未保存の変更を確認する
+    editor.close_scratch();
+    if !force && editor.is_dirty() {
+        return "No write since last change (add ! to override)".to_string();
+    }
+    let previous = editor
+        .filename()
+        .filter(|previous| *previous != filename)
+        .map(|previous| (previous.to_string(), editor.cursor.position()));
+    match editor.open_file(filename.to_string()) {
+        Ok(_) => {
+            if let Some((previous, pos)) = previous {
+                editor.set_alternate(previous, pos);
+            }
+            editor.cursor = Cursor::new();
+            format!("\"{}\" loaded", filename)
+        }
+        Err(e) => format!("Cannot open file: {}", e),
+    }
+}
+
+/// 直前に編集していたファイルに切り替える (`Ctrl-^` / `:e #`)
+///
+/// 切り替え前のファイルを新しい alternate file にし、カーソルは前回の位置に戻す。
+pub fn edit_alternate(editor: &mut Editor, editor_rows: u16, force: bool) -> String {
+    let Some((filename, pos)) = editor.alternate().cloned() else {
+        return "No alternate file".to_string();
+    };
+    let msg = edit_file(editor, &filename, force);
+    if editor.filename() == Some(filename.as_str()) {
+        // 前回から行が減っていてもバッファ内に収める
+        editor.move_cursor(pos, editor_rows);
+    }
+    msg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::Buffer;
+    use crate::config::SignColumn;
+    use crate::cursor::Position;
+
+    fn resolved_name(input: &str) -> Option<String> {
+        resolve(input)
+            .map(|(command, bang)| format!("{}{}", command.name, if bang { "!" } else { "" }))
+    }
+
+    #[test]
+    fn test_resolve_prefix() {
+        assert_eq!(resolved_name("w").as_deref(), Some("write"));
+        assert_eq!(resolved_name("wr").as_deref(), Some("write"));
+        assert_eq!(resolved_name("write!").as_deref(), Some("write!"));
+        assert_eq!(resolved_name("wq").as_deref(), Some("wq"));
+        assert_eq!(resolved_name("s").as_deref(), Some("substitute"));
+        assert_eq!(resolved_name("se").as_deref(), Some("set"));
+        assert_eq!(resolved_name("pu!").as_deref(), Some("put!"));
+        // 省略形より短い、または一致しない場合
+        assert_eq!(resolved_name("p"), None);
+        assert_eq!(resolved_name("writes"), None);
+        assert_eq!(resolved_name("wirte"), None);
+    }
+
+    #[test]
+    fn test_suggest() {
+        assert_eq!(suggest("wirte"), Some("write"));
+        assert_eq!(suggest("yankk"), Some("yank"));
+        assert_eq!(suggest("hlep!"), Some("help"));
+        assert_eq!(suggest("foobar"), None);
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("2"), Ok(Duration::from_secs(2)));
+        assert_eq!(parse_duration("500m"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration(""), Ok(Duration::from_secs(1)));
+        assert!(parse_duration("2s").is_err());
+        assert!(parse_duration("m").is_err());
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("same", "same"), 0);
+    }
+
+    fn run(name: &str, args: &str, range: Option<LineRange>, editor: &mut Editor) -> HandlerResult {
+        let (command, bang) = resolve(name).unwrap();
+        let mut ctx = ExContext {
+            editor,
+            editor_rows: 22,
+            range,
+            bang,
+            args,
+            confirm: false,
+        };
+        (command.run)(&mut ctx)
+    }
+
+    #[test]
+    fn test_run_registered_commands() {
+        let buffer = Buffer::from_lines(["one", "two", "three"]);
+        let mut editor = Editor::from_buffer(buffer, None);
+
+        run("set", "list ts=4", None, &mut editor);
+        assert!(editor.config.list);
+        assert_eq!(editor.config.tabstop, 4);
+
+        run("s", "/o/0/g", Some(LineRange::new(0, 1)), &mut editor);
+        run("y", "a", Some(LineRange::single(2)), &mut editor);
+        run("d", "", Some(LineRange::single(0)), &mut editor);
+        run("pu!", "a", Some(LineRange::single(0)), &mut editor);
+        let rows: Vec<&str> = editor.buffer().iter().map(|r| r.chars()).collect();
+        assert_eq!(rows, vec!["three", "tw0", "three"]);
+
+        // 未保存の変更がある場合は終了しない
+        assert!(matches!(
+            run("q", "", None, &mut editor),
+            HandlerResult::StatusMessage(_)
+        ));
+        assert!(matches!(
+            run("q!", "", None, &mut editor),
+            HandlerResult::Quit
+        ));
+    }
+
+    #[test]
+    fn test_left() {
+        let buffer = Buffer::from_lines(["      one", "        two", "      three"]);
+        let mut editor = Editor::from_buffer(buffer, None);
+        editor.config.expandtab = true;
+        let rows = |editor: &Editor| -> Vec<String> {
+            editor
+                .buffer()
+                .iter()
+                .map(|r| r.chars().to_string())
+                .collect()
+        };
+
+        run("left", "2", Some(LineRange::new(0, 2)), &mut editor);
+        assert_eq!(rows(&editor), vec!["  one", "    two", "  three"]);
+        run("le", "", Some(LineRange::new(0, 2)), &mut editor);
+        assert_eq!(rows(&editor), vec!["one", "  two", "three"]);
+        assert!(editor.is_dirty());
+        assert!(matches!(
+            run("le", "x", None, &mut editor),
+            HandlerResult::StatusMessage(msg) if msg == "Invalid argument: x"
+        ));
+    }
+
+    #[test]
+    fn test_substitute_cursor_position() {
+        let buffer = Buffer::from_lines(["x", "  let value = old_name + old_name;", "  old_name"]);
+        let mut editor = Editor::from_buffer(buffer, None);
+        let run_substitute = |editor: &mut Editor| {
+            let mut ctx = ExContext {
+                editor,
+                editor_rows: 22,
+                range: Some(LineRange::new(0, 2)),
+                bang: false,
+                args: "/old_name/n/g",
+                confirm: false,
+            };
+            substitute(&mut ctx)
+        };
+
+        // 既定では最後に置換した行の最初の非空白文字に移動する
+        editor.cursor.move_to_position(Position::new(1, 14), 3, 22);
+        run_substitute(&mut editor);
+        assert_eq!(editor.cursor.position(), Position::new(2, 2));
+
+        // subkeepcursor では同じ文字の上に残る (行が短くなっても ';' を指す)
+        let prev = editor.history.undo(editor.snapshot()).unwrap();
+        editor.restore_snapshot(prev);
+        editor.config.subkeepcursor = true;
+        editor.cursor.move_to_position(Position::new(1, 33), 3, 22);
+        run_substitute(&mut editor);
+        assert_eq!(
+            editor.buffer().row(1).unwrap().chars(),
+            "  let value = n + n;"
+        );
+        assert_eq!(editor.cursor.position(), Position::new(1, 19));
+
+        // 置換された文字の上にあった場合は置換後の文字列の先頭に移動する
+        let prev = editor.history.undo(editor.snapshot()).unwrap();
+        editor.restore_snapshot(prev);
+        editor.cursor.move_to_position(Position::new(1, 28), 3, 22);
+        run_substitute(&mut editor);
+        assert_eq!(editor.cursor.position(), Position::new(1, 18));
+    }
+
+    #[test]
+    fn test_sort() {
+        let buffer = Buffer::from_lines(["cherry", "apple", "banana pie", "Date"]);
+        let mut editor = Editor::from_buffer(buffer, None);
+        editor.cursor.move_to_position(Position::new(2, 8), 4, 22);
+        let run_sort = |args, bang, editor: &mut Editor| {
+            let mut ctx = ExContext {
+                editor,
+                editor_rows: 22,
+                range: None,
+                bang,
+                args,
+                confirm: false,
+            };
+            sort(&mut ctx)
+        };
+        let rows = |editor: &Editor| -> Vec<String> {
+            editor
+                .buffer()
+                .iter()
+                .map(|r| r.chars().to_string())
+                .collect()
+        };
+
+        run_sort("", false, &mut editor);
+        assert_eq!(rows(&editor), vec!["Date", "apple", "banana pie", "cherry"]);
+        // カーソルは元の位置に戻る
+        assert_eq!(editor.cursor.position(), Position::new(2, 8));
+        run_sort("i", true, &mut editor);
+        assert_eq!(rows(&editor), vec!["Date", "cherry", "banana pie", "apple"]);
+        assert_eq!(editor.cursor.position(), Position::new(2, 8));
+
+        // 行が減っていた場合は最終行の末尾に収める
+        editor.delete_lines_range(2, 3);
+        editor.cursor.move_to_position(Position::new(2, 9), 4, 22);
+        run_sort("", false, &mut editor);
+        assert_eq!(rows(&editor), vec!["Date", "cherry"]);
+        assert_eq!(editor.cursor.position(), Position::new(1, 5));
+        assert!(editor.is_dirty());
+
+        assert!(matches!(
+            run_sort("x", false, &mut editor),
+            HandlerResult::StatusMessage(msg) if msg == "Invalid argument: x"
+        ));
+    }
+
+    #[test]
+    fn test_reverse() {
+        let buffer = Buffer::from_lines(["one", "two", "three", "four"]);
+        let mut editor = Editor::from_buffer(buffer, None);
+        let rows = |editor: &Editor| editor.buffer().lines_in_range(0, usize::MAX);
+
+        run("rev", "", None, &mut editor);
+        assert_eq!(rows(&editor), vec!["four", "three", "two", "one"]);
+        assert!(editor.is_dirty());
+        run("reverse", "", Some(LineRange::new(1, 2)), &mut editor);
+        assert_eq!(rows(&editor), vec!["four", "two", "three", "one"]);
+
+        // undo で 1 回分ずつ元に戻せる
+        let prev = editor.history.undo(editor.snapshot()).unwrap();
+        editor.restore_snapshot(prev);
+        assert_eq!(rows(&editor), vec!["four", "three", "two", "one"]);
+    }
+
+    #[test]
+    fn test_uniq() {
+        let buffer = Buffer::from_lines(["x", "x", "y", "z", "z", "z"]);
+        let mut editor = Editor::from_buffer(buffer, None);
+        let rows = |editor: &Editor| editor.buffer().lines_in_range(0, usize::MAX);
+
+        assert!(matches!(
+            run("uniq", "", Some(LineRange::new(2, 4)), &mut editor),
+            HandlerResult::StatusMessage(msg) if msg == "1 line removed"
+        ));
+        assert_eq!(rows(&editor), vec!["x", "x", "y", "z", "z"]);
+        assert!(editor.is_dirty());
+        assert!(matches!(
+            run("uni!", "", None, &mut editor),
+            HandlerResult::StatusMessage(msg) if msg == "3 lines removed"
+        ));
+        assert_eq!(rows(&editor), vec!["x", "z"]);
+        assert!(matches!(
+            run("uniq", "", None, &mut editor),
+            HandlerResult::StatusMessage(msg) if msg == "0 lines removed"
+        ));
+    }
+
+    #[test]
+    fn test_retab() {
+        let buffer = Buffer::from_lines(["\tkeep", "\tif x {", "\t\ty();\t// z", "\t}", "\tkeep"]);
+        let mut editor = Editor::from_buffer(buffer, None);
+        let rows = |editor: &Editor| editor.buffer().lines_in_range(0, usize::MAX);
+
+        // 範囲内の行だけを変換し、行の途中のタブはそのままにする
+        editor.config.expandtab = true;
+        run("retab", "4", Some(LineRange::new(1, 3)), &mut editor);
+        assert_eq!(
+            rows(&editor),
+            vec![
+                "\tkeep",
+                "        if x {",
+                "                y();\t// z",
+                "        }",
+                "\tkeep"
+            ]
+        );
+        assert_eq!(editor.config.tabstop, 4);
+        assert!(editor.is_dirty());
+
+        // ! の場合は行の途中のタブも変換する
+        run("ret!", "", Some(LineRange::new(2, 2)), &mut editor);
+        assert_eq!(rows(&editor)[2], "                y();    // z");
+
+        assert!(matches!(
+            run("retab", "0", None, &mut editor),
+            HandlerResult::StatusMessage(msg) if msg == "Invalid argument: 0"
+        ));
+    }
+
+    #[test]
+    fn test_diff() {
+        use crate::diff::DiffSign;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+        std::fs::write(&path, "one\ntwo\nthree\n").unwrap();
+        let mut editor = Editor::new();
+        editor
+            .open_file(path.to_string_lossy().into_owned())
+            .unwrap();
+        editor.buffer_mut().set_row(1, "TWO".to_string());
+        editor.buffer_mut().insert_row(3, "four".to_string());
+        assert_eq!(editor.diff_signs(), None);
+
+        run("diff", "", None, &mut editor);
+        assert_eq!(
+            editor.diff_signs(),
+            Some(vec![
+                None,
+                Some(DiffSign::Changed),
+                None,
+                Some(DiffSign::Added)
+            ])
+        );
+        assert_eq!(editor.signcolumn(), SignColumn::Yes);
+
+        // 描画の間にバッファを変更した場合は求め直す
+        editor.buffer_mut().delete_row(3);
+        assert_eq!(
+            editor.diff_signs(),
+            Some(vec![None, Some(DiffSign::Changed), None])
+        );
+        editor.buffer_mut().insert_row(3, "four".to_string());
+
+        // 保存すると保存した内容と比べる
+        editor.save().unwrap();
+        assert_eq!(editor.diff_signs(), Some(vec![None; 4]));
+
+        run("diffo", "", None, &mut editor);
+        assert_eq!(editor.diff_signs(), None);
+        assert_eq!(editor.signcolumn(), SignColumn::No);
+
+        let mut editor = Editor::new();
+        assert!(matches!(
+            run("diff", "", None, &mut editor),
+            HandlerResult::StatusMessage(msg) if msg == "No file name"
+        ));
+    }
+
+    #[test]
+    fn test_quit_all() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+        std::fs::write(&path, "one\n").unwrap();
+        let mut editor = Editor::new();
+        editor
+            .open_file(path.to_string_lossy().into_owned())
+            .unwrap();
+        editor.buffer_mut().insert_row(1, "two".to_string());
+        editor.mark_dirty();
+
+        // :help の裏にある元のバッファに変更がある場合も終了しない
+        editor.open_scratch("[Help]", help_buffer());
+        assert!(matches!(
+            run("qa", "", None, &mut editor),
+            HandlerResult::StatusMessage(msg) if msg.contains("file.txt")
+        ));
+        assert!(matches!(
+            run("qa!", "", None, &mut editor),
+            HandlerResult::Quit
+        ));
+
+        // :wqa は変更のあるバッファを保存してから終了する
+        assert!(matches!(
+            run("wqa", "", None, &mut editor),
+            HandlerResult::Quit
+        ));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "one\ntwo\n");
+        assert!(editor.dirty_buffers().is_empty());
+
+        // ファイル名のないバッファは保存できずに終了しない
+        let mut editor = Editor::new();
+        editor.mark_dirty();
+        assert!(matches!(
+            run("wqa", "", None, &mut editor),
+            HandlerResult::StatusMessage(msg) if msg.starts_with("Error: \"[No Name]\"")
+        ));
+    }
+
+    #[test]
+    fn test_echo() {
+        let mut editor = Editor::from_buffer(Buffer::new(), Some("echo.txt".to_string()));
+        assert!(matches!(
+            run("echo", "line('.')", None, &mut editor),
+            HandlerResult::StatusMessage(msg) if msg == "1"
+        ));
+        assert!(matches!(
+            run("ec", "'file: ' . expand('%')", None, &mut editor),
+            HandlerResult::StatusMessage(msg) if msg == "file: echo.txt"
+        ));
+    }
+
+    #[test]
+    fn test_redraw_and_sleep_results() {
+        let mut editor = Editor::new();
+        assert!(matches!(
+            run("redr", "", None, &mut editor),
+            HandlerResult::Redraw
+        ));
+        assert!(matches!(
+            run("sleep", "500m", None, &mut editor),
+            HandlerResult::Sleep(duration) if duration == Duration::from_millis(500)
+        ));
+        assert!(matches!(
+            run("sl", "x", None, &mut editor),
+            HandlerResult::StatusMessage(_)
+        ));
+    }
+
+    #[test]
+    fn test_write_refuses_to_overwrite_other_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let current = dir.path().join("current.txt");
+        let other = dir.path().join("other.txt");
+        std::fs::write(&current, "one\n").unwrap();
+        std::fs::write(&other, "other\n").unwrap();
+        let other_arg = other.to_string_lossy().into_owned();
+        let mut editor = Editor::new();
+        editor
+            .open_file(current.to_string_lossy().into_owned())
+            .unwrap();
+        editor.buffer_mut().set_row(0, "changed".to_string());
+        editor.mark_dirty();
+
+        assert!(matches!(
+            run("w", &other_arg, None, &mut editor),
+            HandlerResult::StatusMessage(msg) if msg == "File exists (add ! to override)"
+        ));
+        assert_eq!(std::fs::read_to_string(&other).unwrap(), "other\n");
+
+        run("w!", &other_arg, None, &mut editor);
+        assert_eq!(std::fs::read_to_string(&other).unwrap(), "changed\n");
+        // 現在のファイルは保存されず、バッファの名前も変わらない
+        assert_eq!(std::fs::read_to_string(&current).unwrap(), "one\n");
+        assert_eq!(editor.filename(), Some(current.to_string_lossy().as_ref()));
+        assert!(editor.is_dirty());
+
+        // 現在のファイルは別の書き方でも ! なしで保存できる
+        let current_arg = dir.path().join(".").join("current.txt");
+        run("w", &current_arg.to_string_lossy(), None, &mut editor);
+        assert_eq!(std::fs::read_to_string(&current).unwrap(), "changed\n");
+        assert!(!editor.is_dirty());
+
+        // 名前のないバッファは書き込んだファイルの名前になる
+        let new = dir.path().join("new.txt");
+        let mut editor = Editor::new();
+        run("w", &new.to_string_lossy(), None, &mut editor);
+        assert!(new.exists());
+        assert_eq!(editor.filename(), Some(new.to_string_lossy().as_ref()));
+    }
+
+    #[test]
+    fn test_write_readonly() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        std::fs::write(&path, "old\n").unwrap();
+        let mut editor = Editor::new();
+        editor
+            .open_file(path.to_string_lossy().into_owned())
+            .unwrap();
+        editor.set_readonly(true);
+        editor.buffer_mut().set_row(0, "new".to_string());
+        editor.mark_dirty();
+
+        assert!(matches!(
+            run("w", "", None, &mut editor),
+            HandlerResult::StatusMessage(msg) if msg.ends_with("(add ! to override)")
+        ));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "old\n");
+
+        // 別のファイルには ! なしで書き込める
+        let other = dir.path().join("other.txt");
+        run("w", &other.to_string_lossy(), None, &mut editor);
+        assert_eq!(std::fs::read_to_string(&other).unwrap(), "new\n");
+
+        // ! を付けると現在のファイルにも書き込める
+        run("w!", "", None, &mut editor);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new\n");
+        assert!(!editor.is_dirty());
+    }
+
+    #[test]
+    fn test_write_quit() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        let other = dir.path().join("other.txt");
+        std::fs::write(&path, "old\n").unwrap();
+        std::fs::write(&other, "other\n").unwrap();
+        let mut editor = Editor::new();
+        editor
+            .open_file(path.to_string_lossy().into_owned())
+            .unwrap();
+        editor.set_readonly(true);
+        editor
+            .buffer_mut()
+            .set_row(0, "the quick brown fox jumps over the lazy dog".to_string());
+        editor.mark_dirty();
+        editor.config.maxlinelength = 30;
+
+        // :w と同様に、既存の別のファイルや readonly の現在のファイルには書き込まずに終了しない
+        assert!(matches!(
+            run("wq", &other.to_string_lossy(), None, &mut editor),
+            HandlerResult::StatusMessage(msg) if msg == "File exists (add ! to override)"
+        ));
+        assert!(matches!(
+            run("wq", "", None, &mut editor),
+            HandlerResult::StatusMessage(msg) if msg.ends_with("(add ! to override)")
+        ));
+        assert_eq!(std::fs::read_to_string(&other).unwrap(), "other\n");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "old\n");
+
+        // 引数のファイルに書き込んでから終了する
+        let new = dir.path().join("new.txt");
+        assert!(matches!(
+            run("wq", &new.to_string_lossy(), None, &mut editor),
+            HandlerResult::Quit
+        ));
+        assert!(new.exists());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "old\n");
+
+        // ! を付けると現在のファイルに書き込み、改行は取り消せる
+        assert!(matches!(
+            run("wq!", "", None, &mut editor),
+            HandlerResult::Quit
+        ));
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "the quick brown fox jumps over\nthe lazy dog\n"
+        );
+        assert!(editor.history.undo(editor.snapshot()).is_some());
+    }
+
+    #[test]
+    fn test_write_with_maxlinelength() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        std::fs::write(&path, "short\n").unwrap();
+        let mut editor = Editor::new();
+        editor
+            .open_file(path.to_string_lossy().into_owned())
+            .unwrap();
+        editor
+            .buffer_mut()
+            .set_row(0, "the quick brown fox jumps over the lazy dog".to_string());
+        editor.mark_dirty();
+        editor.config.maxlinelength = 30;
+
+        run("w", "", None, &mut editor);
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "the quick brown fox jumps over\nthe lazy dog\n"
+        );
+        assert!(!editor.is_dirty());
+
+        // 改行は取り消せる
+        let prev = editor.history.undo(editor.snapshot()).unwrap();
+        editor.restore_snapshot(prev);
+        assert_eq!(editor.buffer().len(), 1);
+    }
+
+    #[test]
+    fn test_write_all_with_maxlinelength() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        std::fs::write(&path, "short\n").unwrap();
+        let long = "the quick brown fox jumps over the lazy dog";
+        let mut editor = Editor::new();
+        editor
+            .open_file(path.to_string_lossy().into_owned())
+            .unwrap();
+        editor.buffer_mut().set_row(0, long.to_string());
+        editor.mark_dirty();
+        editor.config.maxlinelength = 30;
+
+        // 変更できないバッファは改行せずに保存する
+        editor.set_option("nomodifiable").unwrap();
+        run("wqa", "", None, &mut editor);
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            format!("{}\n", long)
+        );
+
+        // :help の裏にある元のバッファは、元のバッファの設定に従って改行する
+        editor.set_option("modifiable").unwrap();
+        editor.mark_dirty();
+        editor.open_scratch("[Help]", help_buffer());
+        run("wqa", "", None, &mut editor);
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "the quick brown fox jumps over\nthe lazy dog\n"
+        );
+    }
+
+    #[test]
+    fn test_reload_keeps_undo() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        std::fs::write(&path, "saved\n").unwrap();
+        let mut editor = Editor::new();
+        editor
+            .open_file(path.to_string_lossy().into_owned())
+            .unwrap();
+        editor.buffer_mut().set_row(0, "unsaved".to_string());
+        editor.mark_dirty();
+
+        run("e!", "", None, &mut editor);
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "saved");
+        assert!(!editor.is_dirty());
+
+        // 読み込み直す前の変更を u で戻せる
+        let prev = editor.history.undo(editor.snapshot()).unwrap();
+        editor.restore_snapshot(prev);
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "unsaved");
+        assert!(editor.is_dirty());
+    }
+
+    #[test]
+    fn test_mkzimrc_refuses_to_overwrite() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("zimrc");
+        let path_arg = path.to_string_lossy().into_owned();
+        let mut editor = Editor::new();
+        editor.config.number = true;
+        editor.config.tabstop = 2;
+
+        run("mkzimrc", &path_arg, None, &mut editor);
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "set number\nset tabstop=2\n"
+        );
+
+        editor.config.number = false;
+        assert!(matches!(
+            run("mkzimrc", &path_arg, None, &mut editor),
+            HandlerResult::StatusMessage(msg) if msg.ends_with("exists (add ! to override)")
+        ));
+        run("mkzimrc!", &path_arg, None, &mut editor);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "set tabstop=2\n");
+    }
+
+    #[test]
+    fn test_autocmd_filetype() {
+        let mut editor = Editor::new();
+        run("au", "FileType rust,c set sw=2 ts=2", None, &mut editor);
+        assert_eq!(editor.config.filetype_options("rust"), ["sw=2", "ts=2"]);
+        assert_eq!(editor.config.filetype_options("c"), ["sw=2", "ts=2"]);
+
+        // 適用できない設定は登録しない
+        assert!(matches!(
+            run("autocmd", "FileType rust set foo", None, &mut editor),
+            HandlerResult::StatusMessage(msg) if msg == "Unknown option: foo"
+        ));
+        assert!(matches!(
+            run("autocmd", "BufRead rust set ts=2", None, &mut editor),
+            HandlerResult::StatusMessage(msg) if msg == "No such event: BufRead"
+        ));
+        assert_eq!(editor.config.filetype_options("rust"), ["sw=2", "ts=2"]);
+    }
+
+    #[test]
+    fn test_source_zimrc() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("main.rs");
+        std::fs::write(&file, "").unwrap();
+        let zimrc = dir.path().join("zimrc");
+        std::fs::write(
+            &zimrc,
+            "\" options\nset number tabstop=3\n\nautocmd FileType rust set tabstop=2\nset foo\n",
+        )
+        .unwrap();
+
+        let mut editor = Editor::from_buffer(Buffer::new(), Some(file.to_string_lossy().into()));
+        assert_eq!(
+            source_file(&mut editor, &zimrc, 22),
+            Err(format!(
+                "Error in {} line 5: Unknown option: foo",
+                zimrc.display()
+            ))
+        );
+        assert!(editor.config.number);
+        // 読み込んだ設定は開いているファイルのファイルタイプにも適用する
+        assert_eq!(editor.config.tabstop, 2);
+        assert!(!editor.config.expandtab);
+        let notes = dir.path().join("notes.txt");
+        std::fs::write(&notes, "").unwrap();
+        editor.open_file(notes.to_string_lossy().into()).unwrap();
+        assert_eq!(editor.config.tabstop, 3);
+    }
+
+    #[test]
+    fn test_mkzimrc_writes_autocmd() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("zimrc");
+        let mut editor = Editor::new();
+        run("autocmd", "FileType rust set ts=2", None, &mut editor);
+        run("mkzimrc", &path.to_string_lossy(), None, &mut editor);
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "autocmd FileType rust set ts=2\n"
+        );
+
+        let mut restored = Editor::new();
+        source_file(&mut restored, &path, 22).unwrap();
+        assert_eq!(restored.config, editor.config);
+    }
+}
@@ -0,0 +1,248 @@
+use crate::editor::Editor;
+
+/// `:echo` の式を評価する
+///
+/// 対応しているのは文字列 (`'...'` / `"..."`)、数値、オプションの値 (`&tabstop`)、
+/// `line('.')` / `line('$')` / `col('.')` / `col('$')` / `expand('%')` と `.` による連結のみ。
+/// 空白で区切った複数の式は、Vim と同じく空白でつないだ結果にする。
+pub fn evaluate(input: &str, editor: &Editor) -> Result<String, String> {
+    let mut parser = Parser {
+        input,
+        chars: input.chars().collect(),
+        pos: 0,
+        editor,
+    };
+    let mut values = Vec::new();
+    parser.skip_whitespace();
+    while parser.peek().is_some() {
+        values.push(parser.concat()?);
+        parser.skip_whitespace();
+    }
+    Ok(values.join(" "))
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    chars: Vec<char>,
+    pos: usize,
+    editor: &'a Editor,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    /// 次の文字が `c` の場合は読み進める
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        self.take_while(char::is_whitespace);
+    }
+
+    fn take_while(&mut self, f: impl Fn(char) -> bool) -> String {
+        let start = self.pos;
+        while self.peek().is_some_and(&f) {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    fn invalid(&self) -> String {
+        format!("Invalid expression: \"{}\"", self.input)
+    }
+
+    /// `term ('.' term)*`
+    fn concat(&mut self) -> Result<String, String> {
+        let mut value = self.term()?;
+        loop {
+            let save = self.pos;
+            self.skip_whitespace();
+            if !self.eat('.') {
+                self.pos = save;
+                return Ok(value);
+            }
+            self.skip_whitespace();
+            value.push_str(&self.term()?);
+        }
+    }
+
+    fn term(&mut self) -> Result<String, String> {
+        match self.peek() {
+            Some('\'') => self.single_quoted(),
+            Some('"') => self.double_quoted(),
+            Some('&') => {
+                self.pos += 1;
+                let name = self.take_while(|c| c.is_ascii_alphabetic());
+                self.editor
+                    .config
+                    .value(&name)
+                    .map_err(|_| format!("Unknown option: {}", name))
+            }
+            Some(c) if c.is_ascii_digit() => Ok(self.take_while(|c| c.is_ascii_digit())),
+            Some(c) if c.is_ascii_alphabetic() => self.function_call(),
+            _ => Err(self.invalid()),
+        }
+    }
+
+    /// `'...'` (`''` は `'` 1 文字)
+    fn single_quoted(&mut self) -> Result<String, String> {
+        self.pos += 1;
+        let mut value = String::new();
+        loop {
+            match self.peek() {
+                Some('\'') if self.chars.get(self.pos + 1) == Some(&'\'') => {
+                    value.push('\'');
+                    self.pos += 2;
+                }
+                Some('\'') => {
+                    self.pos += 1;
+                    return Ok(value);
+                }
+                Some(c) => {
+                    value.push(c);
+                    self.pos += 1;
+                }
+                None => return Err(format!("Missing quote: {}", self.input)),
+            }
+        }
+    }
+
+    /// `"..."` (`\n` / `\t` / `\"` / `\\` のエスケープに対応する)
+    fn double_quoted(&mut self) -> Result<String, String> {
+        self.pos += 1;
+        let mut value = String::new();
+        loop {
+            let Some(c) = self.peek() else {
+                return Err(format!("Missing quote: {}", self.input));
+            };
+            self.pos += 1;
+            match c {
+                '"' => return Ok(value),
+                '\\' => {
+                    let Some(escaped) = self.peek() else {
+                        return Err(format!("Missing quote: {}", self.input));
+                    };
+                    self.pos += 1;
+                    value.push(match escaped {
+                        'n' => '\n',
+                        't' => '\t',
+                        c => c,
+                    });
+                }
+                c => value.push(c),
+            }
+        }
+    }
+
+    /// `name(arg)`
+    fn function_call(&mut self) -> Result<String, String> {
+        let name = self.take_while(|c| c.is_ascii_alphanumeric() || c == '_');
+        self.skip_whitespace();
+        if !self.eat('(') {
+            return Err(format!("Undefined variable: {}", name));
+        }
+        self.skip_whitespace();
+        let arg = self.concat()?;
+        self.skip_whitespace();
+        if !self.eat(')') {
+            return Err(self.invalid());
+        }
+        self.call(&name, &arg)
+    }
+
+    fn call(&self, name: &str, arg: &str) -> Result<String, String> {
+        let row = self.editor.cursor.file_row();
+        let line = self.editor.buffer().row(row).map_or("", |r| r.chars());
+        match (name, arg) {
+            ("line", ".") => Ok((row + 1).to_string()),
+            ("line", "$") => Ok(self.editor.buffer().len().to_string()),
+            // Vim と同じくバイト単位の列番号 (1-indexed)
+            ("col", ".") => {
+                let bytes: usize = line
+                    .chars()
+                    .take(self.editor.cursor.col_index())
+                    .map(char::len_utf8)
+                    .sum();
+                Ok((bytes + 1).to_string())
+            }
+            ("col", "$") => Ok((line.len() + 1).to_string()),
+            ("expand", "%") => Ok(self.editor.filename().unwrap_or("").to_string()),
+            ("line" | "col" | "expand", _) => Err(format!("Invalid argument: {}", arg)),
+            _ => Err(format!("Unknown function: {}", name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::Buffer;
+    use crate::cursor::Position;
+
+    fn setup() -> Editor {
+        let buffer = Buffer::from_lines(["first", "日本語 text", "third"]);
+        let mut editor = Editor::from_buffer(buffer, Some("notes.txt".to_string()));
+        editor.cursor.move_to_position(Position::new(1, 4), 3, 22);
+        editor
+    }
+
+    #[test]
+    fn test_evaluate_builtins() {
+        let editor = setup();
+        let eval = |input| evaluate(input, &editor);
+
+        assert_eq!(eval("line('.')"), Ok("2".to_string()));
+        assert_eq!(eval("line(\"$\")"), Ok("3".to_string()));
+        // 列番号はバイト単位
+        assert_eq!(eval("col('.')"), Ok("11".to_string()));
+        assert_eq!(eval("expand('%')"), Ok("notes.txt".to_string()));
+        assert_eq!(eval("&tabstop"), Ok("8".to_string()));
+        assert_eq!(eval("&expandtab"), Ok("0".to_string()));
+    }
+
+    #[test]
+    fn test_evaluate_strings_and_concat() {
+        let editor = setup();
+        let eval = |input| evaluate(input, &editor);
+
+        assert_eq!(eval("'it''s'"), Ok("it's".to_string()));
+        assert_eq!(eval("\"a\\tb\""), Ok("a\tb".to_string()));
+        assert_eq!(
+            eval("'line ' . line('.') . '/' . line('$')"),
+            Ok("line 2/3".to_string())
+        );
+        // 空白で区切った複数の式
+        assert_eq!(eval("'ts:' &ts 42"), Ok("ts: 8 42".to_string()));
+        assert_eq!(eval(""), Ok(String::new()));
+    }
+
+    #[test]
+    fn test_evaluate_errors() {
+        let editor = setup();
+        let eval = |input| evaluate(input, &editor);
+
+        assert!(eval("'abc").unwrap_err().starts_with("Missing quote"));
+        assert!(eval("foo").unwrap_err().starts_with("Undefined variable"));
+        assert!(eval("foo(1)").unwrap_err().starts_with("Unknown function"));
+        assert!(
+            eval("line('x')")
+                .unwrap_err()
+                .starts_with("Invalid argument")
+        );
+        assert!(eval("&nosuch").unwrap_err().starts_with("Unknown option"));
+        assert!(
+            eval("line('.'")
+                .unwrap_err()
+                .starts_with("Invalid expression")
+        );
+        assert!(eval("'a' .").unwrap_err().starts_with("Invalid expression"));
+    }
+}
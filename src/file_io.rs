@@ -1,40 +1,355 @@
 use std::{
-    fs::File,
-    io::{self, Write},
+    fs::{self, File, OpenOptions, Permissions},
+    io::{self, Read, Write},
     path::Path,
 };
 
-use crate::buffer::Buffer;
+use encoding_rs::WINDOWS_1252;
+
+use crate::buffer::{Buffer, FileEncoding, FileFormat};
+
+/// UTF-8 の BOM
+const UTF8_BOM: &str = "\u{feff}";
 
 pub struct FileIO;
 
 impl FileIO {
     pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Buffer> {
-        let content = std::fs::read_to_string(path)?;
-        let trailing_newline = content.ends_with('\n');
+        Self::from_reader(File::open(path)?)
+    }
 
-        let mut buffer = Buffer::new();
-        for (index, line) in content.lines().enumerate() {
-            buffer.insert_row(index, line.to_string());
-        }
+    /// Reader の内容をすべて読み込んで Buffer を作成する (標準入力からの読み込みなど)
+    ///
+    /// UTF-8 として不正なバイト列を含む場合は latin1 として読み込む。
+    /// 先頭の BOM は取り除き、有無を Buffer に記録する。
+    pub fn from_reader<R: Read>(mut reader: R) -> io::Result<Buffer> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        let (content, encoding) = match String::from_utf8(bytes) {
+            Ok(content) => (content, FileEncoding::Utf8),
+            Err(e) => {
+                let (content, _) = WINDOWS_1252.decode_without_bom_handling(e.as_bytes());
+                (content.into_owned(), FileEncoding::Latin1)
+            }
+        };
+        let bom = encoding == FileEncoding::Utf8 && content.starts_with(UTF8_BOM);
+        let content = content.strip_prefix(UTF8_BOM).unwrap_or(&content);
+        let file_format = FileFormat::detect(content);
+        let line_ending = file_format.line_ending();
+        let trailing_newline = content.ends_with(line_ending);
+
+        // 空のファイルも Vim と同様に空の 1 行として読み込む
+        let body = content.strip_suffix(line_ending).unwrap_or(content);
+        let mut buffer = Buffer::from_lines(body.split(line_ending));
         buffer.set_trailing_newline(trailing_newline);
+        buffer.set_file_format(file_format);
+        buffer.set_encoding(encoding);
+        buffer.set_bom(bom);
 
         Ok(buffer)
     }
 
     pub fn save<P: AsRef<Path>>(path: P, buffer: &Buffer) -> io::Result<()> {
+        let bytes = Self::to_bytes(buffer)?;
+
         // 既存ファイルがある場合は上書きする
         let mut file = File::create(path)?;
+        file.write_all(&bytes)?;
+        file.flush()?;
+        Ok(())
+    }
+
+    /// 一時ファイルに書き込んでから名前を変更して保存する (`:set writebackup` の場合)
+    ///
+    /// 書き込みの途中で失敗しても元のファイルは壊れない。
+    /// シンボリックリンクの場合はリンク先のファイルを置き換えるので、リンクはそのまま残る。
+    pub fn save_atomic<P: AsRef<Path>>(path: P, buffer: &Buffer) -> io::Result<()> {
+        let path = path.as_ref();
+        let target = if fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_symlink()) {
+            match fs::canonicalize(path) {
+                Ok(target) => target,
+                // リンク先が存在しない場合は、リンクをたどって新しく作成する
+                Err(_) => return Self::save(path, buffer),
+            }
+        } else {
+            path.to_path_buf()
+        };
+        let bytes = Self::to_bytes(buffer)?;
+
+        let file_name = target
+            .file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file name"))?;
+        let temp = target.with_file_name(format!(".{}.zim-tmp", file_name.to_string_lossy()));
+        // 新しいファイルの場合は既定の権限で作成する
+        let permissions = fs::metadata(&target).ok().map(|m| m.permissions());
+        let result = Self::write_and_rename(&temp, &target, &bytes, permissions);
+        if result.is_err() {
+            let _ = fs::remove_file(&temp);
+        }
+        result
+    }
+
+    /// `temp` に書き込んでから `target` に名前を変更する
+    ///
+    /// `permissions` がある場合は一時ファイルに適用して、元のファイルの権限 (実行権限など) を引き継ぐ。
+    fn write_and_rename(
+        temp: &Path,
+        target: &Path,
+        bytes: &[u8],
+        permissions: Option<Permissions>,
+    ) -> io::Result<()> {
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        // 書き込み中に元のファイルより広い権限で読めないよう、最初から同じ権限で作成する
+        #[cfg(unix)]
+        if let Some(permissions) = &permissions {
+            use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+            options.mode(permissions.mode() & 0o777);
+        }
+        let mut file = options.open(temp)?;
+        file.write_all(bytes)?;
+        file.sync_all()?;
+        // umask の影響を受けないよう、作成後にもう一度設定する
+        if let Some(permissions) = permissions {
+            fs::set_permissions(temp, permissions)?;
+        }
+        fs::rename(temp, target)
+    }
 
-        for (i, row) in buffer.rows().iter().enumerate() {
+    /// 保存するバイト列を作成する (改行コード・BOM・文字コードを反映する)
+    fn to_bytes(buffer: &Buffer) -> io::Result<Vec<u8>> {
+        let line_ending = buffer.file_format().line_ending();
+        let mut content = String::new();
+        // BOM は UTF-8 の場合のみ書き込む
+        if buffer.bom() && buffer.encoding() == FileEncoding::Utf8 {
+            content.push_str(UTF8_BOM);
+        }
+        for (i, row) in buffer.iter().enumerate() {
+            content.push_str(row.chars());
             if i < buffer.len() - 1 || buffer.trailing_newline() {
-                writeln!(file, "{}", row.chars())?;
-            } else {
-                write!(file, "{}", row.chars())?;
+                content.push_str(line_ending);
             }
         }
+        // 変換できない文字がある場合は、ファイルを壊さないよう書き込む前にエラーにする
+        Self::encode(&content, buffer.encoding())
+    }
 
-        file.flush()?;
-        Ok(())
+    /// 保存用に文字コードを変換する
+    fn encode(content: &str, encoding: FileEncoding) -> io::Result<Vec<u8>> {
+        match encoding {
+            FileEncoding::Utf8 => Ok(content.as_bytes().to_vec()),
+            FileEncoding::Latin1 => {
+                let (bytes, _, unmappable) = WINDOWS_1252.encode(content);
+                if unmappable {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "cannot convert to latin1",
+                    ));
+                }
+                Ok(bytes.into_owned())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_reader() {
+        let buffer = FileIO::from_reader("line1\nline2\n".as_bytes()).unwrap();
+
+        assert_eq!(buffer.len(), 2);
+        assert_eq!(buffer.row(0).unwrap().chars(), "line1");
+        assert_eq!(buffer.row(1).unwrap().chars(), "line2");
+        assert!(buffer.trailing_newline());
+    }
+
+    #[test]
+    fn test_from_reader_without_trailing_newline() {
+        let buffer = FileIO::from_reader("only".as_bytes()).unwrap();
+
+        assert_eq!(buffer.len(), 1);
+        assert!(!buffer.trailing_newline());
+    }
+
+    #[test]
+    fn test_from_reader_empty_file() {
+        // 空のファイルは空の 1 行 (末尾の改行なし)
+        let buffer = FileIO::from_reader("".as_bytes()).unwrap();
+        assert_eq!(buffer.len(), 1);
+        assert_eq!(buffer.row(0).unwrap().chars(), "");
+        assert!(!buffer.trailing_newline());
+
+        // 改行だけのファイルも 1 行 (末尾の改行あり)
+        let buffer = FileIO::from_reader("\n".as_bytes()).unwrap();
+        assert_eq!(buffer.len(), 1);
+        assert_eq!(buffer.row(0).unwrap().chars(), "");
+        assert!(buffer.trailing_newline());
+    }
+
+    #[test]
+    fn test_from_reader_trailing_blank_line() {
+        // 末尾の空行は 1 行として残す
+        let buffer = FileIO::from_reader("a\n\n".as_bytes()).unwrap();
+        assert_eq!(buffer.len(), 2);
+        assert_eq!(buffer.row(1).unwrap().chars(), "");
+        assert!(buffer.trailing_newline());
+    }
+
+    #[test]
+    fn test_save_round_trip_trailing_newlines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("round_trip.txt");
+        for content in ["a\nb", "a\nb\n", "a\nb\n\n", "a\nb\n\n\n", "\n\n"] {
+            std::fs::write(&path, content).unwrap();
+            for _ in 0..2 {
+                let buffer = FileIO::open(&path).unwrap();
+                FileIO::save(&path, &buffer).unwrap();
+                assert_eq!(
+                    std::fs::read_to_string(&path).unwrap(),
+                    content,
+                    "content: {:?}",
+                    content
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_from_reader_detects_file_format() {
+        let buffer = FileIO::from_reader("a\r\nb\r\n".as_bytes()).unwrap();
+        assert_eq!(buffer.file_format(), FileFormat::Dos);
+        assert_eq!(buffer.len(), 2);
+        assert_eq!(buffer.row(1).unwrap().chars(), "b");
+        assert!(buffer.trailing_newline());
+
+        let buffer = FileIO::from_reader("a\rb".as_bytes()).unwrap();
+        assert_eq!(buffer.file_format(), FileFormat::Mac);
+        assert_eq!(buffer.row(0).unwrap().chars(), "a");
+        assert_eq!(buffer.row(1).unwrap().chars(), "b");
+        assert!(!buffer.trailing_newline());
+
+        let buffer = FileIO::from_reader("a\n".as_bytes()).unwrap();
+        assert_eq!(buffer.file_format(), FileFormat::Unix);
+    }
+
+    #[test]
+    fn test_from_reader_mixed_line_endings() {
+        // \r\n と \n が混ざっている場合は unix として読み、\r は行に残す
+        let buffer = FileIO::from_reader("a\r\nb\nc\n".as_bytes()).unwrap();
+        assert_eq!(buffer.file_format(), FileFormat::Unix);
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(buffer.row(0).unwrap().chars(), "a\r");
+        assert_eq!(buffer.row(1).unwrap().chars(), "b");
+        assert!(buffer.trailing_newline());
+
+        // 保存しても内容は変わらない
+        assert_eq!(FileIO::to_bytes(&buffer).unwrap(), b"a\r\nb\nc\n");
+    }
+
+    #[test]
+    fn test_from_reader_invalid_utf8_as_latin1() {
+        let buffer = FileIO::from_reader(&b"caf\xe9\n"[..]).unwrap();
+        assert_eq!(buffer.encoding(), FileEncoding::Latin1);
+        assert_eq!(buffer.row(0).unwrap().chars(), "caf\u{e9}");
+
+        let buffer = FileIO::from_reader("caf\u{e9}\n".as_bytes()).unwrap();
+        assert_eq!(buffer.encoding(), FileEncoding::Utf8);
+    }
+
+    #[test]
+    fn test_from_reader_strips_bom() {
+        let buffer = FileIO::from_reader(&b"\xef\xbb\xbfone\r\ntwo\r\n"[..]).unwrap();
+        assert!(buffer.bom());
+        assert_eq!(buffer.row(0).unwrap().chars(), "one");
+        assert_eq!(buffer.file_format(), FileFormat::Dos);
+
+        let buffer = FileIO::from_reader("one\n".as_bytes()).unwrap();
+        assert!(!buffer.bom());
+    }
+
+    #[test]
+    fn test_save_latin1_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("latin1.txt");
+        std::fs::write(&path, b"caf\xe9\nna\xefve\n").unwrap();
+
+        let buffer = FileIO::open(&path).unwrap();
+        FileIO::save(&path, &buffer).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"caf\xe9\nna\xefve\n");
+    }
+
+    #[test]
+    fn test_save_latin1_unmappable() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("latin1.txt");
+        let mut buffer = Buffer::from_lines(["\u{3042}"]);
+        buffer.set_encoding(FileEncoding::Latin1);
+
+        assert!(FileIO::save(&path, &buffer).is_err());
+        assert!(!path.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_save_atomic_preserves_symlink() {
+        use std::os::unix::fs::{PermissionsExt, symlink};
+
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("target.txt");
+        let link = dir.path().join("link.txt");
+        std::fs::write(&target, "old\n").unwrap();
+        std::fs::set_permissions(&target, std::fs::Permissions::from_mode(0o600)).unwrap();
+        symlink(&target, &link).unwrap();
+
+        let buffer = FileIO::from_reader("new\n".as_bytes()).unwrap();
+        FileIO::save_atomic(&link, &buffer).unwrap();
+
+        // リンクは残り、リンク先の内容と権限が更新される
+        assert!(
+            std::fs::symlink_metadata(&link)
+                .unwrap()
+                .file_type()
+                .is_symlink()
+        );
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "new\n");
+        let mode = std::fs::metadata(&target).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        // 一時ファイルは残らない
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_save_keeps_executable_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("script.sh");
+        std::fs::write(&path, "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let buffer = FileIO::from_reader("#!/bin/sh\necho hi\n".as_bytes()).unwrap();
+        for save in [FileIO::save::<&Path>, FileIO::save_atomic::<&Path>] {
+            save(&path, &buffer).unwrap();
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o755);
+        }
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "#!/bin/sh\necho hi\n"
+        );
+    }
+
+    #[test]
+    fn test_save_atomic_new_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("new.txt");
+        let buffer = FileIO::from_reader("a\nb\n".as_bytes()).unwrap();
+
+        FileIO::save_atomic(&path, &buffer).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "a\nb\n");
     }
 }
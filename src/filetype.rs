@@ -0,0 +1,90 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// ファイルタイプごとに `:set` で適用する設定の既定値
+///
+/// 設定ファイルの `autocmd FileType` で置き換えられる ([`EditorConfig::filetypes`])。
+///
+/// [`EditorConfig::filetypes`]: crate::config::EditorConfig::filetypes
+const DEFAULT_OPTIONS: &[(&str, &[&str])] = &[
+    ("rust", &["expandtab", "tabstop=4", "shiftwidth=4"]),
+    ("make", &["noexpandtab", "tabstop=8", "shiftwidth=8"]),
+    ("markdown", &["wrap"]),
+];
+
+/// ファイルタイプごとの行コメントの記号 (長いものから順に判定する)
+const COMMENT_LEADERS: &[(&str, &[&str])] = &[
+    ("rust", &["///", "//!", "//"]),
+    ("make", &["#"]),
+    ("sh", &["#"]),
+];
+
+/// ファイル名からファイルタイプを判定する
+pub fn detect(filename: &str) -> Option<&'static str> {
+    let path = Path::new(filename);
+    let name = path.file_name()?.to_str()?;
+    if matches!(name, "Makefile" | "makefile" | "GNUmakefile") {
+        return Some("make");
+    }
+    match path.extension()?.to_str()? {
+        "rs" => Some("rust"),
+        "mk" => Some("make"),
+        "md" | "markdown" => Some("markdown"),
+        "sh" | "bash" => Some("sh"),
+        _ => None,
+    }
+}
+
+/// ファイルタイプごとの `:set` の引数の一覧の既定値
+pub fn default_options() -> BTreeMap<String, Vec<String>> {
+    DEFAULT_OPTIONS
+        .iter()
+        .map(|(name, options)| {
+            let options = options.iter().map(|option| option.to_string()).collect();
+            (name.to_string(), options)
+        })
+        .collect()
+}
+
+/// 行がコメントであれば、行頭の空白を除いた部分の先頭のコメント記号を返す
+pub fn comment_leader(filetype: &str, line: &str) -> Option<&'static str> {
+    let (_, leaders) = COMMENT_LEADERS.iter().find(|(name, _)| *name == filetype)?;
+    let body = line.trim_start_matches([' ', '\t']);
+    leaders
+        .iter()
+        .copied()
+        .find(|leader| body.starts_with(leader))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(detect("src/main.rs"), Some("rust"));
+        assert_eq!(detect("Makefile"), Some("make"));
+        assert_eq!(detect("/tmp/project/Makefile"), Some("make"));
+        assert_eq!(detect("README.md"), Some("markdown"));
+        assert_eq!(detect("build.sh"), Some("sh"));
+        assert_eq!(detect("notes.txt"), None);
+        assert_eq!(detect("Cargo"), None);
+    }
+
+    #[test]
+    fn test_default_options() {
+        let options = default_options();
+        assert!(options["rust"].contains(&"expandtab".to_string()));
+        assert_eq!(options["markdown"], vec!["wrap"]);
+        assert!(!options.contains_key("sh"));
+    }
+
+    #[test]
+    fn test_comment_leader() {
+        assert_eq!(comment_leader("rust", "    // note"), Some("//"));
+        assert_eq!(comment_leader("rust", "/// doc"), Some("///"));
+        assert_eq!(comment_leader("sh", "\t# x"), Some("#"));
+        assert_eq!(comment_leader("rust", "let x = 1; // y"), None);
+        assert_eq!(comment_leader("markdown", "# title"), None);
+    }
+}
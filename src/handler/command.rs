@@ -1,130 +1,47 @@
 use termion::event::Key;
 
-use crate::cursor::Cursor;
+use crate::command_line::CommandLine;
 use crate::editor::Editor;
+use crate::ex_command::{self, ExContext};
 use crate::mode::ModeManager;
+use crate::range::{LineRange, parse_range};
+use crate::search::{Highlight, SearchDirection};
+use crate::substitute::Substitute;
 
-use super::HandlerResult;
+use super::{HandlerResult, search};
 
 pub fn handle(
     key: Key,
     editor: &mut Editor,
-    cursor: &mut Cursor,
     mode_manager: &mut ModeManager,
-    command_buffer: &mut String,
+    command_line: &mut CommandLine,
+    terminal_size: (u16, u16),
     editor_rows: u16,
 ) -> HandlerResult {
-    match key {
+    let CommandLine {
+        prompt,
+        buffer: command_buffer,
+        completion,
+        preview,
+    } = command_line;
+
+    let result = match key {
         Key::Char('\n') => {
-            let parts: Vec<&str> = command_buffer.split_whitespace().collect();
-            let cmd = parts.first().copied().unwrap_or("");
-
-            // コマンド実行
-            let result = match cmd {
-                "q" => {
-                    // 未保存の変更がある場合は警告
-                    if editor.is_dirty() {
-                        mode_manager.enter_normal();
-                        command_buffer.clear();
-                        return HandlerResult::StatusMessage(
-                            "No write since last change (add ! to override)".to_string(),
-                        );
-                    } else {
-                        return HandlerResult::Quit;
-                    }
-                }
-                "q!" => {
-                    return HandlerResult::Quit;
-                }
-                "w" => {
-                    let msg = match editor.save() {
-                        Ok(_) => {
-                            let bytes = editor
-                                .buffer()
-                                .rows()
-                                .iter()
-                                .map(|r| r.chars().len())
-                                .sum::<usize>();
-                            format!(
-                                "\"{}\" {}L {}B written",
-                                editor.filename().unwrap_or("[No Name]"),
-                                editor.buffer().len(),
-                                bytes
-                            )
-                        }
-                        Err(e) => {
-                            format!("Error: {}", e)
-                        }
-                    };
-                    mode_manager.enter_normal();
-                    command_buffer.clear();
-                    HandlerResult::StatusMessage(msg)
-                }
-                "wq" => match editor.save() {
-                    Ok(_) => return HandlerResult::Quit,
-                    Err(e) => {
-                        mode_manager.enter_normal();
-                        command_buffer.clear();
-                        HandlerResult::StatusMessage(format!("Error: {}", e))
-                    }
-                },
-                "e" | "e!" => {
-                    let force = cmd == "e!";
-                    let msg = if let Some(filename) = parts.get(1) {
-                        if !force && editor.is_dirty() {
-                            "No write since last change (add ! to override)".to_string()
-                        } else {
-                            match editor.open_file(filename.to_string()) {
-                                Ok(_) => {
-                                    *cursor = Cursor::new();
-                                    format!("\"{}\" loaded", filename)
-                                }
-                                Err(e) => format!("Cannot open file: {}", e),
-                            }
-                        }
-                    } else {
-                        // ファイル名なしのパターン
-                        if !force && editor.is_dirty() {
-                            "No write since last change (add ! to override)".to_string()
-                        } else {
-                            match editor.reload() {
-                                Ok(_) => {
-                                    // このときはカーソル位置をリセットしない(いきなり位置が変わるとびっくりするため
-                                    let msg = format!(
-                                        "\"{}\" reloaded",
-                                        editor.filename().unwrap_or("[No Name]")
-                                    );
-
-                                    // カーソル位置調整
-                                    // (更新前のカーソル位置よりファイルが短くなった場合などに必要
-                                    let (buffer_len, line_len) =
-                                        editor.buffer_info(cursor.file_row());
-                                    cursor.ensure_within_bounds(buffer_len, line_len, editor_rows);
-
-                                    msg
-                                }
-                                Err(e) => format!("Error: {}", e),
-                            }
-                        }
-                    };
-                    mode_manager.enter_normal();
-                    command_buffer.clear();
-                    HandlerResult::StatusMessage(msg)
-                }
-                "" => {
-                    // 無視
-                    mode_manager.enter_normal();
-                    command_buffer.clear();
-                    HandlerResult::Continue
-                }
-                _ => {
-                    let msg = format!("Not an editor command: {}", command_buffer);
-                    mode_manager.enter_normal();
-                    command_buffer.clear();
-                    HandlerResult::StatusMessage(msg)
-                }
+            let command = std::mem::take(command_buffer);
+            completion.reset();
+            mode_manager.enter_normal();
+            let direction = match *prompt {
+                '/' => Some(SearchDirection::Forward),
+                '?' => Some(SearchDirection::Backward),
+                _ => None,
             };
-            result
+            if let Some(direction) = direction {
+                return search::start(&command, direction, editor, editor_rows);
+            }
+            if !command.trim().is_empty() {
+                editor.last_command = Some(command.clone());
+            }
+            execute(&command, editor, editor_rows)
         }
         Key::Esc => {
             // コマンドモードをキャンセル
@@ -132,14 +49,409 @@ pub fn handle(
             command_buffer.clear();
             HandlerResult::Continue
         }
+        Key::Up => {
+            // 直前に実行したコマンド (検索の場合は直前のパターン) を呼び出す
+            let last = if *prompt == ':' {
+                editor.last_command.as_ref()
+            } else {
+                editor.last_search.as_ref().map(|(pattern, _)| pattern)
+            };
+            if let Some(last_command) = last {
+                *command_buffer = last_command.clone();
+                completion.reset();
+            }
+            HandlerResult::ClearStatus
+        }
+        Key::Char('\t') | Key::Ctrl('d') if *prompt == ':' => {
+            let list = key == Key::Ctrl('d');
+            match completion.complete(command_buffer, list, terminal_size.0 as usize) {
+                Some(candidates) => HandlerResult::StatusMessage(candidates),
+                None => HandlerResult::ClearStatus,
+            }
+        }
         Key::Char(c) => {
             command_buffer.push(c);
-            HandlerResult::Continue
+            completion.reset();
+            HandlerResult::ClearStatus
         }
         Key::Backspace => {
             command_buffer.pop();
-            HandlerResult::Continue
+            completion.reset();
+            HandlerResult::ClearStatus
         }
         _ => HandlerResult::Continue,
+    };
+
+    // 入力中の `:s` のパターンに一致する箇所を強調表示する
+    *preview = if mode_manager.is_command() && *prompt == ':' {
+        substitute_preview(command_buffer, editor)
+    } else {
+        None
+    };
+    result
+}
+
+/// Ex コマンドを実行する
+///
+/// コマンドラインでの Enter と、Normal mode の `@:` から呼ばれる。
+pub fn execute(command: &str, editor: &mut Editor, editor_rows: u16) -> HandlerResult {
+    execute_with_confirm(command, editor, editor_rows, false)
+}
+
+/// Ex コマンドを実行する
+///
+/// `confirm` が true の場合 (`:confirm {cmd}`)、未保存の変更などでエラーにする代わりに確認する。
+pub fn execute_with_confirm(
+    command: &str,
+    editor: &mut Editor,
+    editor_rows: u16,
+    confirm: bool,
+) -> HandlerResult {
+    let last_row = editor.buffer().len().saturating_sub(1);
+    let (range, rest) = match parse_range(command, editor.cursor.file_row(), last_row) {
+        Ok(parsed) => parsed,
+        Err(e) => return HandlerResult::StatusMessage(e),
+    };
+    let (cmd, args) = split_command(rest);
+    if cmd.is_empty() {
+        return match range {
+            _ if !args.is_empty() => not_an_editor_command(command, ""),
+            // `:N` で指定行に移動
+            Some(range) => {
+                editor
+                    .cursor
+                    .move_to_row(range.end, editor.buffer().len(), editor_rows);
+                editor.cursor.move_to_line_start();
+                HandlerResult::ClearStatus
+            }
+            // 無視
+            None => HandlerResult::Continue,
+        };
+    }
+    // 組み込みのコマンドになければ、起動時に追加したコマンドから探す
+    let Some((ex_command, bang)) = ex_command::resolve(cmd)
+        .map(|(c, bang)| (c.clone(), bang))
+        .or_else(|| editor.registry.command(cmd))
+    else {
+        return not_an_editor_command(command, cmd);
+    };
+    if range.is_some() && !ex_command.range {
+        return HandlerResult::StatusMessage("No range allowed".to_string());
+    }
+    if bang && !ex_command.bang {
+        return HandlerResult::StatusMessage("No ! allowed".to_string());
+    }
+
+    let mut ctx = ExContext {
+        editor,
+        editor_rows,
+        range,
+        bang,
+        args,
+        confirm,
+    };
+    (ex_command.run)(&mut ctx)
+}
+
+/// 不明なコマンドのエラー (近い名前のコマンドがあれば候補として表示する)
+fn not_an_editor_command(command: &str, cmd: &str) -> HandlerResult {
+    let msg = match ex_command::suggest(cmd) {
+        Some(name) => format!(
+            "Not an editor command: {} (did you mean :{}?)",
+            command, name
+        ),
+        None => format!("Not an editor command: {}", command),
+    };
+    HandlerResult::Error(msg)
+}
+
+/// コマンド名と引数に分割する
+///
+/// コマンド名は英字の並び (末尾の `!` を含む) で、`s/a/b/` のように
+/// 空白なしで引数が続く場合にも対応する。
+fn split_command(command: &str) -> (&str, &str) {
+    let command = command.trim();
+    let mut len = command
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or(command.len());
+    if command[len..].starts_with('!') {
+        len += 1;
+    }
+    let (name, args) = command.split_at(len);
+    (name, args.trim_start())
+}
+
+/// 入力中の `:s` のパターンに一致する箇所の強調表示を作る
+///
+/// 範囲やパターンが入力途中で不完全な場合は None を返す。
+pub fn substitute_preview(command: &str, editor: &Editor) -> Option<Highlight> {
+    let last_row = editor.buffer().len().saturating_sub(1);
+    let (range, rest) = parse_range(command, editor.cursor.file_row(), last_row).ok()?;
+    let (cmd, args) = split_command(rest);
+    let (command, _) = ex_command::resolve(cmd)?;
+    if command.name != "substitute" {
+        return None;
+    }
+    // `:s` (Editor::substitute) と同じ設定とフラグで一致させる
+    let sub = Substitute::partial(args)?;
+    let regex = sub
+        .regex(editor.config.preservecase, editor.config.verymagic)
+        .ok()?;
+    Some(Highlight {
+        regex,
+        range: Some(range.unwrap_or(LineRange::single(editor.cursor.file_row()))),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::Buffer;
+
+    fn send_key(
+        key: Key,
+        editor: &mut Editor,
+        mode_manager: &mut ModeManager,
+        command_line: &mut CommandLine,
+    ) -> HandlerResult {
+        handle(key, editor, mode_manager, command_line, (80, 24), 22)
+    }
+
+    #[test]
+    fn test_up_prefills_last_command() {
+        let mut editor = Editor::new();
+        let mut mode_manager = ModeManager::new();
+        let mut command_line = CommandLine::new();
+
+        mode_manager.enter_command();
+        for c in "set list".chars() {
+            send_key(
+                Key::Char(c),
+                &mut editor,
+                &mut mode_manager,
+                &mut command_line,
+            );
+        }
+        send_key(
+            Key::Char('\n'),
+            &mut editor,
+            &mut mode_manager,
+            &mut command_line,
+        );
+        assert!(mode_manager.is_normal());
+        assert!(command_line.buffer.is_empty());
+        assert_eq!(editor.last_command.as_deref(), Some("set list"));
+
+        mode_manager.enter_command();
+        send_key(Key::Up, &mut editor, &mut mode_manager, &mut command_line);
+        assert_eq!(command_line.buffer, "set list");
+    }
+
+    fn make_editor_with_lines(lines: &[&str]) -> Editor {
+        let buffer = Buffer::from_lines(lines);
+        Editor::from_buffer(buffer, None)
+    }
+
+    /// `:Upper [text]`: 範囲の行を大文字にする (引数があれば行末に追加する)
+    fn upper(ctx: &mut ExContext) -> HandlerResult {
+        let range = ctx
+            .range
+            .unwrap_or(LineRange::single(ctx.editor.cursor.file_row()));
+        for row in range.start..=range.end {
+            let line = ctx.editor.buffer().row(row).unwrap().chars().to_uppercase();
+            let suffix = if ctx.bang { "!" } else { "" };
+            let line = format!("{}{}{}", line, ctx.args, suffix);
+            ctx.editor.buffer_mut().set_row(row, line);
+        }
+        HandlerResult::ClearStatus
+    }
+
+    #[test]
+    fn test_execute_registered_command() {
+        let mut editor = make_editor_with_lines(&["one", "two", "three"]);
+        editor.registry.register_command("Upper", upper).unwrap();
+
+        execute("2,3Upper", &mut editor, 22);
+        execute("Upper! ?", &mut editor, 22);
+        let rows: Vec<&str> = editor.buffer().iter().map(|r| r.chars()).collect();
+        assert_eq!(rows, vec!["ONE?!", "TWO", "THREE"]);
+
+        // 省略形では呼べない
+        assert!(matches!(
+            execute("Up", &mut editor, 22),
+            HandlerResult::Error(msg) if msg.starts_with("Not an editor command: Up")
+        ));
+    }
+
+    fn row(editor: &Editor, index: usize) -> &str {
+        editor.buffer().row(index).unwrap().chars()
+    }
+
+    #[test]
+    fn test_substitute_preview() {
+        let mut editor = make_editor_with_lines(&["foo", "bar foo"]);
+        let mut mode_manager = ModeManager::new();
+        let mut command_line = CommandLine::new();
+
+        mode_manager.enter_command();
+        for c in "%s/fo".chars() {
+            send_key(
+                Key::Char(c),
+                &mut editor,
+                &mut mode_manager,
+                &mut command_line,
+            );
+        }
+        let preview = command_line.preview.as_ref().unwrap();
+        assert_eq!(preview.range, Some(LineRange::new(0, 1)));
+        assert_eq!(preview.match_cols(1, row(&editor, 1)), vec![(4, 6)]);
+
+        // 不完全なパターンではプレビューしない
+        for c in r"\(".chars() {
+            send_key(
+                Key::Char(c),
+                &mut editor,
+                &mut mode_manager,
+                &mut command_line,
+            );
+        }
+        assert!(command_line.preview.is_none());
+
+        // Esc ではバッファを変更せずにプレビューを消す
+        send_key(
+            Key::Backspace,
+            &mut editor,
+            &mut mode_manager,
+            &mut command_line,
+        );
+        send_key(
+            Key::Backspace,
+            &mut editor,
+            &mut mode_manager,
+            &mut command_line,
+        );
+        assert!(command_line.preview.is_some());
+        send_key(Key::Esc, &mut editor, &mut mode_manager, &mut command_line);
+        assert!(command_line.preview.is_none());
+        assert_eq!(row(&editor, 0), "foo");
+        assert!(!editor.is_dirty());
+    }
+
+    #[test]
+    fn test_substitute_preview_uses_flags() {
+        let editor = make_editor_with_lines(&["FOO foo"]);
+        let preview = |command| substitute_preview(command, &editor).unwrap();
+
+        // :s と同じく i / I で大文字小文字の扱いを変える
+        let highlight = preview("s/foo/bar/i");
+        assert_eq!(
+            highlight.match_cols(0, row(&editor, 0)),
+            vec![(0, 3), (4, 7)]
+        );
+        let highlight = preview("s/foo/bar/");
+        assert_eq!(highlight.match_cols(0, row(&editor, 0)), vec![(4, 7)]);
+    }
+
+    #[test]
+    fn test_delete_yank_and_put() {
+        let mut editor = make_editor_with_lines(&["1", "2", "3", "4", "5", "6"]);
+
+        // :2,5d a で 2〜5 行目をレジスタ a に削除
+        let result = execute("2,5d a", &mut editor, 22);
+        assert!(matches!(result, HandlerResult::StatusMessage(msg) if msg == "4 fewer lines"));
+        assert_eq!(editor.buffer().len(), 2);
+        assert_eq!(row(&editor, 1), "6");
+        assert_eq!(editor.cursor.file_row(), 1);
+        assert_eq!(
+            editor.registers.get('a').unwrap().content,
+            vec!["2", "3", "4", "5"]
+        );
+
+        // :put a で現在行の下に貼り付けて最後の行に移動
+        execute("1put a", &mut editor, 22);
+        let rows: Vec<&str> = (0..editor.buffer().len())
+            .map(|i| row(&editor, i))
+            .collect();
+        assert_eq!(rows, vec!["1", "2", "3", "4", "5", "6"]);
+        assert_eq!(editor.cursor.file_row(), 4);
+
+        // :y は範囲を無名レジスタにヤンクし、カーソルは動かない
+        execute("1,2y", &mut editor, 22);
+        assert_eq!(editor.yank.content(), ["1", "2"]);
+        assert_eq!(editor.cursor.file_row(), 4);
+        execute("put!", &mut editor, 22);
+        assert_eq!(row(&editor, 4), "1");
+        assert_eq!(row(&editor, 5), "2");
+        assert_eq!(row(&editor, 6), "5");
+
+        let result = execute("d !", &mut editor, 22);
+        assert!(
+            matches!(result, HandlerResult::StatusMessage(msg) if msg == "Trailing characters: !")
+        );
+    }
+
+    #[test]
+    fn test_substitute_current_line_and_range() {
+        let mut editor = make_editor_with_lines(&["foo foo", "foo", "bar"]);
+
+        execute("s/foo/baz/", &mut editor, 22);
+        assert_eq!(row(&editor, 0), "baz foo");
+        assert_eq!(row(&editor, 1), "foo");
+        assert!(editor.is_dirty());
+
+        let result = execute("%s/foo/qux/g", &mut editor, 22);
+        assert_eq!(row(&editor, 0), "baz qux");
+        assert_eq!(row(&editor, 1), "qux");
+        assert_eq!(editor.cursor.file_row(), 1);
+        assert!(
+            matches!(result, HandlerResult::StatusMessage(msg) if msg == "2 substitutions on 2 lines")
+        );
+
+        let result = execute("%s/nothing/x/", &mut editor, 22);
+        assert!(matches!(result, HandlerResult::Error(msg) if msg == "Pattern not found: nothing"));
+    }
+
+    #[test]
+    fn test_substitute_preservecase() {
+        let mut editor = make_editor_with_lines(&["foo Foo FOO"]);
+
+        execute("set preservecase", &mut editor, 22);
+        execute("s/foo/bar/g", &mut editor, 22);
+        assert_eq!(row(&editor, 0), "bar Bar BAR");
+    }
+
+    #[test]
+    fn test_line_number_jump() {
+        let mut editor = make_editor_with_lines(&["a", "b", "c"]);
+
+        execute("3", &mut editor, 22);
+        assert_eq!(editor.cursor.file_row(), 2);
+
+        let result = execute("2w", &mut editor, 22);
+        assert!(matches!(result, HandlerResult::StatusMessage(msg) if msg == "No range allowed"));
+    }
+
+    #[test]
+    fn test_command_abbreviation_and_suggestion() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("abbrev.txt");
+        let mut editor = Editor::from_buffer(Buffer::new(), Some(path.to_string_lossy().into()));
+
+        // `:wr` は `:write`
+        let result = execute("wr", &mut editor, 22);
+        assert!(matches!(result, HandlerResult::StatusMessage(msg) if msg.ends_with("written")));
+        assert!(path.exists());
+
+        let result = execute("wirte", &mut editor, 22);
+        assert!(matches!(
+            result,
+            HandlerResult::Error(msg)
+                if msg == "Not an editor command: wirte (did you mean :write?)"
+        ));
+        let result = execute("xyzzy", &mut editor, 22);
+        assert!(matches!(
+            result,
+            HandlerResult::Error(msg) if msg == "Not an editor command: xyzzy"
+        ));
     }
 }
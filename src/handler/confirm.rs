@@ -0,0 +1,126 @@
+use termion::event::Key;
+
+use crate::cursor::Position;
+use crate::editor::Editor;
+use crate::substitute::{ConfirmAnswer, ConfirmSubstitute};
+
+use super::HandlerResult;
+
+/// 確認付き置換 (`:s///c`) の確認中のキー入力を処理する
+pub fn handle(key: Key, editor: &mut Editor, editor_rows: u16) -> HandlerResult {
+    let Some(mut confirm) = editor.confirm.take() else {
+        return HandlerResult::Continue;
+    };
+
+    let answer = match key {
+        Key::Char(c) => ConfirmAnswer::from_char(c),
+        Key::Esc | Key::Ctrl('c') => Some(ConfirmAnswer::Quit),
+        _ => None,
+    };
+    let Some(answer) = answer else {
+        // 無効なキーは無視して同じ一致で確認を続ける
+        let prompt = confirm.prompt();
+        editor.confirm = Some(confirm);
+        return HandlerResult::StatusMessage(prompt);
+    };
+
+    // 最初の置換の前にアンドゥ用のスナップショットを保存する
+    let snapshot = confirm.result().is_none().then(|| editor.snapshot());
+    if confirm.answer(answer, editor.buffer_mut()) {
+        if let Some(snapshot) = snapshot {
+            editor.history.commit(snapshot);
+        }
+        editor.mark_dirty();
+    }
+
+    match confirm.find_next(editor.buffer()) {
+        Some(pos) => {
+            let prompt = confirm.prompt();
+            move_to(pos, editor, editor_rows);
+            editor.confirm = Some(confirm);
+            HandlerResult::StatusMessage(prompt)
+        }
+        None => finish(&confirm, editor, editor_rows),
+    }
+}
+
+/// 確認付き置換を開始して最初の一致に移動する
+pub fn start(
+    mut confirm: ConfirmSubstitute,
+    pattern: &str,
+    editor: &mut Editor,
+    editor_rows: u16,
+) -> HandlerResult {
+    let Some(pos) = confirm.find_next(editor.buffer()) else {
+        return HandlerResult::Error(format!("Pattern not found: {}", pattern));
+    };
+    let prompt = confirm.prompt();
+    move_to(pos, editor, editor_rows);
+    editor.confirm = Some(confirm);
+    HandlerResult::StatusMessage(prompt)
+}
+
+/// 置換の終了時に結果を表示する
+fn finish(confirm: &ConfirmSubstitute, editor: &mut Editor, editor_rows: u16) -> HandlerResult {
+    match confirm.result() {
+        Some(result) => {
+            move_to(Position::new(result.last_row, 0), editor, editor_rows);
+            if result.lines > 1 {
+                HandlerResult::StatusMessage(format!(
+                    "{} substitutions on {} lines",
+                    result.count, result.lines
+                ))
+            } else {
+                HandlerResult::ClearStatus
+            }
+        }
+        None => HandlerResult::ClearStatus,
+    }
+}
+
+/// カーソルを一致の位置に移動する
+fn move_to(pos: Position, editor: &mut Editor, editor_rows: u16) {
+    editor
+        .cursor
+        .move_to_position(pos, editor.buffer().len(), editor_rows);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::Buffer;
+    use crate::handler::command;
+
+    #[test]
+    fn test_confirm_keys() {
+        let buffer = Buffer::from_lines(["foo foo", "foo"]);
+        let mut editor = Editor::from_buffer(buffer, None);
+
+        let result = command::execute("%s/foo/bar/gc", &mut editor, 22);
+        assert!(
+            matches!(result, HandlerResult::StatusMessage(msg) if msg.starts_with("replace with bar"))
+        );
+        assert!(editor.confirm.is_some());
+
+        // 無効なキーでは何もしない
+        handle(Key::Char('x'), &mut editor, 22);
+        assert_eq!(editor.cursor.position(), Position::new(0, 0));
+
+        handle(Key::Char('n'), &mut editor, 22);
+        assert_eq!(editor.cursor.position(), Position::new(0, 4));
+        handle(Key::Char('y'), &mut editor, 22);
+        assert_eq!(editor.cursor.position(), Position::new(1, 0));
+        assert!(editor.is_dirty());
+
+        handle(Key::Esc, &mut editor, 22);
+        assert!(editor.confirm.is_none());
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "foo bar");
+        assert_eq!(editor.buffer().row(1).unwrap().chars(), "foo");
+
+        // 1 回のアンドゥで元に戻る
+        let current = editor.snapshot();
+        let prev = editor.history.undo(current).unwrap();
+        editor.restore_snapshot(prev);
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "foo foo");
+    }
+}
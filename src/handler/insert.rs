@@ -1,60 +1,208 @@
 use termion::event::Key;
 
-use crate::cursor::{Cursor, Position};
+use crate::cursor::Position;
 use crate::editor::Editor;
+use crate::literal::{LiteralInput, LiteralStep};
 use crate::mode::ModeManager;
+use crate::motion;
 
 use super::HandlerResult;
 
+/// autopairs で補完する括弧・引用符の組
+const PAIRS: &[(char, char)] = &[('(', ')'), ('[', ']'), ('{', '}'), ('"', '"'), ('\'', '\'')];
+
+/// autopairs 有効時の文字入力の動作
+#[derive(Debug, PartialEq, Eq)]
+enum PairAction {
+    /// 通常どおり挿入する
+    Insert,
+    /// 入力した文字と対応する閉じ文字を挿入する
+    InsertPair(char),
+    /// 次の文字が入力した閉じ文字なので、挿入せずに飛ばす
+    SkipOver,
+}
+
+/// `col` の位置に `ch` を入力したときの動作を決める
+fn pair_action(line: &str, col: usize, ch: char) -> PairAction {
+    let chars: Vec<char> = line.chars().collect();
+    let prev = col.checked_sub(1).and_then(|i| chars.get(i)).copied();
+    let next = chars.get(col).copied();
+
+    if next == Some(ch) && PAIRS.iter().any(|(_, close)| *close == ch) {
+        return PairAction::SkipOver;
+    }
+    let Some((open, close)) = PAIRS.iter().find(|(open, _)| *open == ch) else {
+        return PairAction::Insert;
+    };
+    // 次が単語の途中の場合は補完しない
+    if next.is_some_and(|c| !c.is_whitespace() && !PAIRS.iter().any(|(_, close)| *close == c)) {
+        return PairAction::Insert;
+    }
+    if open == close {
+        // `don't` のような単語中の引用符や、文字列の中と思われる場合は補完しない
+        let count = chars[..col.min(chars.len())]
+            .iter()
+            .filter(|c| *c == open)
+            .count();
+        if prev.is_some_and(|c| c.is_alphanumeric()) || count % 2 == 1 {
+            return PairAction::Insert;
+        }
+    }
+    PairAction::InsertPair(*close)
+}
+
+/// カーソルが空の括弧・引用符の組の間にあるか (Backspace で両方削除する)
+fn is_empty_pair(line: &str, col: usize) -> bool {
+    let chars: Vec<char> = line.chars().collect();
+    match (
+        col.checked_sub(1).and_then(|i| chars.get(i)),
+        chars.get(col),
+    ) {
+        (Some(prev), Some(next)) => PAIRS.contains(&(*prev, *next)),
+        _ => false,
+    }
+}
+
 pub fn handle(
     key: Key,
     editor: &mut Editor,
-    cursor: &mut Cursor,
     mode_manager: &mut ModeManager,
     terminal_size: (u16, u16),
     editor_rows: u16,
 ) -> HandlerResult {
+    // 変更できないバッファでは、移動と Insert mode の終了以外のキーを受け付けない
+    if !matches!(
+        key,
+        Key::Esc
+            | Key::Left
+            | Key::Right
+            | Key::Up
+            | Key::Down
+            | Key::Home
+            | Key::End
+            | Key::PageUp
+            | Key::PageDown
+    ) && let Err(msg) = editor.check_modifiable()
+    {
+        return HandlerResult::StatusMessage(msg);
+    }
+    if let Some(input) = editor.literal_input.take() {
+        match input.feed(key) {
+            LiteralStep::Pending(input) => editor.literal_input = Some(input),
+            LiteralStep::Done { ch, rest } => {
+                if let Some(ch) = ch {
+                    insert_literal(editor, ch, terminal_size);
+                }
+                if let Some(key) = rest {
+                    return handle(key, editor, mode_manager, terminal_size, editor_rows);
+                }
+            }
+        }
+        return HandlerResult::Continue;
+    }
     match key {
+        Key::Ctrl('v' | 'q') => {
+            // Ctrl-V / Ctrl-Q: 次のキーの文字 (または文字コードで指定した文字) をそのまま挿入する
+            editor.literal_input = Some(LiteralInput::Start);
+        }
         Key::Esc => {
+            editor.finish_insert();
             mode_manager.enter_normal();
-            cursor.move_left();
+            editor.cursor.move_left();
+        }
+        Key::Ctrl('a') | Key::Null => {
+            // Ctrl-A: 直前に挿入したテキストを挿入する (Ctrl-@ は挿入後に Insert mode を終了)
+            if let Some(text) = editor.registers.last_insert() {
+                editor.insert_text(editor.cursor.position(), &text);
+                editor.inserted_text.push_str(&text);
+            }
+            if key == Key::Null {
+                editor.finish_insert();
+                mode_manager.enter_normal();
+                editor.cursor.move_left();
+            }
+        }
+        Key::Ctrl(c @ ('t' | 'd')) => {
+            // Ctrl-T / Ctrl-D: 現在行のインデントを増やす / 減らす (カーソルは同じ文字に留まる)
+            let pos = editor.cursor.position();
+            editor.shift_line(pos.row, pos.col, c == 't');
         }
         Key::Char('\n') => {
             // 改行
-            let pos = cursor.position();
-            editor.insert_newline(pos);
-            cursor.move_down(editor_rows, editor.buffer().len());
-            cursor.move_to_line_start();
+            editor.inserted_text.push('\n');
+            editor.insert_newline_with_comment(editor.cursor.position());
             // TODO:
             // 設定に応じて、改行したときに前の行とインデントを合わせることができるようにする
         }
         Key::Backspace => {
             // 削除
-            let pos = cursor.position();
+            editor.inserted_text.pop();
+            let pos = editor.cursor.position();
 
-            if pos.col > 0 {
+            let line = editor
+                .buffer()
+                .row(pos.row)
+                .map(|r| r.chars())
+                .unwrap_or("");
+            if pos.col > 0 && editor.config.autopairs && is_empty_pair(line, pos.col) {
+                // 空の括弧の組は閉じ括弧も削除
+                editor.delete_char(pos);
+                editor.delete_char(Position::new(pos.row, pos.col - 1));
+                editor.cursor.move_left();
+            } else if pos.col > 0 {
                 // 文字を削除
                 editor.delete_char(Position::new(pos.row, pos.col - 1));
-                cursor.move_left();
+                editor.cursor.move_left();
             } else if pos.row > 0 {
                 // 行頭で Backspace + 前の行と結合
-                let prev_row = pos.row - 1;
-                let prev_line_len = editor
-                    .buffer()
-                    .row(prev_row)
-                    .map(|r| r.char_count())
-                    .unwrap_or(0);
                 editor.join_rows(pos.row);
-                cursor.move_up();
-                cursor.move_to_line_end((prev_line_len as u16) + 1);
+            }
+        }
+        Key::Left
+        | Key::Right
+        | Key::Up
+        | Key::Down
+        | Key::Home
+        | Key::End
+        | Key::PageUp
+        | Key::PageDown => {
+            // Vim と同様に、移動した後に入力したテキストだけを `".` に残す
+            editor.inserted_text.clear();
+            move_cursor(key, editor, terminal_size, editor_rows);
+        }
+        Key::Delete => {
+            // カーソル位置の文字を削除し、行末では次の行と結合する
+            let pos = editor.cursor.position();
+            if pos.col < editor.current_line_len(pos.row) {
+                editor.delete_char(pos);
+            } else if pos.row + 1 < editor.buffer().len() {
+                editor.join_rows(pos.row + 1);
             }
         }
         Key::Char(ch) => {
             // 文字挿入
-            let pos = cursor.position();
-            editor.insert_char(pos, ch);
+            editor.inserted_text.push(ch);
+            let pos = editor.cursor.position();
+            let action = if editor.config.autopairs {
+                let line = editor
+                    .buffer()
+                    .row(pos.row)
+                    .map(|r| r.chars())
+                    .unwrap_or("");
+                pair_action(line, pos.col, ch)
+            } else {
+                PairAction::Insert
+            };
+            match action {
+                PairAction::Insert => editor.insert_char(pos, ch),
+                PairAction::InsertPair(close) => {
+                    editor.insert_char(pos, ch);
+                    editor.insert_char(Position::new(pos.row, pos.col + 1), close);
+                }
+                PairAction::SkipOver => {}
+            }
             // Insert モードでは行末の次の位置まで移動可能
-            cursor.move_right(
+            editor.cursor.move_right(
                 terminal_size.0,
                 editor
                     .buffer()
@@ -63,8 +211,346 @@ pub fn handle(
                     .unwrap_or(0)
                     + 1,
             );
+            // textwidth を超えたら空白の位置で折り返す
+            if !ch.is_whitespace() {
+                editor.auto_wrap(pos.row, editor.cursor.col_index());
+            }
+            if editor.config.showmatch
+                && matches!(ch, ')' | ']' | '}')
+                && let Some(pos) = matching_open_bracket(editor, editor_rows)
+            {
+                return HandlerResult::ShowMatch(pos);
+            }
         }
         _ => {}
     }
     HandlerResult::Continue
 }
+
+/// 矢印キーなどでカーソルを移動する
+///
+/// Insert mode なので、列は行末の次の位置まで移動できる。
+/// `PageUp` / `PageDown` は画面の行数分だけ移動する。
+fn move_cursor(key: Key, editor: &mut Editor, terminal_size: (u16, u16), editor_rows: u16) {
+    let buffer_len = editor.buffer().len();
+    let line_limit = |editor: &Editor| editor.current_line_len(editor.cursor.file_row()) + 1;
+    match key {
+        Key::Left => editor.cursor.move_left(),
+        Key::Right => editor
+            .cursor
+            .move_right(terminal_size.0, line_limit(editor)),
+        Key::Home => editor.cursor.move_to_line_start(),
+        Key::End => editor.cursor.move_to_line_end(line_limit(editor) as u16),
+        Key::Up => editor.cursor.move_up(),
+        Key::Down => editor.cursor.move_down(editor_rows, buffer_len),
+        Key::PageUp => {
+            let row = editor
+                .cursor
+                .file_row()
+                .saturating_sub(editor_rows as usize);
+            editor.cursor.move_to_row(row, buffer_len, editor_rows);
+        }
+        Key::PageDown => {
+            let row = editor.cursor.file_row() + editor_rows as usize;
+            editor.cursor.move_to_row(row, buffer_len, editor_rows);
+        }
+        _ => {}
+    }
+    editor.cursor.adjust_cursor_x(line_limit(editor));
+}
+
+/// 文字を autopairs や textwidth の処理をせずにそのまま挿入する (`Ctrl-V` 用)
+fn insert_literal(editor: &mut Editor, ch: char, terminal_size: (u16, u16)) {
+    editor.inserted_text.push(ch);
+    let pos = editor.cursor.position();
+    editor.insert_char(pos, ch);
+    editor
+        .cursor
+        .move_right(terminal_size.0, editor.current_line_len(pos.row) + 1);
+}
+
+/// 入力した閉じ括弧 (カーソルの直前) に対応する開き括弧の位置 (`:set showmatch` 用)
+///
+/// 対応する括弧が画面に表示されていない場合は None
+fn matching_open_bracket(editor: &Editor, editor_rows: u16) -> Option<Position> {
+    let col = editor.cursor.col_index().checked_sub(1)?;
+    let pos = motion::match_bracket(
+        editor.buffer(),
+        Position::new(editor.cursor.file_row(), col),
+    )?;
+    editor
+        .cursor
+        .is_row_visible(pos.row, editor_rows)
+        .then_some(pos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pair_action_insert() {
+        assert_eq!(pair_action("", 0, '('), PairAction::InsertPair(')'));
+        assert_eq!(pair_action("foo ", 4, '['), PairAction::InsertPair(']'));
+        assert_eq!(pair_action("f()", 2, '{'), PairAction::InsertPair('}'));
+        assert_eq!(pair_action("x = ", 4, '"'), PairAction::InsertPair('"'));
+        // 単語の直前では補完しない
+        assert_eq!(pair_action("foo", 0, '('), PairAction::Insert);
+        // 単語中の引用符や文字列の中では補完しない
+        assert_eq!(pair_action("don", 3, '\''), PairAction::Insert);
+        assert_eq!(pair_action("\"abc ", 5, '"'), PairAction::Insert);
+        assert_eq!(pair_action("a", 1, 'b'), PairAction::Insert);
+    }
+
+    #[test]
+    fn test_pair_action_skip_over() {
+        assert_eq!(pair_action("()", 1, ')'), PairAction::SkipOver);
+        assert_eq!(pair_action("\"\"", 1, '"'), PairAction::SkipOver);
+        assert_eq!(pair_action("(a", 2, ')'), PairAction::Insert);
+    }
+
+    #[test]
+    fn test_is_empty_pair() {
+        assert!(is_empty_pair("()", 1));
+        assert!(is_empty_pair("x['']", 3));
+        assert!(!is_empty_pair("(a)", 1));
+        assert!(!is_empty_pair("()", 0));
+    }
+
+    #[test]
+    fn test_autopairs_in_handler() {
+        let mut editor = Editor::new();
+        editor.config.autopairs = true;
+        let mut mode_manager = ModeManager::new();
+        mode_manager.enter_insert();
+
+        let mut send = |key: Key, editor: &mut Editor| {
+            handle(key, editor, &mut mode_manager, (80, 24), 22);
+        };
+        for c in "f(x".chars() {
+            send(Key::Char(c), &mut editor);
+        }
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "f(x)");
+        send(Key::Char(')'), &mut editor);
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "f(x)");
+        assert_eq!(editor.cursor.col_index(), 4);
+
+        send(Key::Char('['), &mut editor);
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "f(x)[]");
+        send(Key::Backspace, &mut editor);
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "f(x)");
+        assert_eq!(editor.cursor.col_index(), 4);
+    }
+
+    #[test]
+    fn test_insert_literal() {
+        let mut editor = Editor::new();
+        editor.config.autopairs = true;
+        let mut mode_manager = ModeManager::new();
+        mode_manager.enter_insert();
+
+        let mut send = |keys: &[Key], editor: &mut Editor| {
+            for key in keys {
+                handle(*key, editor, &mut mode_manager, (80, 24), 22);
+            }
+        };
+        send(
+            &[Key::Char('a'), Key::Ctrl('v'), Key::Char('\t')],
+            &mut editor,
+        );
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "a\t");
+        // 文字コードで指定した文字と、autopairs を使わない括弧
+        let keys: Vec<Key> = "u00e9".chars().map(Key::Char).collect();
+        send(&[Key::Ctrl('v')], &mut editor);
+        send(&keys, &mut editor);
+        send(&[Key::Ctrl('v'), Key::Char('(')], &mut editor);
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "a\té(");
+        // Esc も文字として挿入し、Insert mode のまま続ける
+        send(&[Key::Ctrl('v'), Key::Esc, Key::Char('b')], &mut editor);
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "a\té(\x1bb");
+        assert_eq!(editor.cursor.col_index(), 6);
+        // 桁数に達する前のキーは、値を挿入した後に通常どおり処理する
+        send(
+            &[Key::Ctrl('v'), Key::Char('x'), Key::Char('9'), Key::Esc],
+            &mut editor,
+        );
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "a\té(\x1bb\t");
+        assert!(editor.literal_input.is_none());
+    }
+
+    #[test]
+    fn test_showmatch_on_closing_bracket() {
+        let mut editor = Editor::new();
+        let mut mode_manager = ModeManager::new();
+        mode_manager.enter_insert();
+
+        let mut send =
+            |key: Key, editor: &mut Editor| handle(key, editor, &mut mode_manager, (80, 24), 22);
+        // 既定では移動しない
+        for c in "(a".chars() {
+            send(Key::Char(c), &mut editor);
+        }
+        assert!(matches!(
+            send(Key::Char(')'), &mut editor),
+            HandlerResult::Continue
+        ));
+
+        editor.config.showmatch = true;
+        for c in " {x[y".chars() {
+            send(Key::Char(c), &mut editor);
+        }
+        assert!(matches!(
+            send(Key::Char(']'), &mut editor),
+            HandlerResult::ShowMatch(pos) if pos == Position::new(0, 6)
+        ));
+        assert!(matches!(
+            send(Key::Char('}'), &mut editor),
+            HandlerResult::ShowMatch(pos) if pos == Position::new(0, 4)
+        ));
+        // 対応する括弧がない場合と、閉じ括弧以外は移動しない
+        assert!(matches!(
+            send(Key::Char(')'), &mut editor),
+            HandlerResult::Continue
+        ));
+        assert!(matches!(
+            send(Key::Char('('), &mut editor),
+            HandlerResult::Continue
+        ));
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "(a) {x[y]})(");
+        assert_eq!(editor.cursor.col_index(), 12);
+    }
+
+    #[test]
+    fn test_textwidth_wraps_while_typing() {
+        let mut editor = Editor::new();
+        editor.config.textwidth = 20;
+        let mut mode_manager = ModeManager::new();
+        mode_manager.enter_insert();
+
+        for c in "one two three four five six".chars() {
+            handle(Key::Char(c), &mut editor, &mut mode_manager, (80, 24), 22);
+        }
+        assert_eq!(
+            editor.buffer().row(0).unwrap().chars(),
+            "one two three four"
+        );
+        assert_eq!(editor.buffer().row(1).unwrap().chars(), "five six");
+        assert_eq!(editor.cursor.position(), Position::new(1, 8));
+    }
+
+    #[test]
+    fn test_ctrl_a_inserts_last_inserted_text() {
+        let mut editor = Editor::new();
+        let mut mode_manager = ModeManager::new();
+        let mut send = |key: Key, editor: &mut Editor| {
+            handle(key, editor, &mut mode_manager, (80, 24), 22);
+        };
+
+        for key in [
+            Key::Char('a'),
+            Key::Char('b'),
+            Key::Char('\n'),
+            Key::Char('c'),
+        ] {
+            send(key, &mut editor);
+        }
+        send(Key::Esc, &mut editor);
+        assert_eq!(editor.registers.last_insert(), Some("ab\nc".to_string()));
+
+        // 新しい Insert mode で Ctrl-A を押すと同じテキストが挿入される
+        editor
+            .cursor
+            .move_to_position(Position::new(1, 1), editor.buffer().len(), 22);
+        send(Key::Ctrl('a'), &mut editor);
+        let rows: Vec<&str> = editor.buffer().iter().map(|r| r.chars()).collect();
+        assert_eq!(rows, vec!["ab", "cab", "c"]);
+        assert_eq!(editor.cursor.position(), Position::new(2, 1));
+
+        // Ctrl-@ は挿入して Insert mode を終了する
+        send(Key::Null, &mut editor);
+        assert_eq!(editor.buffer().row(3).unwrap().chars(), "c");
+        assert_eq!(
+            editor.registers.last_insert(),
+            Some("ab\ncab\nc".to_string())
+        );
+    }
+
+    #[test]
+    fn test_ctrl_t_and_ctrl_d_shift_indent() {
+        let mut editor = Editor::new();
+        editor.buffer_mut().set_row(0, "  foo".to_string());
+        editor.config.shiftwidth = 4;
+        editor.config.expandtab = true;
+        let mut mode_manager = ModeManager::new();
+        let mut send = |key: Key, editor: &mut Editor| {
+            handle(key, editor, &mut mode_manager, (80, 24), 22);
+        };
+
+        // 'o' の上のカーソルは同じ文字に留まる
+        editor.cursor.move_to_position(Position::new(0, 3), 1, 22);
+        send(Key::Ctrl('t'), &mut editor);
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "    foo");
+        assert_eq!(editor.cursor.position(), Position::new(0, 5));
+        assert!(editor.is_dirty());
+        send(Key::Ctrl('d'), &mut editor);
+        send(Key::Ctrl('d'), &mut editor);
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "foo");
+        assert_eq!(editor.cursor.position(), Position::new(0, 1));
+
+        // expandtab でなければタブを使い、空行もインデントする
+        editor.config.expandtab = false;
+        editor.config.tabstop = 4;
+        editor.buffer_mut().set_row(0, String::new());
+        editor.cursor.move_to_position(Position::new(0, 0), 1, 22);
+        send(Key::Ctrl('t'), &mut editor);
+        send(Key::Ctrl('t'), &mut editor);
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "\t\t");
+        assert_eq!(editor.cursor.position(), Position::new(0, 2));
+    }
+
+    #[test]
+    fn test_arrow_keys_and_delete() {
+        let mut editor = Editor::new();
+        editor.buffer_mut().set_row(0, "hello".to_string());
+        editor.buffer_mut().insert_row(1, "hi".to_string());
+        editor.buffer_mut().insert_row(2, "world".to_string());
+        let mut mode_manager = ModeManager::new();
+        let mut send = |key: Key, editor: &mut Editor| {
+            handle(key, editor, &mut mode_manager, (80, 24), 22);
+        };
+
+        // 行末の次の位置まで移動できる
+        send(Key::End, &mut editor);
+        assert_eq!(editor.cursor.position(), Position::new(0, 5));
+        send(Key::Right, &mut editor);
+        assert_eq!(editor.cursor.position(), Position::new(0, 5));
+        // 短い行に移動すると行末の次の位置に収める
+        send(Key::Down, &mut editor);
+        assert_eq!(editor.cursor.position(), Position::new(1, 2));
+        send(Key::Left, &mut editor);
+        send(Key::Up, &mut editor);
+        assert_eq!(editor.cursor.position(), Position::new(0, 1));
+        send(Key::PageDown, &mut editor);
+        assert_eq!(editor.cursor.position(), Position::new(2, 1));
+        send(Key::Home, &mut editor);
+        send(Key::PageUp, &mut editor);
+        assert_eq!(editor.cursor.position(), Position::new(0, 0));
+
+        // Delete はカーソル位置の文字を削除し、行末では次の行と結合する
+        send(Key::Delete, &mut editor);
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "ello");
+        send(Key::End, &mut editor);
+        send(Key::Delete, &mut editor);
+        let rows: Vec<&str> = editor.buffer().iter().map(|r| r.chars()).collect();
+        assert_eq!(rows, vec!["ellohi", "world"]);
+        assert_eq!(editor.cursor.position(), Position::new(0, 4));
+        assert!(editor.is_dirty());
+
+        // 移動した後に入力したテキストだけを ". に残す
+        send(Key::Char('a'), &mut editor);
+        send(Key::Left, &mut editor);
+        send(Key::Char('b'), &mut editor);
+        send(Key::Esc, &mut editor);
+        assert_eq!(editor.registers.last_insert(), Some("b".to_string()));
+    }
+}
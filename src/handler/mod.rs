@@ -1,12 +1,29 @@
 pub mod command;
+pub mod confirm;
 pub mod insert;
 pub mod normal;
+pub mod prompt;
+pub mod search;
 pub mod visual;
 pub mod visual_line;
 
+use std::time::Duration;
+
+use crate::cursor::Position;
+
 pub enum HandlerResult {
     Continue,
     Quit,
     StatusMessage(String),
     ClearStatus,
+    /// 画面を消去してから再描画する (`:redraw`)
+    Redraw,
+    /// 画面を更新してから指定した時間待つ (`:sleep`)
+    Sleep(Duration),
+    /// 対応する括弧の位置に一瞬カーソルを表示する (`:set showmatch`)
+    ShowMatch(Position),
+    /// 入力途中のキーの取り消しや移動できない場合などにベルを鳴らす
+    Bell,
+    /// エラーメッセージを表示してベルを鳴らす (パターンが見つからない場合など)
+    Error(String),
 }
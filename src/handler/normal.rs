@@ -1,282 +1,955 @@
 use termion::event::Key;
 
-use crate::cursor::Cursor;
-use crate::editor::{Editor, PasteDirection, PasteResult};
-use crate::mode::ModeManager;
+use crate::app::effective_number_mode;
+use crate::cursor::Position;
+use crate::editor::{Editor, PasteDirection, word_at, word_range_at};
+use crate::ex_command::{edit_alternate, edit_file, line_count_message, repeat_substitute};
+use crate::mode::{Mode, ModeManager};
+use crate::motion::{Motion, MotionInput, Operator};
+use crate::registry::{KeyContext, KeyMatch};
+use crate::screen::Screen;
+use crate::search::SearchDirection;
+use crate::spell;
 
 use super::HandlerResult;
+use super::command;
+use super::search;
+
+/// Normal mode の入力途中の状態
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct NormalState {
+    /// `d` / `g` / `"` など、続くキーを待っているキー
+    pub pending_key: Option<char>,
+    /// 入力中のカウント (`3j` の `3`)
+    pub count: Option<usize>,
+    /// モーションを待っているオペレータと、その前に入力されたカウント (`2d3w` の `d` と `2`)
+    pub operator: Option<(Operator, Option<usize>)>,
+    /// 起動時に追加したキーの並び (`Registry::register_normal_key`) の入力途中のキー
+    pub user_keys: String,
+}
+
+impl NormalState {
+    /// 続くキーを待っているか (`timeoutlen` の経過で取り消す対象)
+    pub fn is_pending(&self) -> bool {
+        self.pending_key.is_some()
+            || self.count.is_some()
+            || self.operator.is_some()
+            || !self.user_keys.is_empty()
+    }
+
+    /// 入力途中のキーを取り消す
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// 入力途中のキー (`2d3` など)。コマンドラインの右側に表示する
+    pub fn pending_keys(&self) -> String {
+        let mut keys = String::new();
+        if let Some((op, count)) = self.operator {
+            if let Some(count) = count {
+                keys.push_str(&count.to_string());
+            }
+            keys.push(op.key());
+        }
+        if let Some(count) = self.count {
+            keys.push_str(&count.to_string());
+        }
+        if let Some(key) = self.pending_key {
+            keys.push(key);
+        }
+        keys.push_str(&self.user_keys);
+        keys
+    }
+}
+
+/// カウントを扱ってから Normal mode のキーを処理する
+///
+/// `3dd` / `3yy` / `3>>` は 3 行をまとめて削除・ヤンク・インデントし、`3G` / `3gg` は 3 行目に移動する。
+/// 移動 (`h` `j` `k` `l`) と `x` / `p` / `P` はカウントの回数だけ繰り返す。
+/// `d` / `c` / `y` / `>` / `<` の後はモーションを待ち、その範囲に適用する (`dw` / `c$` / `y%`)。
+/// カウントはオペレータの前とモーションの前のどちらにも書けて、両方ある場合は掛け合わせる (`2d3w`)。
+pub fn dispatch(
+    key: Key,
+    editor: &mut Editor,
+    mode_manager: &mut ModeManager,
+    state: &mut NormalState,
+    terminal_size: (u16, u16),
+    editor_rows: u16,
+) -> HandlerResult {
+    // Esc / Ctrl-C は入力途中のオペレータ・カウント・キーと選択中のレジスタを取り消す
+    if matches!(key, Key::Esc | Key::Ctrl('c')) {
+        state.reset();
+        editor.registers.take_selected();
+        return HandlerResult::Bell;
+    }
+    // 起動時に追加したキーの並びは組み込みのキーより優先する
+    if let Key::Char(c) = key
+        && state.operator.is_none()
+        && state.pending_key.is_none()
+    {
+        let mut typed = std::mem::take(&mut state.user_keys);
+        typed.push(c);
+        match editor.registry.match_normal_keys(&typed) {
+            KeyMatch::Action(action) => {
+                let mut ctx = KeyContext {
+                    editor,
+                    editor_rows,
+                    count: state.count.take(),
+                };
+                return action(&mut ctx);
+            }
+            KeyMatch::Prefix => {
+                state.user_keys = typed;
+                return HandlerResult::Continue;
+            }
+            // 途中まで一致していたキーは、組み込みのキーとして入力し直す
+            KeyMatch::None if typed.len() > c.len_utf8() => {
+                let mut result = HandlerResult::Continue;
+                for c in typed.chars() {
+                    result = dispatch_builtin(
+                        Key::Char(c),
+                        editor,
+                        mode_manager,
+                        state,
+                        terminal_size,
+                        editor_rows,
+                    );
+                }
+                return result;
+            }
+            KeyMatch::None => {}
+        }
+    }
+    dispatch_builtin(key, editor, mode_manager, state, terminal_size, editor_rows)
+}
+
+/// 組み込みの Normal mode のキーを処理する
+fn dispatch_builtin(
+    key: Key,
+    editor: &mut Editor,
+    mode_manager: &mut ModeManager,
+    state: &mut NormalState,
+    terminal_size: (u16, u16),
+    editor_rows: u16,
+) -> HandlerResult {
+    // `"1` / `f1` / `` `1 `` など、続くキーを待っている場合の数字はカウントとして扱わない
+    if let Key::Char(c @ '0'..='9') = key
+        && (c != '0' || state.count.is_some())
+        && state.pending_key.is_none()
+    {
+        let digit = c.to_digit(10).unwrap() as usize;
+        state.count = Some(
+            state
+                .count
+                .unwrap_or(0)
+                .saturating_mul(10)
+                .saturating_add(digit),
+        );
+        return HandlerResult::Continue;
+    }
+
+    if state.operator.is_some() {
+        return operator_pending(key, editor, mode_manager, state);
+    }
+    if let Key::Char(c) = key
+        && state.pending_key.is_none()
+        && let Some(op) = Operator::from_char(c)
+    {
+        state.operator = Some((op, state.count.take()));
+        return HandlerResult::ClearStatus;
+    }
+    // `w` / `f{char}` などのモーションで移動する
+    if let Key::Char(c) = key
+        && match state.pending_key {
+            None => matches!(c, 'w' | 'b' | 'e' | '^' | '%' | 'f' | 'F' | 't' | 'T'),
+            Some(p) => matches!(p, 'f' | 'F' | 't' | 'T'),
+        }
+    {
+        match Motion::parse(state.pending_key.take(), c) {
+            Some(MotionInput::Pending(p)) => state.pending_key = Some(p),
+            Some(MotionInput::Motion(motion)) => {
+                let count = state.count.take();
+                let motion = motion.with_count(count);
+                let Some(pos) = motion.target(editor.buffer(), editor.cursor.position(), count)
+                else {
+                    return HandlerResult::Bell;
+                };
+                editor.move_cursor(pos, editor_rows);
+            }
+            None => state.count = None,
+        }
+        return HandlerResult::Continue;
+    }
+
+    let Some(count) = state.count.take() else {
+        return handle(
+            key,
+            editor,
+            mode_manager,
+            &mut state.pending_key,
+            terminal_size,
+            editor_rows,
+        );
+    };
+    let pending_key = state.pending_key;
+    let result = match (key, pending_key) {
+        (Key::Char('J'), None | Some('g')) => {
+            // 3J / 3gJ: 3 行を結合する
+            state.pending_key = None;
+            return join_lines(editor, count, pending_key == Some('g'));
+        }
+        (Key::Char(c @ ('p' | 'P')), None | Some('g')) => {
+            // 3p / 3gp: 3 回分貼り付ける
+            state.pending_key = None;
+            return paste_register(editor, c, count, pending_key.is_some());
+        }
+        (Key::Char('G'), _) | (Key::Char('g'), Some('g')) => {
+            // 指定行に移動する
+            state.pending_key = None;
+            let pos = Position::new(count - 1, editor.cursor.col_index());
+            editor.move_cursor(pos, editor_rows);
+            HandlerResult::Continue
+        }
+        (Key::Char(c @ ('x' | 'X')), _) => {
+            // 3x / 3X: 3 文字をまとめて削除する
+            state.pending_key = None;
+            return delete_chars(editor, count, c == 'X');
+        }
+        (Key::Char('h' | 'j' | 'k' | 'l'), _) => {
+            let before = editor.cursor.position();
+            let mut result = HandlerResult::Continue;
+            for _ in 0..count {
+                let prev = editor.cursor.position();
+                state.pending_key = pending_key;
+                result = handle(
+                    key,
+                    editor,
+                    mode_manager,
+                    &mut state.pending_key,
+                    terminal_size,
+                    editor_rows,
+                );
+                // それ以上移動できない場合は残りのカウントを処理しない
+                if editor.cursor.position() == prev {
+                    break;
+                }
+            }
+            // 途中まで移動できた場合はベルを鳴らさない
+            if matches!(result, HandlerResult::Bell) && editor.cursor.position() != before {
+                result = HandlerResult::Continue;
+            }
+            result
+        }
+        _ => handle(
+            key,
+            editor,
+            mode_manager,
+            &mut state.pending_key,
+            terminal_size,
+            editor_rows,
+        ),
+    };
+    // `3dd` の最初の `d` のように続くキーを待っている間はカウントを残す
+    if state.pending_key.is_some() {
+        state.count = Some(count);
+    }
+    result
+}
+
+/// マークの位置に移動する (`` `[ `` / `'<` など)
+///
+/// `linewise` の場合 (`'x`) はマークの行の最初の非空白文字に移動する。
+pub(super) fn jump_to_mark(
+    name: char,
+    linewise: bool,
+    editor: &mut Editor,
+    editor_rows: u16,
+) -> HandlerResult {
+    let pos = match editor.mark(name) {
+        Ok(pos) => pos,
+        Err(msg) => return HandlerResult::Error(msg),
+    };
+    let pos = if linewise {
+        Position::new(pos.row, editor.first_non_blank(pos.row))
+    } else {
+        pos
+    };
+    editor
+        .cursor
+        .move_to_position(pos, editor.buffer().len(), editor_rows);
+    HandlerResult::Continue
+}
+
+/// レジスタの内容を `count` 回貼り付ける (`p` / `P` / `gp` / `gP`)
+///
+/// `after` が true の場合 (`gp` / `gP`) は貼り付けたテキストの直後にカーソルを置く。
+fn paste_register(editor: &mut Editor, key: char, count: usize, after: bool) -> HandlerResult {
+    if let Err(msg) = editor.check_modifiable() {
+        return HandlerResult::StatusMessage(msg);
+    }
+    editor.history.commit(editor.snapshot());
+    let direction = if key == 'p' {
+        PasteDirection::Below
+    } else {
+        PasteDirection::Above
+    };
+    let pos = editor.cursor.position();
+    if after {
+        editor.paste_and_move_past(pos, direction, count);
+    } else {
+        editor.paste(pos, direction, count);
+    }
+    HandlerResult::ClearStatus
+}
+
+/// カーソル位置から `count` 文字を削除する (`x`)
+///
+/// `before` が true の場合 (`X`) はカーソルより前の文字を削除し、カーソルを左に動かす。
+/// 削除する文字がない場合、`X` はベルを鳴らす。
+fn delete_chars(editor: &mut Editor, count: usize, before: bool) -> HandlerResult {
+    if let Err(msg) = editor.check_modifiable() {
+        return HandlerResult::StatusMessage(msg);
+    }
+    let snapshot = editor.snapshot();
+    let pos = editor.cursor.position();
+    if editor.delete_chars(pos, count, before) == 0 {
+        return if before {
+            HandlerResult::Bell
+        } else {
+            HandlerResult::ClearStatus
+        };
+    }
+    editor.history.commit(snapshot);
+    HandlerResult::ClearStatus
+}
+
+/// カーソルを表示している画面の行の中で移動する (`g0` / `g^` / `g$`)
+///
+/// 画面の行は描画と同じく [`Screen::screen_line_cols`] で求める。`:set wrap` の場合は
+/// 折り返した行のうちカーソルのある 1 行、それ以外は画面の右端で切れる前の部分になる。
+/// `g0` は画面の行の最初の文字、`g^` は最初の非空白文字、`g$` は最後の文字に移動する。
+fn move_in_screen_line(editor: &mut Editor, key: char, width: u16) {
+    let Some(line) = editor.buffer().row(editor.cursor.file_row()) else {
+        return;
+    };
+    let number_mode = effective_number_mode(&editor.config, Mode::Normal);
+    let cols = Screen::text_cols(width, number_mode, editor);
+    let visible = Screen::screen_line_cols(
+        line.chars(),
+        editor.cursor.col_index(),
+        cols,
+        &editor.config,
+    );
+    let col = match key {
+        '0' => visible.first().copied(),
+        '^' => visible
+            .iter()
+            .copied()
+            .find(|&col| {
+                !line
+                    .chars()
+                    .chars()
+                    .nth(col)
+                    .is_some_and(char::is_whitespace)
+            })
+            .or(visible.last().copied()),
+        '$' => visible.last().copied(),
+        _ => None,
+    };
+    editor.cursor.move_to_col(col.unwrap_or(0));
+}
+
+/// カーソル行から `count` 行 (2 行未満の場合は 2 行) を結合する (`J` / `gJ`)
+///
+/// `raw` が true の場合 (`gJ`) は空白を入れずにそのまま連結する。
+/// カーソルは最後に結合した位置に移動する。
+fn join_lines(editor: &mut Editor, count: usize, raw: bool) -> HandlerResult {
+    let row = editor.cursor.file_row();
+    let last = row
+        .saturating_add(count.max(2) - 1)
+        .min(editor.buffer().len().saturating_sub(1));
+    if last == row {
+        return HandlerResult::ClearStatus;
+    }
+    if let Err(msg) = editor.check_modifiable() {
+        return HandlerResult::StatusMessage(msg);
+    }
+    editor.history.commit(editor.snapshot());
+    if raw {
+        for _ in row..last {
+            editor.join_with_next_raw(row);
+        }
+    } else {
+        editor.join_lines(row, last);
+    }
+    HandlerResult::ClearStatus
+}
+
+/// オペレータ (`d` / `c` / `y`) の後のキーを処理する
+///
+/// 同じキーを 2 回続けた場合 (`dd` / `cc` / `yy`) はカウント分の行に、
+/// それ以外はモーションの範囲に適用する。モーションでないキーは操作を取り消す。
+fn operator_pending(
+    key: Key,
+    editor: &mut Editor,
+    mode_manager: &mut ModeManager,
+    state: &mut NormalState,
+) -> HandlerResult {
+    let Some((op, op_count)) = state.operator.take() else {
+        return HandlerResult::Continue;
+    };
+    // `2d3w` は 6 単語分
+    let count = match (op_count, state.count.take()) {
+        (Some(a), Some(b)) => Some(a.saturating_mul(b)),
+        (a, b) => a.or(b),
+    };
+    let Key::Char(c) = key else {
+        state.pending_key = None;
+        return HandlerResult::ClearStatus;
+    };
+    let from = editor.cursor.position();
+
+    let lines = if state.pending_key.is_none() && Operator::from_char(c) == Some(op) {
+        // dd / cc / yy
+        let last_row = editor.buffer().len().saturating_sub(1);
+        let end = from
+            .row
+            .saturating_add(count.unwrap_or(1) - 1)
+            .min(last_row);
+        if op != Operator::Yank {
+            if let Err(msg) = editor.check_modifiable() {
+                return HandlerResult::StatusMessage(msg);
+            }
+            editor.history.commit(editor.snapshot());
+        }
+        editor.apply_operator_to_lines(op, from, end);
+        Some(end - from.row + 1)
+    } else {
+        let motion = match Motion::parse(state.pending_key.take(), c) {
+            Some(MotionInput::Pending(p)) => {
+                state.pending_key = Some(p);
+                state.operator = Some((op, count));
+                return HandlerResult::Continue;
+            }
+            Some(MotionInput::Motion(motion)) => motion.with_count(count),
+            None => return HandlerResult::ClearStatus,
+        };
+        if op != Operator::Yank
+            && let Err(msg) = editor.check_modifiable()
+        {
+            return HandlerResult::StatusMessage(msg);
+        }
+        let lines = motion
+            .is_linewise()
+            .then(|| motion.target(editor.buffer(), from, count))
+            .flatten()
+            .map(|target| target.row.abs_diff(from.row) + 1);
+        let snapshot = editor.snapshot();
+        if !editor.apply_operator(op, from, motion, count) {
+            return HandlerResult::ClearStatus;
+        }
+        if op != Operator::Yank {
+            editor.history.commit(snapshot);
+        }
+        lines
+    };
+
+    if op == Operator::Change {
+        mode_manager.enter_insert();
+        return HandlerResult::ClearStatus;
+    }
+    match (op, lines) {
+        (Operator::Delete, Some(lines)) => line_count_message(lines, "fewer lines"),
+        (Operator::Yank, Some(lines)) => line_count_message(lines, "lines yanked"),
+        _ => HandlerResult::ClearStatus,
+    }
+}
 
 pub fn handle(
     key: Key,
     editor: &mut Editor,
-    cursor: &mut Cursor,
     mode_manager: &mut ModeManager,
     pending_key: &mut Option<char>,
     terminal_size: (u16, u16),
     editor_rows: u16,
 ) -> HandlerResult {
     let mut next_pending_key: Option<char> = None;
+    let before = editor.cursor.position();
 
     match key {
-        Key::Char(':') => {
+        Key::Char(':') if *pending_key == Some('@') => {
+            // @: 直前の Ex コマンドを再実行する
+            *pending_key = None;
+            let Some(command) = editor.last_command.clone() else {
+                return HandlerResult::StatusMessage("No previous command line".to_string());
+            };
+            return command::execute(&command, editor, editor_rows);
+        }
+        Key::Char('@') => {
+            next_pending_key = Some('@');
+        }
+        Key::Char(name) if *pending_key == Some('"') => {
+            // "x: 次の操作で使うレジスタを選択する
+            *pending_key = None;
+            if !editor.registers.select(name) {
+                return HandlerResult::StatusMessage(format!("Invalid register name: {}", name));
+            }
+            return HandlerResult::Continue;
+        }
+        Key::Char('"') => {
+            next_pending_key = Some('"');
+        }
+        Key::Char(name) if matches!(*pending_key, Some('`' | '\'')) => {
+            // `x / 'x: マークの位置 / 行に移動する
+            let linewise = *pending_key == Some('\'');
+            *pending_key = None;
+            return jump_to_mark(name, linewise, editor, editor_rows);
+        }
+        Key::Char(c @ ('`' | '\'')) => {
+            next_pending_key = Some(c);
+        }
+        Key::Char(':') | Key::Char('/') | Key::Char('?') => {
             mode_manager.enter_command();
         }
+        Key::Char('n') | Key::Char('N') if *pending_key != Some('g') => {
+            // 直前の検索を繰り返す (N は逆方向)
+            return search::repeat(editor, editor_rows, key == Key::Char('N'));
+        }
+        Key::Char(c @ ('*' | '#')) => {
+            // カーソル位置の単語を検索する (# は逆方向)。g* / g# は単語の一部にも一致させる
+            let whole_word = *pending_key != Some('g');
+            *pending_key = None;
+            let pos = editor.cursor.position();
+            let line = editor.buffer().row(pos.row).map_or("", |r| r.chars());
+            let Some(range) = word_range_at(line, pos.col) else {
+                return HandlerResult::Error("No string under cursor".to_string());
+            };
+            let word: String = line.chars().skip(range.start).take(range.len()).collect();
+            let pattern = if whole_word {
+                format!(r"\m\<{}\>", word)
+            } else {
+                format!(r"\m{}", word)
+            };
+            let direction = if c == '*' {
+                SearchDirection::Forward
+            } else {
+                SearchDirection::Backward
+            };
+            // 単語の先頭から検索して、カーソル位置の単語自身に一致しないようにする
+            editor.move_cursor(Position::new(pos.row, range.start), editor_rows);
+            return search::start(&pattern, direction, editor, editor_rows);
+        }
         Key::Char('u') => {
-            let current = editor.snapshot(cursor);
+            if let Err(msg) = editor.check_modifiable() {
+                *pending_key = None;
+                return HandlerResult::StatusMessage(msg);
+            }
+            let current = editor.snapshot();
             if let Some(prev) = editor.history.undo(current) {
-                editor.restore_snapshot(prev, cursor);
-                let (buf_len, line_len) = editor.buffer_info(cursor.file_row());
-                cursor.ensure_within_bounds(buf_len, line_len, editor_rows);
+                editor.restore_snapshot(prev);
                 return HandlerResult::StatusMessage("1 change; before #1".to_string());
             }
             return HandlerResult::StatusMessage("Already at oldest change".to_string());
         }
         Key::Ctrl('r') => {
-            let current = editor.snapshot(cursor);
+            if let Err(msg) = editor.check_modifiable() {
+                *pending_key = None;
+                return HandlerResult::StatusMessage(msg);
+            }
+            let current = editor.snapshot();
             if let Some(next) = editor.history.redo(current) {
-                editor.restore_snapshot(next, cursor);
-                let (buf_len, line_len) = editor.buffer_info(cursor.file_row());
-                cursor.ensure_within_bounds(buf_len, line_len, editor_rows);
+                editor.restore_snapshot(next);
                 return HandlerResult::StatusMessage("1 change".to_string());
             }
             return HandlerResult::StatusMessage("Already at newest change".to_string());
         }
+        Key::Ctrl('6') => {
+            // Ctrl-^: 直前に編集していたファイルに切り替える
+            let msg = edit_alternate(editor, editor_rows, false);
+            *pending_key = None;
+            return HandlerResult::StatusMessage(msg);
+        }
         Key::Char('i') => {
-            editor.history.commit(editor.snapshot(cursor));
+            if let Err(msg) = editor.check_modifiable() {
+                *pending_key = None;
+                return HandlerResult::StatusMessage(msg);
+            }
+            editor.history.commit(editor.snapshot());
             mode_manager.enter_insert();
         }
         Key::Char('I') => {
             // 行頭から Insert mode
-            editor.history.commit(editor.snapshot(cursor));
-            cursor.move_to_line_start();
+            if let Err(msg) = editor.check_modifiable() {
+                *pending_key = None;
+                return HandlerResult::StatusMessage(msg);
+            }
+            editor.history.commit(editor.snapshot());
+            editor.cursor.move_to_line_start();
             mode_manager.enter_insert();
         }
         Key::Char('a') => {
             // カーソルの後ろから Insert mode
-            editor.history.commit(editor.snapshot(cursor));
-            let row = cursor.file_row();
+            if let Err(msg) = editor.check_modifiable() {
+                *pending_key = None;
+                return HandlerResult::StatusMessage(msg);
+            }
+            editor.history.commit(editor.snapshot());
+            let row = editor.cursor.file_row();
             if let Some(line) = editor.buffer().row(row) {
                 // Insert mode では行末+1まで移動可能
-                cursor.move_right(terminal_size.0, line.char_count() + 1);
+                editor
+                    .cursor
+                    .move_right(terminal_size.0, line.char_count() + 1);
             }
             mode_manager.enter_insert();
         }
         Key::Char('A') => {
-            // 行末から Insert mode
-            editor.history.commit(editor.snapshot(cursor));
-            let row = cursor.file_row();
-            if let Some(line) = editor.buffer().row(row) {
-                // Insert mode では行末の1つ後ろに配置
-                let line_len = line.char_count() as u16;
-                if line_len == 0 {
-                    cursor.move_to_line_start();
-                } else {
-                    cursor.move_to_line_end(line_len + 1);
-                }
+            // 行末から Insert mode (virtualedit で行末より後ろにいる場合も最後の文字の後ろ)
+            if let Err(msg) = editor.check_modifiable() {
+                *pending_key = None;
+                return HandlerResult::StatusMessage(msg);
             }
+            editor.history.commit(editor.snapshot());
+            let line_len = editor.current_line_len(editor.cursor.file_row());
+            editor.cursor.move_past_line_end(line_len);
             mode_manager.enter_insert();
         }
         Key::Char('o') => {
             // 下に新しい行を追加して Insert mode
             // スナップショットはバッファ変更前に取得する
-            editor.history.commit(editor.snapshot(cursor));
-            let row = cursor.file_row();
-            editor.buffer_mut().insert_row(row + 1, String::new());
-            cursor.move_down(editor_rows, editor.buffer().len());
-            cursor.move_to_line_start();
+            if let Err(msg) = editor.check_modifiable() {
+                *pending_key = None;
+                return HandlerResult::StatusMessage(msg);
+            }
+            editor.history.commit(editor.snapshot());
+            editor.open_line_below(editor.cursor.file_row());
             mode_manager.enter_insert();
         }
         Key::Char('O') => {
             // 上に新しい行を追加して Insert mode
             // スナップショットはバッファ変更前に取得する
-            editor.history.commit(editor.snapshot(cursor));
-            let row = cursor.file_row();
-            editor.buffer_mut().insert_row(row, String::new());
-            cursor.move_to_line_start();
+            if let Err(msg) = editor.check_modifiable() {
+                *pending_key = None;
+                return HandlerResult::StatusMessage(msg);
+            }
+            editor.history.commit(editor.snapshot());
+            editor.open_line_above(editor.cursor.file_row());
             mode_manager.enter_insert();
         }
-        Key::Char('x') => {
-            editor.history.commit(editor.snapshot(cursor));
-            let pos = cursor.position();
-            if editor.delete_char_at_cursor(pos) {
-                // 削除成功後、行末を超えないように調整
-                let line_len = editor.current_line_len(pos.row);
-                if line_len > 0 && cursor.x() > line_len as u16 {
-                    cursor.move_left();
-                }
-            }
-            return HandlerResult::ClearStatus;
+        Key::Char('J') => {
+            // J: 次の行と結合する (gJ は空白を入れずに連結する)
+            let raw = *pending_key == Some('g');
+            *pending_key = None;
+            return join_lines(editor, 1, raw);
         }
-        Key::Char('d') => {
-            // dd コマンド実行時
-            if *pending_key == Some('d') {
-                editor.history.commit(editor.snapshot(cursor));
-                let row = cursor.file_row();
-                if editor.delete_line(row) {
-                    // 削除成功後、カーソル位置調整
-                    let (buffer_len, line_len) = editor.buffer_info(cursor.file_row());
-                    cursor.ensure_within_bounds(buffer_len, line_len, editor_rows);
-                }
+        Key::Char(c @ ('x' | 'X')) => {
+            return delete_chars(editor, 1, c == 'X');
+        }
+        Key::Char('Y') => {
+            // Y: yy と同じ (yanktoeol が有効な場合は y$)
+            let pos = editor.cursor.position();
+            if editor.config.yanktoeol {
+                editor.yank_to_line_end(pos);
             } else {
-                next_pending_key = Some('d');
+                editor.yank_line(pos.row);
             }
-            *pending_key = next_pending_key;
+            *pending_key = None;
             return HandlerResult::ClearStatus;
         }
-        Key::Char('y') => {
-            // yy
-            if *pending_key == Some('y') {
-                let row = cursor.file_row();
-                editor.yank_line(row);
-            } else {
-                next_pending_key = Some('y');
+        Key::Char('D') => {
+            // D: カーソル位置から行末まで削除
+            if let Err(msg) = editor.check_modifiable() {
+                *pending_key = None;
+                return HandlerResult::StatusMessage(msg);
             }
-            *pending_key = next_pending_key;
+            editor.history.commit(editor.snapshot());
+            editor.delete_to_line_end(editor.cursor.position());
+            *pending_key = None;
             return HandlerResult::ClearStatus;
         }
-        Key::Char('p') => {
-            editor.history.commit(editor.snapshot(cursor));
-            let pos = cursor.position();
-
-            match editor.paste(pos, PasteDirection::Below) {
-                PasteResult::InLine => {
-                    let line_len = editor.current_line_len(pos.row);
-                    cursor.move_right(terminal_size.0, line_len);
-                }
-                PasteResult::Below => {
-                    cursor.move_down(editor_rows, editor.buffer().len());
-                }
-                _ => {}
+        Key::Char('C') => {
+            // C: カーソル位置から行末まで削除して Insert mode
+            if let Err(msg) = editor.check_modifiable() {
+                *pending_key = None;
+                return HandlerResult::StatusMessage(msg);
             }
-            *pending_key = next_pending_key;
+            editor.history.commit(editor.snapshot());
+            editor.change_to_line_end(editor.cursor.position());
+            mode_manager.enter_insert();
+            *pending_key = None;
             return HandlerResult::ClearStatus;
         }
-        Key::Char('P') => {
-            editor.history.commit(editor.snapshot(cursor));
-            let pos = cursor.position();
-
-            // Above の場合は特にカーソル移動する必要がない
-            if let PasteResult::InLine = editor.paste(pos, PasteDirection::Above) {
-                let line_len = editor.current_line_len(pos.row);
-                cursor.move_right(terminal_size.0, line_len);
+        Key::Char('&') => {
+            // & / g&: 直前の :s を現在行 / ファイル全体に繰り返す
+            let whole_file = *pending_key == Some('g');
+            *pending_key = None;
+            return repeat_substitute(editor, editor_rows, whole_file);
+        }
+        Key::Char(c @ ('p' | 'P')) if *pending_key == Some('g') => {
+            // gp / gP: 貼り付けたテキストの直後にカーソルを置く
+            *pending_key = None;
+            return paste_register(editor, c, 1, true);
+        }
+        Key::Char('p') if matches!(*pending_key, Some(']') | Some('[')) => {
+            // ]p / [p: 現在行のインデントに合わせて貼り付け
+            if let Err(msg) = editor.check_modifiable() {
+                *pending_key = None;
+                return HandlerResult::StatusMessage(msg);
             }
-            *pending_key = next_pending_key;
+            editor.history.commit(editor.snapshot());
+            let pos = editor.cursor.position();
+            let direction = if *pending_key == Some(']') {
+                PasteDirection::Below
+            } else {
+                PasteDirection::Above
+            };
+            editor.paste_adjusted(pos, direction);
+            *pending_key = None;
+            return HandlerResult::ClearStatus;
+        }
+        Key::Char('s') if matches!(*pending_key, Some(']') | Some('[')) => {
+            // ]s / [s: 次 / 前のスペルミスに移動
+            let direction = if *pending_key == Some(']') {
+                SearchDirection::Forward
+            } else {
+                SearchDirection::Backward
+            };
+            *pending_key = None;
+            let Some(dictionary) = editor.spell_dictionary() else {
+                return HandlerResult::StatusMessage("Spell checking is not enabled".to_string());
+            };
+            let Some(pos) = spell::find_misspelling(
+                dictionary,
+                editor.buffer(),
+                editor.cursor.position(),
+                direction,
+                editor.config.wrapscan,
+            ) else {
+                return HandlerResult::StatusMessage("No misspelled words".to_string());
+            };
+            editor
+                .cursor
+                .move_to_position(pos, editor.buffer().len(), editor_rows);
             return HandlerResult::ClearStatus;
         }
+        Key::Char('c') if matches!(*pending_key, Some(']') | Some('[')) => {
+            // ]c / [c: 次 / 前の変更された行の塊に移動
+            let direction = if *pending_key == Some(']') {
+                SearchDirection::Forward
+            } else {
+                SearchDirection::Backward
+            };
+            *pending_key = None;
+            let Some(row) = editor.find_hunk(editor.cursor.file_row(), direction) else {
+                return HandlerResult::Bell;
+            };
+            editor.move_cursor(Position::new(row, 0), editor_rows);
+            return HandlerResult::ClearStatus;
+        }
+        Key::Char(c @ (']' | '[')) => {
+            next_pending_key = Some(c);
+        }
+        Key::Char(c @ ('g' | 'w')) if *pending_key == Some('z') => {
+            // zg: カーソル位置の単語を個人辞書に追加 / zw: 辞書から取り除く
+            *pending_key = None;
+            let pos = editor.cursor.position();
+            let Some(word) = editor
+                .buffer()
+                .row(pos.row)
+                .and_then(|line| word_at(line.chars(), pos.col))
+            else {
+                return HandlerResult::StatusMessage("No string under cursor".to_string());
+            };
+            let result = if c == 'g' {
+                editor.spell_good(&word)
+            } else {
+                editor.spell_wrong(&word)
+            };
+            return HandlerResult::StatusMessage(result.unwrap_or_else(|e| e));
+        }
+        Key::Char('z') => {
+            next_pending_key = Some('z');
+        }
+        Key::Char(c @ ('p' | 'P')) => {
+            *pending_key = next_pending_key;
+            return paste_register(editor, c, 1, false);
+        }
         // Visual mode 系
         Key::Char('v') => {
-            mode_manager.enter_visual(cursor.position());
+            mode_manager.enter_visual(editor.cursor.position());
         }
         Key::Char('V') => {
-            mode_manager.enter_visual_line(cursor.position());
+            mode_manager.enter_visual_line(editor.cursor.position());
         }
         // 移動系
-        Key::Char('h') => cursor.move_left(),
+        Key::Char('h') => editor.cursor.move_left(),
         Key::Char('j') => {
-            cursor.move_down(editor_rows, editor.buffer().len());
-            // 移動後の行に合わせて x 座標を調整する
-            let row = cursor.file_row();
+            editor.cursor.move_down(editor_rows, editor.buffer().len());
+            // 移動後の行に合わせて x 座標を調整する (virtualedit の範囲内なら行末を超えてもよい)
+            let row = editor.cursor.file_row();
             if let Some(line) = editor.buffer().row(row) {
-                cursor.adjust_cursor_x(line.char_count());
+                editor
+                    .cursor
+                    .adjust_cursor_x(editor.config.virtualedit.line_limit(line.char_count()));
             }
         }
         Key::Char('k') => {
-            cursor.move_up();
-            // 移動後の行に合わせて x 座標を調整する
-            let row = cursor.file_row();
+            editor.cursor.move_up();
+            // 移動後の行に合わせて x 座標を調整する (virtualedit の範囲内なら行末を超えてもよい)
+            let row = editor.cursor.file_row();
             if let Some(line) = editor.buffer().row(row) {
-                cursor.adjust_cursor_x(line.char_count());
+                editor
+                    .cursor
+                    .adjust_cursor_x(editor.config.virtualedit.line_limit(line.char_count()));
             }
         }
         Key::Char('l') => {
-            let row = cursor.file_row();
+            let row = editor.cursor.file_row();
             if let Some(line) = editor.buffer().row(row) {
-                cursor.move_right(terminal_size.0, line.char_count());
+                let limit = editor.config.virtualedit.line_limit(line.char_count());
+                editor.cursor.move_right(terminal_size.0, limit);
+            }
+        }
+        Key::Char(c @ ('0' | '^' | '$')) if *pending_key == Some('g') => {
+            // g0 / g^ / g$: 画面に表示されている部分の先頭 / 最初の非空白文字 / 末尾に移動する
+            *pending_key = None;
+            move_in_screen_line(editor, c, terminal_size.0);
+            return HandlerResult::Continue;
+        }
+        Key::Char('0') | Key::Home => {
+            // smarthome: 最初の非空白文字に移動し、すでにそこにいる場合は行頭に移動する
+            let first_non_blank = editor.first_non_blank(editor.cursor.file_row());
+            if editor.config.smarthome && editor.cursor.col_index() != first_non_blank {
+                editor.cursor.move_to_col(first_non_blank);
+            } else {
+                editor.cursor.move_to_line_start();
             }
         }
-        Key::Char('0') => cursor.move_to_line_start(),
         Key::Char('$') => {
             // 現在の行の長さを取得して行末に移動
-            let row = cursor.file_row();
+            let row = editor.cursor.file_row();
             if let Some(line) = editor.buffer().row(row) {
-                cursor.move_to_line_end(line.char_count() as u16);
+                editor.cursor.move_to_line_end(line.char_count() as u16);
             }
         }
         Key::Char('g') => {
             if *pending_key == Some('g') {
                 // gg: ファイル先頭に移動する
-                cursor.move_to_top();
+                editor.cursor.move_to_top();
                 // 移動後の行に合わせて x 座標を調整する
-                let row = cursor.file_row();
+                let row = editor.cursor.file_row();
                 if let Some(line) = editor.buffer().row(row) {
-                    cursor.adjust_cursor_x(line.char_count());
+                    editor.cursor.adjust_cursor_x(line.char_count());
                 }
             } else {
                 next_pending_key = Some('g');
             }
         }
+        Key::Char('n') if *pending_key == Some('g') => {
+            // gn: 直前の検索パターンの次の一致を Visual mode で選択する
+            *pending_key = None;
+            let (start, end) = match editor.next_match_span(editor.cursor.position()) {
+                Ok(Some(span)) => span,
+                Ok(None) => {
+                    let pattern = editor.last_search.as_ref().map(|(p, _)| p.as_str());
+                    return HandlerResult::Error(format!(
+                        "Pattern not found: {}",
+                        pattern.unwrap_or("")
+                    ));
+                }
+                Err(e) => return HandlerResult::StatusMessage(e),
+            };
+            editor
+                .cursor
+                .move_to_position(start, editor.buffer().len(), editor_rows);
+            mode_manager.enter_visual(start);
+            editor
+                .cursor
+                .move_to_position(end, editor.buffer().len(), editor_rows);
+            return HandlerResult::ClearStatus;
+        }
+        Key::Char('f') if *pending_key == Some('g') => {
+            // gf: カーソル位置のファイルを開く
+            *pending_key = None;
+            let Some(path) = editor.path_under_cursor(editor.cursor.position()) else {
+                return HandlerResult::StatusMessage("No file name under cursor".to_string());
+            };
+            if !path.exists() {
+                return HandlerResult::StatusMessage(format!(
+                    "Can't find file \"{}\"",
+                    path.display()
+                ));
+            }
+            let msg = edit_file(editor, &path.to_string_lossy(), false);
+            return HandlerResult::StatusMessage(msg);
+        }
         Key::Char('G') => {
-            cursor.move_to_bottom(editor.buffer().len(), editor_rows);
+            editor
+                .cursor
+                .move_to_bottom(editor.buffer().len(), editor_rows);
             // 移動後の行に合わせて x 座標を調整する
-            let row = cursor.file_row();
+            let row = editor.cursor.file_row();
             if let Some(line) = editor.buffer().row(row) {
-                cursor.adjust_cursor_x(line.char_count());
+                editor.cursor.adjust_cursor_x(line.char_count());
             }
         }
         _ => {}
     }
 
     *pending_key = next_pending_key;
+    // h / j / k / l で移動できなかった場合はベルを鳴らす
+    if matches!(key, Key::Char('h' | 'j' | 'k' | 'l')) && editor.cursor.position() == before {
+        return HandlerResult::Bell;
+    }
     HandlerResult::Continue
 }
 
 #[cfg(test)]
 mod tests {
-    use super::handle;
+    use super::{NormalState, dispatch, edit_file, handle};
     use termion::event::Key;
     use crate::buffer::Buffer;
-    use crate::cursor::Cursor;
+    use crate::cursor::Position;
     use crate::editor::Editor;
-    use crate::handler::HandlerResult;
+    use crate::handler::{HandlerResult, command, insert};
     use crate::mode::ModeManager;
+    use crate::registry::KeyContext;
+    use crate::search::SearchDirection;
 
     fn make_editor_with_lines(lines: &[&str]) -> Editor {
-        let mut buffer = Buffer::new();
-        for (i, line) in lines.iter().enumerate() {
-            buffer.insert_row(i, line.to_string());
-        }
+        let buffer = Buffer::from_lines(lines);
         Editor::from_buffer(buffer, None)
     }
 
     fn send_key(
         key: Key,
         editor: &mut Editor,
-        cursor: &mut Cursor,
         mode_manager: &mut ModeManager,
         pending_key: &mut Option<char>,
     ) -> HandlerResult {
         let terminal_size = (80u16, 24u16);
         let editor_rows = 22u16; // 24 - UI_HEIGHT(2)
-        handle(key, editor, cursor, mode_manager, pending_key, terminal_size, editor_rows)
+        handle(key, editor, mode_manager, pending_key, terminal_size, editor_rows)
     }
 
     #[test]
     fn test_dd_deletes_correct_line() {
         let mut editor = make_editor_with_lines(&["aaa", "bbb", "ccc", "ddd", "eee"]);
-        let mut cursor = Cursor::new();
-        let mut mode_manager = ModeManager::new();
-        let mut pending_key: Option<char> = None;
+        let mut state = NormalState::default();
 
         // j を 2 回押して "ccc" (row index 2) に移動
-        send_key(Key::Char('j'), &mut editor, &mut cursor, &mut mode_manager, &mut pending_key);
-        send_key(Key::Char('j'), &mut editor, &mut cursor, &mut mode_manager, &mut pending_key);
+        dispatch_keys("jj", &mut editor, &mut state);
 
-        assert_eq!(cursor.file_row(), 2, "cursor should be on row index 2 (ccc)");
+        assert_eq!(editor.cursor.file_row(), 2, "cursor should be on row index 2 (ccc)");
 
         // dd: d を 2 回押す
-        send_key(Key::Char('d'), &mut editor, &mut cursor, &mut mode_manager, &mut pending_key);
-        assert_eq!(pending_key, Some('d'), "after first d, pending_key should be Some('d')");
-        send_key(Key::Char('d'), &mut editor, &mut cursor, &mut mode_manager, &mut pending_key);
+        dispatch_keys("d", &mut editor, &mut state);
+        assert_eq!(
+            state.pending_keys(),
+            "d",
+            "after first d, the operator should be pending"
+        );
+        dispatch_keys("d", &mut editor, &mut state);
 
         // "ccc" が削除されて 4 行になっているはず
         assert_eq!(editor.buffer().len(), 4, "buffer should have 4 lines after dd");
@@ -285,4 +958,1248 @@ mod tests {
         assert_eq!(editor.buffer().row(2).map(|r| r.chars()), Some("ddd"), "ccc should be deleted");
         assert_eq!(editor.buffer().row(3).map(|r| r.chars()), Some("eee"));
     }
+
+    #[test]
+    fn test_dd_on_only_line_leaves_empty_row() {
+        let mut editor = make_editor_with_lines(&["only"]);
+        let mut state = NormalState::default();
+
+        dispatch_keys("dd", &mut editor, &mut state);
+
+        assert_eq!(editor.buffer().len(), 1);
+        assert_eq!(editor.buffer().row(0).map(|r| r.chars()), Some(""));
+        assert_eq!(editor.cursor.position(), Position::new(0, 0));
+    }
+
+    #[test]
+    fn test_edits_keep_cursor_in_buffer() {
+        let mut editor = make_editor_with_lines(&["one", "two", "three"]);
+        let mut mode_manager = ModeManager::new();
+        let mut state = NormalState::default();
+
+        // 最終行の行末で dd すると、新しい最終行に移る
+        dispatch_keys("G$dd", &mut editor, &mut state);
+        assert_eq!(editor.buffer().len(), 2);
+        assert_eq!(editor.cursor.position(), Position::new(1, 0));
+
+        // 行末で p すると、貼り付けた行に移る
+        dispatch_keys("$p", &mut editor, &mut state);
+        assert_eq!(editor.buffer().row(2).map(|r| r.chars()), Some("three"));
+        assert_eq!(editor.cursor.position(), Position::new(2, 0));
+
+        // undo で行が減ってもカーソルは範囲内に収める
+        dispatch_keys("u", &mut editor, &mut state);
+        assert_eq!(editor.buffer().len(), 2);
+        assert_eq!(editor.cursor.position(), Position::new(1, 2));
+
+        // o<Esc> で追加した空行に留まる
+        send_keys_with_insert(
+            &[Key::Char('o'), Key::Esc],
+            &mut editor,
+            &mut mode_manager,
+            &mut state,
+        );
+        assert_eq!(editor.buffer().len(), 3);
+        assert_eq!(editor.cursor.position(), Position::new(2, 0));
+    }
+
+    #[test]
+    fn test_stale_pending_key_does_not_delete_line() {
+        let mut editor = make_editor_with_lines(&["aaa", "bbb"]);
+        let mut state = NormalState::default();
+
+        // gd の後の d は dd ではなく、新しい d の入力になる
+        dispatch_keys("gdd", &mut editor, &mut state);
+        assert_eq!(editor.buffer().len(), 2);
+        assert_eq!(state.pending_keys(), "d");
+    }
+
+    #[test]
+    fn test_at_colon_replays_last_command() {
+        let mut editor = make_editor_with_lines(&["aaa"]);
+        let mut mode_manager = ModeManager::new();
+        let mut pending_key: Option<char> = None;
+
+        // 直前のコマンドがない場合はエラー
+        send_key(
+            Key::Char('@'),
+            &mut editor,
+            &mut mode_manager,
+            &mut pending_key,
+        );
+        let result = send_key(
+            Key::Char(':'),
+            &mut editor,
+            &mut mode_manager,
+            &mut pending_key,
+        );
+        assert!(
+            matches!(result, HandlerResult::StatusMessage(msg) if msg == "No previous command line")
+        );
+        assert!(mode_manager.is_normal());
+
+        editor.last_command = Some("set invlist".to_string());
+        send_key(
+            Key::Char('@'),
+            &mut editor,
+            &mut mode_manager,
+            &mut pending_key,
+        );
+        assert_eq!(pending_key, Some('@'));
+        send_key(
+            Key::Char(':'),
+            &mut editor,
+            &mut mode_manager,
+            &mut pending_key,
+        );
+        assert!(editor.config.list);
+        assert!(mode_manager.is_normal(), "@: should not enter command mode");
+
+        // もう一度実行するとトグルされて元に戻る
+        send_key(
+            Key::Char('@'),
+            &mut editor,
+            &mut mode_manager,
+            &mut pending_key,
+        );
+        send_key(
+            Key::Char(':'),
+            &mut editor,
+            &mut mode_manager,
+            &mut pending_key,
+        );
+        assert!(!editor.config.list);
+    }
+
+    #[test]
+    fn test_uppercase_register_appends() {
+        let mut editor = make_editor_with_lines(&["one", "two", "three"]);
+        let mut state = NormalState::default();
+
+        // "ayy j "Ayy で 2 行をレジスタ a に集める
+        dispatch_keys("\"ayyj\"Ayy", &mut editor, &mut state);
+        assert_eq!(
+            editor.registers.get('a').unwrap().content,
+            vec!["one", "two"]
+        );
+
+        // "ap で最終行の下に貼り付け
+        dispatch_keys("G\"ap", &mut editor, &mut state);
+        let rows: Vec<&str> = editor.buffer().iter().map(|r| r.chars()).collect();
+        assert_eq!(rows, vec!["one", "two", "three", "one", "two"]);
+    }
+
+    #[test]
+    fn test_black_hole_register_keeps_unnamed() {
+        let mut editor = make_editor_with_lines(&["keep", "drop", "xyz"]);
+        let mut state = NormalState::default();
+
+        // yy で無名レジスタに "keep" を入れる
+        dispatch_keys("yyj\"_dd", &mut editor, &mut state);
+        assert_eq!(editor.buffer().len(), 2);
+        assert_eq!(editor.yank.content(), &["keep"]);
+        assert!(editor.yank.is_newline_yank());
+
+        // "_x も同様
+        dispatch_keys("\"_x", &mut editor, &mut state);
+        assert_eq!(editor.buffer().row(1).unwrap().chars(), "yz");
+        assert_eq!(editor.yank.content(), &["keep"]);
+    }
+
+    #[test]
+    fn test_numbered_registers_after_dd() {
+        let mut editor = make_editor_with_lines(&["one", "two", "three", "four"]);
+        let mut state = NormalState::default();
+
+        dispatch_keys("dddddd", &mut editor, &mut state);
+        assert_eq!(editor.registers.get('1').unwrap().content, vec!["three"]);
+        assert_eq!(editor.registers.get('2').unwrap().content, vec!["two"]);
+        assert_eq!(editor.registers.get('3').unwrap().content, vec!["one"]);
+
+        // "2p で 2 つ前に削除した行を貼り付け
+        dispatch_keys("\"2p", &mut editor, &mut state);
+        let rows: Vec<&str> = editor.buffer().iter().map(|r| r.chars()).collect();
+        assert_eq!(rows, vec!["four", "two"]);
+    }
+
+    #[test]
+    fn test_gn_selects_next_match() {
+        let mut editor = make_editor_with_lines(&["abc foo", "foo"]);
+        editor.last_search = Some(("foo".to_string(), SearchDirection::Forward));
+        let mut mode_manager = ModeManager::new();
+        let mut pending_key: Option<char> = None;
+
+        send_key(
+            Key::Char('g'),
+            &mut editor,
+            &mut mode_manager,
+            &mut pending_key,
+        );
+        send_key(
+            Key::Char('n'),
+            &mut editor,
+            &mut mode_manager,
+            &mut pending_key,
+        );
+        assert!(mode_manager.is_visual());
+        assert_eq!(mode_manager.visual_start(), Some(Position::new(0, 4)));
+        assert_eq!(editor.cursor.position(), Position::new(0, 6));
+        assert_eq!(pending_key, None);
+    }
+
+    #[test]
+    fn test_shift_d_deletes_to_line_end() {
+        let mut editor = make_editor_with_lines(&["hello world", "next"]);
+        let mut mode_manager = ModeManager::new();
+        let mut pending_key: Option<char> = None;
+
+        for key in ['l', 'l', 'l', 'l', 'l', 'D'] {
+            send_key(
+                Key::Char(key),
+                &mut editor,
+                &mut mode_manager,
+                &mut pending_key,
+            );
+        }
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "hello");
+        assert_eq!(editor.buffer().len(), 2);
+        assert_eq!(editor.yank.content(), &[" world"]);
+        assert!(!editor.yank.is_newline_yank());
+        assert_eq!(editor.cursor.position(), Position::new(0, 4));
+
+        // C は削除して Insert mode に入る
+        send_key(
+            Key::Char('0'),
+            &mut editor,
+            &mut mode_manager,
+            &mut pending_key,
+        );
+        send_key(
+            Key::Char('C'),
+            &mut editor,
+            &mut mode_manager,
+            &mut pending_key,
+        );
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "");
+        assert!(mode_manager.is_insert());
+    }
+
+    #[test]
+    fn test_shift_y_with_yanktoeol() {
+        let mut editor = make_editor_with_lines(&["hello world"]);
+        let mut mode_manager = ModeManager::new();
+        let mut pending_key: Option<char> = None;
+
+        // 既定では yy と同じ
+        send_key(
+            Key::Char('Y'),
+            &mut editor,
+            &mut mode_manager,
+            &mut pending_key,
+        );
+        assert_eq!(editor.yank.content(), &["hello world"]);
+        assert!(editor.yank.is_newline_yank());
+
+        editor.config.yanktoeol = true;
+        for key in ['l', 'l', 'l', 'l', 'l', 'l', 'Y'] {
+            send_key(
+                Key::Char(key),
+                &mut editor,
+                &mut mode_manager,
+                &mut pending_key,
+            );
+        }
+        assert_eq!(editor.yank.content(), &["world"]);
+        assert!(!editor.yank.is_newline_yank());
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "hello world");
+    }
+
+    fn dispatch_keys(keys: &str, editor: &mut Editor, state: &mut NormalState) {
+        let mut mode_manager = ModeManager::new();
+        for c in keys.chars() {
+            dispatch(Key::Char(c), editor, &mut mode_manager, state, (80, 24), 22);
+        }
+    }
+
+    #[test]
+    fn test_invalid_motion_rings_bell() {
+        let mut editor = make_editor_with_lines(&["ab", "cd"]);
+        let mut state = NormalState::default();
+        let mut mode_manager = ModeManager::new();
+        let mut press = |keys: &str, editor: &mut Editor| {
+            let mut result = HandlerResult::Continue;
+            for c in keys.chars() {
+                result = dispatch(
+                    Key::Char(c),
+                    editor,
+                    &mut mode_manager,
+                    &mut state,
+                    (80, 24),
+                    22,
+                );
+            }
+            result
+        };
+
+        assert!(matches!(press("h", &mut editor), HandlerResult::Bell));
+        assert!(matches!(press("k", &mut editor), HandlerResult::Bell));
+        assert!(matches!(press("fz", &mut editor), HandlerResult::Bell));
+        // 途中まで移動できたカウント付きの移動ではベルを鳴らさない
+        assert!(matches!(press("5j", &mut editor), HandlerResult::Continue));
+        assert_eq!(editor.cursor.file_row(), 1);
+        assert!(matches!(press("j", &mut editor), HandlerResult::Bell));
+        assert!(matches!(press("l", &mut editor), HandlerResult::Continue));
+    }
+
+    #[test]
+    fn test_cancel_pending_keys() {
+        let mut editor = make_editor_with_lines(&["one two", "three"]);
+        let mut state = NormalState::default();
+        let mut mode_manager = ModeManager::new();
+
+        let cancels = [
+            ("2d3", Key::Ctrl('c')),
+            ("5g", Key::Esc),
+            ("\"a", Key::Ctrl('c')),
+        ];
+        for (keys, cancel) in cancels {
+            dispatch_keys(keys, &mut editor, &mut state);
+            let result = dispatch(
+                cancel,
+                &mut editor,
+                &mut mode_manager,
+                &mut state,
+                (80, 24),
+                22,
+            );
+            assert!(matches!(result, HandlerResult::Bell));
+            assert_eq!(state, NormalState::default());
+            assert_eq!(editor.registers.take_selected(), None);
+        }
+
+        // 取り消した後のキーは新しいコマンドとして扱う
+        dispatch_keys("dw", &mut editor, &mut state);
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "two");
+        assert_eq!(editor.buffer().len(), 2);
+    }
+
+    #[test]
+    fn test_dispatch_counts() {
+        let mut editor = make_editor_with_lines(&["one", "two", "three", "four", "five", "six"]);
+        let mut state = NormalState::default();
+
+        dispatch_keys("3j", &mut editor, &mut state);
+        assert_eq!(editor.cursor.file_row(), 3);
+        assert_eq!(state.count, None);
+
+        dispatch_keys("gg", &mut editor, &mut state);
+        assert_eq!(editor.cursor.file_row(), 0);
+        dispatch_keys("5gg", &mut editor, &mut state);
+        assert_eq!(editor.cursor.file_row(), 4);
+
+        dispatch_keys("2x", &mut editor, &mut state);
+        assert_eq!(editor.buffer().row(4).unwrap().chars(), "ve");
+
+        // 3dd は 3 行をまとめて削除してレジスタに入れる
+        dispatch_keys("gg3dd", &mut editor, &mut state);
+        let rows: Vec<&str> = editor.buffer().iter().map(|r| r.chars()).collect();
+        assert_eq!(rows, vec!["four", "ve", "six"]);
+        assert_eq!(editor.yank.content(), &["one", "two", "three"]);
+
+        // 行数を超えるカウントは最終行までにする
+        dispatch_keys("j10dd", &mut editor, &mut state);
+        let rows: Vec<&str> = editor.buffer().iter().map(|r| r.chars()).collect();
+        assert_eq!(rows, vec!["four"]);
+        assert_eq!(state, NormalState::default());
+    }
+
+    #[test]
+    fn test_huge_count_with_motion() {
+        let mut editor = make_editor_with_lines(&["one", "two", "three"]);
+        let mut state = NormalState::default();
+
+        // 移動できなくなった時点で止まる
+        dispatch_keys("99999999999999999999j", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(2, 0));
+        dispatch_keys("999999999l", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(2, 4));
+        assert_eq!(state, NormalState::default());
+    }
+
+    #[test]
+    fn test_huge_count_with_operator_line() {
+        let mut editor = make_editor_with_lines(&["one", "two", "three"]);
+        let mut state = NormalState::default();
+
+        // 桁あふれするカウントは usize::MAX に丸め、最終行までを削除する
+        dispatch_keys("j99999999999999999999dd", &mut editor, &mut state);
+        let rows: Vec<&str> = editor.buffer().iter().map(|r| r.chars()).collect();
+        assert_eq!(rows, vec!["one"]);
+        assert_eq!(editor.yank.content(), &["two", "three"]);
+        assert_eq!(state, NormalState::default());
+    }
+
+    #[test]
+    fn test_x_uses_small_delete_register() {
+        let mut editor = make_editor_with_lines(&["one", "abcdef"]);
+        let mut state = NormalState::default();
+
+        dispatch_keys("ddl3x", &mut editor, &mut state);
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "aef");
+        // 3 文字をまとめて "- と無名レジスタに入れ、"1 は変えない
+        assert_eq!(editor.registers.get('-').unwrap().content, vec!["bcd"]);
+        assert_eq!(editor.yank.content(), &["bcd"]);
+        assert_eq!(editor.registers.get('1').unwrap().content, vec!["one"]);
+        assert_eq!(editor.cursor.position(), Position::new(0, 1));
+
+        // 1 回の u で 3 文字とも戻る
+        dispatch_keys("u", &mut editor, &mut state);
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "abcdef");
+    }
+
+    #[test]
+    fn test_x_upper_deletes_before_cursor() {
+        let mut editor = make_editor_with_lines(&["abcdef"]);
+        let mut state = NormalState::default();
+
+        dispatch_keys("4lX", &mut editor, &mut state);
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "abcef");
+        assert_eq!(editor.cursor.position(), Position::new(0, 3));
+
+        // 行頭を超えるカウントは行頭までにする
+        dispatch_keys("9X", &mut editor, &mut state);
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "ef");
+        assert_eq!(editor.registers.get('-').unwrap().content, vec!["abc"]);
+        assert_eq!(editor.cursor.position(), Position::new(0, 0));
+
+        // 行頭ではベルを鳴らす
+        let mut mode_manager = ModeManager::new();
+        let result = dispatch(
+            Key::Char('X'),
+            &mut editor,
+            &mut mode_manager,
+            &mut state,
+            (80, 24),
+            22,
+        );
+        assert!(matches!(result, HandlerResult::Bell));
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "ef");
+    }
+
+    #[test]
+    fn test_move_in_screen_line() {
+        let long = format!("  {}", "a".repeat(100));
+        let mut editor = make_editor_with_lines(&[&long, "  short"]);
+        let mut state = NormalState::default();
+
+        // 幅 80 の端末では 80 文字目までが表示される
+        dispatch_keys("g$", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(0, 79));
+        dispatch_keys("g0", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(0, 0));
+        dispatch_keys("g^", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(0, 2));
+        assert_eq!(state, NormalState::default());
+
+        // 行番号欄の分だけ表示できる幅が狭くなる
+        editor.config.number = true;
+        dispatch_keys("g$", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(0, 75));
+
+        // 画面に収まる行では行末に移動する
+        dispatch_keys("jg$", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(1, 6));
+    }
+
+    #[test]
+    fn test_move_in_screen_line_wrapped() {
+        let line = format!("{}{}  {}", "a".repeat(80), "b".repeat(80), "c".repeat(38));
+        let mut editor = make_editor_with_lines(&[&line]);
+        let mut state = NormalState::default();
+        editor.set_option("wrap").unwrap();
+
+        // 幅 80 の端末では 80 文字ごとに折り返すので、2 行目の両端に移動する
+        dispatch_keys("fbg$", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(0, 159));
+        dispatch_keys("g0", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(0, 80));
+
+        // 3 行目
+        dispatch_keys("$g0", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(0, 160));
+        dispatch_keys("g^", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(0, 162));
+        dispatch_keys("g$", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(0, 199));
+
+        // 行番号欄の分だけ狭い幅 (76 文字) で折り返す
+        editor.config.number = true;
+        dispatch_keys("g0", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(0, 152));
+        dispatch_keys("0fbg$", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(0, 151));
+        dispatch_keys("g0", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(0, 76));
+    }
+
+    #[test]
+    fn test_dispatch_register_digit_is_not_count() {
+        let mut editor = make_editor_with_lines(&["one", "two"]);
+        let mut state = NormalState::default();
+
+        dispatch_keys("dd\"1p", &mut editor, &mut state);
+        let rows: Vec<&str> = editor.buffer().iter().map(|r| r.chars()).collect();
+        assert_eq!(rows, vec!["two", "one"]);
+    }
+
+    #[test]
+    fn test_operator_with_motion() {
+        let mut editor =
+            make_editor_with_lines(&["hello world", "abcxdef x", "if (a(b)) {", "  one", "two"]);
+        let mut state = NormalState::default();
+
+        // d$ はカーソル位置から行末まで
+        dispatch_keys("6ld$", &mut editor, &mut state);
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "hello ");
+        assert_eq!(editor.yank.content(), &["world"]);
+        assert_eq!(editor.cursor.position(), Position::new(0, 5));
+
+        // dfx は x を含めて削除し、dtx は x の手前まで
+        dispatch_keys("j0dfx", &mut editor, &mut state);
+        assert_eq!(editor.buffer().row(1).unwrap().chars(), "def x");
+        dispatch_keys("dtx", &mut editor, &mut state);
+        assert_eq!(editor.buffer().row(1).unwrap().chars(), "x");
+
+        // y% は対応する括弧までをヤンクし、バッファは変更しない
+        dispatch_keys("jf(y%", &mut editor, &mut state);
+        assert_eq!(editor.yank.content(), &["(a(b))"]);
+        assert_eq!(editor.buffer().row(2).unwrap().chars(), "if (a(b)) {");
+        assert_eq!(editor.cursor.position(), Position::new(2, 3));
+
+        // dG は現在行から最終行までを行単位で削除する
+        dispatch_keys("jdG", &mut editor, &mut state);
+        let rows: Vec<&str> = editor.buffer().iter().map(|r| r.chars()).collect();
+        assert_eq!(rows, vec!["hello ", "x", "if (a(b)) {"]);
+        assert_eq!(editor.yank.content(), &["  one", "two"]);
+        assert_eq!(editor.cursor.file_row(), 2);
+        assert_eq!(state, NormalState::default());
+    }
+
+    #[test]
+    fn test_operator_motion_across_lines_is_charwise() {
+        let mut editor = make_editor_with_lines(&["f(a,", "  b) x", "end"]);
+        let mut state = NormalState::default();
+
+        // d% で行をまたいで削除しても文字単位でレジスタに入れる
+        dispatch_keys("f(d%", &mut editor, &mut state);
+        let rows: Vec<&str> = editor.buffer().iter().map(|r| r.chars()).collect();
+        assert_eq!(rows, vec!["f x", "end"]);
+        assert_eq!(editor.yank.content(), &["(a,", "  b)"]);
+        assert!(!editor.yank.is_newline_yank());
+
+        // p は新しい行を開かずにカーソルの後ろに挿入する
+        dispatch_keys("p", &mut editor, &mut state);
+        let rows: Vec<&str> = editor.buffer().iter().map(|r| r.chars()).collect();
+        assert_eq!(rows, vec!["f (a,", "  b)x", "end"]);
+        assert_eq!(editor.cursor.position(), Position::new(0, 2));
+
+        // y% も文字単位
+        editor.yank.yank_lines(vec!["line".to_string()]);
+        dispatch_keys("y%", &mut editor, &mut state);
+        assert_eq!(editor.yank.content(), &["(a,", "  b)"]);
+        assert!(!editor.yank.is_newline_yank());
+    }
+
+    #[test]
+    fn test_find_digit() {
+        let mut editor = make_editor_with_lines(&["a1b2c3d", "x1y2z3w"]);
+        let mut state = NormalState::default();
+
+        // f1 / t3 は数字の位置に移動する (カウントにしない)
+        dispatch_keys("f1", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(0, 1));
+        dispatch_keys("t3", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(0, 4));
+
+        // df2 は 2 を含めて削除し、dt3 は 3 の手前まで
+        dispatch_keys("j0df2", &mut editor, &mut state);
+        assert_eq!(editor.buffer().row(1).unwrap().chars(), "z3w");
+        dispatch_keys("dt3", &mut editor, &mut state);
+        assert_eq!(editor.buffer().row(1).unwrap().chars(), "3w");
+        assert_eq!(state, NormalState::default());
+    }
+
+    #[test]
+    fn test_operator_word_motions() {
+        let mut editor = make_editor_with_lines(&["foo bar baz", "qux"]);
+        let mut state = NormalState::default();
+
+        dispatch_keys("dw", &mut editor, &mut state);
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "bar baz");
+        // cw は単語の後ろの空白を残す
+        dispatch_keys("cw", &mut editor, &mut state);
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), " baz");
+        // 行の最後の単語の dw は次の行を結合しない
+        dispatch_keys("ldw", &mut editor, &mut state);
+        let rows: Vec<&str> = editor.buffer().iter().map(|r| r.chars()).collect();
+        assert_eq!(rows, vec![" ", "qux"]);
+        // モーションでないキーはオペレータを取り消す
+        dispatch_keys("dzj", &mut editor, &mut state);
+        assert_eq!(editor.buffer().len(), 2);
+        assert_eq!(editor.cursor.file_row(), 1);
+    }
+
+    #[test]
+    fn test_operator_counts() {
+        let words = "a b c d e f g h i j";
+        let mut editor = make_editor_with_lines(&[words, "one", "two", "three"]);
+        let mut state = NormalState::default();
+
+        // d3w: モーションの前のカウント
+        dispatch_keys("d3w", &mut editor, &mut state);
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "d e f g h i j");
+        // 2d3w: オペレータとモーションの前のカウントを掛け合わせる
+        dispatch_keys("2d3w", &mut editor, &mut state);
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "j");
+        assert_eq!(state, NormalState::default());
+
+        // 2dd / d2d は 2 行を削除する
+        dispatch_keys("j2dd", &mut editor, &mut state);
+        let rows: Vec<&str> = editor.buffer().iter().map(|r| r.chars()).collect();
+        assert_eq!(rows, vec!["j", "three"]);
+        dispatch_keys("ggd2d", &mut editor, &mut state);
+        assert_eq!(editor.buffer().len(), 1);
+        assert_eq!(editor.yank.content(), &["j", "three"]);
+    }
+
+    #[test]
+    fn test_pending_keys() {
+        let mut editor = make_editor_with_lines(&["a b c d e f"]);
+        let mut state = NormalState::default();
+
+        dispatch_keys("2d3", &mut editor, &mut state);
+        assert!(state.is_pending());
+        assert_eq!(state.pending_keys(), "2d3");
+        dispatch_keys("f", &mut editor, &mut state);
+        // モーションの前のカウントはオペレータのカウントに掛け合わせる
+        assert_eq!(state.pending_keys(), "6df");
+
+        state.reset();
+        assert!(!state.is_pending());
+        assert_eq!(state.pending_keys(), "");
+        // 取り消した後のキーは新しいコマンドとして扱う
+        dispatch_keys("x", &mut editor, &mut state);
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), " b c d e f");
+    }
+
+    #[test]
+    fn test_join_and_join_raw() {
+        let lines = ["foo", "    bar", "baz", "qux"];
+        let mut state = NormalState::default();
+
+        // J は空白を 1 つ入れて、次の行の先頭の空白を取り除く
+        let mut editor = make_editor_with_lines(&lines);
+        dispatch_keys("J", &mut editor, &mut state);
+        let rows: Vec<&str> = editor.buffer().iter().map(|r| r.chars()).collect();
+        assert_eq!(rows, vec!["foo bar", "baz", "qux"]);
+        assert_eq!(editor.cursor.position(), Position::new(0, 3));
+        dispatch_keys("3J", &mut editor, &mut state);
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "foo bar baz qux");
+
+        // gJ はそのまま連結する
+        let mut editor = make_editor_with_lines(&lines);
+        dispatch_keys("gJ", &mut editor, &mut state);
+        let rows: Vec<&str> = editor.buffer().iter().map(|r| r.chars()).collect();
+        assert_eq!(rows, vec!["foo    bar", "baz", "qux"]);
+        assert_eq!(editor.cursor.position(), Position::new(0, 3));
+        dispatch_keys("3gJ", &mut editor, &mut state);
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "foo    barbazqux");
+        assert_eq!(editor.cursor.position(), Position::new(0, 13));
+
+        // 最終行では何もしない
+        dispatch_keys("J", &mut editor, &mut state);
+        assert_eq!(editor.buffer().len(), 1);
+        assert_eq!(state, NormalState::default());
+
+        // 桁あふれするカウントは最終行までを結合する
+        let mut editor = make_editor_with_lines(&lines);
+        dispatch_keys("99999999999999999999J", &mut editor, &mut state);
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "foo bar baz qux");
+        dispatch_keys("u99999999999999999999gJ", &mut editor, &mut state);
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "foo    barbazqux");
+        assert_eq!(state, NormalState::default());
+    }
+
+    #[test]
+    fn test_paste_cursor_position() {
+        let mut state = NormalState::default();
+
+        // 複数行の p は最初に貼り付けた行の最初の非空白文字へ
+        let mut editor = make_editor_with_lines(&["a", "b"]);
+        editor
+            .yank
+            .yank_lines(vec!["    one".to_string(), "two".to_string()]);
+        dispatch_keys("p", &mut editor, &mut state);
+        assert_eq!(editor.buffer().len(), 4);
+        assert_eq!(editor.cursor.position(), Position::new(1, 4));
+        dispatch_keys("jP", &mut editor, &mut state);
+        assert_eq!(editor.buffer().row(2).unwrap().chars(), "    one");
+        assert_eq!(editor.cursor.position(), Position::new(2, 4));
+
+        // 行内は最後に貼り付けた文字へ
+        let mut editor = make_editor_with_lines(&["abc"]);
+        editor.yank.yank_inline("123".to_string());
+        dispatch_keys("p", &mut editor, &mut state);
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "a123bc");
+        assert_eq!(editor.cursor.position(), Position::new(0, 3));
+        dispatch_keys("P", &mut editor, &mut state);
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "a121233bc");
+        assert_eq!(editor.cursor.position(), Position::new(0, 5));
+    }
+
+    #[test]
+    fn test_paste_three_yanked_lines() {
+        let mut editor = make_editor_with_lines(&["  one", "two", "three", "four"]);
+        let mut state = NormalState::default();
+
+        // 3yy で 3 行ヤンクして最終行の下に貼り付けると、貼り付けた最初の行に移動する
+        dispatch_keys("3yyGp", &mut editor, &mut state);
+        let rows: Vec<&str> = editor.buffer().iter().map(|r| r.chars()).collect();
+        assert_eq!(
+            rows,
+            vec!["  one", "two", "three", "four", "  one", "two", "three"]
+        );
+        assert_eq!(editor.cursor.position(), Position::new(4, 2));
+
+        // P でも貼り付けた最初の行 (= 元の行) に留まる
+        dispatch_keys("ggjP", &mut editor, &mut state);
+        assert_eq!(editor.buffer().len(), 10);
+        assert_eq!(editor.buffer().row(1).unwrap().chars(), "  one");
+        assert_eq!(editor.cursor.position(), Position::new(1, 2));
+    }
+
+    #[test]
+    fn test_open_line_marks_dirty_and_undo() {
+        let mut editor = make_editor_with_lines(&["one", "two"]);
+        let mut mode_manager = ModeManager::new();
+        let mut pending_key: Option<char> = None;
+
+        send_key(
+            Key::Char('o'),
+            &mut editor,
+            &mut mode_manager,
+            &mut pending_key,
+        );
+        assert!(editor.is_dirty());
+        assert_eq!(editor.buffer().len(), 3);
+        assert_eq!(editor.buffer().row(1).unwrap().chars(), "");
+        assert_eq!(editor.cursor.position(), Position::new(1, 0));
+
+        // u で追加した行を取り消す
+        mode_manager.enter_normal();
+        send_key(
+            Key::Char('u'),
+            &mut editor,
+            &mut mode_manager,
+            &mut pending_key,
+        );
+        let rows: Vec<&str> = editor.buffer().iter().map(|r| r.chars()).collect();
+        assert_eq!(rows, vec!["one", "two"]);
+
+        // O も同様
+        let mut editor = make_editor_with_lines(&["one", "two"]);
+        send_key(
+            Key::Char('j'),
+            &mut editor,
+            &mut mode_manager,
+            &mut pending_key,
+        );
+        send_key(
+            Key::Char('O'),
+            &mut editor,
+            &mut mode_manager,
+            &mut pending_key,
+        );
+        assert!(editor.is_dirty());
+        assert_eq!(editor.buffer().row(1).unwrap().chars(), "");
+        mode_manager.enter_normal();
+        send_key(
+            Key::Char('u'),
+            &mut editor,
+            &mut mode_manager,
+            &mut pending_key,
+        );
+        assert_eq!(editor.buffer().len(), 2);
+        assert_eq!(editor.cursor.file_row(), 1);
+    }
+
+    #[test]
+    fn test_open_line_then_esc_blocks_quit() {
+        let mut editor = make_editor_with_lines(&["one"]);
+        let mut mode_manager = ModeManager::new();
+        let mut pending_key: Option<char> = None;
+
+        // o<Esc> で空行だけを追加しても未保存の変更として扱う
+        send_key(
+            Key::Char('o'),
+            &mut editor,
+            &mut mode_manager,
+            &mut pending_key,
+        );
+        insert::handle(Key::Esc, &mut editor, &mut mode_manager, (80, 24), 22);
+        assert!(editor.is_dirty());
+        let result = command::execute("q", &mut editor, 22);
+        assert!(matches!(result, HandlerResult::StatusMessage(msg) if msg.starts_with("No write")));
+    }
+
+    #[test]
+    fn test_first_non_blank_and_smarthome() {
+        let mut editor = make_editor_with_lines(&["    indented", "   "]);
+        let mut state = NormalState::default();
+
+        // ^ は最初の非空白文字へ
+        dispatch_keys("$^", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(0, 4));
+        // 既定では 0 は常に行頭へ
+        dispatch_keys("0", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(0, 0));
+        dispatch_keys("0", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(0, 0));
+
+        // smarthome では最初の非空白文字と行頭を行き来する
+        editor.config.smarthome = true;
+        dispatch_keys("$0", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(0, 4));
+        dispatch_keys("0", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(0, 0));
+        let mut mode_manager = ModeManager::new();
+        handle(
+            Key::Home,
+            &mut editor,
+            &mut mode_manager,
+            &mut None,
+            (80, 24),
+            22,
+        );
+        assert_eq!(editor.cursor.position(), Position::new(0, 4));
+
+        // 空白だけの行では最後の文字へ
+        dispatch_keys("j^", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(1, 2));
+    }
+
+    #[test]
+    fn test_repeat_substitute() {
+        let mut editor = make_editor_with_lines(&["a a", "a a", "a a"]);
+        let mut state = NormalState::default();
+
+        // 直前の :s がない場合
+        let mut mode_manager = ModeManager::new();
+        let result = handle(
+            Key::Char('&'),
+            &mut editor,
+            &mut mode_manager,
+            &mut None,
+            (80, 24),
+            22,
+        );
+        assert!(
+            matches!(result, HandlerResult::StatusMessage(msg) if msg.starts_with("No previous"))
+        );
+
+        command::execute("s/a/b/g", &mut editor, 22);
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "b b");
+
+        // & は別の行にフラグなしで適用する
+        dispatch_keys("j&", &mut editor, &mut state);
+        let rows: Vec<&str> = editor.buffer().iter().map(|r| r.chars()).collect();
+        assert_eq!(rows, vec!["b b", "b a", "a a"]);
+
+        // g& はファイル全体に同じフラグで適用する
+        dispatch_keys("g&", &mut editor, &mut state);
+        let rows: Vec<&str> = editor.buffer().iter().map(|r| r.chars()).collect();
+        assert_eq!(rows, vec!["b b", "b b", "b b"]);
+        assert_eq!(editor.cursor.file_row(), 2);
+        assert_eq!(state, NormalState::default());
+    }
+
+    #[test]
+    fn test_go_to_percentage() {
+        let lines: Vec<String> = (1..=10).map(|i| format!("    {}", i)).collect();
+        let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+        let mut editor = make_editor_with_lines(&lines);
+        let mut state = NormalState::default();
+
+        dispatch_keys("50%", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(4, 4));
+        dispatch_keys("100%", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(9, 4));
+
+        // 演算子と組み合わせると行単位になる
+        dispatch_keys("ggd20%", &mut editor, &mut state);
+        assert_eq!(editor.buffer().len(), 8);
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "    3");
+        assert_eq!(state, NormalState::default());
+    }
+
+    #[test]
+    fn test_paste_with_count() {
+        let mut state = NormalState::default();
+
+        // 3p: 行内の内容を 3 回繰り返して貼り付け、最後の文字に移動する
+        let mut editor = make_editor_with_lines(&["ab"]);
+        editor.yank.yank_inline("xy".to_string());
+        dispatch_keys("3p", &mut editor, &mut state);
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "axyxyxyb");
+        assert_eq!(editor.cursor.position(), Position::new(0, 6));
+
+        // 2p: 行単位の内容を 2 回分続けて下に貼り付ける
+        let mut editor = make_editor_with_lines(&["a", "b"]);
+        editor
+            .yank
+            .yank_lines(vec!["one".to_string(), "two".to_string()]);
+        dispatch_keys("2p", &mut editor, &mut state);
+        let rows: Vec<&str> = editor.buffer().iter().map(|r| r.chars()).collect();
+        assert_eq!(rows, vec!["a", "one", "two", "one", "two", "b"]);
+        assert_eq!(editor.cursor.position(), Position::new(1, 0));
+
+        // 2gp: 貼り付けた全体の次の行へ
+        dispatch_keys("k2gp", &mut editor, &mut state);
+        assert_eq!(editor.buffer().len(), 10);
+        assert_eq!(editor.cursor.position(), Position::new(5, 0));
+        assert_eq!(state, NormalState::default());
+    }
+
+    #[test]
+    fn test_gp_moves_cursor_after_paste() {
+        let mut state = NormalState::default();
+
+        // 行内: 貼り付けたテキストの直後の文字へ
+        let mut editor = make_editor_with_lines(&["abc"]);
+        editor.yank.yank_inline("12".to_string());
+        dispatch_keys("gp", &mut editor, &mut state);
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "a12bc");
+        assert_eq!(editor.cursor.position(), Position::new(0, 3));
+        dispatch_keys("gP", &mut editor, &mut state);
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "a1212bc");
+        assert_eq!(editor.cursor.position(), Position::new(0, 5));
+
+        // 行単位: 貼り付けた行の次の行の先頭へ
+        let mut editor = make_editor_with_lines(&["a", "b"]);
+        editor.yank.yank_line("new".to_string());
+        dispatch_keys("gp", &mut editor, &mut state);
+        let rows: Vec<&str> = editor.buffer().iter().map(|r| r.chars()).collect();
+        assert_eq!(rows, vec!["a", "new", "b"]);
+        assert_eq!(editor.cursor.position(), Position::new(2, 0));
+        dispatch_keys("gP", &mut editor, &mut state);
+        assert_eq!(editor.buffer().row(2).unwrap().chars(), "new");
+        assert_eq!(editor.cursor.position(), Position::new(3, 0));
+
+        // バッファの末尾に貼り付けた場合は最後の行
+        dispatch_keys("gp", &mut editor, &mut state);
+        assert_eq!(editor.buffer().len(), 5);
+        assert_eq!(editor.cursor.position(), Position::new(4, 0));
+        assert_eq!(state, NormalState::default());
+    }
+
+    #[test]
+    fn test_shift_with_motion() {
+        let mut editor = make_editor_with_lines(&["a", "b", "c", "d"]);
+        editor.config.shiftwidth = 2;
+        editor.config.expandtab = true;
+        let mut state = NormalState::default();
+
+        dispatch_keys(">2j", &mut editor, &mut state);
+        let rows: Vec<&str> = editor.buffer().iter().map(|r| r.chars()).collect();
+        assert_eq!(rows, vec!["  a", "  b", "  c", "d"]);
+        assert_eq!(editor.cursor.position(), Position::new(0, 2));
+    }
+
+    #[test]
+    fn test_spell_jump_and_add_word() {
+        let dir = tempfile::tempdir().unwrap();
+        let dictionary = dir.path().join("words");
+        let spellfile = dir.path().join("spell.add");
+        std::fs::write(&dictionary, "the\nquick\nfox\n").unwrap();
+
+        let mut editor = make_editor_with_lines(&["the quikc fox", "the zim fox"]);
+        let mut state = NormalState::default();
+        editor
+            .set_option(&format!("dictionary={}", dictionary.display()))
+            .unwrap();
+        editor
+            .set_option(&format!("spellfile={}", spellfile.display()))
+            .unwrap();
+        editor.set_option("spell").unwrap();
+
+        dispatch_keys("]s", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(0, 4));
+        dispatch_keys("]s", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(1, 4));
+
+        // zg で追加した単語はスペルミスとして扱わない
+        dispatch_keys("zg[s", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(0, 4));
+        assert_eq!(std::fs::read_to_string(&spellfile).unwrap(), "zim\n");
+
+        // zw で取り除くと再びスペルミスになる
+        dispatch_keys("jzw", &mut editor, &mut state);
+        assert!(!editor.spell_dictionary().unwrap().contains("zim"));
+        assert_eq!(std::fs::read_to_string(&spellfile).unwrap(), "");
+    }
+
+    #[test]
+    fn test_line_end_with_trailing_spaces() {
+        let mut editor = make_editor_with_lines(&["ab  ", "日本  ", "x"]);
+        let mut state = NormalState::default();
+
+        // l は末尾の空白にも移動でき、最後の文字で止まる
+        dispatch_keys("lll", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(0, 3));
+        dispatch_keys("l", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(0, 3));
+        dispatch_keys("0$", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(0, 3));
+
+        // マルチバイト文字の行も文字単位で数える
+        dispatch_keys("j0$", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(1, 3));
+        dispatch_keys("0llll", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(1, 3));
+        dispatch_keys("h", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(1, 2));
+
+        // 末尾の空白から上下に移動すると、短い行では最後の文字に収まる
+        dispatch_keys("$j", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(2, 0));
+
+        // 最終行の最後の単語からの w は行末 (末尾の空白) に移動する
+        let mut editor = make_editor_with_lines(&["ab  "]);
+        dispatch_keys("w", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(0, 3));
+        dispatch_keys("0e", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(0, 1));
+    }
+
+    #[test]
+    fn test_search_word_under_cursor() {
+        let mut editor = make_editor_with_lines(&["foo bar", "foobar", "x foo", "barfoo foo"]);
+        let mut state = NormalState::default();
+        editor.cursor.move_to_position(Position::new(0, 1), 4, 22);
+
+        // * は単語全体に一致する箇所だけを探す (foobar / barfoo の中には止まらない)
+        dispatch_keys("*", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(2, 2));
+        dispatch_keys("n", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(3, 7));
+        dispatch_keys("#", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(2, 2));
+
+        // g* は単語の一部にも一致する
+        editor.cursor.move_to_position(Position::new(0, 0), 4, 22);
+        dispatch_keys("g*", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(1, 0));
+
+        // 単語の上にない場合はエラー
+        editor.cursor.move_to_position(Position::new(0, 3), 4, 22);
+        let mut mode_manager = ModeManager::new();
+        assert!(matches!(
+            dispatch(
+                Key::Char('*'),
+                &mut editor,
+                &mut mode_manager,
+                &mut state,
+                (80, 24),
+                22
+            ),
+            HandlerResult::Error(msg) if msg == "No string under cursor"
+        ));
+    }
+
+    /// 行末に `count` 個の `!` を追加する
+    fn shout(ctx: &mut KeyContext) -> HandlerResult {
+        let row = ctx.editor.cursor.file_row();
+        let line = ctx.editor.buffer().row(row).unwrap().chars().to_string();
+        let line = line + &"!".repeat(ctx.count.unwrap_or(1));
+        ctx.editor.buffer_mut().set_row(row, line);
+        HandlerResult::ClearStatus
+    }
+
+    #[test]
+    fn test_registered_normal_key() {
+        let mut editor = make_editor_with_lines(&["hey", "you"]);
+        let mut state = NormalState::default();
+        editor.registry.register_normal_key("\\s", shout).unwrap();
+        // 組み込みのキーより優先する
+        editor.registry.register_normal_key("J", shout).unwrap();
+
+        dispatch_keys("\\", &mut editor, &mut state);
+        assert_eq!(state.pending_keys(), "\\");
+        dispatch_keys("s", &mut editor, &mut state);
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "hey!");
+        dispatch_keys("3\\s", &mut editor, &mut state);
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "hey!!!!");
+        dispatch_keys("J", &mut editor, &mut state);
+        assert_eq!(editor.buffer().len(), 2);
+        assert_eq!(editor.buffer().row(0).unwrap().chars(), "hey!!!!!");
+
+        // 途中まで一致したキーは組み込みのキーとして処理する (\ は何もせず j で下に移動する)
+        dispatch_keys("\\j", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(1, 0));
+        assert!(!state.is_pending());
+    }
+
+    #[test]
+    fn test_jump_to_changed_hunk() {
+        let mut editor = make_editor_with_lines(&["a", "b", "c", "d", "e", "f"]);
+        let mut state = NormalState::default();
+        editor.buffer_mut().set_row(1, "B".to_string());
+        editor.buffer_mut().insert_row(2, "new".to_string());
+        editor.buffer_mut().delete_row(5);
+
+        dispatch_keys("]c", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(1, 0));
+        dispatch_keys("]c", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(5, 0));
+        // 後ろに変更がなければ移動しない
+        dispatch_keys("]", &mut editor, &mut state);
+        let mut mode_manager = ModeManager::new();
+        assert!(matches!(
+            dispatch(
+                Key::Char('c'),
+                &mut editor,
+                &mut mode_manager,
+                &mut state,
+                (80, 24),
+                22
+            ),
+            HandlerResult::Bell
+        ));
+        assert_eq!(editor.cursor.position(), Position::new(5, 0));
+        dispatch_keys("[c", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(1, 0));
+    }
+
+    #[test]
+    fn test_ctrl_caret_switches_to_alternate_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = dir.path().join("a.txt").display().to_string();
+        let b = dir.path().join("b.txt").display().to_string();
+        std::fs::write(&a, "a1\na2\na3\n").unwrap();
+        std::fs::write(&b, "b1\n").unwrap();
+
+        let mut editor = Editor::new();
+        let mut mode_manager = ModeManager::new();
+        let mut state = NormalState::default();
+        let mut ctrl_caret = |editor: &mut Editor| {
+            dispatch(
+                Key::Ctrl('6'),
+                editor,
+                &mut mode_manager,
+                &mut state,
+                (80, 24),
+                22,
+            )
+        };
+
+        let result = ctrl_caret(&mut editor);
+        assert!(matches!(result, HandlerResult::StatusMessage(msg) if msg == "No alternate file"));
+        edit_file(&mut editor, &a, false);
+        editor.cursor.move_to_position(Position::new(2, 1), 3, 22);
+        edit_file(&mut editor, &b, false);
+
+        // A に戻るとカーソルは前回の位置になる
+        ctrl_caret(&mut editor);
+        assert_eq!(editor.filename(), Some(a.as_str()));
+        assert_eq!(editor.cursor.position(), Position::new(2, 1));
+        ctrl_caret(&mut editor);
+        assert_eq!(editor.filename(), Some(b.as_str()));
+
+        // 未保存の変更がある場合は切り替えない
+        editor.mark_dirty();
+        let result = ctrl_caret(&mut editor);
+        assert!(matches!(result, HandlerResult::StatusMessage(msg) if msg.starts_with("No write")));
+        assert_eq!(editor.filename(), Some(b.as_str()));
+    }
+
+    /// Insert mode に入るキーを含むキー列を送る
+    fn send_keys_with_insert(
+        keys: &[Key],
+        editor: &mut Editor,
+        mode_manager: &mut ModeManager,
+        state: &mut NormalState,
+    ) {
+        for key in keys {
+            if mode_manager.is_insert() {
+                crate::handler::insert::handle(*key, editor, mode_manager, (80, 24), 22);
+            } else {
+                dispatch(*key, editor, mode_manager, state, (80, 24), 22);
+            }
+        }
+    }
+
+    #[test]
+    fn test_virtualedit_moves_past_line_end() {
+        let mut editor = make_editor_with_lines(&["abc", "a", "abcdef"]);
+        let mut state = NormalState::default();
+
+        // onemore では最後の文字の 1 つ後ろまで
+        editor.set_option("virtualedit=onemore").unwrap();
+        dispatch_keys("llll", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(0, 3));
+
+        // all では文字のない位置にも移動でき、短い行に移動しても列を保つ
+        editor.set_option("ve=all").unwrap();
+        dispatch_keys("lllj", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(1, 6));
+
+        // 文字を入力した場合のみ空白で埋める
+        assert_eq!(editor.buffer().row(1).unwrap().chars(), "a");
+        let mut mode_manager = ModeManager::new();
+        let keys = [Key::Char('i'), Key::Char('x'), Key::Esc];
+        send_keys_with_insert(&keys, &mut editor, &mut mode_manager, &mut state);
+        assert_eq!(editor.buffer().row(1).unwrap().chars(), "a     x");
+        assert_eq!(editor.cursor.position(), Position::new(1, 6));
+    }
+
+    #[test]
+    fn test_append_at_line_end() {
+        let mut editor = make_editor_with_lines(&["", "a", "あいう"]);
+        let mut mode_manager = ModeManager::new();
+        let mut state = NormalState::default();
+
+        for row in 0..3 {
+            editor.cursor.move_to_position(Position::new(row, 0), 3, 22);
+            let keys = [Key::Char('A'), Key::Char('x'), Key::Esc];
+            send_keys_with_insert(&keys, &mut editor, &mut mode_manager, &mut state);
+        }
+        let rows: Vec<&str> = editor.buffer().iter().map(|r| r.chars()).collect();
+        assert_eq!(rows, vec!["x", "ax", "あいうx"]);
+        // Esc で最後に入力した文字の上に戻る
+        assert_eq!(editor.cursor.position(), Position::new(2, 3));
+    }
+
+    #[test]
+    fn test_dd_and_shift_move_to_first_non_blank() {
+        let mut editor = make_editor_with_lines(&["first", "    second", "\tthird", "  "]);
+        editor.config.shiftwidth = 2;
+        editor.config.expandtab = true;
+        let mut state = NormalState::default();
+
+        dispatch_keys("lldd", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(0, 4));
+        dispatch_keys("0dd", &mut editor, &mut state);
+        assert_eq!(editor.cursor.position(), Position::new(0, 1));
+
+        // 2>> は 2 行をインデントして、最初の行の最初の空白以外の文字に移動する
+        dispatch_keys("02>>", &mut editor, &mut state);
+        let rows: Vec<&str> = editor.buffer().iter().map(|r| r.chars()).collect();
+        assert_eq!(rows, vec!["          third", "    "]);
+        assert_eq!(editor.cursor.position(), Position::new(0, 10));
+
+        // 空白だけの行では最後の文字
+        dispatch_keys("j<<", &mut editor, &mut state);
+        assert_eq!(editor.buffer().row(1).unwrap().chars(), "  ");
+        assert_eq!(editor.cursor.position(), Position::new(1, 1));
+    }
 }
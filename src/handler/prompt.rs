@@ -0,0 +1,134 @@
+use termion::event::Key;
+
+use crate::editor::Editor;
+use crate::prompt::{Prompt, PromptAnswer};
+
+use super::HandlerResult;
+
+/// 確認 (`:confirm q` など) の応答のキー入力を処理する
+pub fn handle(key: Key, editor: &mut Editor) -> HandlerResult {
+    let Some(prompt) = editor.prompt.take() else {
+        return HandlerResult::Continue;
+    };
+
+    let answer = match key {
+        Key::Char(c) => PromptAnswer::from_char(c),
+        Key::Esc | Key::Ctrl('c') => Some(PromptAnswer::Cancel),
+        _ => None,
+    };
+    let Some(answer) = answer else {
+        // 無効なキーは無視して確認を続ける
+        let msg = prompt.message(editor.filename());
+        editor.prompt = Some(prompt);
+        return HandlerResult::StatusMessage(msg);
+    };
+
+    match (prompt, answer) {
+        (Prompt::SaveChanges, PromptAnswer::Yes) => {
+            if editor.is_readonly() {
+                return HandlerResult::StatusMessage(
+                    "'readonly' option is set (add ! to override)".to_string(),
+                );
+            }
+            match editor.save() {
+                Ok(_) => HandlerResult::Quit,
+                Err(e) => HandlerResult::StatusMessage(format!("Error: {}", e)),
+            }
+        }
+        (Prompt::SaveChanges, PromptAnswer::No) => HandlerResult::Quit,
+        (_, PromptAnswer::Cancel) => HandlerResult::ClearStatus,
+    }
+}
+
+/// 確認を開始して、メッセージを表示する
+pub fn start(prompt: Prompt, editor: &mut Editor) -> HandlerResult {
+    let msg = prompt.message(editor.filename());
+    editor.prompt = Some(prompt);
+    HandlerResult::StatusMessage(msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::command;
+
+    fn dirty_editor(path: &std::path::Path) -> Editor {
+        std::fs::write(path, "one\n").unwrap();
+        let mut editor = Editor::new();
+        editor
+            .open_file(path.to_string_lossy().into_owned())
+            .unwrap();
+        editor.buffer_mut().insert_row(1, "two".to_string());
+        editor.mark_dirty();
+        editor
+    }
+
+    #[test]
+    fn test_confirm_quit_answers() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+
+        // y: 保存して終了する
+        let mut editor = dirty_editor(&path);
+        let result = command::execute("confirm q", &mut editor, 22);
+        assert!(
+            matches!(result, HandlerResult::StatusMessage(msg) if msg.starts_with("Save changes to"))
+        );
+        // 無効なキーでは確認を続ける
+        assert!(matches!(
+            handle(Key::Char('x'), &mut editor),
+            HandlerResult::StatusMessage(_)
+        ));
+        assert!(matches!(
+            handle(Key::Char('y'), &mut editor),
+            HandlerResult::Quit
+        ));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "one\ntwo\n");
+        assert!(editor.prompt.is_none());
+
+        // n: 保存せずに終了する
+        let mut editor = dirty_editor(&path);
+        command::execute("conf q", &mut editor, 22);
+        assert!(matches!(
+            handle(Key::Char('n'), &mut editor),
+            HandlerResult::Quit
+        ));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "one\n");
+
+        // c / Esc: 取り消して編集を続ける
+        let mut editor = dirty_editor(&path);
+        command::execute("confirm q", &mut editor, 22);
+        assert!(matches!(
+            handle(Key::Char('c'), &mut editor),
+            HandlerResult::ClearStatus
+        ));
+        assert!(editor.is_dirty());
+        command::execute("confirm q", &mut editor, 22);
+        assert!(matches!(
+            handle(Key::Esc, &mut editor),
+            HandlerResult::ClearStatus
+        ));
+        assert!(editor.prompt.is_none());
+    }
+
+    #[test]
+    fn test_confirm_option() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+        let mut editor = dirty_editor(&path);
+
+        // 既定では確認せずに警告する
+        let result = command::execute("q", &mut editor, 22);
+        assert!(matches!(result, HandlerResult::StatusMessage(msg) if msg.starts_with("No write")));
+        assert!(editor.prompt.is_none());
+
+        editor.config.confirm = true;
+        command::execute("q", &mut editor, 22);
+        assert_eq!(editor.prompt, Some(Prompt::SaveChanges));
+        // ! 付きの場合は確認しない
+        assert!(matches!(
+            command::execute("q!", &mut editor, 22),
+            HandlerResult::Quit
+        ));
+    }
+}
@@ -0,0 +1,129 @@
+use crate::editor::Editor;
+use crate::search::{self, SearchDirection, SearchResult};
+
+use super::HandlerResult;
+
+/// `/` / `?` で入力したパターンで検索する
+///
+/// パターンが空の場合は直前のパターンで検索する。
+pub fn start(
+    pattern: &str,
+    direction: SearchDirection,
+    editor: &mut Editor,
+    editor_rows: u16,
+) -> HandlerResult {
+    let pattern = if pattern.is_empty() {
+        match &editor.last_search {
+            Some((last, _)) => last.clone(),
+            None => {
+                return HandlerResult::StatusMessage("No previous regular expression".to_string());
+            }
+        }
+    } else {
+        pattern.to_string()
+    };
+    editor.last_search = Some((pattern, direction));
+    repeat(editor, editor_rows, false)
+}
+
+/// 直前の検索を繰り返す (`n` / `N`)
+///
+/// `reverse` が true の場合は直前の検索と逆方向に検索する。
+pub fn repeat(editor: &mut Editor, editor_rows: u16, reverse: bool) -> HandlerResult {
+    let Some((pattern, direction)) = editor.last_search.clone() else {
+        return HandlerResult::StatusMessage("No previous regular expression".to_string());
+    };
+    let direction = if reverse {
+        direction.reverse()
+    } else {
+        direction
+    };
+    let regex = match editor.search_regex(&pattern) {
+        Ok(regex) => regex,
+        Err(e) => return HandlerResult::StatusMessage(e),
+    };
+
+    let wrapscan = editor.config.wrapscan;
+    let result = search::find(
+        editor.buffer(),
+        &regex,
+        editor.cursor.position(),
+        direction,
+        wrapscan,
+    );
+    let buffer_len = editor.buffer().len();
+    match result {
+        SearchResult::Found(pos) => {
+            editor.cursor.move_to_position(pos, buffer_len, editor_rows);
+            HandlerResult::ClearStatus
+        }
+        SearchResult::Wrapped(pos) => {
+            editor.cursor.move_to_position(pos, buffer_len, editor_rows);
+            let msg = match direction {
+                SearchDirection::Forward => "search hit BOTTOM, continuing at TOP",
+                SearchDirection::Backward => "search hit TOP, continuing at BOTTOM",
+            };
+            HandlerResult::StatusMessage(msg.to_string())
+        }
+        SearchResult::NotFound => HandlerResult::Error(format!("Pattern not found: {}", pattern)),
+        SearchResult::HitBoundary => {
+            let edge = match direction {
+                SearchDirection::Forward => "BOTTOM",
+                SearchDirection::Backward => "TOP",
+            };
+            HandlerResult::Error(format!(
+                "search hit {} without match for: {}",
+                edge, pattern
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::Buffer;
+    use crate::cursor::Position;
+
+    #[test]
+    fn test_wrapscan_option() {
+        let buffer = Buffer::from_lines(["foo", "bar", "baz"]);
+        let mut editor = Editor::from_buffer(buffer, None);
+        editor.cursor.move_down(22, 3);
+
+        // 一致がカーソルより前にしかない場合は折り返す
+        let result = start("foo", SearchDirection::Forward, &mut editor, 22);
+        assert!(
+            matches!(result, HandlerResult::StatusMessage(msg) if msg == "search hit BOTTOM, continuing at TOP")
+        );
+        assert_eq!(editor.cursor.position(), Position::new(0, 0));
+
+        // nowrapscan では折り返さない
+        editor.cursor.move_down(22, 3);
+        editor.config.wrapscan = false;
+        let result = repeat(&mut editor, 22, false);
+        assert!(
+            matches!(result, HandlerResult::Error(msg) if msg == "search hit BOTTOM without match for: foo")
+        );
+        assert_eq!(editor.cursor.position(), Position::new(1, 0));
+
+        // N は逆方向
+        let result = repeat(&mut editor, 22, true);
+        assert!(matches!(result, HandlerResult::ClearStatus));
+        assert_eq!(editor.cursor.position(), Position::new(0, 0));
+    }
+
+    #[test]
+    fn test_wholeword_option() {
+        let buffer = Buffer::from_lines(["foobar", "foo_bar", "a foo"]);
+        let mut editor = Editor::from_buffer(buffer, None);
+
+        // wholeword では単語の一部には一致しない
+        editor.config.wholeword = true;
+        start("foo", SearchDirection::Forward, &mut editor, 22);
+        assert_eq!(editor.cursor.position(), Position::new(2, 2));
+        editor.config.wholeword = false;
+        repeat(&mut editor, 22, false);
+        assert_eq!(editor.cursor.position(), Position::new(0, 0));
+    }
+}
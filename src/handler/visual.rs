@@ -1,53 +1,215 @@
 use termion::event::Key;
 
-use crate::cursor::Cursor;
+use crate::cursor::Position;
 use crate::editor::Editor;
 use crate::mode::ModeManager;
+use crate::range::LineRange;
 
 use super::HandlerResult;
+use super::normal::jump_to_mark;
+
+/// Visual / VisualLine mode で選択範囲に適用する操作
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VisualOperator {
+    /// `>`
+    ShiftRight,
+    /// `<`
+    ShiftLeft,
+    /// `u`
+    Lowercase,
+    /// `U`
+    Uppercase,
+    /// `~`
+    ToggleCase,
+    /// `r{char}`
+    Replace(char),
+    /// `J`
+    Join,
+    /// `gJ` (空白を入れずに結合する)
+    JoinRaw,
+    /// `g<` (共通のインデントを取り除く)
+    Outdent,
+}
+
+impl VisualOperator {
+    /// `g` に続くキーに対応する操作を返す
+    pub fn from_g_char(c: char) -> Option<Self> {
+        match c {
+            'J' => Some(Self::JoinRaw),
+            '<' => Some(Self::Outdent),
+            _ => None,
+        }
+    }
+}
+
+impl VisualOperator {
+    /// キーに対応する操作を返す (`r` は続く文字が必要なため含まない)
+    pub fn from_char(c: char) -> Option<Self> {
+        match c {
+            '>' => Some(Self::ShiftRight),
+            '<' => Some(Self::ShiftLeft),
+            'u' => Some(Self::Lowercase),
+            'U' => Some(Self::Uppercase),
+            '~' => Some(Self::ToggleCase),
+            'J' => Some(Self::Join),
+            _ => None,
+        }
+    }
+}
+
+/// 選択範囲
+pub struct Selection {
+    pub start: Position,
+    pub end: Position,
+    /// VisualLine mode の場合は true
+    pub linewise: bool,
+}
+
+/// 選択範囲に操作を適用し、Normal mode に戻る
+///
+/// カーソルは選択範囲の先頭 (行を結合した場合は最後に結合した位置) に移動する。
+pub fn apply_operator(
+    op: VisualOperator,
+    selection: Selection,
+    editor: &mut Editor,
+    mode_manager: &mut ModeManager,
+) -> HandlerResult {
+    let (start, end) = Editor::normalize_range(selection.start, selection.end);
+    // VisualLine mode では行全体が対象
+    let (start, end) = if selection.linewise {
+        (
+            Position::new(start.row, 0),
+            Position::new(end.row, usize::MAX),
+        )
+    } else {
+        (start, end)
+    };
+
+    if let Err(msg) = editor.check_modifiable() {
+        return HandlerResult::StatusMessage(msg);
+    }
+    editor.history.commit(editor.snapshot());
+    match op {
+        VisualOperator::ShiftRight => editor.shift_lines(start.row, end.row, true),
+        VisualOperator::ShiftLeft => editor.shift_lines(start.row, end.row, false),
+        VisualOperator::Lowercase => {
+            editor.map_range_chars(start, end, |c| c.to_lowercase().next().unwrap_or(c))
+        }
+        VisualOperator::Uppercase => {
+            editor.map_range_chars(start, end, |c| c.to_uppercase().next().unwrap_or(c))
+        }
+        VisualOperator::ToggleCase => editor.map_range_chars(start, end, toggle_case),
+        VisualOperator::Replace(ch) => editor.map_range_chars(start, end, |_| ch),
+        VisualOperator::Join => {
+            editor.join_lines(start.row, end.row);
+        }
+        VisualOperator::JoinRaw => {
+            // 範囲が 1 行の場合は次の行と結合する
+            for _ in start.row..end.row.max(start.row + 1) {
+                editor.join_with_next_raw(start.row);
+            }
+        }
+        VisualOperator::Outdent => {
+            editor.reduce_common_indent(LineRange::new(start.row, end.row), 0);
+        }
+    }
+
+    mode_manager.enter_normal();
+    mode_manager.clear_visual();
+    HandlerResult::ClearStatus
+}
+
+/// 大文字と小文字を入れ替える
+fn toggle_case(c: char) -> char {
+    if c.is_uppercase() {
+        c.to_lowercase().next().unwrap_or(c)
+    } else {
+        c.to_uppercase().next().unwrap_or(c)
+    }
+}
 
 pub fn handle(
     key: Key,
     editor: &mut Editor,
-    cursor: &mut Cursor,
     mode_manager: &mut ModeManager,
+    pending_key: &mut Option<char>,
     terminal_size: (u16, u16),
     editor_rows: u16,
 ) -> HandlerResult {
+    if *pending_key == Some('r') {
+        *pending_key = None;
+        if let (Key::Char(ch), Some(start)) = (key, mode_manager.visual_start()) {
+            let selection = Selection {
+                start,
+                end: editor.cursor.position(),
+                linewise: false,
+            };
+            let op = VisualOperator::Replace(ch);
+            return apply_operator(op, selection, editor, mode_manager);
+        }
+        return HandlerResult::Continue;
+    }
+    if *pending_key == Some('g') {
+        *pending_key = None;
+        if let (Key::Char(c), Some(start)) = (key, mode_manager.visual_start())
+            && let Some(op) = VisualOperator::from_g_char(c)
+        {
+            let selection = Selection {
+                start,
+                end: editor.cursor.position(),
+                linewise: false,
+            };
+            return apply_operator(op, selection, editor, mode_manager);
+        }
+        return HandlerResult::Continue;
+    }
+    if let Some(mark @ ('`' | '\'')) = *pending_key {
+        // v`] のように選択範囲をマークの位置まで広げる
+        *pending_key = None;
+        if let Key::Char(name) = key {
+            return jump_to_mark(name, mark == '\'', editor, editor_rows);
+        }
+        return HandlerResult::Continue;
+    }
+
     match key {
         Key::Esc => {
             mode_manager.enter_normal();
             mode_manager.clear_visual();
             return HandlerResult::ClearStatus;
         }
-        Key::Char('h') => cursor.move_left(),
+        Key::Char('h') => editor.cursor.move_left(),
         Key::Char('j') => {
-            cursor.move_down(editor_rows, editor.buffer().len());
+            editor.cursor.move_down(editor_rows, editor.buffer().len());
             // 移動後の行に合わせて x 座標を調整する
-            let row = cursor.file_row();
+            let row = editor.cursor.file_row();
             if let Some(line) = editor.buffer().row(row) {
-                cursor.adjust_cursor_x(line.char_count());
+                editor.cursor.adjust_cursor_x(line.char_count());
             }
         }
         Key::Char('k') => {
-            cursor.move_up();
+            editor.cursor.move_up();
             // 移動後の行に合わせて x 座標を調整する
-            let row = cursor.file_row();
+            let row = editor.cursor.file_row();
             if let Some(line) = editor.buffer().row(row) {
-                cursor.adjust_cursor_x(line.char_count());
+                editor.cursor.adjust_cursor_x(line.char_count());
             }
         }
         Key::Char('l') => {
-            let row = cursor.file_row();
+            let row = editor.cursor.file_row();
             if let Some(line) = editor.buffer().row(row) {
-                cursor.move_right(terminal_size.0, line.char_count());
+                editor.cursor.move_right(terminal_size.0, line.char_count());
             }
         }
         Key::Char('y') => {
-            // ヤンク
+            // ヤンク (Vim と同じく、カーソルは選択範囲の先頭に移動する)
             if let Some(start) = mode_manager.visual_start() {
-                let end = cursor.position();
+                let end = editor.cursor.position();
                 editor.yank_range(start, end);
+                let (top, _) = Editor::normalize_range(start, end);
+                editor
+                    .cursor
+                    .move_to_position(top, editor.buffer().len(), editor_rows);
                 mode_manager.enter_normal();
                 mode_manager.clear_visual();
                 return HandlerResult::StatusMessage("Yanked selection".to_string());
@@ -56,40 +218,213 @@ pub fn handle(
         Key::Char('d') => {
             // 削除してヤンク
             if let Some(start) = mode_manager.visual_start() {
-                editor.history.commit(editor.snapshot(cursor));
-                let end = cursor.position();
-                if editor.delete_range(start, end) {
-                    // 削除後、カーソルを範囲の開始位置に移動
-                    let (norm_start, _) = Editor::normalize_range(start, end);
-
-                    // カーソルを norm_start の位置に合わせる
-                    // row の移動
-                    let current_row = cursor.file_row();
-                    if norm_start.row < current_row {
-                        for _ in 0..(current_row - norm_start.row) {
-                            cursor.move_up();
-                        }
-                    } else if norm_start.row > current_row {
-                        for _ in 0..(norm_start.row - current_row) {
-                            cursor.move_down(editor_rows, editor.buffer().len());
-                        }
-                    }
-
-                    // col の移動
-                    cursor.move_to_line_start();
-                    let line_len = editor.current_line_len(norm_start.row);
-                    for _ in 0..norm_start.col.min(line_len) {
-                        cursor.move_right(terminal_size.0, line_len);
-                    }
-
-                    cursor.scroll(editor_rows, editor.buffer().len());
+                if let Err(msg) = editor.check_modifiable() {
+                    return HandlerResult::StatusMessage(msg);
                 }
+                editor.history.commit(editor.snapshot());
+                // 削除後、カーソルは範囲の開始位置に移動する
+                editor.delete_selection(start, editor.cursor.position());
                 mode_manager.enter_normal();
                 mode_manager.clear_visual();
                 return HandlerResult::StatusMessage("Deleted selection".to_string());
             }
         }
+        Key::Char('o') | Key::Char('O') => {
+            // 選択範囲のもう一方の端に移動する
+            // (矩形選択がないため `O` も `o` と同じ)
+            if let Some(start) = mode_manager.swap_visual_start(editor.cursor.position()) {
+                editor
+                    .cursor
+                    .move_to_position(start, editor.buffer().len(), editor_rows);
+            }
+        }
+        Key::Char('p') | Key::Char('P') => {
+            // 選択範囲をレジスタの内容で置き換える
+            if let Some(start) = mode_manager.visual_start() {
+                if let Err(msg) = editor.check_modifiable() {
+                    return HandlerResult::StatusMessage(msg);
+                }
+                let snapshot = editor.snapshot();
+                let end = editor.cursor.position();
+                if !editor.replace_selection(start, end, false) {
+                    return HandlerResult::Bell;
+                }
+                editor.history.commit(snapshot);
+                mode_manager.enter_normal();
+                mode_manager.clear_visual();
+                return HandlerResult::ClearStatus;
+            }
+        }
+        Key::Char(c @ ('r' | 'g' | '`' | '\'')) => *pending_key = Some(c),
+        Key::Char(c) => {
+            if let (Some(op), Some(start)) =
+                (VisualOperator::from_char(c), mode_manager.visual_start())
+            {
+                let selection = Selection {
+                    start,
+                    end: editor.cursor.position(),
+                    linewise: false,
+                };
+                return apply_operator(op, selection, editor, mode_manager);
+            }
+        }
         _ => {}
     }
     HandlerResult::Continue
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::Buffer;
+
+    fn make_editor_with_lines(lines: &[&str]) -> Editor {
+        let buffer = Buffer::from_lines(lines);
+        Editor::from_buffer(buffer, None)
+    }
+
+    fn rows(editor: &Editor) -> Vec<&str> {
+        editor.buffer().iter().map(|r| r.chars()).collect()
+    }
+
+    fn send_keys(keys: &str, editor: &mut Editor, mode_manager: &mut ModeManager) {
+        let mut pending_key = None;
+        for c in keys.chars() {
+            handle(
+                Key::Char(c),
+                editor,
+                mode_manager,
+                &mut pending_key,
+                (80, 24),
+                22,
+            );
+        }
+    }
+
+    #[test]
+    fn test_visual_uppercase_span() {
+        let mut editor = make_editor_with_lines(&["hello world"]);
+        let mut mode_manager = ModeManager::new();
+
+        // "world" を選択して U
+        for _ in 0..6 {
+            editor.cursor.move_right(80, 11);
+        }
+        mode_manager.enter_visual(editor.cursor.position());
+        send_keys("llllU", &mut editor, &mut mode_manager);
+
+        assert_eq!(rows(&editor), vec!["hello WORLD"]);
+        assert!(mode_manager.is_normal());
+        assert_eq!(editor.cursor.position(), Position::new(0, 6));
+    }
+
+    #[test]
+    fn test_visual_shift_right_three_lines() {
+        let mut editor = make_editor_with_lines(&["a", "b", "", "c"]);
+        editor.config.shiftwidth = 4;
+        editor.config.expandtab = true;
+        let mut mode_manager = ModeManager::new();
+
+        mode_manager.enter_visual(editor.cursor.position());
+        send_keys("jj>", &mut editor, &mut mode_manager);
+
+        // 空行はインデントしない
+        assert_eq!(rows(&editor), vec!["    a", "    b", "", "c"]);
+        assert!(mode_manager.is_normal());
+        assert_eq!(editor.cursor.position(), Position::new(0, 4));
+    }
+
+    #[test]
+    fn test_visual_replace_toggle_and_join() {
+        let mut editor = make_editor_with_lines(&["abc", "  Def", "ghi"]);
+        let mut mode_manager = ModeManager::new();
+
+        mode_manager.enter_visual(editor.cursor.position());
+        send_keys("lrx", &mut editor, &mut mode_manager);
+        assert_eq!(rows(&editor), vec!["xxc", "  Def", "ghi"]);
+
+        mode_manager.enter_visual(editor.cursor.position());
+        send_keys("j~", &mut editor, &mut mode_manager);
+        assert_eq!(rows(&editor), vec!["XXC", "  Def", "ghi"]);
+
+        mode_manager.enter_visual(editor.cursor.position());
+        send_keys("jJ", &mut editor, &mut mode_manager);
+        assert_eq!(rows(&editor), vec!["XXC Def", "ghi"]);
+        // J と同じく結合した位置に移動する
+        assert_eq!(editor.cursor.position(), Position::new(0, 3));
+    }
+
+    #[test]
+    fn test_visual_join_raw() {
+        let mut editor = make_editor_with_lines(&["abc", "  def", "ghi", "jkl"]);
+        let mut mode_manager = ModeManager::new();
+
+        mode_manager.enter_visual(editor.cursor.position());
+        send_keys("jjgJ", &mut editor, &mut mode_manager);
+        assert_eq!(rows(&editor), vec!["abc  defghi", "jkl"]);
+        assert!(mode_manager.is_normal());
+        assert_eq!(editor.cursor.position(), Position::new(0, 8));
+    }
+
+    #[test]
+    fn test_visual_outdent() {
+        let mut editor = make_editor_with_lines(&["      a", "        b", "c"]);
+        editor.config.expandtab = true;
+        let mut mode_manager = ModeManager::new();
+
+        mode_manager.enter_visual(editor.cursor.position());
+        send_keys("jg<", &mut editor, &mut mode_manager);
+        assert_eq!(rows(&editor), vec!["a", "  b", "c"]);
+        assert!(mode_manager.is_normal());
+        assert_eq!(editor.cursor.position(), Position::new(0, 0));
+    }
+
+    #[test]
+    fn test_visual_o_swaps_ends() {
+        let mut editor = make_editor_with_lines(&["abcdef", "ghijkl"]);
+        let mut mode_manager = ModeManager::new();
+
+        editor.cursor.move_right(80, 6);
+        mode_manager.enter_visual(editor.cursor.position());
+        send_keys("jll", &mut editor, &mut mode_manager);
+        assert_eq!(editor.cursor.position(), Position::new(1, 3));
+
+        send_keys("o", &mut editor, &mut mode_manager);
+        assert_eq!(editor.cursor.position(), Position::new(0, 1));
+        assert_eq!(mode_manager.visual_start(), Some(Position::new(1, 3)));
+
+        // もう一度 o で元に戻る
+        send_keys("o", &mut editor, &mut mode_manager);
+        assert_eq!(editor.cursor.position(), Position::new(1, 3));
+        assert_eq!(mode_manager.visual_start(), Some(Position::new(0, 1)));
+    }
+
+    #[test]
+    fn test_visual_yank_moves_cursor_to_selection_start() {
+        let mut editor = make_editor_with_lines(&["abcdef", "ghijkl", "mnopqr"]);
+        let mut mode_manager = ModeManager::new();
+
+        // 下から上に向かって選択する
+        editor.cursor.move_to_position(Position::new(2, 4), 3, 22);
+        mode_manager.enter_visual(editor.cursor.position());
+        send_keys("khhy", &mut editor, &mut mode_manager);
+
+        assert!(mode_manager.is_normal());
+        assert_eq!(editor.cursor.position(), Position::new(1, 2));
+        assert_eq!(editor.yank.content(), &["ijkl", "mnopq"]);
+    }
+
+    #[test]
+    fn test_visual_paste_with_empty_register() {
+        let mut editor = make_editor_with_lines(&["hello"]);
+        let mut mode_manager = ModeManager::new();
+
+        // レジスタが空の場合は何もせず、Visual mode のまま
+        mode_manager.enter_visual(editor.cursor.position());
+        send_keys("lp", &mut editor, &mut mode_manager);
+
+        assert_eq!(rows(&editor), vec!["hello"]);
+        assert!(mode_manager.is_visual());
+        assert!(editor.history.undo(editor.snapshot()).is_none());
+    }
+}
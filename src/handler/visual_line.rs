@@ -1,18 +1,47 @@
 use termion::event::Key;
 
-use crate::cursor::Cursor;
+use crate::cursor::Position;
 use crate::editor::Editor;
 use crate::mode::ModeManager;
 
 use super::HandlerResult;
+use super::visual::{Selection, VisualOperator, apply_operator};
 
 pub fn handle(
     key: Key,
     editor: &mut Editor,
-    cursor: &mut Cursor,
     mode_manager: &mut ModeManager,
+    pending_key: &mut Option<char>,
     editor_rows: u16,
 ) -> HandlerResult {
+    if *pending_key == Some('r') {
+        *pending_key = None;
+        if let (Key::Char(ch), Some(start)) = (key, mode_manager.visual_start()) {
+            let selection = Selection {
+                start,
+                end: editor.cursor.position(),
+                linewise: true,
+            };
+            let op = VisualOperator::Replace(ch);
+            return apply_operator(op, selection, editor, mode_manager);
+        }
+        return HandlerResult::Continue;
+    }
+    if *pending_key == Some('g') {
+        *pending_key = None;
+        if let (Key::Char(c), Some(start)) = (key, mode_manager.visual_start())
+            && let Some(op) = VisualOperator::from_g_char(c)
+        {
+            let selection = Selection {
+                start,
+                end: editor.cursor.position(),
+                linewise: true,
+            };
+            return apply_operator(op, selection, editor, mode_manager);
+        }
+        return HandlerResult::Continue;
+    }
+
     match key {
         Key::Esc => {
             mode_manager.enter_normal();
@@ -20,15 +49,18 @@ pub fn handle(
             return HandlerResult::ClearStatus;
         }
         Key::Char('j') => {
-            cursor.move_down(editor_rows, editor.buffer().len());
+            editor.cursor.move_down(editor_rows, editor.buffer().len());
         }
         Key::Char('k') => {
-            cursor.move_up();
+            editor.cursor.move_up();
         }
         Key::Char('y') => {
             if let Some(start) = mode_manager.visual_start() {
-                let end = cursor.position();
+                let end = editor.cursor.position();
                 editor.yank_lines_range(start.row, end.row);
+                // カーソルは選択範囲の先頭行に移動する
+                let top = start.row.min(end.row);
+                editor.move_cursor(Position::new(top, editor.cursor.col_index()), editor_rows);
                 mode_manager.enter_normal();
                 mode_manager.clear_visual();
                 return HandlerResult::StatusMessage("Yanked lines".to_string());
@@ -36,30 +68,54 @@ pub fn handle(
         }
         Key::Char('d') => {
             if let Some(start) = mode_manager.visual_start() {
-                editor.history.commit(editor.snapshot(cursor));
-                let end = cursor.position();
-                let min_row = start.row.min(end.row);
-                if editor.delete_lines_range(start.row, end.row) {
-                    // カーソルを min_row か、バッファ末尾のいずれか小さい方へ
-                    let buffer_len = editor.buffer().len();
-                    let target_row = min_row.min(buffer_len.saturating_sub(1));
-                    let current_row = cursor.file_row();
-                    if target_row < current_row {
-                        for _ in 0..(current_row - target_row) {
-                            cursor.move_up();
-                        }
-                    } else if target_row > current_row {
-                        for _ in 0..(target_row - current_row) {
-                            cursor.move_down(editor_rows, editor.buffer().len());
-                        }
-                    }
-                    cursor.move_to_line_start();
+                if let Err(msg) = editor.check_modifiable() {
+                    return HandlerResult::StatusMessage(msg);
                 }
+                editor.history.commit(editor.snapshot());
+                editor.delete_lines_range(start.row, editor.cursor.file_row());
                 mode_manager.enter_normal();
                 mode_manager.clear_visual();
                 return HandlerResult::StatusMessage("Deleted lines".to_string());
             }
         }
+        Key::Char('o') | Key::Char('O') => {
+            // 選択範囲のもう一方の端に移動する
+            if let Some(start) = mode_manager.swap_visual_start(editor.cursor.position()) {
+                editor
+                    .cursor
+                    .move_to_position(start, editor.buffer().len(), editor_rows);
+            }
+        }
+        Key::Char('p') | Key::Char('P') => {
+            // 選択範囲をレジスタの内容で置き換える
+            if let Some(start) = mode_manager.visual_start() {
+                if let Err(msg) = editor.check_modifiable() {
+                    return HandlerResult::StatusMessage(msg);
+                }
+                let snapshot = editor.snapshot();
+                let end = editor.cursor.position();
+                if !editor.replace_selection(start, end, true) {
+                    return HandlerResult::Bell;
+                }
+                editor.history.commit(snapshot);
+                mode_manager.enter_normal();
+                mode_manager.clear_visual();
+                return HandlerResult::ClearStatus;
+            }
+        }
+        Key::Char(c @ ('r' | 'g')) => *pending_key = Some(c),
+        Key::Char(c) => {
+            if let (Some(op), Some(start)) =
+                (VisualOperator::from_char(c), mode_manager.visual_start())
+            {
+                let selection = Selection {
+                    start,
+                    end: editor.cursor.position(),
+                    linewise: true,
+                };
+                return apply_operator(op, selection, editor, mode_manager);
+            }
+        }
         _ => {}
     }
     HandlerResult::Continue
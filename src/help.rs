@@ -0,0 +1,282 @@
+use crate::buffer::Buffer;
+
+/// Normal mode のキー一覧 (キー, 説明)
+pub const NORMAL_KEYS: &[(&str, &str)] = &[
+    ("h j k l", "move left / down / up / right"),
+    (
+        "0 $",
+        "move to start / end of line (:set smarthome toggles 0)",
+    ),
+    (
+        "g0 g^ g$",
+        "go to first / first non-blank / last character of the screen line",
+    ),
+    ("gg G", "move to first / last line"),
+    ("w b e", "move to next word / previous word / word end"),
+    ("^ %", "move to first non-blank / matching bracket"),
+    ("{N}%", "go to line N percent through the file"),
+    (
+        "f{c} t{c}",
+        "move to / before next {c} in line (F T backward)",
+    ),
+    ("i I", "insert before cursor / at line start"),
+    ("a A", "append after cursor / at line end"),
+    ("o O", "open a new line below / above"),
+    ("x X", "delete character under / before cursor (into \"-)"),
+    ("J gJ", "join lines (gJ without inserting spaces)"),
+    ("dd", "delete line"),
+    (
+        "d c y{motion}",
+        "delete / change / yank over motion (dw, c$, yG)",
+    ),
+    (">> <<", "shift line right / left"),
+    ("> <{motion}", "shift lines over motion (>j, <G)"),
+    ("yy", "yank line"),
+    ("D C", "delete / change to end of line"),
+    ("Y", "yank line (to end of line with yanktoeol)"),
+    ("p P", "paste after / before cursor"),
+    ("gp gP", "paste and leave cursor just after the text"),
+    ("]p [p", "paste lines adjusted to current indent"),
+    ("\"{a-z}", "use register for next yank / delete / paste"),
+    ("\"{A-Z}", "append to register"),
+    (
+        "Esc Ctrl-C",
+        "cancel pending count / operator / register and ring the bell",
+    ),
+    ("\"_", "black hole register (discard deleted text)"),
+    ("\"1-\"9 \"-", "recent line deletes / small delete"),
+    ("u Ctrl-R", "undo / redo"),
+    ("{N}j {N}dd", "repeat motion / x / p, or apply to N lines"),
+    ("{N}d{N}w", "counts before operator and motion multiply"),
+    ("{N}G {N}gg", "go to line N"),
+    ("v V", "start visual / visual line mode"),
+    (
+        "`[ `] '[ ']",
+        "go to start / end of last change or yank (' for line)",
+    ),
+    ("`< `> '< '>", "go to start / end of last visual selection"),
+    ("gf", "open the file under the cursor"),
+    ("Ctrl-^", "edit the alternate (previously edited) file"),
+    (":", "enter command mode"),
+    ("@:", "repeat last command line"),
+    ("/{pat} ?{pat}", "search forward / backward"),
+    ("n N", "repeat last search / in opposite direction"),
+    ("* #", "search whole word under cursor forward / backward"),
+    ("g* g#", "search word under cursor without word boundaries"),
+    ("& g&", "repeat last :s on line / in whole file"),
+    ("gn", "select next search match"),
+    ("]s [s", "move to next / previous misspelled word (spell)"),
+    ("]c [c", "move to next / previous changed lines"),
+    ("zg zw", "add / remove word under cursor in spellfile"),
+];
+
+/// Visual / VisualLine mode のキー一覧
+pub const VISUAL_KEYS: &[(&str, &str)] = &[
+    ("y", "yank selection"),
+    ("d", "delete selection"),
+    ("> <", "indent / dedent selected lines"),
+    ("g<", "remove common indent of selected lines"),
+    ("u U ~", "lowercase / uppercase / toggle case"),
+    ("r{char}", "replace selected characters"),
+    ("J gJ", "join selected lines (gJ without spaces)"),
+    ("o", "go to other end of selection"),
+    (
+        "`{mark}",
+        "extend selection to mark (v`] selects last change)",
+    ),
+    ("p", "replace selection with register"),
+    ("Esc", "return to normal mode"),
+];
+
+/// Insert mode のキー一覧
+pub const INSERT_KEYS: &[(&str, &str)] = &[
+    ("Esc", "return to normal mode"),
+    ("Enter", "split line"),
+    ("Backspace", "delete previous character"),
+    (
+        "Delete",
+        "delete character under cursor (joins lines at line end)",
+    ),
+    ("Arrows Home End", "move cursor without leaving insert mode"),
+    ("PageUp PageDown", "move cursor one screen up / down"),
+    ("Ctrl-A", "insert last inserted text"),
+    (
+        "Ctrl-T Ctrl-D",
+        "indent / dedent current line by shiftwidth",
+    ),
+    (
+        "Ctrl-V {key}",
+        "insert key literally (Ctrl-V u00e9 / x41 / 065 by code)",
+    ),
+    ("Ctrl-@", "insert last inserted text and stop insert"),
+];
+
+/// Ex コマンド一覧
+pub const EX_COMMANDS: &[(&str, &str)] = &[
+    ("w", "write file"),
+    ("w!", "write file even if readonly"),
+    (
+        "w {file}",
+        "write to another file (w! to overwrite an existing one)",
+    ),
+    ("q", "quit (close help)"),
+    ("q!", "quit without saving"),
+    ("qa", "quit all buffers (qa! discards changes)"),
+    ("wq", "write and quit"),
+    ("wqa", "write all changed buffers and quit"),
+    ("confirm q", "quit, asking whether to save changes"),
+    ("e {file}", "edit file"),
+    ("e #", "edit the alternate file"),
+    ("e!", "reload file, discarding changes"),
+    (
+        "set {option}",
+        "set option (list, number, tabstop, fileencoding, ...)",
+    ),
+    ("s/{pat}/{rep}/[gc]", "substitute in line (% for all lines)"),
+    ("d [x]", "delete lines (range) into register x"),
+    (
+        "left [col]",
+        "remove common indent of lines (range) down to col",
+    ),
+    (
+        "sort[!] [i]",
+        "sort lines (range, default all); ! reverses, i ignores case",
+    ),
+    ("reverse", "reverse the order of lines (range, default all)"),
+    (
+        "retab[!] [ts]",
+        "re-indent lines (range, default all) per expandtab; ! also mid-line tabs",
+    ),
+    (
+        "uniq[!]",
+        "remove repeated lines (range); ! keeps only repeated ones",
+    ),
+    (
+        "diff",
+        "show signs for lines changed since the last save (+ ~ -)",
+    ),
+    ("diffoff", "stop marking changed lines"),
+    ("y [x]", "yank lines (range) into register x"),
+    ("put [x]", "put register x below the line (put! above)"),
+    ("mkzimrc [file]", "write changed options as set commands"),
+    (
+        "autocmd FileType {ft} set {option}",
+        "set options when opening files of type ft",
+    ),
+    (
+        "source {file}",
+        "run ex commands in file (~/.zimrc at startup)",
+    ),
+    (
+        "echo {expr}",
+        "show line('.'), col('.'), expand('%') or &option",
+    ),
+    ("redraw", "clear and redraw the screen"),
+    ("sleep [N][m]", "wait N seconds (or N milliseconds with m)"),
+    ("{N}", "go to line N"),
+    ("help", "show this help"),
+];
+
+/// `:help` で表示する内容を生成する
+pub fn help_text() -> String {
+    let mut lines = vec![
+        "zim help".to_string(),
+        String::new(),
+        "Type :q to close this help.".to_string(),
+    ];
+
+    let sections = [
+        ("NORMAL MODE", NORMAL_KEYS, ""),
+        ("VISUAL MODE", VISUAL_KEYS, ""),
+        ("INSERT MODE", INSERT_KEYS, ""),
+        ("EX COMMANDS", EX_COMMANDS, ":"),
+    ];
+    for (title, entries, prefix) in sections {
+        lines.push(String::new());
+        lines.push(title.to_string());
+        for (key, description) in entries {
+            lines.push(format!(
+                "  {:<16}{}",
+                format!("{}{}", prefix, key),
+                description
+            ));
+        }
+    }
+    lines.join("\n")
+}
+
+/// help_text の内容を持つ Buffer を作成する
+pub fn help_buffer() -> Buffer {
+    Buffer::from_lines(help_text().lines())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_help_text_lists_each_entry() {
+        let text = help_text();
+        for (key, description) in NORMAL_KEYS.iter().chain(VISUAL_KEYS).chain(INSERT_KEYS) {
+            assert!(text.contains(key), "missing key: {}", key);
+            assert!(text.contains(description));
+        }
+        for (command, _) in EX_COMMANDS {
+            assert!(
+                text.contains(&format!(":{}", command)),
+                "missing command: {}",
+                command
+            );
+        }
+    }
+
+    #[test]
+    fn test_ex_commands_are_implemented() {
+        use crate::command_line::CommandLine;
+        use crate::editor::Editor;
+        use crate::handler::{HandlerResult, command};
+        use crate::mode::ModeManager;
+        use termion::event::Key;
+
+        for (command, _) in EX_COMMANDS {
+            // ファイルを書き出すコマンドは実行せずに、登録されていることだけを確認する
+            if command.starts_with("mkzimrc") {
+                assert!(crate::ex_command::resolve("mkzimrc").is_some());
+                continue;
+            }
+            let mut editor = Editor::new();
+            let mut mode_manager = ModeManager::new();
+            mode_manager.enter_command();
+            let mut command_line = CommandLine::new();
+            // 引数のプレースホルダは除いて実行する (行番号は 1 とする)
+            command_line.buffer = command
+                .split_whitespace()
+                .next()
+                .unwrap()
+                .replace("{N}", "1");
+
+            let result = command::handle(
+                Key::Char('\n'),
+                &mut editor,
+                &mut mode_manager,
+                &mut command_line,
+                (80, 24),
+                22,
+            );
+            if let HandlerResult::Error(msg) = result {
+                assert!(
+                    !msg.starts_with("Not an editor command"),
+                    "not implemented: {}",
+                    command
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_help_buffer_matches_text() {
+        let buffer = help_buffer();
+        assert_eq!(buffer.len(), help_text().lines().count());
+        assert_eq!(buffer.row(0).unwrap().chars(), "zim help");
+    }
+}
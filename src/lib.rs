@@ -1,13 +1,30 @@
 pub mod app;
 pub mod buffer;
+pub mod command_line;
+pub mod config;
 pub mod cursor;
+pub mod diff;
 pub mod editor;
+pub mod ex_command;
+pub mod expr;
 pub mod file_io;
+pub mod filetype;
 pub mod handler;
+pub mod help;
 pub mod history;
+pub mod literal;
 pub mod logger;
 pub mod mode;
+pub mod motion;
+pub mod pattern;
+pub mod prompt;
+pub mod range;
+pub mod register;
+pub mod registry;
 pub mod screen;
+pub mod search;
+pub mod spell;
+pub mod substitute;
 pub mod terminal;
 pub mod yank;
 
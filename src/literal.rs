@@ -0,0 +1,203 @@
+use termion::event::Key;
+
+/// Insert mode の `Ctrl-V` に続く入力 (次のキーの文字をそのまま挿入する)
+///
+/// Vim と同じく、数字で文字コードを指定することもできる。
+///
+/// - `123`: 10 進数 (3 桁まで)
+/// - `o123`: 8 進数 (3 桁まで)
+/// - `x7f`: 16 進数 (2 桁まで)
+/// - `u00e9`: 16 進数 (4 桁まで)
+/// - `U0001f600`: 16 進数 (8 桁まで)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LiteralInput {
+    /// `Ctrl-V` の直後
+    Start,
+    /// 文字コードを入力中
+    Code {
+        /// 文字コードを始めた文字 (10 進数の場合は None)
+        prefix: Option<char>,
+        radix: u32,
+        max_len: usize,
+        digits: String,
+    },
+}
+
+/// `LiteralInput::feed` の結果
+#[derive(Debug, PartialEq, Eq)]
+pub enum LiteralStep {
+    /// 続きの入力を待つ
+    Pending(LiteralInput),
+    /// 入力が終わった
+    Done {
+        /// 挿入する文字 (文字コードが文字として正しくない場合は None)
+        ch: Option<char>,
+        /// 文字コードの入力を終わらせたキー (挿入した後に通常どおり処理する)
+        rest: Option<Key>,
+    },
+}
+
+impl LiteralInput {
+    /// キーを 1 つ受け取って状態を進める
+    pub fn feed(self, key: Key) -> LiteralStep {
+        match (self, key) {
+            (Self::Start, Key::Char(c)) if c.is_ascii_digit() => Self::Code {
+                prefix: None,
+                radix: 10,
+                max_len: 3,
+                digits: String::new(),
+            }
+            .feed(key),
+            (Self::Start, Key::Char(prefix @ ('o' | 'O'))) => Self::code(prefix, 8, 3),
+            (Self::Start, Key::Char(prefix @ ('x' | 'X'))) => Self::code(prefix, 16, 2),
+            (Self::Start, Key::Char(prefix @ 'u')) => Self::code(prefix, 16, 4),
+            (Self::Start, Key::Char(prefix @ 'U')) => Self::code(prefix, 16, 8),
+            (Self::Start, key) => LiteralStep::Done {
+                ch: key_char(key),
+                rest: None,
+            },
+            (
+                Self::Code {
+                    prefix,
+                    radix,
+                    max_len,
+                    mut digits,
+                },
+                Key::Char(c),
+            ) if c.is_digit(radix) => {
+                digits.push(c);
+                if digits.len() < max_len {
+                    LiteralStep::Pending(Self::Code {
+                        prefix,
+                        radix,
+                        max_len,
+                        digits,
+                    })
+                } else {
+                    LiteralStep::Done {
+                        ch: code_char(&digits, radix),
+                        rest: None,
+                    }
+                }
+            }
+            (
+                Self::Code {
+                    prefix,
+                    radix,
+                    digits,
+                    ..
+                },
+                key,
+            ) => {
+                // 数字以外のキーで入力を終える。数字がなければ始めた文字をそのまま挿入する
+                let ch = if digits.is_empty() {
+                    prefix
+                } else {
+                    code_char(&digits, radix)
+                };
+                LiteralStep::Done {
+                    ch,
+                    rest: Some(key),
+                }
+            }
+        }
+    }
+
+    fn code(prefix: char, radix: u32, max_len: usize) -> LiteralStep {
+        LiteralStep::Pending(Self::Code {
+            prefix: Some(prefix),
+            radix,
+            max_len,
+            digits: String::new(),
+        })
+    }
+}
+
+fn code_char(digits: &str, radix: u32) -> Option<char> {
+    u32::from_str_radix(digits, radix)
+        .ok()
+        .and_then(char::from_u32)
+}
+
+/// キーが表す文字 (`Ctrl-V` の後に入力したキーをそのまま挿入する場合)
+fn key_char(key: Key) -> Option<char> {
+    match key {
+        // Enter は Vim と同じく CR (^M) にする
+        Key::Char('\n') => Some('\r'),
+        Key::Char(c) => Some(c),
+        Key::Ctrl(c) if c.is_ascii_alphabetic() => {
+            Some((c.to_ascii_uppercase() as u8 - b'@') as char)
+        }
+        Key::Esc => Some('\x1b'),
+        Key::Backspace => Some('\x08'),
+        Key::Delete => Some('\x7f'),
+        Key::Null => Some('\0'),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `Ctrl-V` の後に `keys` を入力した結果
+    fn feed_all(keys: &[Key]) -> LiteralStep {
+        let mut input = LiteralInput::Start;
+        for (i, key) in keys.iter().enumerate() {
+            match input.feed(*key) {
+                LiteralStep::Pending(next) => input = next,
+                done => {
+                    assert_eq!(i, keys.len() - 1, "finished before the last key");
+                    return done;
+                }
+            }
+        }
+        LiteralStep::Pending(input)
+    }
+
+    fn chars(s: &str) -> Vec<Key> {
+        s.chars().map(Key::Char).collect()
+    }
+
+    fn done(ch: Option<char>, rest: Option<Key>) -> LiteralStep {
+        LiteralStep::Done { ch, rest }
+    }
+
+    #[test]
+    fn test_literal_key() {
+        assert_eq!(feed_all(&[Key::Char('\t')]), done(Some('\t'), None));
+        assert_eq!(feed_all(&[Key::Esc]), done(Some('\x1b'), None));
+        assert_eq!(feed_all(&[Key::Ctrl('a')]), done(Some('\x01'), None));
+        assert_eq!(feed_all(&[Key::Char('\n')]), done(Some('\r'), None));
+        assert_eq!(feed_all(&[Key::Left]), done(None, None));
+    }
+
+    #[test]
+    fn test_literal_code() {
+        assert_eq!(feed_all(&chars("u00e9")), done(Some('é'), None));
+        assert_eq!(feed_all(&chars("U0001f600")), done(Some('😀'), None));
+        assert_eq!(feed_all(&chars("x41")), done(Some('A'), None));
+        assert_eq!(feed_all(&chars("065")), done(Some('A'), None));
+        assert_eq!(feed_all(&chars("o101")), done(Some('A'), None));
+        // 桁数に達する前に数字以外を入力すると、そこまでの値を挿入して続きのキーを処理する
+        assert_eq!(
+            feed_all(&chars("ue9z")),
+            done(Some('é'), Some(Key::Char('z')))
+        );
+        assert_eq!(
+            feed_all(&chars("9a")),
+            done(Some('\t'), Some(Key::Char('a')))
+        );
+        // 数字がなければ始めた文字をそのまま挿入する
+        assert_eq!(
+            feed_all(&chars("ug")),
+            done(Some('u'), Some(Key::Char('g')))
+        );
+        // 文字として正しくない値
+        assert_eq!(feed_all(&chars("ud800")), done(None, None));
+        assert!(matches!(
+            feed_all(&chars("u00")),
+            LiteralStep::Pending(LiteralInput::Code { .. })
+        ));
+    }
+}
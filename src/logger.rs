@@ -24,7 +24,7 @@ impl Logger {
 use std::cell::RefCell;
 
 thread_local! {
-    static LOGGER: RefCell<Option<Logger>> = RefCell::new(None);
+    static LOGGER: RefCell<Option<Logger>> = const { RefCell::new(None) };
 }
 
 pub fn init(path: &str) -> std::io::Result<()> {
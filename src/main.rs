@@ -1,24 +1,84 @@
-use std::io;
+use std::io::{self, Read};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use termion::input::TermRead;
 use zim::{
-    app::App, buffer::Buffer, editor::Editor, file_io::FileIO, handler::HandlerResult, logger,
-    terminal::Terminal,
+    app::App,
+    buffer::Buffer,
+    editor::Editor,
+    ex_command,
+    file_io::FileIO,
+    handler::HandlerResult,
+    logger,
+    terminal::{CursorShape, Terminal},
 };
 
+/// キー入力がない間に、ファイルが外部で変更されていないか確認する間隔
+const FILE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 起動時に開く対象
+enum Input {
+    /// 空のバッファ
+    Empty,
+    /// ファイル
+    File(String),
+    /// 標準入力 (`-`)
+    Stdin,
+}
+
+/// コマンドライン引数
+struct Args {
+    input: Input,
+    /// `--goto-byte N`: ファイル先頭からのバイト位置 (0 始まり) にカーソルを置く
+    goto_byte: Option<usize>,
+}
+
+fn parse_args(args: &[String]) -> Result<Args, String> {
+    let mut input = Input::Empty;
+    let mut goto_byte = None;
+    let mut rest = args.iter().skip(1);
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--goto-byte" => {
+                let value = rest
+                    .next()
+                    .ok_or_else(|| "Argument missing after: --goto-byte".to_string())?;
+                let offset = value
+                    .parse()
+                    .map_err(|_| format!("Invalid argument: --goto-byte {}", value))?;
+                goto_byte = Some(offset);
+            }
+            "-" => input = Input::Stdin,
+            path => input = Input::File(path.to_string()),
+        }
+    }
+    Ok(Args { input, goto_byte })
+}
+
 fn main() -> io::Result<()> {
     // ロガー初期化 (debug build のみ)
     let _ = logger::init("/tmp/zim_debug.log");
 
-    // ターミナル初期化
-    let mut terminal = Terminal::new()?;
-    terminal.clear_screen()?;
-
     // コマンドライン引数からファイル名を取得する
+    // 標準入力はターミナルを raw mode にする前にすべて読み込む
     let args: Vec<String> = std::env::args().collect();
-    let editor = if args.len() > 1 {
-        let path = &args[1];
-        match FileIO::open(path) {
+    let Args { input, goto_byte } = match parse_args(&args) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("zim: {}", e);
+            std::process::exit(2);
+        }
+    };
+    let editor = match &input {
+        Input::Empty => Editor::new(),
+        Input::Stdin => {
+            let mut editor = Editor::from_buffer(FileIO::from_reader(io::stdin().lock())?, None);
+            editor.set_readonly(true);
+            editor
+        }
+        Input::File(path) => match FileIO::open(path) {
             Ok(buf) => Editor::from_buffer(buf, Some(path.clone())),
             Err(e) if e.kind() == io::ErrorKind::NotFound => {
                 // ファイルが存在しない場合は空バッファで開く
@@ -29,24 +89,105 @@ fn main() -> io::Result<()> {
                 eprintln!("Error opening file: {}", e);
                 return Err(e);
             }
-        }
-    } else {
-        Editor::new()
+        },
     };
 
+    // ターミナル初期化
+    let mut terminal = Terminal::new()?;
+    terminal.clear_screen()?;
+
     let mut app = App::new(editor, terminal.size());
+    if let Some(path) = ex_command::user_zimrc().filter(|path| path.exists())
+        && let Err(e) = ex_command::source_file(&mut app.editor, &path, app.editor_rows)
+    {
+        app.status_message = e;
+    }
+    if let Some(offset) = goto_byte {
+        let pos = app.editor.buffer().byte_to_position(offset);
+        app.set_cursor_position(pos.row, pos.col);
+    }
 
     // 初期描画
+    let mut cursor_shape = CursorShape::for_mode(app.mode_manager.current());
+    terminal.set_cursor_shape(cursor_shape)?;
     app.refresh(terminal.stdout())?;
 
     // main loop
-    let stdin = io::stdin();
-    for key in stdin.keys() {
+    // 標準入力を読み込んだ場合はキー入力を /dev/tty から受け取る
+    let keys: Box<dyn Read + Send> = match input {
+        Input::Stdin => Box::new(termion::get_tty()?),
+        _ => Box::new(io::stdin()),
+    };
+    // 続くキーを待つ間に timeoutlen で取り消せるよう、キー入力は別スレッドで読み込む
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for key in keys.keys() {
+            if sender.send(key).is_err() {
+                break;
+            }
+        }
+    });
+    // showmatch の表示中に入力されたキー (次に処理する)
+    let mut next_key = None;
+    loop {
+        let key = match next_key.take() {
+            Some(key) => key,
+            None => {
+                let timeout = app
+                    .pending_timeout()
+                    .map_or(FILE_CHECK_INTERVAL, |timeout| {
+                        timeout.min(FILE_CHECK_INTERVAL)
+                    });
+                match receiver.recv_timeout(timeout) {
+                    Ok(key) => key,
+                    Err(RecvTimeoutError::Timeout) => {
+                        let expired = app.expire_pending(Instant::now());
+                        if expired | app.check_file_changed() {
+                            app.refresh(terminal.stdout())?;
+                        }
+                        continue;
+                    }
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+        };
         match app.handle_key(key?) {
             HandlerResult::Quit => break,
+            HandlerResult::Redraw => terminal.clear_screen()?,
+            HandlerResult::Sleep(duration) => {
+                // 待っている間に入力されたキー (:q など) は、再開後に順番に処理される
+                app.refresh(terminal.stdout())?;
+                std::thread::sleep(duration);
+            }
+            HandlerResult::Bell | HandlerResult::Error(_) => {
+                let config = &app.editor.config;
+                if config.visualbell || config.errorbells {
+                    terminal.bell(config.visualbell)?;
+                }
+            }
+            HandlerResult::ShowMatch(pos) => {
+                // キーが入力されたらすぐに元の位置に戻る
+                app.refresh_showing_match(terminal.stdout(), pos)?;
+                match receiver.recv_timeout(app.match_time()) {
+                    Ok(key) => next_key = Some(key),
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
             _ => {}
         }
-        app.refresh(terminal.stdout())?;
+        // モードが変わった場合はカーソルの形を変える
+        let shape = CursorShape::for_mode(app.mode_manager.current());
+        if shape != cursor_shape {
+            terminal.set_cursor_shape(shape)?;
+            cursor_shape = shape;
+        }
+        // Insert mode で文字を入力しただけならカーソル行だけを描画し直す
+        if app.line_only_changed() {
+            app.refresh_line(terminal.stdout())?;
+        } else {
+            app.refresh(terminal.stdout())?;
+        }
     }
 
     Ok(())
@@ -81,4 +81,15 @@ impl ModeManager {
     pub fn visual_start(&self) -> Option<Position> {
         self.visual_start
     }
+
+    /// 選択範囲の起点を入れ替える (Visual mode の `o` 用)
+    ///
+    /// # Returns
+    ///
+    /// 入れ替える前の起点。Visual mode でない場合は None
+    pub fn swap_visual_start(&mut self, pos: Position) -> Option<Position> {
+        let start = self.visual_start?;
+        self.visual_start = Some(pos);
+        Some(start)
+    }
 }
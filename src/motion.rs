@@ -0,0 +1,625 @@
+use crate::buffer::{Buffer, Row};
+use crate::cursor::Position;
+
+/// Normal mode のオペレータ (続けて入力したモーションの範囲に適用する)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    /// `d`
+    Delete,
+    /// `c`
+    Change,
+    /// `y`
+    Yank,
+    /// `>`
+    ShiftRight,
+    /// `<`
+    ShiftLeft,
+}
+
+impl Operator {
+    pub fn from_char(c: char) -> Option<Self> {
+        match c {
+            'd' => Some(Self::Delete),
+            'c' => Some(Self::Change),
+            'y' => Some(Self::Yank),
+            '>' => Some(Self::ShiftRight),
+            '<' => Some(Self::ShiftLeft),
+            _ => None,
+        }
+    }
+
+    /// オペレータのキー (`:showcmd` のような入力途中の表示用)
+    pub fn key(self) -> char {
+        match self {
+            Self::Delete => 'd',
+            Self::Change => 'c',
+            Self::Yank => 'y',
+            Self::ShiftRight => '>',
+            Self::ShiftLeft => '<',
+        }
+    }
+
+    /// モーションによらず行単位で適用するか (`>w` も行全体をインデントする)
+    pub fn is_linewise(self) -> bool {
+        matches!(self, Self::ShiftRight | Self::ShiftLeft)
+    }
+}
+
+/// カーソルの移動 (単独で使うほか、オペレータの範囲にもなる)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Motion {
+    /// `h`
+    Left,
+    /// `l`
+    Right,
+    /// `j`
+    Down,
+    /// `k`
+    Up,
+    /// `0`
+    LineStart,
+    /// `^`
+    FirstNonBlank,
+    /// `$`
+    LineEnd,
+    /// `w`
+    WordForward,
+    /// `b`
+    WordBackward,
+    /// `e`
+    WordEnd,
+    /// `gg`
+    FirstLine,
+    /// `G`
+    LastLine,
+    /// `f{char}`
+    FindForward(char),
+    /// `F{char}`
+    FindBackward(char),
+    /// `t{char}`
+    TillForward(char),
+    /// `T{char}`
+    TillBackward(char),
+    /// `%`
+    MatchPair,
+    /// `{count}%` (ファイル内の割合の位置の行)
+    Percent,
+}
+
+/// モーションのキー入力を解釈した結果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MotionInput {
+    Motion(Motion),
+    /// `f` / `g` など、続くキーが必要
+    Pending(char),
+}
+
+/// 文字の種類 (単語の区切りの判定用)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CharClass {
+    Blank,
+    Punctuation,
+    Word,
+}
+
+fn char_class(c: char) -> CharClass {
+    if c.is_whitespace() {
+        CharClass::Blank
+    } else if c.is_alphanumeric() || c == '_' {
+        CharClass::Word
+    } else {
+        CharClass::Punctuation
+    }
+}
+
+impl Motion {
+    /// キー入力をモーションとして解釈する
+    ///
+    /// `prefix` は直前に入力した `f` / `g` などのキー。モーションでない場合は None を返す。
+    pub fn parse(prefix: Option<char>, c: char) -> Option<MotionInput> {
+        let motion = match (prefix, c) {
+            (Some('f'), c) => Self::FindForward(c),
+            (Some('F'), c) => Self::FindBackward(c),
+            (Some('t'), c) => Self::TillForward(c),
+            (Some('T'), c) => Self::TillBackward(c),
+            (Some('g'), 'g') => Self::FirstLine,
+            (Some(_), _) => return None,
+            (None, 'f' | 'F' | 't' | 'T' | 'g') => return Some(MotionInput::Pending(c)),
+            (None, 'h') => Self::Left,
+            (None, 'l') => Self::Right,
+            (None, 'j') => Self::Down,
+            (None, 'k') => Self::Up,
+            (None, '0') => Self::LineStart,
+            (None, '^') => Self::FirstNonBlank,
+            (None, '$') => Self::LineEnd,
+            (None, 'w') => Self::WordForward,
+            (None, 'b') => Self::WordBackward,
+            (None, 'e') => Self::WordEnd,
+            (None, 'G') => Self::LastLine,
+            (None, '%') => Self::MatchPair,
+            _ => return None,
+        };
+        Some(MotionInput::Motion(motion))
+    }
+
+    /// カウントによって意味が変わるモーションを置き換える
+    ///
+    /// `%` はカウントがある場合は対応する括弧ではなく、ファイル内の割合の位置に移動する。
+    pub fn with_count(self, count: Option<usize>) -> Self {
+        match (self, count) {
+            (Self::MatchPair, Some(_)) => Self::Percent,
+            (motion, _) => motion,
+        }
+    }
+
+    /// 行単位のモーションか (`dj` / `dG` は行全体を削除する)
+    pub fn is_linewise(self) -> bool {
+        matches!(
+            self,
+            Self::Down | Self::Up | Self::FirstLine | Self::LastLine | Self::Percent
+        )
+    }
+
+    /// 移動先の文字も範囲に含むか
+    pub fn is_inclusive(self) -> bool {
+        matches!(
+            self,
+            Self::LineEnd
+                | Self::WordEnd
+                | Self::FindForward(_)
+                | Self::TillForward(_)
+                | Self::MatchPair
+        )
+    }
+
+    /// `from` からの移動先を返す (移動できない場合は None)
+    ///
+    /// `count` は繰り返しの回数。`G` / `gg` の場合は行番号 (1-indexed) として扱う。
+    /// 排他的なモーションの移動先は行末の次の列 (行の長さ) になることがある。
+    pub fn target(self, buffer: &Buffer, from: Position, count: Option<usize>) -> Option<Position> {
+        let last_row = buffer.len().checked_sub(1)?;
+        let n = count.unwrap_or(1).max(1);
+        let pos = match self {
+            Self::Left => {
+                if from.col == 0 {
+                    return None;
+                }
+                Position::new(from.row, from.col.saturating_sub(n))
+            }
+            Self::Right => {
+                let len = line_len(buffer, from.row);
+                if from.col >= len {
+                    return None;
+                }
+                Position::new(from.row, (from.col + n).min(len))
+            }
+            Self::Down => {
+                if from.row >= last_row {
+                    return None;
+                }
+                Position::new((from.row + n).min(last_row), from.col)
+            }
+            Self::Up => {
+                if from.row == 0 {
+                    return None;
+                }
+                Position::new(from.row.saturating_sub(n), from.col)
+            }
+            Self::LineStart => Position::new(from.row, 0),
+            Self::FirstNonBlank => Position::new(from.row, first_non_blank(buffer, from.row)),
+            Self::LineEnd => {
+                let row = (from.row + n - 1).min(last_row);
+                Position::new(row, line_len(buffer, row).saturating_sub(1))
+            }
+            Self::FirstLine => Position::new(count.map_or(0, |n| n - 1).min(last_row), from.col),
+            Self::LastLine => Position::new(
+                count
+                    .map_or(last_row, |n| n.saturating_sub(1))
+                    .min(last_row),
+                from.col,
+            ),
+            Self::WordForward => {
+                let mut pos = from;
+                for _ in 0..n {
+                    pos = word_forward(buffer, pos);
+                }
+                pos
+            }
+            Self::WordBackward => repeat(n, from, |pos| word_backward(buffer, pos))?,
+            Self::WordEnd => repeat(n, from, |pos| word_end(buffer, pos))?,
+            Self::FindForward(c) => {
+                Position::new(from.row, find_in_line(buffer, from, c, n, true)?)
+            }
+            Self::FindBackward(c) => {
+                Position::new(from.row, find_in_line(buffer, from, c, n, false)?)
+            }
+            Self::TillForward(c) => {
+                Position::new(from.row, find_in_line(buffer, from, c, n, true)? - 1)
+            }
+            Self::TillBackward(c) => {
+                Position::new(from.row, find_in_line(buffer, from, c, n, false)? + 1)
+            }
+            Self::MatchPair => match_pair(buffer, from)?,
+            Self::Percent => {
+                if n > 100 {
+                    return None;
+                }
+                let row = ((n * buffer.len()).div_ceil(100))
+                    .saturating_sub(1)
+                    .min(last_row);
+                Position::new(row, first_non_blank(buffer, row))
+            }
+        };
+        Some(pos)
+    }
+}
+
+/// `step` を `n` 回繰り返す (1 回も移動できない場合は None)
+fn repeat(
+    n: usize,
+    from: Position,
+    step: impl Fn(Position) -> Option<Position>,
+) -> Option<Position> {
+    let mut pos = step(from)?;
+    for _ in 1..n {
+        match step(pos) {
+            Some(next) => pos = next,
+            None => break,
+        }
+    }
+    Some(pos)
+}
+
+fn line_len(buffer: &Buffer, row: usize) -> usize {
+    buffer.row(row).map_or(0, |line| line.char_count())
+}
+
+/// 行の最初の空白以外の文字の列 (空白だけの行では最後の文字の列)
+pub fn first_non_blank(buffer: &Buffer, row: usize) -> usize {
+    buffer.row(row).map_or(0, Row::first_non_blank)
+}
+
+/// 指定位置の文字 (行末の次の列は改行として扱う)
+fn char_at(buffer: &Buffer, pos: Position) -> char {
+    buffer
+        .row(pos.row)
+        .and_then(|line| line.chars().chars().nth(pos.col))
+        .unwrap_or('\n')
+}
+
+/// 次の文字の位置 (行末の改行を含む)
+fn next_pos(buffer: &Buffer, pos: Position) -> Option<Position> {
+    if pos.col < line_len(buffer, pos.row) {
+        Some(Position::new(pos.row, pos.col + 1))
+    } else if pos.row + 1 < buffer.len() {
+        Some(Position::new(pos.row + 1, 0))
+    } else {
+        None
+    }
+}
+
+/// 前の文字の位置 (行末の改行を含む)
+fn prev_pos(buffer: &Buffer, pos: Position) -> Option<Position> {
+    if pos.col > 0 {
+        Some(Position::new(
+            pos.row,
+            pos.col.min(line_len(buffer, pos.row)) - 1,
+        ))
+    } else if pos.row > 0 {
+        Some(Position::new(pos.row - 1, line_len(buffer, pos.row - 1)))
+    } else {
+        None
+    }
+}
+
+/// 空行か (`w` / `b` は空行で止まる)
+fn is_empty_line(buffer: &Buffer, row: usize) -> bool {
+    line_len(buffer, row) == 0
+}
+
+/// `w`: 次の単語の先頭。次の単語がない場合はバッファの末尾 (最終行の行末の次の列)
+fn word_forward(buffer: &Buffer, from: Position) -> Position {
+    let last_row = buffer.len().saturating_sub(1);
+    let end = Position::new(last_row, line_len(buffer, last_row));
+    let mut pos = from;
+    let class = char_class(char_at(buffer, pos));
+    if class != CharClass::Blank {
+        loop {
+            let Some(next) = next_pos(buffer, pos) else {
+                return end;
+            };
+            pos = next;
+            if char_class(char_at(buffer, pos)) != class {
+                break;
+            }
+        }
+    }
+    while char_class(char_at(buffer, pos)) == CharClass::Blank {
+        if pos.row != from.row && is_empty_line(buffer, pos.row) {
+            break;
+        }
+        let Some(next) = next_pos(buffer, pos) else {
+            return end;
+        };
+        pos = next;
+    }
+    pos
+}
+
+/// `e`: 次の単語の末尾
+fn word_end(buffer: &Buffer, from: Position) -> Option<Position> {
+    let mut pos = next_pos(buffer, from)?;
+    while char_class(char_at(buffer, pos)) == CharClass::Blank {
+        pos = next_pos(buffer, pos)?;
+    }
+    let class = char_class(char_at(buffer, pos));
+    while let Some(next) = next_pos(buffer, pos)
+        && char_class(char_at(buffer, next)) == class
+    {
+        pos = next;
+    }
+    Some(pos)
+}
+
+/// `b`: 前の単語の先頭
+fn word_backward(buffer: &Buffer, from: Position) -> Option<Position> {
+    let mut pos = prev_pos(buffer, from)?;
+    while char_class(char_at(buffer, pos)) == CharClass::Blank {
+        if pos.row != from.row && is_empty_line(buffer, pos.row) {
+            return Some(pos);
+        }
+        let Some(prev) = prev_pos(buffer, pos) else {
+            return Some(pos);
+        };
+        pos = prev;
+    }
+    let class = char_class(char_at(buffer, pos));
+    while let Some(prev) = prev_pos(buffer, pos)
+        && char_class(char_at(buffer, prev)) == class
+    {
+        pos = prev;
+    }
+    Some(pos)
+}
+
+/// カーソル位置の単語の末尾から数えて `count` 個目の単語の末尾 (`cw` 用)
+///
+/// `cw` はカーソル位置の単語の後ろの空白を含めないため、`ce` と同じ範囲になる。
+/// ただし 1 文字の単語の上にいる場合は、その文字だけを対象にする。
+pub fn current_word_end(buffer: &Buffer, from: Position, count: usize) -> Position {
+    let class = char_class(char_at(buffer, from));
+    let mut pos = from;
+    while let Some(next) = next_pos(buffer, pos)
+        && char_class(char_at(buffer, next)) == class
+    {
+        pos = next;
+    }
+    for _ in 1..count {
+        match word_end(buffer, pos) {
+            Some(next) => pos = next,
+            None => break,
+        }
+    }
+    pos
+}
+
+/// 行内で `count` 個目の `target` の列 (`f` / `F` / `t` / `T` 用)
+fn find_in_line(
+    buffer: &Buffer,
+    from: Position,
+    target: char,
+    count: usize,
+    forward: bool,
+) -> Option<usize> {
+    let chars: Vec<char> = buffer.row(from.row)?.chars().chars().collect();
+    let cols = chars
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| **c == target)
+        .map(|(col, _)| col);
+    if forward {
+        cols.filter(|col| *col > from.col).nth(count - 1)
+    } else {
+        cols.rev().filter(|col| *col < from.col).nth(count - 1)
+    }
+}
+
+/// `%`: カーソル位置以降の最初の括弧に対応する括弧
+fn match_pair(buffer: &Buffer, from: Position) -> Option<Position> {
+    let col = buffer
+        .row(from.row)?
+        .chars()
+        .chars()
+        .skip(from.col)
+        .position(|c| "()[]{}".contains(c))?;
+    match_bracket(buffer, Position::new(from.row, from.col + col))
+}
+
+/// `pos` の位置の括弧に対応する括弧 (`%` と `:set showmatch` 用)
+///
+/// `pos` が括弧でない場合や、対応する括弧がない場合は None
+pub fn match_bracket(buffer: &Buffer, pos: Position) -> Option<Position> {
+    let (open, close, forward) = match char_at(buffer, pos) {
+        '(' => ('(', ')', true),
+        '[' => ('[', ']', true),
+        '{' => ('{', '}', true),
+        ')' => ('(', ')', false),
+        ']' => ('[', ']', false),
+        '}' => ('{', '}', false),
+        _ => return None,
+    };
+    let mut pos = pos;
+    let mut depth = 0usize;
+    loop {
+        let c = char_at(buffer, pos);
+        if c == open || c == close {
+            if (c == open) == forward {
+                depth += 1;
+            } else {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(pos);
+                }
+            }
+        }
+        pos = if forward {
+            next_pos(buffer, pos)?
+        } else {
+            prev_pos(buffer, pos)?
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(buffer: &Buffer, motion: Motion, row: usize, col: usize) -> Option<Position> {
+        motion.target(buffer, Position::new(row, col), None)
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            Motion::parse(None, 'w'),
+            Some(MotionInput::Motion(Motion::WordForward))
+        );
+        assert_eq!(Motion::parse(None, 'f'), Some(MotionInput::Pending('f')));
+        assert_eq!(
+            Motion::parse(Some('t'), 'x'),
+            Some(MotionInput::Motion(Motion::TillForward('x')))
+        );
+        assert_eq!(
+            Motion::parse(Some('g'), 'g'),
+            Some(MotionInput::Motion(Motion::FirstLine))
+        );
+        assert_eq!(Motion::parse(Some('g'), 'x'), None);
+        assert_eq!(Motion::parse(None, 'q'), None);
+    }
+
+    #[test]
+    fn test_word_motions() {
+        let buffer = Buffer::from_lines(["foo.bar  baz", "", "  qux"]);
+
+        assert_eq!(
+            target(&buffer, Motion::WordForward, 0, 0),
+            Some(Position::new(0, 3))
+        );
+        assert_eq!(
+            target(&buffer, Motion::WordForward, 0, 4),
+            Some(Position::new(0, 9))
+        );
+        // 空行で止まり、最後の単語からはバッファの末尾まで
+        assert_eq!(
+            target(&buffer, Motion::WordForward, 0, 9),
+            Some(Position::new(1, 0))
+        );
+        assert_eq!(
+            target(&buffer, Motion::WordForward, 2, 2),
+            Some(Position::new(2, 5))
+        );
+
+        assert_eq!(
+            target(&buffer, Motion::WordEnd, 0, 0),
+            Some(Position::new(0, 2))
+        );
+        assert_eq!(
+            target(&buffer, Motion::WordEnd, 0, 9),
+            Some(Position::new(0, 11))
+        );
+        // 行をまたいで次の単語の末尾へ
+        assert_eq!(
+            target(&buffer, Motion::WordEnd, 0, 11),
+            Some(Position::new(2, 4))
+        );
+
+        assert_eq!(
+            target(&buffer, Motion::WordBackward, 2, 2),
+            Some(Position::new(1, 0))
+        );
+        assert_eq!(
+            target(&buffer, Motion::WordBackward, 0, 9),
+            Some(Position::new(0, 4))
+        );
+        assert_eq!(target(&buffer, Motion::WordBackward, 0, 0), None);
+
+        assert_eq!(
+            current_word_end(&buffer, Position::new(0, 1), 1),
+            Position::new(0, 2)
+        );
+        assert_eq!(
+            current_word_end(&buffer, Position::new(0, 3), 2),
+            Position::new(0, 6)
+        );
+    }
+
+    #[test]
+    fn test_find_and_match_pair() {
+        let buffer = Buffer::from_lines(["a(b, c(d)) x", "{", "  [1]", "}"]);
+
+        assert_eq!(
+            Motion::FindForward('c').target(&buffer, Position::new(0, 0), None),
+            Some(Position::new(0, 5))
+        );
+        assert_eq!(
+            Motion::FindForward('(').target(&buffer, Position::new(0, 0), Some(2)),
+            Some(Position::new(0, 6))
+        );
+        assert_eq!(
+            target(&buffer, Motion::TillForward('x'), 0, 0),
+            Some(Position::new(0, 10))
+        );
+        assert_eq!(
+            target(&buffer, Motion::FindBackward('a'), 0, 5),
+            Some(Position::new(0, 0))
+        );
+        assert_eq!(
+            target(&buffer, Motion::TillBackward('a'), 0, 5),
+            Some(Position::new(0, 1))
+        );
+        assert_eq!(target(&buffer, Motion::FindForward('z'), 0, 0), None);
+
+        assert_eq!(
+            target(&buffer, Motion::MatchPair, 0, 0),
+            Some(Position::new(0, 9))
+        );
+        assert_eq!(
+            target(&buffer, Motion::MatchPair, 0, 9),
+            Some(Position::new(0, 1))
+        );
+        // 複数行にまたがる括弧
+        assert_eq!(
+            target(&buffer, Motion::MatchPair, 3, 0),
+            Some(Position::new(1, 0))
+        );
+        assert_eq!(target(&buffer, Motion::MatchPair, 0, 11), None);
+
+        // match_bracket はカーソル位置の括弧のみを対象にする
+        assert_eq!(
+            match_bracket(&buffer, Position::new(2, 4)),
+            Some(Position::new(2, 2))
+        );
+        assert_eq!(match_bracket(&buffer, Position::new(0, 0)), None);
+    }
+
+    #[test]
+    fn test_percent() {
+        let lines: Vec<String> = (1..=10).map(|i| format!("  line{}", i)).collect();
+        let buffer = Buffer::from_lines(&lines);
+        let percent = |n| {
+            Motion::MatchPair
+                .with_count(Some(n))
+                .target(&buffer, Position::new(0, 0), Some(n))
+        };
+
+        // 最初の非空白文字に移動する
+        assert_eq!(percent(50), Some(Position::new(4, 2)));
+        assert_eq!(percent(100), Some(Position::new(9, 2)));
+        // 切り上げる
+        assert_eq!(percent(1), Some(Position::new(0, 2)));
+        assert_eq!(percent(55), Some(Position::new(5, 2)));
+        assert_eq!(percent(101), None);
+        assert!(Motion::Percent.is_linewise());
+        assert_eq!(Motion::MatchPair.with_count(None), Motion::MatchPair);
+    }
+}
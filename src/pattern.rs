@@ -0,0 +1,285 @@
+use std::iter::Peekable;
+use std::str::Chars;
+
+use regex::{Regex, RegexBuilder};
+
+/// Vim の magic 形式の正規表現を regex クレートの構文に変換する
+///
+/// magic 形式では `.` `*` `[]` `^` `$` はそのまま特殊文字として扱い、
+/// `\(` `\)` `\|` `\+` `\?` `\=` `\{` のようにバックスラッシュ付きで
+/// グループ化や量指定子になる。`\<` `\>` は単語の先頭・末尾の境界に変換する。
+pub fn translate_magic(pattern: &str) -> String {
+    let mut result = String::new();
+    let mut chars = pattern.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => match chars.next() {
+                Some(c @ ('(' | ')' | '|' | '+' | '?')) => result.push(c),
+                Some('{') => {
+                    // `\{n,m}` の閉じ括弧はエスケープしなくてもよい
+                    result.push('{');
+                    while let Some(c) = chars.next() {
+                        match c {
+                            '}' => break,
+                            '\\' if chars.peek() == Some(&'}') => {
+                                chars.next();
+                                break;
+                            }
+                            c => result.push(c),
+                        }
+                    }
+                    result.push('}');
+                }
+                Some('=') => result.push('?'),
+                Some('<') => result.push_str(r"\b{start}"),
+                Some('>') => result.push_str(r"\b{end}"),
+                Some(c @ ('d' | 'D' | 'w' | 'W' | 's' | 'S')) => {
+                    result.push('\\');
+                    result.push(c);
+                }
+                Some('t') => result.push_str(r"\t"),
+                Some('n') => result.push_str(r"\n"),
+                Some(c) => result.push_str(&regex::escape(&c.to_string())),
+                None => result.push_str(r"\\"),
+            },
+            '[' => push_char_class(&mut chars, &mut result),
+            '.' | '*' | '^' | '$' => result.push(ch),
+            c => result.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    result
+}
+
+/// Vim の very magic 形式 (`\v`) の正規表現を regex クレートの構文に変換する
+///
+/// very magic 形式では `(` `)` `|` `+` `?` `=` `{` `<` `>` もバックスラッシュなしで
+/// 特殊文字として扱い、バックスラッシュを付けるとリテラルになる。
+/// 先頭の `\v` は取り除く。
+pub fn translate_very_magic(pattern: &str) -> String {
+    let pattern = pattern.strip_prefix(r"\v").unwrap_or(pattern);
+    let mut result = String::new();
+    let mut chars = pattern.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => match chars.next() {
+                Some(c @ ('d' | 'D' | 'w' | 'W' | 's' | 'S')) => {
+                    result.push('\\');
+                    result.push(c);
+                }
+                Some('t') => result.push_str(r"\t"),
+                Some('n') => result.push_str(r"\n"),
+                Some(c) => result.push_str(&regex::escape(&c.to_string())),
+                None => result.push_str(r"\\"),
+            },
+            '{' => {
+                // `{n,m}` は閉じ括弧までそのまま量指定子として渡す
+                result.push('{');
+                for c in chars.by_ref() {
+                    if c == '}' {
+                        break;
+                    }
+                    result.push(c);
+                }
+                result.push('}');
+            }
+            '=' => result.push('?'),
+            '<' => result.push_str(r"\b{start}"),
+            '>' => result.push_str(r"\b{end}"),
+            '[' => push_char_class(&mut chars, &mut result),
+            '(' | ')' | '|' | '+' | '?' | '.' | '*' | '^' | '$' => result.push(ch),
+            c => result.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    result
+}
+
+/// `[` に続く文字クラスを閉じ括弧まで変換して `result` に追加する
+///
+/// 閉じ括弧がない場合は Vim と同様に `[` 以降をリテラルとして扱う。
+fn push_char_class(chars: &mut Peekable<Chars>, result: &mut String) {
+    let mut class = String::from("[");
+    if chars.peek() == Some(&'^') {
+        class.push(chars.next().unwrap());
+    }
+    if chars.peek() == Some(&']') {
+        class.push_str(r"\]");
+        chars.next();
+    }
+    let mut closed = false;
+    while let Some(c) = chars.next() {
+        match c {
+            ']' => {
+                closed = true;
+                break;
+            }
+            '\\' => {
+                class.push('\\');
+                if let Some(next) = chars.next() {
+                    class.push(next);
+                }
+            }
+            '[' => class.push_str(r"\["),
+            c => class.push(c),
+        }
+    }
+    if closed {
+        class.push(']');
+        result.push_str(&class);
+    } else {
+        result.push_str(&regex::escape(&class));
+    }
+}
+
+/// 検索パターンをコンパイルする
+///
+/// パターン中の `\c` は大文字小文字を無視、`\C` は区別する指定として扱い、
+/// `ignore_case` より優先する。先頭の `\v` は very magic 形式、`\m` は magic 形式の
+/// 指定として扱い、`very_magic` より優先する。
+pub fn compile(pattern: &str, ignore_case: bool, very_magic: bool) -> Result<Regex, String> {
+    compile_with(pattern, ignore_case, very_magic, false)
+}
+
+/// 単語全体に一致する箇所だけを探すように検索パターンをコンパイルする (`:set wholeword` 用)
+///
+/// パターン全体を `\<` と `\>` で囲んだ場合と同じになる。
+pub fn compile_whole_word(pattern: &str, very_magic: bool) -> Result<Regex, String> {
+    compile_with(pattern, false, very_magic, true)
+}
+
+fn compile_with(
+    pattern: &str,
+    ignore_case: bool,
+    very_magic: bool,
+    whole_word: bool,
+) -> Result<Regex, String> {
+    let mut ignore_case = ignore_case;
+    let (very_magic, rest) = if let Some(rest) = pattern.strip_prefix(r"\v") {
+        (true, rest)
+    } else if let Some(rest) = pattern.strip_prefix(r"\m") {
+        (false, rest)
+    } else {
+        (very_magic, pattern)
+    };
+    let mut stripped = String::new();
+    let mut chars = rest.chars();
+    while let Some(ch) = chars.next() {
+        if ch == '\\' {
+            match chars.next() {
+                Some('c') => ignore_case = true,
+                Some('C') => ignore_case = false,
+                Some(c) => {
+                    stripped.push('\\');
+                    stripped.push(c);
+                }
+                None => stripped.push('\\'),
+            }
+        } else {
+            stripped.push(ch);
+        }
+    }
+
+    let mut translated = if very_magic {
+        translate_very_magic(&stripped)
+    } else {
+        translate_magic(&stripped)
+    };
+    if whole_word {
+        translated = format!(r"\b{{start}}(?:{})\b{{end}}", translated);
+    }
+    RegexBuilder::new(&translated)
+        .case_insensitive(ignore_case)
+        .build()
+        .map_err(|_| format!("Invalid pattern: {}", pattern))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_translate_magic_literals() {
+        // magic 形式では括弧や + はリテラル
+        assert_eq!(translate_magic("f(x)+1"), r"f\(x\)\+1");
+        assert_eq!(translate_magic("a.b*"), "a.b*");
+    }
+
+    #[test]
+    fn test_translate_magic_escaped_specials() {
+        assert_eq!(translate_magic(r"\(foo\|bar\)\+"), "(foo|bar)+");
+        assert_eq!(translate_magic(r"colou\=r"), "colou?r");
+        assert_eq!(translate_magic(r"\<word\>"), r"\b{start}word\b{end}");
+        assert_eq!(translate_magic(r"a\{2,3}"), "a{2,3}");
+        assert_eq!(translate_magic(r"\d\+"), r"\d+");
+    }
+
+    #[test]
+    fn test_translate_magic_char_class() {
+        assert_eq!(translate_magic("[a-z]x"), "[a-z]x");
+        assert_eq!(translate_magic("[^]]"), r"[^\]]");
+        assert_eq!(translate_magic("[abc"), r"\[abc");
+    }
+
+    #[test]
+    fn test_translate_very_magic() {
+        assert_eq!(translate_very_magic(r"\v(foo|bar)+"), "(foo|bar)+");
+        assert_eq!(
+            translate_very_magic(r"\v(foo|bar)+"),
+            translate_magic(r"\(foo\|bar\)\+")
+        );
+        assert_eq!(translate_very_magic(r"colou=r a{2,3}"), "colou?r a{2,3}");
+        assert_eq!(translate_very_magic(r"<\w+>"), r"\b{start}\w+\b{end}");
+        // バックスラッシュ付きはリテラル
+        assert_eq!(translate_very_magic(r"f\(x\)\+1"), r"f\(x\)\+1");
+        assert_eq!(translate_very_magic("[a-z]+"), "[a-z]+");
+    }
+
+    #[test]
+    fn test_compile_very_magic() {
+        assert!(
+            compile(r"\v(foo|bar)+$", false, false)
+                .unwrap()
+                .is_match("foobar")
+        );
+        assert!(
+            compile("(foo|bar)+$", false, true)
+                .unwrap()
+                .is_match("barfoo")
+        );
+        // \m で magic 形式に戻す
+        assert!(compile(r"\m(a)", false, true).unwrap().is_match("(a)"));
+    }
+
+    #[test]
+    fn test_compile_case_flags() {
+        assert!(compile("foo", false, false).unwrap().is_match("foo"));
+        assert!(!compile("foo", false, false).unwrap().is_match("FOO"));
+        assert!(compile(r"foo\c", false, false).unwrap().is_match("FOO"));
+        assert!(!compile(r"\Cfoo", true, false).unwrap().is_match("FOO"));
+        assert!(compile("foo", true, false).unwrap().is_match("Foo"));
+    }
+
+    #[test]
+    fn test_compile_invalid() {
+        assert!(compile(r"\(", false, false).is_err());
+    }
+
+    #[test]
+    fn test_whole_word() {
+        let regex = compile(r"\<foo\>", false, false).unwrap();
+        assert!(regex.is_match("a foo b"));
+        assert!(regex.is_match("foo.bar"));
+        assert!(!regex.is_match("foobar"));
+        assert!(!regex.is_match("barfoo"));
+        // \< は単語の先頭だけに一致する
+        assert!(!compile(r"o\<", false, false).unwrap().is_match("foo bar"));
+
+        let regex = compile_whole_word("foo", false).unwrap();
+        assert!(regex.is_match("(foo)"));
+        assert!(!regex.is_match("foobar"));
+        let regex = compile_whole_word(r"\vfoo|bar", true).unwrap();
+        assert!(regex.is_match("bar"));
+        assert!(!regex.is_match("foox xbar"));
+    }
+}
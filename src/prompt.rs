@@ -0,0 +1,40 @@
+/// 1 キーの入力で応答する確認 (`:confirm q` など)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Prompt {
+    /// 未保存の変更を保存してから終了するか
+    SaveChanges,
+}
+
+impl Prompt {
+    /// ステータス行に表示する確認のメッセージ
+    pub fn message(self, filename: Option<&str>) -> String {
+        match self {
+            Self::SaveChanges => format!(
+                "Save changes to \"{}\"? (y)es (n)o (c)ancel",
+                filename.unwrap_or("[No Name]")
+            ),
+        }
+    }
+}
+
+/// 確認に対する応答
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PromptAnswer {
+    /// `y`: 保存する
+    Yes,
+    /// `n`: 保存しない
+    No,
+    /// `c` / Esc: 取り消す
+    Cancel,
+}
+
+impl PromptAnswer {
+    pub fn from_char(c: char) -> Option<Self> {
+        match c.to_ascii_lowercase() {
+            'y' => Some(Self::Yes),
+            'n' => Some(Self::No),
+            'c' => Some(Self::Cancel),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,166 @@
+/// Ex コマンドの行範囲 (0-indexed, 両端を含む)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl LineRange {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    /// 1 行だけの範囲
+    pub fn single(row: usize) -> Self {
+        Self::new(row, row)
+    }
+}
+
+/// コマンド先頭の行範囲をパースする
+///
+/// `%` / `.` / `$` / 行番号 / `+N` / `-N` と、それらを `,` でつないだ形式に対応する。
+///
+/// # Arguments
+///
+/// - `command`: コマンド文字列 (先頭の `:` は含まない)
+/// - `current_row`: カーソル行 (0-indexed)
+/// - `last_row`: バッファの最終行 (0-indexed)
+///
+/// # Returns
+///
+/// (行範囲, 行範囲を除いた残りのコマンド)。行範囲の指定がない場合は None
+pub fn parse_range(
+    command: &str,
+    current_row: usize,
+    last_row: usize,
+) -> Result<(Option<LineRange>, &str), String> {
+    let command = command.trim_start();
+    if let Some(rest) = command.strip_prefix('%') {
+        return Ok((Some(LineRange::new(0, last_row)), rest));
+    }
+
+    let (start, rest) = parse_address(command, current_row, last_row)?;
+    let Some(start) = start else {
+        return Ok((None, rest));
+    };
+
+    let (end, rest) = match rest.strip_prefix(',') {
+        Some(after_comma) => {
+            let (end, rest) = parse_address(after_comma, current_row, last_row)?;
+            // `N,` のように終端が省略された場合はカーソル行
+            (end.unwrap_or(current_row), rest)
+        }
+        None => (start, rest),
+    };
+
+    if start > last_row || end > last_row {
+        return Err("Invalid range".to_string());
+    }
+    // 逆順の範囲は入れ替える
+    Ok((Some(LineRange::new(start.min(end), start.max(end))), rest))
+}
+
+/// 1 つのアドレスをパースして行 (0-indexed) を返す
+fn parse_address(
+    input: &str,
+    current_row: usize,
+    last_row: usize,
+) -> Result<(Option<usize>, &str), String> {
+    let (base, mut rest) = if let Some(rest) = input.strip_prefix('.') {
+        (Some(current_row as i64), rest)
+    } else if let Some(rest) = input.strip_prefix('$') {
+        (Some(last_row as i64), rest)
+    } else {
+        match split_number(input) {
+            // 行番号は 1-indexed。0 は先頭行として扱う
+            (Some(n), rest) => (Some(n.max(1) - 1), rest),
+            (None, rest) => (None, rest),
+        }
+    };
+
+    // `+N` / `-N` のオフセット (ベースが省略された場合はカーソル行が基準)
+    let mut row = base;
+    while let Some(sign) = rest.chars().next().filter(|c| *c == '+' || *c == '-') {
+        let (n, after) = split_number(&rest[1..]);
+        let offset = n.unwrap_or(1);
+        let current = row.unwrap_or(current_row as i64);
+        row = Some(if sign == '+' {
+            current + offset
+        } else {
+            current - offset
+        });
+        rest = after;
+    }
+
+    match row {
+        Some(row) if row < 0 => Err("Invalid range".to_string()),
+        Some(row) => Ok((Some(row as usize), rest)),
+        None => Ok((None, rest)),
+    }
+}
+
+/// 先頭の数字をパースする
+fn split_number(input: &str) -> (Option<i64>, &str) {
+    let len = input
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(input.len());
+    if len == 0 {
+        return (None, input);
+    }
+    (input[..len].parse().ok(), &input[len..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_range_none() {
+        assert_eq!(parse_range("w", 3, 9), Ok((None, "w")));
+        assert_eq!(parse_range("s/a/b/", 3, 9), Ok((None, "s/a/b/")));
+    }
+
+    #[test]
+    fn test_parse_range_whole_buffer() {
+        assert_eq!(
+            parse_range("%s/a/b/g", 3, 9),
+            Ok((Some(LineRange::new(0, 9)), "s/a/b/g"))
+        );
+    }
+
+    #[test]
+    fn test_parse_range_numbers() {
+        assert_eq!(
+            parse_range("2,5d", 0, 9),
+            Ok((Some(LineRange::new(1, 4)), "d"))
+        );
+        assert_eq!(parse_range("7", 0, 9), Ok((Some(LineRange::single(6)), "")));
+        // 逆順は入れ替える
+        assert_eq!(
+            parse_range("5,2d", 0, 9),
+            Ok((Some(LineRange::new(1, 4)), "d"))
+        );
+    }
+
+    #[test]
+    fn test_parse_range_symbols_and_offsets() {
+        assert_eq!(
+            parse_range(".,$y", 3, 9),
+            Ok((Some(LineRange::new(3, 9)), "y"))
+        );
+        assert_eq!(
+            parse_range(".-1,.+2d", 3, 9),
+            Ok((Some(LineRange::new(2, 5)), "d"))
+        );
+        assert_eq!(
+            parse_range("+2", 3, 9),
+            Ok((Some(LineRange::single(5)), ""))
+        );
+    }
+
+    #[test]
+    fn test_parse_range_invalid() {
+        assert!(parse_range("20", 0, 9).is_err());
+        assert!(parse_range(".-5", 2, 9).is_err());
+    }
+}
@@ -0,0 +1,271 @@
+use std::collections::HashMap;
+
+use crate::yank::YankType;
+
+/// レジスタの内容
+#[derive(Debug, Clone, PartialEq)]
+pub struct Register {
+    pub content: Vec<String>,
+    pub yank_type: YankType,
+}
+
+impl Register {
+    pub fn new(content: Vec<String>, yank_type: YankType) -> Self {
+        Self { content, yank_type }
+    }
+
+    pub fn is_newline_yank(&self) -> bool {
+        matches!(self.yank_type, YankType::NewLine)
+    }
+
+    /// 内容を末尾に追加する
+    ///
+    /// どちらかが行単位の場合は新しい行として追加し、結果も行単位になる。
+    /// 両方とも行内の場合は最終行に続けて連結する。
+    fn append(&mut self, content: Vec<String>, yank_type: YankType) {
+        if self.is_newline_yank() || matches!(yank_type, YankType::NewLine) {
+            self.content.extend(content);
+            self.yank_type = YankType::NewLine;
+            return;
+        }
+
+        let mut lines = content.into_iter();
+        if let (Some(last), Some(first)) = (self.content.last_mut(), lines.next()) {
+            last.push_str(&first);
+        }
+        self.content.extend(lines);
+    }
+}
+
+/// ブラックホールレジスタ。書き込んだ内容は捨てられる
+pub const BLACK_HOLE: char = '_';
+
+/// 1 行に満たない削除を保存するレジスタ
+pub const SMALL_DELETE: char = '-';
+
+/// 直前に Insert mode で入力したテキストを保存するレジスタ (読み取り専用)
+pub const LAST_INSERT: char = '.';
+
+/// 名前付きレジスタ (`"a`〜`"z`)、削除履歴 (`"1`〜`"9` / `"-`)、
+/// ブラックホールレジスタ (`"_`)、直前の挿入 (`".`) を管理する
+///
+/// 無名レジスタは YankManager が担当する。
+#[derive(Debug, Default)]
+pub struct RegisterFile {
+    registers: HashMap<char, Register>,
+    /// `"x` で選択され、次の操作で使うレジスタ
+    selected: Option<char>,
+}
+
+impl RegisterFile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// レジスタ名として有効か
+    pub fn is_valid_name(name: char) -> bool {
+        name.is_ascii_alphabetic()
+            || matches!(name, '1'..='9' | SMALL_DELETE | BLACK_HOLE | LAST_INSERT)
+    }
+
+    /// 次の操作で使うレジスタを選択する
+    ///
+    /// # Returns
+    ///
+    /// 無効なレジスタ名の場合は false
+    pub fn select(&mut self, name: char) -> bool {
+        if !Self::is_valid_name(name) {
+            return false;
+        }
+        self.selected = Some(name);
+        true
+    }
+
+    /// 選択中のレジスタを取り出す (選択は解除される)
+    pub fn take_selected(&mut self) -> Option<char> {
+        self.selected.take()
+    }
+
+    /// レジスタに書き込む
+    ///
+    /// 大文字のレジスタ名 (`"A` など) は対応する小文字のレジスタに追加する。
+    /// 読み取り専用の `".` には書き込めない。
+    pub fn set(&mut self, name: char, content: Vec<String>, yank_type: YankType) {
+        if !Self::is_valid_name(name) || matches!(name, BLACK_HOLE | LAST_INSERT) {
+            return;
+        }
+        let key = name.to_ascii_lowercase();
+        match self.registers.get_mut(&key) {
+            Some(register) if name.is_ascii_uppercase() => register.append(content, yank_type),
+            _ => {
+                self.registers
+                    .insert(key, Register::new(content, yank_type));
+            }
+        }
+    }
+
+    /// 削除したテキストを削除履歴に保存する
+    ///
+    /// 行単位の削除は `"1` に入れ、それまでの `"1`〜`"8` を `"2`〜`"9` にずらす。
+    /// 行内の削除は `"-` に入れる。
+    pub fn push_delete(&mut self, content: Vec<String>, yank_type: YankType) {
+        if !matches!(yank_type, YankType::NewLine) {
+            self.set(SMALL_DELETE, content, yank_type);
+            return;
+        }
+        for n in (1..9).rev() {
+            let from = char::from_digit(n, 10).unwrap();
+            let to = char::from_digit(n + 1, 10).unwrap();
+            match self.registers.remove(&from) {
+                Some(register) => self.registers.insert(to, register),
+                None => self.registers.remove(&to),
+            };
+        }
+        self.set('1', content, yank_type);
+    }
+
+    /// Insert mode で入力したテキストを `".` に保存する (空の場合は何もしない)
+    pub fn set_last_insert(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        let content = text.split('\n').map(str::to_string).collect();
+        self.registers
+            .insert(LAST_INSERT, Register::new(content, YankType::InLine));
+    }
+
+    /// `".` の内容を改行で連結して返す
+    pub fn last_insert(&self) -> Option<String> {
+        self.get(LAST_INSERT)
+            .map(|register| register.content.join("\n"))
+    }
+
+    /// レジスタの内容を取得する (大文字・小文字は区別しない)
+    ///
+    /// ブラックホールレジスタは常に空 (None) を返す。
+    pub fn get(&self, name: char) -> Option<&Register> {
+        self.registers.get(&name.to_ascii_lowercase())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_set_and_get() {
+        let mut registers = RegisterFile::new();
+        registers.set('a', lines(&["one"]), YankType::NewLine);
+        registers.set('a', lines(&["two"]), YankType::NewLine);
+
+        // 小文字は置き換え
+        let register = registers.get('a').unwrap();
+        assert_eq!(register.content, lines(&["two"]));
+        assert!(registers.get('b').is_none());
+    }
+
+    #[test]
+    fn test_uppercase_appends_line_wise_yanks() {
+        let mut registers = RegisterFile::new();
+        registers.set('a', lines(&["first"]), YankType::NewLine);
+        registers.set('A', lines(&["second"]), YankType::NewLine);
+
+        let register = registers.get('a').unwrap();
+        assert_eq!(register.content, lines(&["first", "second"]));
+        assert!(register.is_newline_yank());
+    }
+
+    #[test]
+    fn test_uppercase_appends_inline_yanks() {
+        let mut registers = RegisterFile::new();
+        registers.set('b', lines(&["foo"]), YankType::InLine);
+        registers.set('B', lines(&["bar"]), YankType::InLine);
+
+        let register = registers.get('b').unwrap();
+        assert_eq!(register.content, lines(&["foobar"]));
+        assert!(!register.is_newline_yank());
+    }
+
+    #[test]
+    fn test_uppercase_to_empty_register() {
+        let mut registers = RegisterFile::new();
+        registers.set('C', lines(&["text"]), YankType::InLine);
+        assert_eq!(registers.get('c').unwrap().content, lines(&["text"]));
+    }
+
+    #[test]
+    fn test_select() {
+        let mut registers = RegisterFile::new();
+        assert!(!registers.select('!'));
+        assert_eq!(registers.take_selected(), None);
+
+        assert!(registers.select('A'));
+        assert_eq!(registers.take_selected(), Some('A'));
+        assert_eq!(registers.take_selected(), None);
+    }
+
+    #[test]
+    fn test_black_hole_drops_writes() {
+        let mut registers = RegisterFile::new();
+        assert!(registers.select(BLACK_HOLE));
+        registers.set(BLACK_HOLE, lines(&["gone"]), YankType::NewLine);
+        assert!(registers.get(BLACK_HOLE).is_none());
+    }
+
+    #[test]
+    fn test_last_insert_is_read_only() {
+        let mut registers = RegisterFile::new();
+        registers.set(LAST_INSERT, lines(&["yanked"]), YankType::InLine);
+        assert!(registers.get(LAST_INSERT).is_none());
+
+        registers.set_last_insert("one\ntwo");
+        assert_eq!(
+            registers.get(LAST_INSERT).unwrap().content,
+            lines(&["one", "two"])
+        );
+        assert_eq!(registers.last_insert(), Some("one\ntwo".to_string()));
+
+        // 何も入力しなかった場合は前回の内容を残す
+        registers.set_last_insert("");
+        assert_eq!(registers.last_insert(), Some("one\ntwo".to_string()));
+    }
+
+    #[test]
+    fn test_push_delete_shifts_numbered_registers() {
+        let mut registers = RegisterFile::new();
+        for line in ["first", "second", "third"] {
+            registers.push_delete(lines(&[line]), YankType::NewLine);
+        }
+
+        assert_eq!(registers.get('1').unwrap().content, lines(&["third"]));
+        assert_eq!(registers.get('2').unwrap().content, lines(&["second"]));
+        assert_eq!(registers.get('3').unwrap().content, lines(&["first"]));
+        assert!(registers.get('4').is_none());
+    }
+
+    #[test]
+    fn test_push_delete_keeps_nine_entries() {
+        let mut registers = RegisterFile::new();
+        for n in 0..12 {
+            registers.push_delete(lines(&[&n.to_string()]), YankType::NewLine);
+        }
+        assert_eq!(registers.get('1').unwrap().content, lines(&["11"]));
+        assert_eq!(registers.get('9').unwrap().content, lines(&["3"]));
+    }
+
+    #[test]
+    fn test_push_delete_small_delete() {
+        let mut registers = RegisterFile::new();
+        registers.push_delete(lines(&["line"]), YankType::NewLine);
+        registers.push_delete(lines(&["x"]), YankType::InLine);
+
+        assert_eq!(registers.get(SMALL_DELETE).unwrap().content, lines(&["x"]));
+        // 行内の削除では番号付きレジスタはずれない
+        assert_eq!(registers.get('1').unwrap().content, lines(&["line"]));
+        assert!(registers.get('2').is_none());
+    }
+}
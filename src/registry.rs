@@ -0,0 +1,160 @@
+use crate::editor::Editor;
+use crate::ex_command::{self, ExCommand, ExContext};
+use crate::handler::HandlerResult;
+
+/// Normal mode に追加したキーの実行時に渡す情報
+pub struct KeyContext<'a> {
+    pub editor: &'a mut Editor,
+    pub editor_rows: u16,
+    /// キーの前に入力されたカウント
+    pub count: Option<usize>,
+}
+
+/// Normal mode に追加したキーの処理
+pub type KeyAction = fn(&mut KeyContext) -> HandlerResult;
+
+/// 起動時に追加する Ex コマンドと Normal mode のキーの一覧
+///
+/// 組み込みのコマンド ([`ex_command::COMMANDS`]) と同じく関数ポインタで登録する。
+#[derive(Default, Clone)]
+pub struct Registry {
+    commands: Vec<ExCommand>,
+    normal_keys: Vec<(String, KeyAction)>,
+}
+
+/// 入力したキーの並びと追加したキーの照合結果
+pub enum KeyMatch {
+    /// 登録したキーの並びと一致した
+    Action(KeyAction),
+    /// 登録したキーの並びの途中
+    Prefix,
+    /// 一致しない
+    None,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ex コマンドを追加する
+    ///
+    /// 名前は英字のみで、省略形は使えない (`:Hello` は `Hello` と正確に入力する)。
+    /// 範囲と `!` はどちらも受け付けるので、必要に応じて `run` の中で確かめる。
+    /// 組み込みのコマンドや追加済みのコマンドと同じ名前にはできない。
+    pub fn register_command(
+        &mut self,
+        name: &'static str,
+        run: fn(&mut ExContext) -> HandlerResult,
+    ) -> Result<(), String> {
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphabetic()) {
+            return Err(format!("Invalid command name: {}", name));
+        }
+        if ex_command::resolve(name).is_some() || self.command(name).is_some() {
+            return Err(format!("Command already exists: {}", name));
+        }
+        self.commands.push(ExCommand {
+            name,
+            abbrev: name,
+            range: true,
+            bang: true,
+            run,
+        });
+        Ok(())
+    }
+
+    /// Normal mode のキーの並び (`\h`、`gH` など) を追加する
+    ///
+    /// 同じ並びの組み込みのキーより優先する。追加済みの並びは置き換える。
+    pub fn register_normal_key(&mut self, seq: &str, action: KeyAction) -> Result<(), String> {
+        if seq.is_empty() || seq.chars().any(|c| c.is_ascii_digit() || c.is_control()) {
+            return Err(format!("Invalid key sequence: {}", seq));
+        }
+        match self.normal_keys.iter_mut().find(|(keys, _)| keys == seq) {
+            Some(entry) => entry.1 = action,
+            None => self.normal_keys.push((seq.to_string(), action)),
+        }
+        Ok(())
+    }
+
+    /// 追加した Ex コマンドを名前で探す (末尾の `!` は [`ex_command::resolve`] と同じく扱う)
+    pub fn command(&self, input: &str) -> Option<(ExCommand, bool)> {
+        let (name, bang) = match input.strip_suffix('!') {
+            Some(name) => (name, true),
+            None => (input, false),
+        };
+        self.commands
+            .iter()
+            .find(|c| c.name == name)
+            .map(|c| (c.clone(), bang))
+    }
+
+    /// 入力したキーの並びが追加したキーと一致するか調べる
+    pub fn match_normal_keys(&self, typed: &str) -> KeyMatch {
+        let mut result = KeyMatch::None;
+        for (keys, action) in &self.normal_keys {
+            if keys == typed {
+                return KeyMatch::Action(*action);
+            }
+            if keys.starts_with(typed) {
+                result = KeyMatch::Prefix;
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hello(_ctx: &mut ExContext) -> HandlerResult {
+        HandlerResult::StatusMessage("hello".to_string())
+    }
+
+    fn nothing(_ctx: &mut KeyContext) -> HandlerResult {
+        HandlerResult::Continue
+    }
+
+    #[test]
+    fn test_register_command() {
+        let mut registry = Registry::new();
+        assert_eq!(registry.register_command("Hello", hello), Ok(()));
+        assert_eq!(
+            registry.command("Hello").map(|(c, _)| c.name),
+            Some("Hello")
+        );
+        assert_eq!(registry.command("Hello!").map(|(_, bang)| bang), Some(true));
+        // 省略形は使えない
+        assert!(registry.command("Hel").is_none());
+
+        assert_eq!(
+            registry.register_command("Hello", hello),
+            Err("Command already exists: Hello".to_string())
+        );
+        // 組み込みのコマンドの省略形も使えない
+        assert_eq!(
+            registry.register_command("wr", hello),
+            Err("Command already exists: wr".to_string())
+        );
+        assert_eq!(
+            registry.register_command("Say2", hello),
+            Err("Invalid command name: Say2".to_string())
+        );
+    }
+
+    #[test]
+    fn test_match_normal_keys() {
+        let mut registry = Registry::new();
+        registry.register_normal_key("\\h", nothing).unwrap();
+        assert!(matches!(registry.match_normal_keys("\\"), KeyMatch::Prefix));
+        assert!(matches!(
+            registry.match_normal_keys("\\h"),
+            KeyMatch::Action(_)
+        ));
+        assert!(matches!(registry.match_normal_keys("\\x"), KeyMatch::None));
+        assert!(matches!(registry.match_normal_keys("h"), KeyMatch::None));
+        assert!(registry.register_normal_key("2x", nothing).is_err());
+        assert!(registry.register_normal_key("", nothing).is_err());
+    }
+}
@@ -1,278 +1,1668 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::io::{self, Write};
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
 use termion;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
-use crate::UI_HEIGHT;
-use crate::buffer::Buffer;
+use crate::buffer::{Buffer, FileEncoding, FileFormat, Row};
+use crate::config::{EditorConfig, FileNameStyle, LineNumberMode, SignColumn};
 use crate::cursor::{Cursor, Position};
+use crate::diff::DiffSign;
+use crate::editor::Editor;
 use crate::mode::Mode;
+use crate::range::LineRange;
+use crate::search::Highlight;
+use crate::{COMMAND_LINE_HEIGHT, UI_HEIGHT};
 
-pub struct Screen;
+/// 入力途中のキーを表示する領域の幅 (コマンドラインの右端)
+const SHOWCMD_WIDTH: u16 = 11;
+
+/// `visualbell` で画面を反転しておく時間
+const VISUAL_BELL_DURATION: Duration = Duration::from_millis(50);
+
+/// 画面上の 1 セル分の表示内容
+///
+/// バッファ上の 1 文字 (タブは展開後の文字列) か、listchars の行末記号に対応する。
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisplayCell {
+    /// 対応するバッファ上の列 (0-indexed)。行末記号の場合は None
+    pub col: Option<usize>,
+    pub text: String,
+    /// listchars による記号か (色を変えて表示する)
+    pub is_marker: bool,
+}
+
+impl DisplayCell {
+    pub fn width(&self) -> usize {
+        self.text.width()
+    }
+}
+
+/// 描画時に必要なエディタ以外の状態
+pub struct ViewState<'a> {
+    pub mode: Mode,
+    /// コマンドラインの先頭に表示する文字 (`:` / `/` / `?`)
+    pub command_prompt: char,
+    pub command_buffer: &'a str,
+    pub status_message: &'a str,
+    pub visual_start: Option<Position>,
+    pub number_mode: LineNumberMode,
+    /// 強調表示するパターン (`:s` 入力中のプレビュー)
+    pub highlight: Option<&'a Highlight>,
+    /// 入力途中のキー (`2d` / `g` など)
+    pub pending_keys: &'a str,
+}
+
+/// 各行の描画で共通の、行番号欄の幅や選択範囲
+#[derive(Clone, Copy)]
+struct RowLayout {
+    sign_width: usize,
+    number_width: usize,
+    /// テキストを表示できる幅
+    cols: usize,
+    /// Visual mode の選択範囲 (正規化済み)
+    selection: Option<(Position, Position)>,
+    line_selection: bool,
+    /// カーソルのある行 (相対行番号の基準)
+    cursor_row: usize,
+}
+
+/// 画面の 1 行に表示する、バッファの行の一部
+#[derive(Clone, Copy)]
+struct ScreenLine {
+    file_row: usize,
+    /// 折り返した行のうち何番目の画面の行か (`:set wrap`)
+    wrap_index: usize,
+}
+
+/// 行内の範囲 (char 単位、終端を含まない) の一覧
+type Spans = Vec<(usize, usize)>;
+
+/// 行ごとに計算した強調表示の範囲のキャッシュ
+///
+/// 行のバージョンが変わっていない行は計算し直さない。行の挿入・削除で行番号がずれた行は
+/// バージョンが一致しないので計算し直す。
+struct RowCache<K> {
+    /// 計算結果に影響する行以外の状態 (パターンや辞書)。変わった場合はすべて計算し直す
+    key: Option<K>,
+    /// 行番号 → (行のバージョン, 範囲)
+    rows: HashMap<usize, (u64, Spans)>,
+}
+
+impl<K> Default for RowCache<K> {
+    fn default() -> Self {
+        Self {
+            key: None,
+            rows: HashMap::new(),
+        }
+    }
+}
+
+impl<K: PartialEq> RowCache<K> {
+    /// `file_row` 行目の範囲を返す (キャッシュにない場合は `compute` で計算する)
+    fn get(
+        &mut self,
+        key: K,
+        file_row: usize,
+        row: &Row,
+        compute: impl FnOnce() -> Spans,
+    ) -> Spans {
+        if self.key.as_ref() != Some(&key) {
+            self.rows.clear();
+            self.key = Some(key);
+        }
+        if let Some((version, spans)) = self.rows.get(&file_row)
+            && *version == row.version()
+        {
+            return spans.clone();
+        }
+        let spans = compute();
+        self.rows.insert(file_row, (row.version(), spans.clone()));
+        spans
+    }
+
+    /// 表示していない行のキャッシュを捨てる
+    fn retain_visible(&mut self, first_row: usize, rows: usize) {
+        self.rows
+            .retain(|&file_row, _| file_row >= first_row && file_row < first_row + rows);
+    }
+}
+
+/// 画面の描画 (行ごとの強調表示の範囲のキャッシュを持つ)
+#[derive(Default)]
+pub struct Screen {
+    /// パターンに一致する範囲 (パターンと強調表示する行の範囲ごと)
+    matches: RefCell<RowCache<(String, Option<LineRange>)>>,
+    /// 辞書にない単語の範囲 (辞書の番号ごと)
+    misspellings: RefCell<RowCache<u64>>,
+}
+
+/// 制御文字の表示 (`^[` / `^?` / `<80>`)
+fn control_char_notation(ch: char) -> String {
+    match ch as u32 {
+        code @ 0..0x20 => format!("^{}", char::from(code as u8 + b'@')),
+        0x7f => "^?".to_string(),
+        code => format!("<{:02x}>", code),
+    }
+}
 
 impl Screen {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// ベルを鳴らす (`visual` の場合は音の代わりに画面全体を一瞬反転する)
+    pub fn bell(stdout: &mut impl Write, visual: bool) -> io::Result<()> {
+        if visual {
+            // DECSCNM (反転表示モード) を有効にしてから元に戻す
+            write!(stdout, "\x1b[?5h")?;
+            stdout.flush()?;
+            std::thread::sleep(VISUAL_BELL_DURATION);
+            write!(stdout, "\x1b[?5l")?;
+        } else {
+            write!(stdout, "\x07")?;
+        }
+        stdout.flush()
+    }
+
+    /// テキストを表示する行数 (ステータスバーとコマンドラインの分を除く)
+    ///
+    /// テキストを 1 行も表示できない小さな端末では、ステータスバーを表示せずにその行もテキストに使う。
     pub fn editor_rows(rows: u16) -> u16 {
-        rows.saturating_sub(UI_HEIGHT)
+        if Self::shows_status_bar(rows) {
+            rows - UI_HEIGHT
+        } else {
+            rows.saturating_sub(COMMAND_LINE_HEIGHT)
+        }
     }
 
-    pub fn draw_rows(
-        stdout: &mut impl Write,
-        rows: u16,
-        cols: u16,
-        buffer: &Buffer,
-        row_offset: u16,
-        selection: Option<(Position, Position)>,
-        line_selection: bool,
-    ) -> io::Result<()> {
-        let editor_rows = Self::editor_rows(rows);
-
-        for i in 0..editor_rows {
-            let file_row = (row_offset + i) as usize;
-
-            if file_row < buffer.len() {
-                // バッファ内容を表示
-                if let Some(row) = buffer.row(file_row) {
-                    let text = row.render();
-                    let chars: Vec<char> = text.chars().collect();
-
-                    // 選択範囲のハイライト処理
-                    if let Some((start, end)) = selection {
-                        // 範囲を正規化
-                        let (norm_start, norm_end) = if start <= end {
-                            (start, end)
-                        } else {
-                            (end, start)
-                        };
-
-                        // この行が選択範囲内かチェック
-                        if file_row >= norm_start.row && file_row <= norm_end.row {
-                            if line_selection {
-                                // 行全体をハイライト
-                                write!(stdout, "{}", termion::style::Invert)?;
-                                if chars.is_empty() {
-                                    write!(stdout, " ")?;
-                                } else {
-                                    let display: String = if chars.len() > cols as usize {
-                                        chars.iter().take(cols as usize).collect()
-                                    } else {
-                                        text.to_string()
-                                    };
-                                    write!(stdout, "{}", display)?;
-                                }
-                                write!(stdout, "{}", termion::style::Reset)?;
-                            } else {
-                                // 行内の選択範囲を計算
-                                let start_col = if file_row == norm_start.row {
-                                    norm_start.col
-                                } else {
-                                    0
-                                };
-
-                                let end_col = if file_row == norm_end.row {
-                                    norm_end.col.min(chars.len().saturating_sub(1))
-                                } else {
-                                    chars.len().saturating_sub(1)
-                                };
-
-                                // ハイライト表示（before + selected + after が cols を超えないよう管理）
-                                let mut remaining = cols as usize;
-
-                                // 選択前
-                                let before_len = start_col.min(remaining);
-                                let before: String = chars.iter().take(before_len).collect();
-                                write!(stdout, "{}", before)?;
-                                remaining = remaining.saturating_sub(before_len);
-
-                                // 選択部分（反転）
-                                let selected_len =
-                                    (end_col.saturating_sub(start_col) + 1).min(remaining);
-                                write!(stdout, "{}", termion::style::Invert)?;
-                                let selected: String = chars
-                                    .iter()
-                                    .skip(start_col)
-                                    .take(selected_len)
-                                    .collect();
-                                write!(stdout, "{}", selected)?;
-                                write!(stdout, "{}", termion::style::Reset)?;
-                                remaining = remaining.saturating_sub(selected_len);
-
-                                // 選択後
-                                let after: String =
-                                    chars.iter().skip(end_col + 1).take(remaining).collect();
-                                write!(stdout, "{}", after)?;
+    /// ステータスバーを表示する余地があるか
+    fn shows_status_bar(rows: u16) -> bool {
+        rows > UI_HEIGHT
+    }
+
+    /// 行の内容を表示用のセル列に変換する
+    ///
+    /// タブは tabstop に合わせて展開し、`list` が有効な場合は
+    /// タブ・行末の空白・行末を listchars の記号で表示する。
+    pub fn display_cells(line: &str, config: &EditorConfig) -> Vec<DisplayCell> {
+        let list_chars = config.list.then_some(&config.listchars);
+        // 行末の空白が始まる位置 (char 単位)
+        let trail_start = line.trim_end_matches(' ').chars().count();
+
+        let mut cells = Vec::new();
+        let mut vcol = 0;
+        for (col, ch) in line.chars().enumerate() {
+            let cell = match ch {
+                '\t' => {
+                    let width = config.tabstop - vcol % config.tabstop;
+                    match list_chars.and_then(|lcs| lcs.tab) {
+                        Some((head, fill)) => {
+                            let mut text = head.to_string();
+                            text.extend(std::iter::repeat_n(fill, width - 1));
+                            DisplayCell {
+                                col: Some(col),
+                                text,
+                                is_marker: true,
                             }
-                        } else {
-                            // 選択範囲外の通常表示
-                            let display_text: String = if chars.len() > cols as usize {
-                                chars.iter().take(cols as usize).collect()
-                            } else {
-                                text.to_string()
-                            };
-                            write!(stdout, "{}", display_text)?;
                         }
-                    } else {
-                        // 選択なしの通常表示
-                        let display_text: String = if chars.len() > cols as usize {
-                            chars.iter().take(cols as usize).collect()
-                        } else {
-                            text.to_string()
-                        };
-                        write!(stdout, "{}", display_text)?;
+                        None => DisplayCell {
+                            col: Some(col),
+                            text: " ".repeat(width),
+                            is_marker: false,
+                        },
                     }
                 }
-                // 行末までクリア
-                write!(stdout, "{}", termion::clear::UntilNewline)?;
-            } else {
-                // ファイルの終端を超えたら ~ を表示
-                write!(stdout, "~")?;
-                write!(stdout, "{}", termion::clear::UntilNewline)?;
+                ' ' if col >= trail_start => match list_chars.and_then(|lcs| lcs.trail) {
+                    Some(trail) => DisplayCell {
+                        col: Some(col),
+                        text: trail.to_string(),
+                        is_marker: true,
+                    },
+                    None => DisplayCell {
+                        col: Some(col),
+                        text: " ".to_string(),
+                        is_marker: false,
+                    },
+                },
+                // 制御文字は Vim と同じく ^[ のように表示する
+                ch if ch.is_control() => DisplayCell {
+                    col: Some(col),
+                    text: control_char_notation(ch),
+                    is_marker: true,
+                },
+                ch => DisplayCell {
+                    col: Some(col),
+                    text: ch.to_string(),
+                    is_marker: false,
+                },
+            };
+            vcol += cell.width();
+            cells.push(cell);
+        }
+
+        if let Some(eol) = list_chars.and_then(|lcs| lcs.eol) {
+            cells.push(DisplayCell {
+                col: None,
+                text: eol.to_string(),
+                is_marker: true,
+            });
+        }
+        cells
+    }
+
+    /// 行のセル列を、幅 `cols` の画面の行ごとに分ける (セルの添字の範囲)
+    ///
+    /// `wrap` が無効な場合は画面の右端で切れるので、収まる部分だけの 1 行になる。
+    /// 右端に収まらない幅の広い文字は次の行に送る。
+    pub fn wrap_cells(cells: &[DisplayCell], cols: usize, wrap: bool) -> Vec<Range<usize>> {
+        let mut rows = Vec::new();
+        let mut start = 0;
+        let mut width = 0;
+        for (i, cell) in cells.iter().enumerate() {
+            if width + cell.width() > cols {
+                if !wrap {
+                    rows.push(start..i);
+                    return rows;
+                }
+                if i > start {
+                    rows.push(start..i);
+                    start = i;
+                    width = 0;
+                }
             }
+            width += cell.width();
+        }
+        rows.push(start..cells.len());
+        rows
+    }
+
+    /// バッファ上の列 `col` を表示する画面の行 ([`Screen::wrap_cells`] の添字)
+    ///
+    /// 行末より後ろの列 (`virtualedit`) は最後の画面の行に表示する。
+    fn wrap_index(cells: &[DisplayCell], rows: &[Range<usize>], col: usize) -> usize {
+        rows.iter()
+            .position(|row| cells[row.clone()].iter().any(|cell| cell.col == Some(col)))
+            .unwrap_or(rows.len() - 1)
+    }
+
+    /// 幅 `cols` の画面で、行 `line` を表示するのに使う画面の行数
+    pub fn screen_line_count(line: &str, cols: usize, config: &EditorConfig) -> usize {
+        Self::wrap_cells(&Self::display_cells(line, config), cols, config.wrap).len()
+    }
+
+    /// 幅 `cols` の画面で、`col` 列目と同じ画面の行に表示されるバッファ上の列の一覧
+    ///
+    /// `g0` / `g^` / `g$` で使う。`:set wrap` の場合は折り返した行のうちの 1 行、
+    /// それ以外の場合は画面の右端で切れる前の部分になる。
+    pub fn screen_line_cols(
+        line: &str,
+        col: usize,
+        cols: usize,
+        config: &EditorConfig,
+    ) -> Vec<usize> {
+        let cells = Self::display_cells(line, config);
+        let rows = Self::wrap_cells(&cells, cols, config.wrap);
+        let index = Self::wrap_index(&cells, &rows, col);
+        cells[rows[index].clone()]
+            .iter()
+            .filter_map(|cell| cell.col)
+            .collect()
+    }
+
+    /// `:set wrap` で、バッファ上の列 `col` を表示する位置 (行内の画面の行, 表示カラム)
+    ///
+    /// 表示カラムは 1-indexed で、[`Screen::screen_col`] と同じく行末より後ろの列は
+    /// 1 列を 1 カラムとして数える。
+    fn wrapped_screen_pos(
+        line: &str,
+        col: usize,
+        cols: usize,
+        config: &EditorConfig,
+    ) -> (usize, u16) {
+        let cells = Self::display_cells(line, config);
+        let rows = Self::wrap_cells(&cells, cols, config.wrap);
+        let index = Self::wrap_index(&cells, &rows, col);
+        let width: usize = cells[rows[index].clone()]
+            .iter()
+            .take_while(|cell| cell.col.is_some_and(|c| c < col))
+            .map(|cell| cell.width())
+            .sum();
+        let virtual_cols = col.saturating_sub(line.chars().count());
+        (index, (width + virtual_cols) as u16 + 1)
+    }
+
+    /// 画面の一番上の行から数えた、カーソルを表示する画面の行 (0-indexed)
+    ///
+    /// `:set wrap` の場合は、上にある行を折り返した分だけ下にずれる。
+    pub fn cursor_screen_row(editor: &Editor, cursor: &Cursor, cols: usize) -> usize {
+        let config = &editor.config;
+        if !config.wrap {
+            return cursor.y() as usize - 1;
+        }
+        let first_row = cursor.row_offset() as usize;
+        let above: usize = (first_row..cursor.file_row())
+            .filter_map(|row| editor.buffer().row(row))
+            .map(|row| Self::screen_line_count(row.chars(), cols, config))
+            .sum();
+        let line = editor
+            .buffer()
+            .row(cursor.file_row())
+            .map(|r| r.chars())
+            .unwrap_or("");
+        let (index, _) = Self::wrapped_screen_pos(line, cursor.col_index(), cols, config);
+        above + index
+    }
+
+    /// `:set wrap` で折り返した行の分だけスクロールして、カーソルを画面内に収める
+    ///
+    /// `Cursor::scroll` は 1 行を画面の 1 行として数えるので、その後に呼ぶ。
+    pub fn scroll_wrapped(editor: &mut Editor, cols: usize, editor_rows: u16) {
+        if !editor.config.wrap {
+            return;
+        }
+        while (editor.cursor.row_offset() as usize) < editor.cursor.file_row()
+            && Self::cursor_screen_row(editor, &editor.cursor, cols) >= editor_rows as usize
+        {
+            let row_offset = editor.cursor.row_offset() + 1;
+            editor.cursor.set_row_offset(row_offset);
+        }
+    }
+
+    /// バッファ上の列 (0-indexed) に対応する端末上の表示カラムを返す (1-indexed)
+    ///
+    /// 全角文字やタブの展開を考慮するため、端末の Goto にはこの値を使う。
+    /// 行末より後ろの列 (`virtualedit`) は 1 列を 1 カラムとして数える。
+    pub fn screen_col(line: &str, col: usize, config: &EditorConfig) -> u16 {
+        let width: usize = Self::display_cells(line, config)
+            .iter()
+            .take_while(|cell| cell.col.is_some_and(|c| c < col))
+            .map(|cell| cell.width())
+            .sum();
+        let virtual_cols = col.saturating_sub(line.chars().count());
+        (width + virtual_cols) as u16 + 1
+    }
+
+    /// 行番号欄の幅 (区切りの空白を含む)。行番号を表示しない場合は 0
+    ///
+    /// `numberwidth` を最小の幅とし、行数の桁数が多い場合は広げる。
+    pub fn number_width(
+        number_mode: LineNumberMode,
+        buffer_len: usize,
+        numberwidth: usize,
+    ) -> usize {
+        match number_mode {
+            LineNumberMode::Off => 0,
+            _ => (buffer_len.max(1).to_string().len() + 1).max(numberwidth),
+        }
+    }
+
+    /// テキストの左側の欄 (サイン列と行番号欄) 全体の幅
+    pub fn gutter_width(
+        number_mode: LineNumberMode,
+        buffer_len: usize,
+        numberwidth: usize,
+        signcolumn: SignColumn,
+    ) -> usize {
+        signcolumn.width() + Self::number_width(number_mode, buffer_len, numberwidth)
+    }
 
-            if i < editor_rows - 1 {
+    /// 幅 `width` の端末でテキストを表示できる幅
+    ///
+    /// サイン列と行番号欄を除いた幅。欄だけで端末の幅を超える場合は欄を表示しないので、
+    /// 端末の幅をそのまま返す ([`Screen::row_layout`] と同じ)。
+    pub fn text_cols(width: u16, number_mode: LineNumberMode, editor: &Editor) -> usize {
+        let gutter = Self::gutter_width(
+            number_mode,
+            editor.buffer().len(),
+            editor.config.numberwidth,
+            editor.signcolumn(),
+        );
+        let width = width as usize;
+        if gutter >= width {
+            width
+        } else {
+            width - gutter
+        }
+    }
+
+    /// 行番号欄に表示する文字列を返す (幅は number_width と同じ)
+    pub fn line_number_label(
+        number_mode: LineNumberMode,
+        file_row: usize,
+        cursor_row: usize,
+        width: usize,
+    ) -> String {
+        let digits = width.saturating_sub(1);
+        match number_mode {
+            LineNumberMode::Off => String::new(),
+            LineNumberMode::Absolute => format!("{:>digits$} ", file_row + 1),
+            LineNumberMode::Relative => format!("{:>digits$} ", file_row.abs_diff(cursor_row)),
+            LineNumberMode::Hybrid if file_row == cursor_row => {
+                // Vim と同様にカーソル行の絶対行番号は左寄せで表示する
+                format!("{:<digits$} ", file_row + 1)
+            }
+            LineNumberMode::Hybrid => format!("{:>digits$} ", file_row.abs_diff(cursor_row)),
+        }
+    }
+
+    /// `file_row` 行目のサイン
+    fn sign_at(signs: Option<&[Option<DiffSign>]>, file_row: usize) -> Option<DiffSign> {
+        signs?.get(file_row).copied().flatten()
+    }
+
+    fn sign_color(sign: DiffSign) -> String {
+        match sign {
+            DiffSign::Added => termion::color::Fg(termion::color::Green).to_string(),
+            DiffSign::Changed => termion::color::Fg(termion::color::Blue).to_string(),
+            DiffSign::Removed => termion::color::Fg(termion::color::Red).to_string(),
+        }
+    }
+
+    /// 行の描画に使う、画面全体で共通の値
+    fn row_layout(
+        size: (u16, u16),
+        editor: &Editor,
+        cursor: &Cursor,
+        view: &ViewState,
+    ) -> RowLayout {
+        let config = &editor.config;
+        let mut number_width =
+            Self::number_width(view.number_mode, editor.buffer().len(), config.numberwidth);
+        let mut sign_width = editor.signcolumn().width();
+        // テキストを表示する余地がない狭い端末では、サイン列と行番号を表示しない
+        if sign_width + number_width >= size.0 as usize {
+            number_width = 0;
+            sign_width = 0;
+        }
+        let gutter_width = sign_width + number_width;
+        // Visual / VisualLine モードの場合は選択範囲を計算
+        let selection = match view.mode {
+            Mode::Visual | Mode::VisualLine => view
+                .visual_start
+                .map(|start| Editor::normalize_range(start, cursor.position())),
+            _ => None,
+        };
+        RowLayout {
+            sign_width,
+            number_width,
+            cols: (size.0 as usize).saturating_sub(gutter_width),
+            selection,
+            line_selection: view.mode == Mode::VisualLine,
+            cursor_row: cursor.file_row(),
+        }
+    }
+
+    pub fn draw_rows(
+        &self,
+        stdout: &mut impl Write,
+        size: (u16, u16),
+        editor: &Editor,
+        cursor: &Cursor,
+        view: &ViewState,
+    ) -> io::Result<()> {
+        let editor_rows = Self::editor_rows(size.1);
+        let layout = Self::row_layout(size, editor, cursor, view);
+        let signs = editor.diff_signs();
+        let first_row = cursor.row_offset() as usize;
+        self.matches
+            .borrow_mut()
+            .retain_visible(first_row, editor_rows as usize);
+        self.misspellings
+            .borrow_mut()
+            .retain_visible(first_row, editor_rows as usize);
+
+        // `:set wrap` の場合は 1 行を複数の画面の行に折り返す
+        let mut lines = Vec::with_capacity(editor_rows as usize);
+        let mut file_row = first_row;
+        while lines.len() < editor_rows as usize {
+            let wraps = editor.buffer().row(file_row).map_or(1, |row| {
+                Self::screen_line_count(row.chars(), layout.cols, &editor.config)
+            });
+            lines.extend((0..wraps).map(|wrap_index| ScreenLine {
+                file_row,
+                wrap_index,
+            }));
+            file_row += 1;
+        }
+        lines.truncate(editor_rows as usize);
+
+        for (i, line) in lines.into_iter().enumerate() {
+            let sign = Self::sign_at(signs.as_deref(), line.file_row);
+            self.draw_line(stdout, editor, view, &layout, line, sign)?;
+
+            if i < editor_rows as usize - 1 {
                 write!(stdout, "\r\n")?;
             }
         }
         Ok(())
     }
 
+    /// バッファの `line.file_row` 行目を画面の現在の行に描画する (行末までクリアする)
+    ///
+    /// 折り返した 2 行目以降 (`line.wrap_index` が 1 以上) はサイン列と行番号欄を空ける。
+    fn draw_line(
+        &self,
+        stdout: &mut impl Write,
+        editor: &Editor,
+        view: &ViewState,
+        layout: &RowLayout,
+        line: ScreenLine,
+        sign: Option<DiffSign>,
+    ) -> io::Result<()> {
+        let config = &editor.config;
+        let RowLayout {
+            sign_width,
+            number_width,
+            cols,
+            selection,
+            line_selection,
+            cursor_row,
+        } = *layout;
+        let ScreenLine {
+            file_row,
+            wrap_index,
+        } = line;
+
+        if let Some(row) = editor.buffer().row(file_row) {
+            if wrap_index > 0 {
+                write!(stdout, "{}", " ".repeat(sign_width + number_width))?;
+            } else if sign_width > 0 {
+                match sign {
+                    Some(sign) => write!(
+                        stdout,
+                        "{}{}{}{}",
+                        Self::sign_color(sign),
+                        sign.symbol(),
+                        termion::color::Fg(termion::color::Reset),
+                        " ".repeat(sign_width - 1)
+                    )?,
+                    None => write!(stdout, "{}", " ".repeat(sign_width))?,
+                }
+            }
+            if number_width > 0 && wrap_index == 0 {
+                let label =
+                    Self::line_number_label(view.number_mode, file_row, cursor_row, number_width);
+                write!(
+                    stdout,
+                    "{}{}{}",
+                    termion::color::Fg(termion::color::Yellow),
+                    label,
+                    termion::color::Fg(termion::color::Reset)
+                )?;
+            }
+
+            let cells = Self::display_cells(row.chars(), config);
+
+            // この行の選択範囲 (バッファ上の列, 両端を含む)
+            let selected_cols = selection.and_then(|(norm_start, norm_end)| {
+                if file_row < norm_start.row || file_row > norm_end.row {
+                    return None;
+                }
+                if line_selection {
+                    return Some((0, usize::MAX));
+                }
+                let start_col = if file_row == norm_start.row {
+                    norm_start.col
+                } else {
+                    0
+                };
+                let end_col = if file_row == norm_end.row {
+                    norm_end.col
+                } else {
+                    usize::MAX
+                };
+                Some((start_col, end_col))
+            });
+
+            // この行でパターンに一致する範囲
+            let matched_cols = view
+                .highlight
+                .map(|highlight| {
+                    let key = (highlight.regex.as_str().to_string(), highlight.range);
+                    self.matches.borrow_mut().get(key, file_row, row, || {
+                        highlight.match_cols(file_row, row.chars())
+                    })
+                })
+                .unwrap_or_default();
+            // この行で辞書にない単語の範囲 (`:set spell`)
+            let misspelled_cols = editor
+                .spell_dictionary()
+                .map(|dictionary| {
+                    self.misspellings
+                        .borrow_mut()
+                        .get(dictionary.version(), file_row, row, || {
+                            dictionary.misspelled_cols(row.chars())
+                        })
+                })
+                .unwrap_or_default();
+
+            if line_selection && selected_cols.is_some() && row.is_empty() && wrap_index == 0 {
+                // 空行でも選択されていることが分かるように反転した空白を表示
+                write!(
+                    stdout,
+                    "{} {}",
+                    termion::style::Invert,
+                    termion::style::Reset
+                )?;
+            }
+
+            let segment = Self::wrap_cells(&cells, cols, config.wrap)
+                .get(wrap_index)
+                .cloned()
+                .unwrap_or_default();
+            let mut width = 0;
+            for cell in &cells[segment] {
+                width += cell.width();
+                if width > cols {
+                    break;
+                }
+                let selected = match (selected_cols, cell.col) {
+                    (Some(_), None) => line_selection,
+                    (Some((start, end)), Some(col)) => start <= col && col <= end,
+                    (None, _) => false,
+                };
+                let matched = cell.col.is_some_and(|col| {
+                    matched_cols
+                        .iter()
+                        .any(|(start, end)| *start <= col && col < *end)
+                });
+                let misspelled = cell.col.is_some_and(|col| {
+                    misspelled_cols
+                        .iter()
+                        .any(|(start, end)| *start <= col && col < *end)
+                });
+                if misspelled {
+                    write!(stdout, "{}", termion::style::Underline)?;
+                }
+                if selected {
+                    write!(stdout, "{}", termion::style::Invert)?;
+                } else if matched {
+                    write!(
+                        stdout,
+                        "{}{}",
+                        termion::color::Bg(termion::color::Yellow),
+                        termion::color::Fg(termion::color::Black)
+                    )?;
+                }
+                if cell.is_marker {
+                    write!(
+                        stdout,
+                        "{}{}{}",
+                        termion::color::Fg(termion::color::Blue),
+                        cell.text,
+                        termion::color::Fg(termion::color::Reset)
+                    )?;
+                } else {
+                    write!(stdout, "{}", cell.text)?;
+                }
+                if selected || matched || misspelled {
+                    write!(stdout, "{}", termion::style::Reset)?;
+                }
+            }
+            // 行末までクリア
+            write!(stdout, "{}", termion::clear::UntilNewline)?;
+        } else {
+            // ファイルの終端を超えたら ~ を表示
+            write!(stdout, "~")?;
+            write!(stdout, "{}", termion::clear::UntilNewline)?;
+        }
+
+        Ok(())
+    }
+
+    /// ステータスバーを現在の行に描画する (端末の幅を超える部分は表示しない)
     pub fn draw_status_bar(
         stdout: &mut impl Write,
-        filename: Option<&str>,
-        buffer_len: usize,
+        name: &str,
+        readonly: bool,
+        buffer: &Buffer,
         cursor_file_row: usize,
         cols: u16,
     ) -> io::Result<()> {
         // ステータスバー（反転表示）
-        write!(stdout, "\r\n{}", termion::style::Invert)?;
+        write!(stdout, "{}", termion::style::Invert)?;
 
-        let name = filename.unwrap_or("[No Name]");
-        let status = format!("{} - {} lines", name, buffer_len);
-        write!(stdout, "{}", status)?;
+        let readonly = if readonly { " [RO]" } else { "" };
+        // unix 以外の改行コードや utf-8 以外の文字コードの場合は表示する
+        let format = match buffer.file_format() {
+            FileFormat::Unix => String::new(),
+            format => format!(" [{}]", format.name()),
+        };
+        let encoding = match buffer.encoding() {
+            FileEncoding::Utf8 => String::new(),
+            encoding => format!(" [{}]", encoding.name()),
+        };
+        let bom = if buffer.bom() { " [BOM]" } else { "" };
+        let line_count = buffer.line_count();
+        let lines = if line_count == 1 { "line" } else { "lines" };
+        let status = format!(
+            "{}{}{}{}{} - {} {}",
+            name, readonly, format, encoding, bom, line_count, lines
+        );
 
         // 現在の行番号の右端に表示
-        let current_line = if buffer_len > 0 {
-            cursor_file_row + 1
-        } else {
-            0
-        };
-        let pos = format!(" {}/{} ", current_line, buffer_len);
+        let pos = format!(" {}/{} ", cursor_file_row + 1, line_count);
         let padding = (cols as usize)
-            .saturating_sub(status.len())
+            .saturating_sub(status.width())
             .saturating_sub(pos.len());
-        write!(stdout, "{}{}", " ".repeat(padding), pos)?;
+        let line = format!("{}{}{}", status, " ".repeat(padding), pos);
+        write!(stdout, "{}", truncate_to_width(&line, cols as usize))?;
 
         write!(stdout, "{}", termion::style::Reset)?;
         Ok(())
     }
 
+    /// コマンドラインを現在の行に描画する (端末の幅を超える部分は表示しない)
     pub fn draw_command_line(
         stdout: &mut impl Write,
         mode: Mode,
+        prompt: char,
         command_buffer: &str,
         status_message: &str,
+        pending_keys: &str,
+        size: (u16, u16),
     ) -> io::Result<()> {
-        write!(stdout, "\r\n")?;
         // 行をクリアしてから描画
         write!(stdout, "{}", termion::clear::CurrentLine)?;
+        let cols = size.0 as usize;
         match mode {
             Mode::Command => {
                 // コマンドバッファをそのまま表示（: は含まれていない前提）
-                write!(stdout, ":{}", command_buffer)?;
+                let (_, visible) = Self::visible_command(prompt, command_buffer, cols);
+                write!(stdout, "{}", visible)?;
             }
             Mode::Normal => {
-                write!(stdout, "{}", status_message)?;
+                write!(stdout, "{}", truncate_to_width(status_message, cols))?;
             }
             Mode::Insert => {
-                write!(stdout, "-- INSERT --")?;
+                write!(stdout, "{}", truncate_to_width("-- INSERT --", cols))?;
             }
             Mode::Visual => {
-                write!(stdout, "-- Visual --")?;
+                write!(stdout, "{}", truncate_to_width("-- Visual --", cols))?;
             }
             Mode::VisualLine => {
-                write!(stdout, "-- VISUAL LINE --")?;
+                write!(stdout, "{}", truncate_to_width("-- VISUAL LINE --", cols))?;
             }
         }
+        // 入力途中のキーは Vim の showcmd と同じくコマンドラインの右端に表示する
+        // (表示する領域がない狭い端末では表示しない)
+        if mode != Mode::Command && !pending_keys.is_empty() && size.0 > SHOWCMD_WIDTH {
+            let col = size.0.saturating_sub(SHOWCMD_WIDTH) + 1;
+            write!(
+                stdout,
+                "{}{}",
+                termion::cursor::Goto(col, size.1),
+                pending_keys
+            )?;
+        }
         Ok(())
     }
 
-    pub fn refresh(
+    /// カーソルを表示する位置に端末のカーソルを移動する
+    fn place_cursor(
         stdout: &mut impl Write,
+        size: (u16, u16),
+        editor: &Editor,
         cursor: &Cursor,
-        mode: Mode,
-        command_buffer: &str,
-        buffer: &Buffer,
-        filename: Option<&str>,
-        status_message: &str,
-        visual_start: Option<Position>,
+        view: &ViewState,
     ) -> io::Result<()> {
-        // カーソルを隠す
-        write!(stdout, "{}", termion::cursor::Hide)?;
-        // カーソルを左上に移動
-        write!(stdout, "{}", termion::cursor::Goto(1, 1))?;
-
-        let size = termion::terminal_size()?;
-
-        // Visual / VisualLine モードの場合は選択範囲を計算
-        let (selection, line_selection) = match mode {
-            Mode::Visual => (visual_start.map(|start| (start, cursor.position())), false),
-            Mode::VisualLine => (visual_start.map(|start| (start, cursor.position())), true),
-            _ => (None, false),
-        };
-
-        // 行を描画
-        Self::draw_rows(
-            stdout,
-            size.1,
-            size.0,
-            buffer,
-            cursor.row_offset(),
-            selection,
-            line_selection,
-        )?;
-
-        // ステータスバー描画
-        Self::draw_status_bar(stdout, filename, buffer.len(), cursor.file_row(), size.0)?;
-
-        // コマンドライン / ステータスライン (最下行)
-        Self::draw_command_line(stdout, mode, command_buffer, status_message)?;
-
-        // カーソル位置に移動
-        let current_line = buffer
+        let current_line = editor
+            .buffer()
             .row(cursor.file_row())
             .map(|r| r.chars())
             .unwrap_or("");
-        match mode {
+        match view.mode {
             Mode::Command => {
                 // コマンドモード時はコマンドライン上にカーソル
+                let (width, _) = Self::visible_command(
+                    view.command_prompt,
+                    view.command_buffer,
+                    size.0 as usize,
+                );
                 write!(
                     stdout,
                     "{}",
-                    termion::cursor::Goto((command_buffer.len() as u16) + 2, size.1)
+                    termion::cursor::Goto((width as u16 + 1).min(size.0).max(1), size.1.max(1))
                 )?;
             }
             Mode::Normal | Mode::Insert | Mode::Visual | Mode::VisualLine => {
-                // 全角文字を考慮した端末カラム位置を使用
+                // 全角文字・タブを考慮した端末カラム位置を使用
+                let layout = Self::row_layout(size, editor, cursor, view);
+                let gutter_width = layout.sign_width + layout.number_width;
+                let config = &editor.config;
+                let col = if config.wrap {
+                    Self::wrapped_screen_pos(current_line, cursor.col_index(), layout.cols, config)
+                        .1
+                } else {
+                    Self::screen_col(current_line, cursor.col_index(), config)
+                };
+                let screen_col = col + gutter_width as u16;
+                let screen_row = Self::cursor_screen_row(editor, cursor, layout.cols) as u16 + 1;
+                // 小さな端末でも画面の外には置かない
                 write!(
                     stdout,
                     "{}",
-                    termion::cursor::Goto(cursor.screen_col(current_line), cursor.y())
+                    termion::cursor::Goto(
+                        screen_col.min(size.0).max(1),
+                        screen_row.min(size.1).max(1)
+                    )
                 )?;
             }
         }
+        Ok(())
+    }
 
-        // カーソルスタイルを設定
-        match mode {
-            Mode::Insert => {
-                // Insert モードでは縦棒カーソル
-                write!(stdout, "{}", termion::cursor::SteadyBar)?;
-            }
-            Mode::Normal | Mode::Command | Mode::Visual | Mode::VisualLine => {
-                // Normal/Command/Visual モードではブロックカーソル
-                write!(stdout, "{}", termion::cursor::SteadyBlock)?;
-            }
+    /// カーソル行だけを描画し直す (Insert mode で文字を入力したときなどの高速な更新)
+    ///
+    /// ほかの行やステータスバーが変わらない場合にだけ使う。
+    /// 折り返した行の数は変わりうるので、`:set wrap` の場合は使わない。
+    pub fn refresh_line(
+        &self,
+        stdout: &mut impl Write,
+        size: (u16, u16),
+        cursor: &Cursor,
+        editor: &Editor,
+        view: &ViewState,
+    ) -> io::Result<()> {
+        write!(
+            stdout,
+            "{}{}",
+            termion::cursor::Hide,
+            termion::cursor::Goto(1, cursor.y())
+        )?;
+        let layout = Self::row_layout(size, editor, cursor, view);
+        let sign = Self::sign_at(editor.diff_signs().as_deref(), cursor.file_row());
+        let line = ScreenLine {
+            file_row: cursor.file_row(),
+            wrap_index: 0,
+        };
+        self.draw_line(stdout, editor, view, &layout, line, sign)?;
+        Self::place_cursor(stdout, size, editor, cursor, view)?;
+        write!(stdout, "{}", termion::cursor::Show)?;
+        stdout.flush()
+    }
+
+    /// 画面全体を描画する
+    ///
+    /// `size` は端末の大きさ (列, 行)。
+    pub fn refresh(
+        &self,
+        stdout: &mut impl Write,
+        size: (u16, u16),
+        cursor: &Cursor,
+        editor: &Editor,
+        view: &ViewState,
+    ) -> io::Result<()> {
+        let buffer = editor.buffer();
+        let mode = view.mode;
+        // カーソルを隠す
+        write!(stdout, "{}", termion::cursor::Hide)?;
+        // カーソルを左上に移動
+        write!(stdout, "{}", termion::cursor::Goto(1, 1))?;
+
+        // 行を描画
+        self.draw_rows(stdout, size, editor, cursor, view)?;
+
+        // ステータスバー描画
+        if !Self::shows_status_bar(size.1) {
+            // テキストを表示する余地がない場合は表示しない
+        } else if mode == Mode::Command && !view.status_message.is_empty() {
+            // コマンドモード中のメッセージ (補完候補など) はステータスバーの位置に表示する
+            write!(
+                stdout,
+                "{}{}{}",
+                termion::cursor::Goto(1, size.1 - COMMAND_LINE_HEIGHT),
+                termion::clear::CurrentLine,
+                truncate_to_width(view.status_message, size.0 as usize)
+            )?;
+        } else {
+            // スクラッチバッファの名前 (`[Help]` など) はパスではないのでそのまま表示する
+            let name = match editor.display_name() {
+                Some(name) if editor.is_scratch() => name.to_string(),
+                _ => {
+                    let cwd = std::env::current_dir().unwrap_or_default();
+                    format_filename(editor.filename(), editor.config.statusfilename, &cwd)
+                }
+            };
+            write!(
+                stdout,
+                "{}",
+                termion::cursor::Goto(1, size.1 - COMMAND_LINE_HEIGHT)
+            )?;
+            Self::draw_status_bar(
+                stdout,
+                &name,
+                editor.is_readonly(),
+                buffer,
+                cursor.file_row(),
+                size.0,
+            )?;
         }
 
+        // コマンドライン / ステータスライン (最下行)
+        if size.0 == 0 || size.1 == 0 {
+            // 何も表示できない
+            write!(stdout, "{}", termion::cursor::Show)?;
+            return stdout.flush();
+        }
+        write!(stdout, "{}", termion::cursor::Goto(1, size.1))?;
+        Self::draw_command_line(
+            stdout,
+            mode,
+            view.command_prompt,
+            view.command_buffer,
+            view.status_message,
+            view.pending_keys,
+            size,
+        )?;
+
+        Self::place_cursor(stdout, size, editor, cursor, view)?;
+
         // カーソル表示
         write!(stdout, "{}", termion::cursor::Show)?;
         stdout.flush()?;
         Ok(())
     }
+
+    /// コマンドモードで表示する `prompt` と入力中のコマンド
+    ///
+    /// 端末の幅に収まらない場合は、カーソルを置く 1 セルを残して末尾が見えるように先頭を省く。
+    ///
+    /// # Returns
+    ///
+    /// (表示幅, 表示する文字列)
+    fn visible_command(prompt: char, command_buffer: &str, cols: usize) -> (usize, String) {
+        let text = format!("{}{}", prompt, command_buffer);
+        let mut width = text.width();
+        let mut chars = text.chars();
+        while width >= cols.max(1) {
+            let Some(c) = chars.next() else {
+                break;
+            };
+            width -= UnicodeWidthChar::width(c).unwrap_or(0);
+        }
+        (width, chars.collect())
+    }
+
+    /// 画面全体をメモリ上に描画して、各セルに表示される文字を求める (描画のテスト用)
+    pub fn render_to_grid(
+        &self,
+        size: (u16, u16),
+        cursor: &Cursor,
+        editor: &Editor,
+        view: &ViewState,
+    ) -> io::Result<ScreenGrid> {
+        let mut out = Vec::new();
+        self.refresh(&mut out, size, cursor, editor, view)?;
+        Ok(ScreenGrid::parse(&String::from_utf8_lossy(&out), size))
+    }
+}
+
+/// 表示幅が `width` に収まるように末尾を切り詰める
+fn truncate_to_width(text: &str, width: usize) -> &str {
+    let mut used = 0;
+    for (i, c) in text.char_indices() {
+        used += UnicodeWidthChar::width(c).unwrap_or(0);
+        if used > width {
+            return &text[..i];
+        }
+    }
+    text
+}
+
+/// 端末に表示される文字の格子
+///
+/// 描画結果のエスケープシーケンスのうち、カーソル移動 (`Goto`) と行・画面の消去だけを解釈し、
+/// 色などの属性は取り除く。全角文字の 2 セル目は `'\0'` にする。
+/// 最下行での改行は端末と同じく画面をスクロールするので、描画が画面からはみ出すと分かる。
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScreenGrid {
+    cells: Vec<Vec<char>>,
+    /// 最後に移動したカーソルの位置 (0-indexed の列, 行)
+    pub cursor: (usize, usize),
+}
+
+impl ScreenGrid {
+    /// 端末への出力を `size` (列, 行) の画面として解釈する
+    pub fn parse(output: &str, size: (u16, u16)) -> Self {
+        let (cols, rows) = (size.0 as usize, size.1 as usize);
+        let mut grid = Self {
+            cells: vec![vec![' '; cols]; rows],
+            cursor: (0, 0),
+        };
+        let mut chars = output.chars().peekable();
+        while let Some(ch) = chars.next() {
+            let (x, y) = grid.cursor;
+            match ch {
+                '\x1b' => {
+                    if chars.next_if_eq(&'[').is_none() {
+                        chars.next();
+                        continue;
+                    }
+                    let mut params = String::new();
+                    while let Some(c) = chars.next_if(|c| !('@'..='~').contains(c)) {
+                        params.push(c);
+                    }
+                    let Some(command) = chars.next() else {
+                        break;
+                    };
+                    grid.escape(command, &params);
+                }
+                '\r' => grid.cursor.0 = 0,
+                // 最下行での改行は端末と同じく画面をスクロールする
+                '\n' if y + 1 >= rows => {
+                    if rows > 0 {
+                        grid.cells.remove(0);
+                        grid.cells.push(vec![' '; cols]);
+                    }
+                }
+                '\n' => grid.cursor.1 = y + 1,
+                c if c.is_control() => {}
+                c => {
+                    let width = UnicodeWidthChar::width(c).unwrap_or(0);
+                    if width == 0 || x + width > cols || y >= rows {
+                        continue;
+                    }
+                    grid.cells[y][x] = c;
+                    if width == 2 {
+                        grid.cells[y][x + 1] = '\0';
+                    }
+                    grid.cursor.0 = x + width;
+                }
+            }
+        }
+        grid
+    }
+
+    /// CSI シーケンス (`ESC [ params command`) を解釈する
+    fn escape(&mut self, command: char, params: &str) {
+        let (x, y) = self.cursor;
+        match (command, params) {
+            ('H', _) => {
+                let mut numbers = params.split(';').map(|n| n.parse::<usize>().unwrap_or(1));
+                let row = numbers.next().unwrap_or(1).max(1);
+                let col = numbers.next().unwrap_or(1).max(1);
+                let cols = self.cells.first().map_or(0, Vec::len);
+                self.cursor = (
+                    (col - 1).min(cols.saturating_sub(1)),
+                    (row - 1).min(self.cells.len().saturating_sub(1)),
+                );
+            }
+            ('K', "" | "0") => self.clear_row(y, x),
+            ('K', "2") => self.clear_row(y, 0),
+            ('J', "" | "0") => {
+                self.clear_row(y, x);
+                for row in y + 1..self.cells.len() {
+                    self.clear_row(row, 0);
+                }
+            }
+            ('J', "2") => {
+                for row in 0..self.cells.len() {
+                    self.clear_row(row, 0);
+                }
+            }
+            // 色などの属性やカーソルの表示・非表示は無視する
+            _ => {}
+        }
+    }
+
+    fn clear_row(&mut self, y: usize, from: usize) {
+        if let Some(row) = self.cells.get_mut(y) {
+            row.iter_mut().skip(from).for_each(|cell| *cell = ' ');
+        }
+    }
+
+    /// `y` 行目 (0-indexed) に表示される文字列 (行末の空白は除く)
+    pub fn row(&self, y: usize) -> String {
+        let Some(row) = self.cells.get(y) else {
+            return String::new();
+        };
+        let text: String = row.iter().filter(|c| **c != '\0').collect();
+        text.trim_end().to_string()
+    }
+
+    /// 表示される全行 (行末の空白は除く)
+    pub fn rows(&self) -> Vec<String> {
+        (0..self.cells.len()).map(|y| self.row(y)).collect()
+    }
+
+    /// `(x, y)` (0-indexed) のセルの文字
+    pub fn cell(&self, x: usize, y: usize) -> Option<char> {
+        self.cells.get(y)?.get(x).copied()
+    }
+}
+
+/// ステータスバーに表示するファイル名を `statusfilename` の形式で返す
+///
+/// 相対パスは `cwd` を基準に解決する。ファイル名がない場合は `[No Name]` を返す。
+pub fn format_filename(filename: Option<&str>, style: FileNameStyle, cwd: &Path) -> String {
+    let Some(filename) = filename else {
+        return "[No Name]".to_string();
+    };
+    let path = Path::new(filename);
+    match style {
+        FileNameStyle::Full => normalize_path(&cwd.join(path)).display().to_string(),
+        FileNameStyle::Relative => {
+            let full = normalize_path(&cwd.join(path));
+            match full.strip_prefix(normalize_path(cwd)) {
+                Ok(relative) if !relative.as_os_str().is_empty() => relative.display().to_string(),
+                _ => filename.to_string(),
+            }
+        }
+        FileNameStyle::Tail => path.file_name().map_or_else(
+            || filename.to_string(),
+            |name| name.to_string_lossy().into_owned(),
+        ),
+    }
+}
+
+/// `.` / `..` をファイルシステムにアクセスせずに取り除く
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    fn display_text(cells: &[DisplayCell]) -> String {
+        cells.iter().map(|cell| cell.text.as_str()).collect()
+    }
+
+    fn list_config() -> EditorConfig {
+        let mut config = EditorConfig::new();
+        config.list = true;
+        config.tabstop = 4;
+        config
+    }
+
+    #[test]
+    fn test_display_cells_without_list() {
+        let mut config = EditorConfig::new();
+        config.tabstop = 4;
+        let cells = Screen::display_cells("a\tb  ", &config);
+
+        // タブは次のタブストップまで空白で展開される
+        assert_eq!(display_text(&cells), "a   b  ");
+        assert!(cells.iter().all(|cell| !cell.is_marker));
+    }
+
+    #[test]
+    fn test_display_cells_control_chars() {
+        let config = EditorConfig::new();
+        let cells = Screen::display_cells("a\x1bb\x7f\u{85}", &config);
+
+        assert_eq!(display_text(&cells), "a^[b^?<85>");
+        assert!(cells[1].is_marker);
+        // カーソル位置は表示の幅で数える
+        assert_eq!(Screen::screen_col("a\x1bb", 2, &config), 4);
+    }
+
+    #[test]
+    fn test_display_cells_list_mode() {
+        let cells = Screen::display_cells("a\tb  ", &list_config());
+
+        assert_eq!(display_text(&cells), "a▸  b··$");
+        // 行末記号はバッファ上の列を持たない
+        assert_eq!(cells.last().unwrap().col, None);
+        assert!(cells.last().unwrap().is_marker);
+    }
+
+    #[test]
+    fn test_display_cells_list_inner_spaces_are_not_trail() {
+        let cells = Screen::display_cells("a b ", &list_config());
+        assert_eq!(display_text(&cells), "a b·$");
+    }
+
+    #[test]
+    fn test_display_cells_custom_listchars() {
+        let mut config = list_config();
+        config.apply("listchars=tab:>-").unwrap();
+        let cells = Screen::display_cells("\tx ", &config);
+
+        // eol / trail が未指定の場合は記号を表示しない
+        assert_eq!(display_text(&cells), ">---x ");
+    }
+
+    #[test]
+    fn test_screen_col_with_tabs() {
+        let config = list_config();
+        // "\t\tx" の x はタブ 2 つ (4 + 4 カラム) の後ろ
+        assert_eq!(Screen::screen_col("\t\tx", 0, &config), 1);
+        assert_eq!(Screen::screen_col("\t\tx", 1, &config), 5);
+        assert_eq!(Screen::screen_col("\t\tx", 2, &config), 9);
+        // 途中から始まるタブは次のタブストップまでの幅になる
+        assert_eq!(Screen::screen_col("ab\tx", 3, &config), 5);
+    }
+
+    #[test]
+    fn test_gutter_width() {
+        let width = |mode, len, numberwidth, signcolumn| {
+            Screen::gutter_width(mode, len, numberwidth, signcolumn)
+        };
+        assert_eq!(width(LineNumberMode::Off, 100, 4, SignColumn::No), 0);
+        assert_eq!(width(LineNumberMode::Absolute, 0, 4, SignColumn::No), 4);
+        assert_eq!(width(LineNumberMode::Relative, 999, 4, SignColumn::No), 4);
+        assert_eq!(width(LineNumberMode::Absolute, 1000, 4, SignColumn::No), 5);
+
+        // numberwidth は最小の幅で、桁数が多ければ広げる
+        assert_eq!(width(LineNumberMode::Absolute, 10, 1, SignColumn::No), 3);
+        assert_eq!(width(LineNumberMode::Absolute, 10, 8, SignColumn::No), 8);
+        assert_eq!(
+            width(LineNumberMode::Absolute, 123456, 3, SignColumn::No),
+            7
+        );
+
+        // サイン列は行番号を表示しなくても 1 桁分を確保する
+        assert_eq!(width(LineNumberMode::Off, 100, 4, SignColumn::Yes), 1);
+        assert_eq!(width(LineNumberMode::Hybrid, 100, 4, SignColumn::Yes), 5);
+        assert_eq!(width(LineNumberMode::Absolute, 10, 6, SignColumn::Yes), 7);
+    }
+
+    #[test]
+    fn test_line_number_label() {
+        assert_eq!(
+            Screen::line_number_label(LineNumberMode::Absolute, 4, 2, 4),
+            "  5 "
+        );
+        assert_eq!(
+            Screen::line_number_label(LineNumberMode::Relative, 4, 2, 4),
+            "  2 "
+        );
+        assert_eq!(
+            Screen::line_number_label(LineNumberMode::Relative, 2, 2, 4),
+            "  0 "
+        );
+        assert_eq!(
+            Screen::line_number_label(LineNumberMode::Hybrid, 2, 2, 4),
+            "3   "
+        );
+        assert_eq!(
+            Screen::line_number_label(LineNumberMode::Hybrid, 0, 2, 4),
+            "  2 "
+        );
+    }
+
+    #[test]
+    fn test_screen_col_multibyte() {
+        let config = EditorConfig::new();
+        assert_eq!(Screen::screen_col("あいう", 2, &config), 5);
+    }
+
+    #[test]
+    fn test_screen_col_past_line_end() {
+        // virtualedit で行末より後ろにある列
+        let config = EditorConfig::new();
+        assert_eq!(Screen::screen_col("あい", 4, &config), 7);
+        assert_eq!(Screen::screen_col("", 3, &config), 4);
+    }
+
+    fn insert_view(number_mode: LineNumberMode) -> ViewState<'static> {
+        ViewState {
+            mode: Mode::Insert,
+            command_prompt: ':',
+            command_buffer: "",
+            status_message: "",
+            visual_start: None,
+            number_mode,
+            highlight: None,
+            pending_keys: "",
+        }
+    }
+
+    #[test]
+    fn test_refresh_line() {
+        let buffer = Buffer::from_lines(["first", "a\tb", "last"]);
+        let editor = Editor::from_buffer(buffer, None);
+        let mut cursor = Cursor::new();
+        cursor.move_to_position(Position::new(1, 2), 3, 22);
+
+        let mut out = Vec::new();
+        let view = insert_view(LineNumberMode::Off);
+        Screen::new()
+            .refresh_line(&mut out, (80, 24), &cursor, &editor, &view)
+            .unwrap();
+        // 2 行目だけを描画し、タブの後ろ (9 列目) にカーソルを置く
+        let expected = format!(
+            "{}{}a       b{}{}{}",
+            termion::cursor::Hide,
+            termion::cursor::Goto(1, 2),
+            termion::clear::UntilNewline,
+            termion::cursor::Goto(9, 2),
+            termion::cursor::Show
+        );
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+
+        // 行番号欄の分だけカーソルを右にずらす
+        let mut out = Vec::new();
+        let view = insert_view(LineNumberMode::Absolute);
+        Screen::new()
+            .refresh_line(&mut out, (80, 24), &cursor, &editor, &view)
+            .unwrap();
+        let expected = format!(
+            "{}{}{}  2 {}a       b{}{}{}",
+            termion::cursor::Hide,
+            termion::cursor::Goto(1, 2),
+            termion::color::Fg(termion::color::Yellow),
+            termion::color::Fg(termion::color::Reset),
+            termion::clear::UntilNewline,
+            termion::cursor::Goto(13, 2),
+            termion::cursor::Show
+        );
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_render_to_grid() {
+        let buffer = Buffer::from_lines(["fn main() {", "\t日本語", "}"]);
+        let editor = Editor::from_buffer(buffer, None);
+        let mut cursor = Cursor::new();
+        cursor.move_to_position(Position::new(1, 1), 3, 4);
+        let view = ViewState {
+            mode: Mode::Normal,
+            status_message: "hello",
+            ..insert_view(LineNumberMode::Absolute)
+        };
+
+        let grid = Screen::new()
+            .render_to_grid((24, 6), &cursor, &editor, &view)
+            .unwrap();
+        assert_eq!(
+            grid.rows(),
+            vec![
+                "  1 fn main() {",
+                "  2         日本語",
+                "  3 }",
+                "~",
+                "[No Name] - 3 lines 2/3",
+                "hello",
+            ]
+        );
+        // 全角文字は 2 セルを使い、カーソルはタブの後ろの「日」に置く
+        assert_eq!(grid.cell(12, 1), Some('日'));
+        assert_eq!(grid.cell(13, 1), Some('\0'));
+        assert_eq!(grid.cursor, (12, 1));
+    }
+
+    #[test]
+    fn test_render_wrapped_lines() {
+        let buffer = Buffer::from_lines(["abcdefghij", "abc日本", "end"]);
+        let mut editor = Editor::from_buffer(buffer, None);
+        editor.config.wrap = true;
+        let mut cursor = Cursor::new();
+        cursor.move_to_position(Position::new(0, 9), 3, 5);
+        let view = ViewState {
+            mode: Mode::Normal,
+            ..insert_view(LineNumberMode::Absolute)
+        };
+        let render = |cursor: &Cursor| {
+            Screen::new()
+                .render_to_grid((8, 7), cursor, &editor, &view)
+                .unwrap()
+        };
+
+        // 折り返した行は行番号欄を空け、右端に収まらない全角文字は次の行に送る
+        let grid = render(&cursor);
+        assert_eq!(
+            grid.rows(),
+            vec![
+                "  1 abcd",
+                "    efgh",
+                "    ij",
+                "  2 abc",
+                "    日本",
+                "[No Name",
+                "",
+            ]
+        );
+        assert_eq!(grid.cursor, (5, 2));
+
+        // カーソルは上の行を折り返した分だけ下に置く
+        cursor.move_to_position(Position::new(1, 4), 3, 5);
+        assert_eq!(render(&cursor).cursor, (6, 4));
+        assert_eq!(Screen::cursor_screen_row(&editor, &cursor, 4), 4);
+    }
+
+    #[test]
+    fn test_wrap_cells() {
+        let config = EditorConfig::new();
+        let cells = Screen::display_cells("ab日本c", &config);
+        let rows = Screen::wrap_cells(&cells, 3, false);
+        assert_eq!((rows.len(), &rows[0]), (1, &(0..2)));
+        assert_eq!(Screen::wrap_cells(&cells, 3, true), vec![0..2, 2..3, 3..5]);
+        let rows = Screen::wrap_cells(&[], 3, true);
+        assert_eq!((rows.len(), &rows[0]), (1, &(0..0)));
+        assert_eq!(
+            Screen::screen_line_cols("ab日本c", 4, 3, &config),
+            vec![0, 1]
+        );
+
+        let config = EditorConfig {
+            wrap: true,
+            ..EditorConfig::new()
+        };
+        assert_eq!(
+            Screen::screen_line_cols("ab日本c", 4, 3, &config),
+            vec![3, 4]
+        );
+        // 行末より後ろの列は最後の画面の行に含める
+        assert_eq!(
+            Screen::screen_line_cols("ab日本c", 9, 3, &config),
+            vec![3, 4]
+        );
+        assert_eq!(Screen::screen_line_count("ab日本c", 3, &config), 3);
+    }
+
+    #[test]
+    fn test_refresh_tiny_terminal() {
+        let buffer = Buffer::from_lines(["first line", "日本語のテキスト"]);
+        let editor = Editor::from_buffer(buffer, Some("a_long_file_name.txt".to_string()));
+        let cursor = Cursor::new();
+        let view = ViewState {
+            mode: Mode::Normal,
+            status_message: "a rather long status message",
+            pending_keys: "2d",
+            ..insert_view(LineNumberMode::Absolute)
+        };
+        let rows = |size| {
+            Screen::new()
+                .render_to_grid(size, &cursor, &editor, &view)
+                .unwrap()
+                .rows()
+        };
+
+        // テキストを表示できない場合はコマンドラインだけを表示する
+        assert_eq!(rows((1, 1)), vec!["a"]);
+        // ステータスバーを表示する余地がなければ表示しない
+        assert_eq!(rows((10, 2)), vec!["  1 first", "a rather l"]);
+        assert_eq!(
+            rows((80, 3)),
+            vec![
+                "  1 first line".to_string(),
+                format!("a_long_file_name.txt - 2 lines{}1/2", " ".repeat(46)),
+                format!("a rather long status message{}2d", " ".repeat(41)),
+            ]
+        );
+        // 行番号欄が収まらない場合は表示しない
+        assert_eq!(rows((3, 5)), vec!["fir", "日", "~", "a_l", "a r"]);
+        assert!(rows((0, 0)).is_empty());
+
+        // コマンドモードで入力が幅に収まらない場合は末尾を表示する
+        let view = ViewState {
+            mode: Mode::Command,
+            command_buffer: "s/first/second/",
+            ..view
+        };
+        let grid = Screen::new()
+            .render_to_grid((8, 2), &cursor, &editor, &view)
+            .unwrap();
+        assert_eq!(grid.rows(), vec!["  1 firs", "second/"]);
+        assert_eq!(grid.cursor, (7, 1));
+    }
+
+    /// `calls` に呼び出し回数を数えながら、行全体を範囲にする
+    fn cached_spans(
+        cache: &mut RowCache<u64>,
+        key: u64,
+        buffer: &Buffer,
+        file_row: usize,
+        calls: &Cell<usize>,
+    ) -> Spans {
+        let row = buffer.row(file_row).unwrap();
+        cache.get(key, file_row, row, || {
+            calls.set(calls.get() + 1);
+            vec![(0, row.char_count())]
+        })
+    }
+
+    #[test]
+    fn test_row_cache() {
+        let mut buffer = Buffer::from_lines(["foo", "bar"]);
+        let mut cache = RowCache::default();
+        let calls = Cell::new(0);
+
+        assert_eq!(
+            cached_spans(&mut cache, 0, &buffer, 0, &calls),
+            vec![(0, 3)]
+        );
+        assert_eq!(
+            cached_spans(&mut cache, 0, &buffer, 1, &calls),
+            vec![(0, 3)]
+        );
+        assert_eq!(calls.get(), 2);
+
+        // 変更していない行はキャッシュから返す
+        cached_spans(&mut cache, 0, &buffer, 0, &calls);
+        cached_spans(&mut cache, 0, &buffer, 1, &calls);
+        assert_eq!(calls.get(), 2);
+
+        // 変更した行だけ計算し直す
+        buffer.insert_char(Position::new(1, 3), 's');
+        cached_spans(&mut cache, 0, &buffer, 0, &calls);
+        assert_eq!(
+            cached_spans(&mut cache, 0, &buffer, 1, &calls),
+            vec![(0, 4)]
+        );
+        assert_eq!(calls.get(), 3);
+
+        // 行番号がずれた行と、キーが変わった場合も計算し直す
+        buffer.delete_row(0);
+        assert_eq!(
+            cached_spans(&mut cache, 0, &buffer, 0, &calls),
+            vec![(0, 4)]
+        );
+        assert_eq!(calls.get(), 4);
+        cached_spans(&mut cache, 1, &buffer, 0, &calls);
+        assert_eq!(calls.get(), 5);
+    }
+
+    #[test]
+    fn test_refresh_caches_matches() {
+        let buffer = Buffer::from_lines(["foo bar", "bar foo"]);
+        let mut editor = Editor::from_buffer(buffer, None);
+        let cursor = Cursor::new();
+        let highlight = Highlight::word("foo", None);
+        let view = ViewState {
+            mode: Mode::Normal,
+            highlight: Some(&highlight),
+            ..insert_view(LineNumberMode::Off)
+        };
+        let screen = Screen::new();
+        let cached = |screen: &Screen, row: usize| screen.matches.borrow().rows[&row].1.clone();
+
+        screen
+            .render_to_grid((20, 5), &cursor, &editor, &view)
+            .unwrap();
+        assert_eq!(cached(&screen, 0), vec![(0, 3)]);
+        assert_eq!(cached(&screen, 1), vec![(4, 7)]);
+
+        // 変更した行の範囲は描画し直したときに新しくなる
+        editor.insert_char(Position::new(0, 0), 'x');
+        editor.insert_char(Position::new(0, 1), ' ');
+        screen
+            .render_to_grid((20, 5), &cursor, &editor, &view)
+            .unwrap();
+        assert_eq!(cached(&screen, 0), vec![(2, 5)]);
+        assert_eq!(cached(&screen, 1), vec![(4, 7)]);
+    }
+
+    #[test]
+    fn test_screen_grid_parse() {
+        let output = format!(
+            "abcdef{}{}XY{}\r\n{}text{}",
+            termion::cursor::Goto(3, 1),
+            termion::color::Fg(termion::color::Red),
+            termion::clear::UntilNewline,
+            termion::style::Invert,
+            termion::style::Reset,
+        );
+        let grid = ScreenGrid::parse(&output, (8, 3));
+        assert_eq!(grid.rows(), vec!["abXY", "text", ""]);
+        // 画面の幅を超える文字は表示しない
+        let grid = ScreenGrid::parse("0123456789", (8, 1));
+        assert_eq!(grid.row(0), "01234567");
+        assert_eq!(grid.cell(8, 0), None);
+    }
+
+    #[test]
+    fn test_bell() {
+        let mut out = Vec::new();
+        Screen::bell(&mut out, false).unwrap();
+        assert_eq!(out, b"\x07");
+
+        // visualbell は反転表示にしてから元に戻す
+        let mut out = Vec::new();
+        Screen::bell(&mut out, true).unwrap();
+        assert_eq!(out, b"\x1b[?5h\x1b[?5l");
+    }
+
+    #[test]
+    fn test_format_filename() {
+        let cwd = Path::new("/home/user/project");
+        let format = |name, style| format_filename(name, style, cwd);
+
+        let name = Some("src/foo/bar.rs");
+        assert_eq!(
+            format(name, FileNameStyle::Full),
+            "/home/user/project/src/foo/bar.rs"
+        );
+        assert_eq!(format(name, FileNameStyle::Relative), "src/foo/bar.rs");
+        assert_eq!(format(name, FileNameStyle::Tail), "bar.rs");
+
+        // 絶対パスはカレントディレクトリ以下なら相対パスにする
+        let absolute = Some("/home/user/project/src/foo/bar.rs");
+        assert_eq!(format(absolute, FileNameStyle::Relative), "src/foo/bar.rs");
+        assert_eq!(
+            format(Some("/etc/hosts"), FileNameStyle::Relative),
+            "/etc/hosts"
+        );
+        assert_eq!(
+            format(Some("../other/./a.txt"), FileNameStyle::Full),
+            "/home/user/other/a.txt"
+        );
+
+        for style in [
+            FileNameStyle::Full,
+            FileNameStyle::Relative,
+            FileNameStyle::Tail,
+        ] {
+            assert_eq!(format(None, style), "[No Name]");
+        }
+    }
 }
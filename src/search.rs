@@ -0,0 +1,353 @@
+use regex::Regex;
+
+use crate::buffer::Buffer;
+use crate::cursor::Position;
+use crate::range::LineRange;
+
+/// 検索の方向
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchDirection {
+    /// `/`
+    Forward,
+    /// `?`
+    Backward,
+}
+
+impl SearchDirection {
+    pub fn reverse(self) -> Self {
+        match self {
+            Self::Forward => Self::Backward,
+            Self::Backward => Self::Forward,
+        }
+    }
+}
+
+/// 検索結果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchResult {
+    /// 一致した位置
+    Found(Position),
+    /// ファイルの端で折り返して見つかった位置
+    Wrapped(Position),
+    /// 一致なし
+    NotFound,
+    /// wrapscan が無効で、ファイルの端までに一致がなかった
+    HitBoundary,
+}
+
+/// 画面上で強調表示するパターン
+#[derive(Debug, Clone)]
+pub struct Highlight {
+    pub regex: Regex,
+    /// 強調表示する行の範囲。None の場合はすべての行
+    pub range: Option<LineRange>,
+}
+
+impl Highlight {
+    /// 単語全体に一致する箇所の強調表示 (`:set hlword` 用)
+    pub fn word(word: &str, range: Option<LineRange>) -> Self {
+        let regex = Regex::new(&format!(r"\b{}\b", regex::escape(word)))
+            .expect("escaped word is a valid regex");
+        Self { regex, range }
+    }
+
+    /// 範囲内の一致の数と、`pos` を含む一致が何番目か (`match 2 of 5` 用)
+    ///
+    /// `pos` が一致の上にない場合は、`pos` より前の一致の数を返す。
+    pub fn count_matches(&self, buffer: &Buffer, pos: Position) -> (usize, usize) {
+        let rows = match self.range {
+            Some(range) => range.start..=range.end.min(buffer.len().saturating_sub(1)),
+            None => 0..=buffer.len().saturating_sub(1),
+        };
+        let mut current = 0;
+        let mut total = 0;
+        for row in rows {
+            let Some(line) = buffer.row(row) else {
+                continue;
+            };
+            for (start, _) in self.match_cols(row, line.chars()) {
+                total += 1;
+                if Position::new(row, start) <= pos {
+                    current = total;
+                }
+            }
+        }
+        (current, total)
+    }
+
+    /// 行内で強調表示する範囲 (char 単位、終端を含まない) を返す
+    ///
+    /// 空の一致 (`^` など) は表示できないので含めない。
+    pub fn match_cols(&self, row: usize, line: &str) -> Vec<(usize, usize)> {
+        if self
+            .range
+            .is_some_and(|range| row < range.start || row > range.end)
+        {
+            return Vec::new();
+        }
+        self.regex
+            .find_iter(line)
+            .filter(|m| !m.is_empty())
+            .map(|m| {
+                let start = line[..m.start()].chars().count();
+                (start, start + m.as_str().chars().count())
+            })
+            .collect()
+    }
+}
+
+/// `from` の次 (Backward の場合は前) の一致を探す
+///
+/// `wrapscan` が true の場合はファイルの端で反対側に折り返して検索を続ける。
+pub fn find(
+    buffer: &Buffer,
+    regex: &Regex,
+    from: Position,
+    direction: SearchDirection,
+    wrapscan: bool,
+) -> SearchResult {
+    let len = buffer.len();
+    if len == 0 {
+        return SearchResult::NotFound;
+    }
+    let from_row = from.row.min(len - 1);
+
+    match direction {
+        SearchDirection::Forward => {
+            // カーソルより後ろ → 以降の行
+            for row in from_row..len {
+                let min_col = if row == from_row {
+                    Some(from.col)
+                } else {
+                    None
+                };
+                if let Some(col) = first_match(buffer, regex, row, min_col) {
+                    return SearchResult::Found(Position::new(row, col));
+                }
+            }
+            if !wrapscan {
+                return SearchResult::HitBoundary;
+            }
+            // 先頭に折り返してカーソル位置まで
+            for row in 0..=from_row {
+                if let Some(col) = first_match(buffer, regex, row, None)
+                    && (row < from_row || col <= from.col)
+                {
+                    return SearchResult::Wrapped(Position::new(row, col));
+                }
+            }
+        }
+        SearchDirection::Backward => {
+            for row in (0..=from_row).rev() {
+                let max_col = if row == from_row {
+                    Some(from.col)
+                } else {
+                    None
+                };
+                if let Some(col) = last_match(buffer, regex, row, max_col) {
+                    return SearchResult::Found(Position::new(row, col));
+                }
+            }
+            if !wrapscan {
+                return SearchResult::HitBoundary;
+            }
+            for row in (from_row..len).rev() {
+                if let Some(col) = last_match(buffer, regex, row, None)
+                    && (row > from_row || col >= from.col)
+                {
+                    return SearchResult::Wrapped(Position::new(row, col));
+                }
+            }
+        }
+    }
+    SearchResult::NotFound
+}
+
+/// 行内で `after` 列より後ろにある最初の一致の列 (char 単位)
+fn first_match(buffer: &Buffer, regex: &Regex, row: usize, after: Option<usize>) -> Option<usize> {
+    let line = buffer.row(row)?.chars();
+    regex
+        .find_iter(line)
+        .map(|m| line[..m.start()].chars().count())
+        .find(|col| after.is_none_or(|after| *col > after))
+}
+
+/// 行内で `before` 列より前にある最後の一致の列 (char 単位)
+fn last_match(buffer: &Buffer, regex: &Regex, row: usize, before: Option<usize>) -> Option<usize> {
+    let line = buffer.row(row)?.chars();
+    regex
+        .find_iter(line)
+        .map(|m| line[..m.start()].chars().count())
+        .filter(|col| before.is_none_or(|before| *col < before))
+        .last()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern;
+
+    fn regex(pattern: &str) -> Regex {
+        pattern::compile(pattern, false, false).unwrap()
+    }
+
+    #[test]
+    fn test_find_forward_and_backward() {
+        let buffer = Buffer::from_lines(["foo bar", "baz foo", "foo"]);
+        let re = regex("foo");
+
+        assert_eq!(
+            find(
+                &buffer,
+                &re,
+                Position::new(0, 0),
+                SearchDirection::Forward,
+                true
+            ),
+            SearchResult::Found(Position::new(1, 4))
+        );
+        assert_eq!(
+            find(
+                &buffer,
+                &re,
+                Position::new(1, 4),
+                SearchDirection::Backward,
+                true
+            ),
+            SearchResult::Found(Position::new(0, 0))
+        );
+    }
+
+    #[test]
+    fn test_find_wraps_when_match_is_before_cursor() {
+        let buffer = Buffer::from_lines(["foo", "bar", "baz"]);
+        let re = regex("foo");
+
+        assert_eq!(
+            find(
+                &buffer,
+                &re,
+                Position::new(1, 0),
+                SearchDirection::Forward,
+                true
+            ),
+            SearchResult::Wrapped(Position::new(0, 0))
+        );
+        let re = regex("baz");
+        assert_eq!(
+            find(
+                &buffer,
+                &re,
+                Position::new(1, 0),
+                SearchDirection::Backward,
+                true
+            ),
+            SearchResult::Wrapped(Position::new(2, 0))
+        );
+    }
+
+    #[test]
+    fn test_find_nowrapscan_stops_at_boundary() {
+        let buffer = Buffer::from_lines(["foo", "bar", "baz"]);
+        let re = regex("foo");
+
+        assert_eq!(
+            find(
+                &buffer,
+                &re,
+                Position::new(1, 0),
+                SearchDirection::Forward,
+                false
+            ),
+            SearchResult::HitBoundary
+        );
+        let re = regex("baz");
+        assert_eq!(
+            find(
+                &buffer,
+                &re,
+                Position::new(1, 0),
+                SearchDirection::Backward,
+                false
+            ),
+            SearchResult::HitBoundary
+        );
+    }
+
+    #[test]
+    fn test_highlight_match_cols() {
+        let highlight = Highlight {
+            regex: regex("o\\+"),
+            range: Some(LineRange::new(1, 2)),
+        };
+        assert_eq!(highlight.match_cols(0, "foo"), Vec::new());
+        assert_eq!(highlight.match_cols(1, "foo bo"), vec![(1, 3), (5, 6)]);
+        assert_eq!(highlight.match_cols(2, "あo"), vec![(1, 2)]);
+
+        let highlight = Highlight {
+            regex: regex("^"),
+            range: None,
+        };
+        assert_eq!(highlight.match_cols(5, "foo"), Vec::new());
+    }
+
+    #[test]
+    fn test_find_only_match_under_cursor() {
+        let buffer = Buffer::from_lines(["foo", "bar"]);
+        let re = regex("foo");
+
+        // カーソル位置の一致しかない場合は折り返して同じ位置に戻る
+        assert_eq!(
+            find(
+                &buffer,
+                &re,
+                Position::new(0, 0),
+                SearchDirection::Forward,
+                true
+            ),
+            SearchResult::Wrapped(Position::new(0, 0))
+        );
+        assert_eq!(
+            find(
+                &buffer,
+                &regex("qux"),
+                Position::new(0, 0),
+                SearchDirection::Forward,
+                true
+            ),
+            SearchResult::NotFound
+        );
+    }
+
+    #[test]
+    fn test_word_highlight_count_matches() {
+        let buffer = Buffer::from_lines(["foo foobar foo", "bar", "foo_x foo", "foo"]);
+        let highlight = Highlight::word("foo", None);
+
+        // 単語全体のみ数える
+        assert_eq!(
+            highlight.count_matches(&buffer, Position::new(0, 0)),
+            (1, 4)
+        );
+        assert_eq!(
+            highlight.count_matches(&buffer, Position::new(0, 12)),
+            (2, 4)
+        );
+        assert_eq!(
+            highlight.count_matches(&buffer, Position::new(2, 6)),
+            (3, 4)
+        );
+        // 一致の上にない場合は直前の一致まで
+        assert_eq!(
+            highlight.count_matches(&buffer, Position::new(1, 0)),
+            (2, 4)
+        );
+
+        // 範囲を表示中の行に限定する
+        let highlight = Highlight::word("foo", Some(LineRange::new(1, 10)));
+        assert_eq!(
+            highlight.count_matches(&buffer, Position::new(3, 0)),
+            (2, 2)
+        );
+    }
+}
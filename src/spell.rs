@@ -0,0 +1,283 @@
+use std::collections::HashSet;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::buffer::Buffer;
+use crate::cursor::Position;
+use crate::search::SearchDirection;
+
+/// `spellfile` が空の場合に使う個人辞書のファイル名 (ホームディレクトリ直下)
+const DEFAULT_SPELLFILE: &str = ".zim_spell.add";
+
+/// `:set spell` で使う単語の一覧
+///
+/// 単語は小文字にして保持し、大文字小文字を区別せずに照合する。
+#[derive(Debug, Clone, Default)]
+pub struct Dictionary {
+    words: HashSet<String>,
+    /// 単語を追加・削除するたびに新しい値になる番号 (空の辞書は 0)
+    version: u64,
+}
+
+/// 辞書の番号の払い出し元 (0 は空の辞書が使うので 1 から)
+static NEXT_VERSION: AtomicU64 = AtomicU64::new(1);
+
+impl Dictionary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_words<'a>(words: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut dictionary = Self::new();
+        for word in words {
+            dictionary.add(word);
+        }
+        dictionary
+    }
+
+    /// 1 行に 1 単語のファイルから単語を追加する
+    pub fn load_file(&mut self, path: &Path) -> io::Result<()> {
+        let content = fs::read_to_string(path)?;
+        for word in content.lines() {
+            self.add(word.trim());
+        }
+        Ok(())
+    }
+
+    pub fn add(&mut self, word: &str) {
+        if !word.is_empty() {
+            self.words.insert(word.to_ascii_lowercase());
+            self.touch();
+        }
+    }
+
+    pub fn remove(&mut self, word: &str) {
+        self.words.remove(&word.to_ascii_lowercase());
+        self.touch();
+    }
+
+    /// 単語の一覧の番号
+    ///
+    /// 番号が同じ辞書は同じ単語を持つため、スペルミスの範囲のキャッシュのキーに使える。
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    fn touch(&mut self) {
+        self.version = NEXT_VERSION.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn contains(&self, word: &str) -> bool {
+        self.words.contains(&word.to_ascii_lowercase())
+    }
+
+    /// 行内で辞書にない単語の範囲 (char 単位、終端を含まない) を返す
+    ///
+    /// ASCII の英字だけからなる単語のみを対象にし、数字や `_` を含む識別子は無視する。
+    pub fn misspelled_cols(&self, line: &str) -> Vec<(usize, usize)> {
+        let mut result = Vec::new();
+        let mut word = String::new();
+        let mut start = 0;
+        for (col, ch) in line.chars().chain(std::iter::once(' ')).enumerate() {
+            if ch.is_ascii_alphanumeric() || ch == '_' {
+                if word.is_empty() {
+                    start = col;
+                }
+                word.push(ch);
+                continue;
+            }
+            if !word.is_empty()
+                && word.chars().all(|c| c.is_ascii_alphabetic())
+                && !self.contains(&word)
+            {
+                result.push((start, col));
+            }
+            word.clear();
+        }
+        result
+    }
+}
+
+/// `from` の次 (Backward の場合は前) のスペルミスの位置を探す (`]s` / `[s` 用)
+///
+/// `wrapscan` が true の場合はファイルの端で反対側に折り返して検索を続ける。
+pub fn find_misspelling(
+    dictionary: &Dictionary,
+    buffer: &Buffer,
+    from: Position,
+    direction: SearchDirection,
+    wrapscan: bool,
+) -> Option<Position> {
+    let len = buffer.len();
+    if len == 0 {
+        return None;
+    }
+    let from_row = from.row.min(len - 1);
+    // 折り返す場合は開始行をもう一度調べる (カーソルより前/後ろの部分のため)
+    let rows: Vec<usize> = match (direction, wrapscan) {
+        (SearchDirection::Forward, false) => (from_row..len).collect(),
+        (SearchDirection::Forward, true) => (from_row..len).chain(0..=from_row).collect(),
+        (SearchDirection::Backward, false) => (0..=from_row).rev().collect(),
+        (SearchDirection::Backward, true) => {
+            (0..=from_row).rev().chain((from_row..len).rev()).collect()
+        }
+    };
+    let first_pass_len = match direction {
+        SearchDirection::Forward => len - from_row,
+        SearchDirection::Backward => from_row + 1,
+    };
+    for (i, row) in rows.into_iter().enumerate() {
+        let Some(line) = buffer.row(row) else {
+            continue;
+        };
+        let cols = dictionary.misspelled_cols(line.chars());
+        let first_pass = i < first_pass_len;
+        let found = match direction {
+            SearchDirection::Forward => cols
+                .iter()
+                .map(|(start, _)| *start)
+                .find(|col| !(first_pass && row == from_row) || *col > from.col),
+            SearchDirection::Backward => cols
+                .iter()
+                .rev()
+                .map(|(start, _)| *start)
+                .find(|col| !(first_pass && row == from_row) || *col < from.col),
+        };
+        if let Some(col) = found {
+            return Some(Position::new(row, col));
+        }
+    }
+    None
+}
+
+/// 個人辞書のパス (`spellfile` が空の場合は `~/.zim_spell.add`)
+pub fn personal_file(spellfile: &str) -> Option<PathBuf> {
+    if !spellfile.is_empty() {
+        return Some(PathBuf::from(spellfile));
+    }
+    let home = std::env::var_os("HOME")?;
+    Some(Path::new(&home).join(DEFAULT_SPELLFILE))
+}
+
+/// 個人辞書に単語を追加する (`zg` 用)
+pub fn append_word(path: &Path, word: &str) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", word)
+}
+
+/// 個人辞書から単語を取り除く (`zw` 用)
+///
+/// ファイルが存在しない場合は何もしない。
+pub fn remove_word(path: &Path, word: &str) -> io::Result<()> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    let kept: String = content
+        .lines()
+        .filter(|line| !line.trim().eq_ignore_ascii_case(word))
+        .map(|line| format!("{}\n", line))
+        .collect();
+    fs::write(path, kept)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dictionary() -> Dictionary {
+        Dictionary::from_words(["the", "quick", "brown", "fox", "jumps"])
+    }
+
+    #[test]
+    fn test_misspelled_cols() {
+        let dictionary = dictionary();
+        // 大文字小文字は区別しない
+        assert_eq!(
+            dictionary.misspelled_cols("The quick brwn fox"),
+            vec![(10, 14)]
+        );
+        // 数字や _ を含む単語と非 ASCII の文字は対象外
+        assert_eq!(dictionary.misspelled_cols("fox2 foo_bar 日本"), Vec::new());
+        assert_eq!(
+            dictionary.misspelled_cols("jumps ovr, teh"),
+            vec![(6, 9), (11, 14)]
+        );
+    }
+
+    #[test]
+    fn test_dictionary_version() {
+        let mut dictionary = Dictionary::new();
+        assert_eq!(dictionary.version(), Dictionary::new().version());
+
+        // 単語を追加・削除するたびに別の番号になる
+        dictionary.add("fox");
+        let version = dictionary.version();
+        assert_ne!(version, 0);
+        dictionary.remove("fox");
+        assert_ne!(dictionary.version(), version);
+    }
+
+    #[test]
+    fn test_find_misspelling() {
+        let dictionary = dictionary();
+        let buffer = Buffer::from_lines(["the quikc fox", "brown fox", "jumsp the"]);
+
+        let forward =
+            |pos| find_misspelling(&dictionary, &buffer, pos, SearchDirection::Forward, true);
+        assert_eq!(forward(Position::new(0, 0)), Some(Position::new(0, 4)));
+        assert_eq!(forward(Position::new(0, 4)), Some(Position::new(2, 0)));
+        // ファイル末尾で先頭に折り返す
+        assert_eq!(forward(Position::new(2, 0)), Some(Position::new(0, 4)));
+
+        let backward = |pos, wrapscan| {
+            find_misspelling(
+                &dictionary,
+                &buffer,
+                pos,
+                SearchDirection::Backward,
+                wrapscan,
+            )
+        };
+        assert_eq!(
+            backward(Position::new(2, 0), true),
+            Some(Position::new(0, 4))
+        );
+        assert_eq!(backward(Position::new(0, 4), false), None);
+        assert_eq!(
+            backward(Position::new(0, 4), true),
+            Some(Position::new(2, 0))
+        );
+
+        let correct = Buffer::from_lines(["the fox"]);
+        assert_eq!(
+            find_misspelling(
+                &dictionary,
+                &correct,
+                Position::new(0, 0),
+                SearchDirection::Forward,
+                true
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_personal_dictionary_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("spell.add");
+
+        append_word(&path, "zim").unwrap();
+        append_word(&path, "termion").unwrap();
+        let mut dictionary = Dictionary::new();
+        dictionary.load_file(&path).unwrap();
+        assert!(dictionary.contains("Zim"));
+        assert!(dictionary.contains("termion"));
+
+        remove_word(&path, "ZIM").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "termion\n");
+    }
+}
@@ -0,0 +1,623 @@
+use regex::{Captures, Regex};
+
+use crate::buffer::Buffer;
+use crate::cursor::Position;
+use crate::pattern;
+use crate::range::LineRange;
+
+/// `:s` のフラグ
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SubstituteFlags {
+    /// `g`: 行内のすべての一致を置換する
+    pub global: bool,
+    /// `i` / `I`: 大文字小文字を無視する / 区別する
+    pub ignore_case: Option<bool>,
+    /// `c`: 置換ごとに確認する
+    pub confirm: bool,
+}
+
+impl SubstituteFlags {
+    /// フラグを 1 文字設定する (不明なフラグの場合は false)
+    fn set(&mut self, flag: char) -> bool {
+        match flag {
+            'g' => self.global = true,
+            'i' => self.ignore_case = Some(true),
+            'I' => self.ignore_case = Some(false),
+            'c' => self.confirm = true,
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// `:s/pattern/replacement/flags` の内容
+#[derive(Debug, Clone, PartialEq)]
+pub struct Substitute {
+    pub pattern: String,
+    pub replacement: String,
+    pub flags: SubstituteFlags,
+}
+
+/// 置換の結果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubstituteResult {
+    /// 置換した箇所の数
+    pub count: usize,
+    /// 置換した行数
+    pub lines: usize,
+    /// 最後に置換した行 (0-indexed)
+    pub last_row: usize,
+}
+
+impl Substitute {
+    /// `/pattern/replacement/flags` 形式の引数をパースする
+    ///
+    /// 区切り文字は先頭の文字で、`\` でエスケープすると区切り文字自体を含められる。
+    /// 末尾の区切り文字は省略できる。
+    pub fn parse(args: &str) -> Result<Self, String> {
+        let mut fields = split_fields(args)?.into_iter();
+        let pattern = fields.next().unwrap_or_default();
+        if pattern.is_empty() {
+            return Err("No previous regular expression".to_string());
+        }
+        let replacement = fields.next().unwrap_or_default();
+
+        let mut flags = SubstituteFlags::default();
+        for flag in fields.next().unwrap_or_default().trim().chars() {
+            if !flags.set(flag) {
+                return Err(format!("Trailing characters: {}", flag));
+            }
+        }
+
+        Ok(Self {
+            pattern,
+            replacement,
+            flags,
+        })
+    }
+
+    /// 入力途中の引数をパースする (プレビュー用)
+    ///
+    /// [`Substitute::parse`] と違い、入力途中の不明なフラグは無視する。
+    /// 区切り文字が不正な場合やパターンが空の場合は None を返す。
+    pub fn partial(args: &str) -> Option<Self> {
+        let mut fields = split_fields(args).ok()?.into_iter();
+        let pattern = fields.next().filter(|pattern| !pattern.is_empty())?;
+        let replacement = fields.next().unwrap_or_default();
+        let mut flags = SubstituteFlags::default();
+        for flag in fields.next().unwrap_or_default().trim().chars() {
+            flags.set(flag);
+        }
+        Some(Self {
+            pattern,
+            replacement,
+            flags,
+        })
+    }
+
+    /// パターンをコンパイルする
+    ///
+    /// 大文字小文字を保持して置換する場合は、`Foo` や `FOO` にも一致するよう
+    /// 大文字小文字を無視して検索する。`very_magic` が true の場合は very magic 形式で解釈する。
+    pub fn regex(&self, keep_case: bool, very_magic: bool) -> Result<Regex, String> {
+        let ignore_case = self.flags.ignore_case.unwrap_or(keep_case);
+        pattern::compile(&self.pattern, ignore_case, very_magic)
+    }
+
+    /// 1 行に置換を適用する
+    ///
+    /// `keep_case` が true の場合は一致したテキストの大文字小文字に合わせて置換する。
+    ///
+    /// # Returns
+    ///
+    /// 置換後の行と置換した箇所の数。一致しなかった場合は None
+    pub fn apply_line(
+        &self,
+        regex: &Regex,
+        line: &str,
+        keep_case: bool,
+    ) -> Option<(String, usize)> {
+        let limit = if self.flags.global { 0 } else { 1 };
+        let mut count = 0;
+        let replaced = regex.replacen(line, limit, |caps: &Captures| {
+            count += 1;
+            self.replacement_for(caps, keep_case)
+        });
+        (count > 0).then(|| (replaced.into_owned(), count))
+    }
+
+    /// 置換前の行の `col` 列 (char 単位) の文字が、`apply_line` で置換した後の行で何列目になるかを求める
+    ///
+    /// `col` が置換された範囲の中にある場合は、置換後の文字列の先頭の列を返す。
+    pub fn map_col(&self, regex: &Regex, line: &str, col: usize, keep_case: bool) -> usize {
+        let offset = line.char_indices().nth(col).map_or(line.len(), |(i, _)| i);
+        let limit = if self.flags.global { usize::MAX } else { 1 };
+        // 置換による列のずれ
+        let mut shift: isize = 0;
+        for caps in regex.captures_iter(line).take(limit) {
+            let m = caps.get(0).unwrap();
+            if m.start() > offset {
+                break;
+            }
+            let start = line[..m.start()].chars().count() as isize;
+            if m.end() > offset {
+                return (start + shift) as usize;
+            }
+            let replaced = self.replacement_for(&caps, keep_case).chars().count() as isize;
+            shift += replaced - m.as_str().chars().count() as isize;
+        }
+        (col as isize + shift) as usize
+    }
+
+    /// 1 つの一致に対する置換文字列を作る
+    fn replacement_for(&self, caps: &Captures, keep_case: bool) -> String {
+        let replacement = expand_replacement(&self.replacement, caps);
+        if keep_case {
+            preserve_case(&caps[0], &replacement)
+        } else {
+            replacement
+        }
+    }
+}
+
+/// 引数を区切り文字で pattern / replacement / flags に分割する
+fn split_fields(args: &str) -> Result<Vec<String>, String> {
+    let mut chars = args.chars();
+    let delimiter = chars
+        .next()
+        .filter(|c| !c.is_alphanumeric() && !c.is_whitespace() && !matches!(c, '\\' | '"' | '|'))
+        .ok_or_else(|| "Invalid substitute command".to_string())?;
+
+    let mut fields = vec![String::new()];
+    while let Some(ch) = chars.next() {
+        let field_count = fields.len();
+        let field = fields.last_mut().unwrap();
+        if ch == '\\' {
+            match chars.next() {
+                Some(c) if c == delimiter => field.push(c),
+                Some(c) => {
+                    field.push('\\');
+                    field.push(c);
+                }
+                None => field.push('\\'),
+            }
+        } else if ch == delimiter && field_count < 3 {
+            fields.push(String::new());
+        } else {
+            field.push(ch);
+        }
+    }
+    Ok(fields)
+}
+
+/// `:s///c` の確認に対する応答
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfirmAnswer {
+    /// `y`: 置換する
+    Yes,
+    /// `n`: 置換しない
+    No,
+    /// `a`: これ以降をすべて置換する
+    All,
+    /// `q` / Esc: 置換を終了する
+    Quit,
+    /// `l`: 置換して終了する
+    Last,
+}
+
+impl ConfirmAnswer {
+    pub fn from_char(c: char) -> Option<Self> {
+        match c {
+            'y' => Some(Self::Yes),
+            'n' => Some(Self::No),
+            'a' => Some(Self::All),
+            'q' => Some(Self::Quit),
+            'l' => Some(Self::Last),
+            _ => None,
+        }
+    }
+}
+
+/// 確認付き置換 (`:s///c`) の進行状態
+///
+/// 一致ごとに `find_next` でカーソルを移動し、`answer` で応答を適用する。
+pub struct ConfirmSubstitute {
+    sub: Substitute,
+    regex: Regex,
+    keep_case: bool,
+    /// 最終行 (0-indexed)
+    end_row: usize,
+    /// 次に検索を始める行と、その行内のバイト位置
+    row: usize,
+    col: usize,
+    /// 確認中の一致 (開始・終了のバイト位置)
+    current: Option<(usize, usize)>,
+    done: bool,
+    result: Option<SubstituteResult>,
+}
+
+impl ConfirmSubstitute {
+    pub fn new(
+        sub: Substitute,
+        range: LineRange,
+        keep_case: bool,
+        very_magic: bool,
+    ) -> Result<Self, String> {
+        let regex = sub.regex(keep_case, very_magic)?;
+        Ok(Self {
+            sub,
+            regex,
+            keep_case,
+            end_row: range.end,
+            row: range.start,
+            col: 0,
+            current: None,
+            done: false,
+            result: None,
+        })
+    }
+
+    /// 一致ごとに表示する確認のプロンプト
+    pub fn prompt(&self) -> String {
+        format!("replace with {} (y/n/a/q/l)?", self.sub.replacement)
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// これまでの置換結果。まだ置換していない場合は None
+    pub fn result(&self) -> Option<SubstituteResult> {
+        self.result
+    }
+
+    /// 次の一致を探す
+    ///
+    /// # Returns
+    ///
+    /// 一致の開始位置。一致がなくなった場合は None
+    pub fn find_next(&mut self, buffer: &Buffer) -> Option<Position> {
+        self.current = None;
+        if self.done {
+            return None;
+        }
+        while self.row <= self.end_row {
+            let Some(line) = buffer.row(self.row).map(|r| r.chars()) else {
+                break;
+            };
+            if self.col <= line.len()
+                && let Some(m) = self.regex.find_at(line, self.col)
+            {
+                self.current = Some((m.start(), m.end()));
+                return Some(Position::new(self.row, line[..m.start()].chars().count()));
+            }
+            self.row += 1;
+            self.col = 0;
+        }
+        self.done = true;
+        None
+    }
+
+    /// 確認中の一致に応答を適用する
+    ///
+    /// # Returns
+    ///
+    /// バッファを変更した場合は true
+    pub fn answer(&mut self, answer: ConfirmAnswer, buffer: &mut Buffer) -> bool {
+        let Some((start, end)) = self.current.take() else {
+            return false;
+        };
+        match answer {
+            ConfirmAnswer::Yes => self.replace(buffer, start, end),
+            ConfirmAnswer::No => self.advance(buffer, end, start == end),
+            ConfirmAnswer::All => {
+                self.replace(buffer, start, end);
+                while self.find_next(buffer).is_some() {
+                    let (start, end) = self.current.take().unwrap();
+                    self.replace(buffer, start, end);
+                }
+            }
+            ConfirmAnswer::Quit => self.done = true,
+            ConfirmAnswer::Last => {
+                self.replace(buffer, start, end);
+                self.done = true;
+            }
+        }
+        matches!(
+            answer,
+            ConfirmAnswer::Yes | ConfirmAnswer::All | ConfirmAnswer::Last
+        )
+    }
+
+    /// `start..end` の一致を置換する
+    fn replace(&mut self, buffer: &mut Buffer, start: usize, end: usize) {
+        let Some(line) = buffer.get_row_content(self.row) else {
+            return;
+        };
+        let Some(caps) = self.regex.captures_at(&line, start) else {
+            return;
+        };
+        let replacement = self.sub.replacement_for(&caps, self.keep_case);
+        let next = start + replacement.len();
+        buffer.set_row(
+            self.row,
+            format!("{}{}{}", &line[..start], replacement, &line[end..]),
+        );
+
+        let row = self.row;
+        let result = self.result.get_or_insert(SubstituteResult {
+            count: 0,
+            lines: 0,
+            last_row: row,
+        });
+        result.count += 1;
+        if result.lines == 0 || result.last_row != row {
+            result.lines += 1;
+        }
+        result.last_row = row;
+
+        self.advance(buffer, next, start == end);
+    }
+
+    /// 次の検索開始位置に進める
+    ///
+    /// `g` フラグがない場合は次の行へ、ある場合は `next` から検索する。
+    /// 空文字列に一致した場合は同じ位置で止まらないよう 1 文字進める。
+    fn advance(&mut self, buffer: &Buffer, next: usize, empty_match: bool) {
+        if !self.sub.flags.global {
+            self.row += 1;
+            self.col = 0;
+            return;
+        }
+        self.col = next;
+        if empty_match {
+            let line = buffer.row(self.row).map(|r| r.chars()).unwrap_or("");
+            match line.get(next..).and_then(|rest| rest.chars().next()) {
+                Some(c) => self.col += c.len_utf8(),
+                None => {
+                    self.row += 1;
+                    self.col = 0;
+                }
+            }
+        }
+    }
+}
+
+/// 置換文字列中の `&` / `\0` (一致全体) と `\1`〜`\9` (グループ) を展開する
+pub fn expand_replacement(template: &str, caps: &Captures) -> String {
+    let mut result = String::new();
+    let mut chars = template.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '&' => result.push_str(&caps[0]),
+            '\\' => match chars.next() {
+                Some(d @ '0'..='9') => {
+                    let index = d.to_digit(10).unwrap() as usize;
+                    result.push_str(caps.get(index).map(|m| m.as_str()).unwrap_or(""));
+                }
+                Some('t') => result.push('\t'),
+                Some(c) => result.push(c),
+                None => result.push('\\'),
+            },
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+/// 一致したテキストの大文字小文字に合わせて置換文字列を変換する
+///
+/// - `foo` (小文字): そのまま
+/// - `Foo` (先頭のみ大文字): 先頭を大文字にする
+/// - `FOO` (すべて大文字): すべて大文字にする
+pub fn preserve_case(matched: &str, replacement: &str) -> String {
+    let letters: Vec<char> = matched.chars().filter(|c| c.is_alphabetic()).collect();
+    let Some(first) = letters.first() else {
+        return replacement.to_string();
+    };
+
+    if letters.len() > 1 && letters.iter().all(|c| c.is_uppercase()) {
+        replacement.to_uppercase()
+    } else if first.is_uppercase() {
+        let mut chars = replacement.chars();
+        match chars.next() {
+            Some(head) => head.to_uppercase().chain(chars).collect(),
+            None => String::new(),
+        }
+    } else {
+        replacement.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_substitute() {
+        let sub = Substitute::parse("/foo/bar/g").unwrap();
+        assert_eq!(sub.pattern, "foo");
+        assert_eq!(sub.replacement, "bar");
+        assert!(sub.flags.global);
+
+        // 末尾の区切り文字は省略可能
+        let sub = Substitute::parse("/foo/bar").unwrap();
+        assert_eq!(sub.replacement, "bar");
+        assert!(!sub.flags.global);
+
+        // 別の区切り文字とエスケープ
+        let sub = Substitute::parse(r"#a\#b#c/d#").unwrap();
+        assert_eq!(sub.pattern, "a#b");
+        assert_eq!(sub.replacement, "c/d");
+    }
+
+    #[test]
+    fn test_parse_substitute_errors() {
+        assert!(Substitute::parse("").is_err());
+        assert!(Substitute::parse("//bar/").is_err());
+        assert!(Substitute::parse("/a/b/x").is_err());
+    }
+
+    #[test]
+    fn test_partial() {
+        let partial_pattern = |args| Substitute::partial(args).map(|sub| sub.pattern);
+        // 入力途中のパターン
+        assert_eq!(partial_pattern("/fo"), Some("fo".to_string()));
+        assert_eq!(partial_pattern("/foo/ba"), Some("foo".to_string()));
+        assert_eq!(partial_pattern(r"#a\#"), Some("a#".to_string()));
+        // 末尾の `\` や閉じていないグループでもパニックしない
+        assert_eq!(partial_pattern(r"/a\"), Some(r"a\".to_string()));
+        assert_eq!(partial_pattern(r"/\(a"), Some(r"\(a".to_string()));
+        // パターンが空、区切り文字が不正
+        assert_eq!(partial_pattern(""), None);
+        assert_eq!(partial_pattern("/"), None);
+        assert_eq!(partial_pattern("a/b"), None);
+
+        // 入力途中のフラグのうち、分かるものだけを使う
+        let sub = Substitute::partial("/foo/bar/ix").unwrap();
+        assert_eq!(sub.flags.ignore_case, Some(true));
+    }
+
+    #[test]
+    fn test_apply_line() {
+        let sub = Substitute::parse(r"/\(\w\+\)=\(\w\+\)/\2=\1/").unwrap();
+        let regex = sub.regex(false, false).unwrap();
+        assert_eq!(
+            sub.apply_line(&regex, "a=b c=d", false),
+            Some(("b=a c=d".to_string(), 1))
+        );
+
+        let sub = Substitute::parse("/o/[&]/g").unwrap();
+        let regex = sub.regex(false, false).unwrap();
+        assert_eq!(
+            sub.apply_line(&regex, "foo", false),
+            Some(("f[o][o]".to_string(), 2))
+        );
+        assert_eq!(sub.apply_line(&regex, "bar", false), None);
+    }
+
+    #[test]
+    fn test_map_col() {
+        // "a long word here" -> "a w here"
+        let sub = Substitute::parse("/long word/w/").unwrap();
+        let regex = sub.regex(false, false).unwrap();
+        let line = "a long word here";
+        // 一致より前の文字は動かない
+        assert_eq!(sub.map_col(&regex, line, 0, false), 0);
+        // 一致の中の文字は置換後の文字列の先頭
+        assert_eq!(sub.map_col(&regex, line, 7, false), 2);
+        // 一致より後ろの文字は長さの差だけずれる
+        assert_eq!(sub.map_col(&regex, line, 12, false), 4);
+
+        // g フラグでは手前のすべての一致の分だけずれる。g がなければ最初の一致だけ
+        let sub = Substitute::parse("/o/[&]/g").unwrap();
+        let regex = sub.regex(false, false).unwrap();
+        assert_eq!(sub.map_col(&regex, "foo bar", 4, false), 8);
+        let sub = Substitute::parse("/o/[&]/").unwrap();
+        let regex = sub.regex(false, false).unwrap();
+        assert_eq!(sub.map_col(&regex, "foo bar", 4, false), 6);
+    }
+
+    fn rows(buffer: &Buffer) -> Vec<&str> {
+        buffer.iter().map(|r| r.chars()).collect()
+    }
+
+    fn start_confirm(args: &str, buffer: &Buffer) -> ConfirmSubstitute {
+        let sub = Substitute::parse(args).unwrap();
+        assert!(sub.flags.confirm);
+        let range = LineRange::new(0, buffer.len() - 1);
+        ConfirmSubstitute::new(sub, range, false, false).unwrap()
+    }
+
+    #[test]
+    fn test_confirm_yes_and_no() {
+        let mut buffer = Buffer::from_lines(["foo foo foo"]);
+        let mut confirm = start_confirm("/foo/bar/gc", &buffer);
+
+        assert_eq!(confirm.find_next(&buffer), Some(Position::new(0, 0)));
+        assert!(confirm.answer(ConfirmAnswer::Yes, &mut buffer));
+        assert_eq!(confirm.find_next(&buffer), Some(Position::new(0, 4)));
+        assert!(!confirm.answer(ConfirmAnswer::No, &mut buffer));
+        assert_eq!(confirm.find_next(&buffer), Some(Position::new(0, 8)));
+        assert!(confirm.answer(ConfirmAnswer::Yes, &mut buffer));
+        assert_eq!(confirm.find_next(&buffer), None);
+
+        assert!(confirm.is_done());
+        assert_eq!(rows(&buffer), vec!["bar foo bar"]);
+        assert_eq!(
+            confirm.result(),
+            Some(SubstituteResult {
+                count: 2,
+                lines: 1,
+                last_row: 0
+            })
+        );
+    }
+
+    #[test]
+    fn test_confirm_all() {
+        let mut buffer = Buffer::from_lines(["foo", "x", "foo foo"]);
+        let mut confirm = start_confirm("/foo/bar/gc", &buffer);
+
+        confirm.find_next(&buffer);
+        assert!(!confirm.answer(ConfirmAnswer::No, &mut buffer));
+        assert_eq!(confirm.find_next(&buffer), Some(Position::new(2, 0)));
+        assert!(confirm.answer(ConfirmAnswer::All, &mut buffer));
+
+        assert!(confirm.is_done());
+        assert_eq!(rows(&buffer), vec!["foo", "x", "bar bar"]);
+        assert_eq!(confirm.result().unwrap().count, 2);
+    }
+
+    #[test]
+    fn test_confirm_quit_and_last() {
+        let mut buffer = Buffer::from_lines(["foo", "foo", "foo"]);
+        let mut confirm = start_confirm("/foo/bar/c", &buffer);
+        confirm.find_next(&buffer);
+        assert!(confirm.answer(ConfirmAnswer::Yes, &mut buffer));
+        confirm.find_next(&buffer);
+        assert!(!confirm.answer(ConfirmAnswer::Quit, &mut buffer));
+        assert!(confirm.is_done());
+        assert_eq!(confirm.find_next(&buffer), None);
+        assert_eq!(rows(&buffer), vec!["bar", "foo", "foo"]);
+
+        let mut buffer = Buffer::from_lines(["foo", "foo", "foo"]);
+        let mut confirm = start_confirm("/foo/bar/c", &buffer);
+        confirm.find_next(&buffer);
+        assert!(!confirm.answer(ConfirmAnswer::No, &mut buffer));
+        confirm.find_next(&buffer);
+        assert!(confirm.answer(ConfirmAnswer::Last, &mut buffer));
+        assert!(confirm.is_done());
+        assert_eq!(rows(&buffer), vec!["foo", "bar", "foo"]);
+        assert_eq!(confirm.result().unwrap().last_row, 1);
+    }
+
+    #[test]
+    fn test_confirm_answer_from_char() {
+        assert_eq!(ConfirmAnswer::from_char('y'), Some(ConfirmAnswer::Yes));
+        assert_eq!(ConfirmAnswer::from_char('l'), Some(ConfirmAnswer::Last));
+        assert_eq!(ConfirmAnswer::from_char('x'), None);
+    }
+
+    #[test]
+    fn test_preserve_case() {
+        // lower
+        assert_eq!(preserve_case("foo", "bar"), "bar");
+        // Title
+        assert_eq!(preserve_case("Foo", "bar"), "Bar");
+        // UPPER
+        assert_eq!(preserve_case("FOO", "bar"), "BAR");
+        // 英字を含まない場合はそのまま
+        assert_eq!(preserve_case("123", "bar"), "bar");
+    }
+
+    #[test]
+    fn test_apply_line_preserve_case() {
+        let sub = Substitute::parse("/foo/bar/g").unwrap();
+        let regex = sub.regex(true, false).unwrap();
+        assert_eq!(
+            sub.apply_line(&regex, "foo Foo FOO", true),
+            Some(("bar Bar BAR".to_string(), 3))
+        );
+    }
+}
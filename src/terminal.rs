@@ -2,6 +2,38 @@ use std::io::{self, Stdout, Write};
 
 use termion::raw::{IntoRawMode, RawTerminal};
 
+use crate::mode::Mode;
+use crate::screen::Screen;
+
+/// カーソルの形 (DECSCUSR)
+///
+/// 対応していない端末ではエスケープシーケンスは無視される。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CursorShape {
+    Block,
+    Bar,
+    Underline,
+}
+
+impl CursorShape {
+    /// モードに対応するカーソルの形
+    pub fn for_mode(mode: Mode) -> Self {
+        match mode {
+            Mode::Insert => Self::Bar,
+            Mode::Normal | Mode::Command | Mode::Visual | Mode::VisualLine => Self::Block,
+        }
+    }
+
+    /// `ESC [ n SP q` 形式のエスケープシーケンス (点滅なし)
+    pub fn escape_sequence(self) -> &'static str {
+        match self {
+            Self::Block => "\x1b[2 q",
+            Self::Underline => "\x1b[4 q",
+            Self::Bar => "\x1b[6 q",
+        }
+    }
+}
+
 pub struct Terminal {
     stdout: RawTerminal<Stdout>,
     size: (u16, u16),
@@ -39,13 +71,49 @@ impl Terminal {
         )?;
         self.stdout.flush()
     }
+
+    pub fn bell(&mut self, visual: bool) -> io::Result<()> {
+        Screen::bell(&mut self.stdout, visual)
+    }
+
+    pub fn set_cursor_shape(&mut self, shape: CursorShape) -> io::Result<()> {
+        write!(self.stdout, "{}", shape.escape_sequence())?;
+        self.stdout.flush()
+    }
 }
 
 impl Drop for Terminal {
     fn drop(&mut self) {
         // カーソルスタイルをリセット
-        let _ = write!(self.stdout, "{}", termion::cursor::SteadyBlock);
+        let _ = self.set_cursor_shape(CursorShape::Block);
         // 終了時の画面クリア
         let _ = self.clear_screen();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cursor_shape_escape_sequence() {
+        assert_eq!(CursorShape::Block.escape_sequence().as_bytes(), b"\x1b[2 q");
+        assert_eq!(
+            CursorShape::Underline.escape_sequence().as_bytes(),
+            b"\x1b[4 q"
+        );
+        assert_eq!(CursorShape::Bar.escape_sequence().as_bytes(), b"\x1b[6 q");
+        // termion の定義と一致する
+        assert_eq!(
+            CursorShape::Bar.escape_sequence(),
+            termion::cursor::SteadyBar.to_string()
+        );
+    }
+
+    #[test]
+    fn test_cursor_shape_for_mode() {
+        assert_eq!(CursorShape::for_mode(Mode::Normal), CursorShape::Block);
+        assert_eq!(CursorShape::for_mode(Mode::Insert), CursorShape::Bar);
+        assert_eq!(CursorShape::for_mode(Mode::Visual), CursorShape::Block);
+    }
+}
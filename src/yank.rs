@@ -1,6 +1,6 @@
 use arboard::Clipboard;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum YankType {
     /// 行内にペースト
     InLine,
@@ -15,6 +15,12 @@ pub struct YankManager {
     clipboard: Option<Clipboard>,
 }
 
+impl Default for YankManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl YankManager {
     pub fn new() -> Self {
         Self {
@@ -39,6 +45,12 @@ impl YankManager {
         self.yank_type = YankType::NewLine;
     }
 
+    /// 内容と種類を指定して設定する
+    pub fn set(&mut self, content: Vec<String>, yank_type: YankType) {
+        self.buffer = content;
+        self.yank_type = yank_type;
+    }
+
     pub fn is_newline_yank(&self) -> bool {
         matches!(self.yank_type, YankType::NewLine)
     }